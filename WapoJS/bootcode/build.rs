@@ -1,16 +1,23 @@
+/// The bootcode profiles, each embedded when its `profile-*` feature is enabled.
+const PROFILES: &[&str] = &["minimal", "browser", "nodejs", "worker"];
+
 fn main() {
     yarn_build();
 
-    #[cfg(feature = "browser")]
-    let src_file = std::path::PathBuf::from("js/dist/browser.js");
-    #[cfg(feature = "nodejs")]
-    let src_file = std::path::PathBuf::from("js/dist/nodejs.js");
-    let src = std::fs::read_to_string(&src_file).expect("failed to read bootcode.js");
     let outdir = std::env::var("OUT_DIR").expect("Missing OUT_DIR");
     let outdir = std::path::PathBuf::from(outdir);
-    let bytecode = qjsbind::compile(&src, "<bootcode>").expect("failed to compile the bootcode");
-    std::fs::write(outdir.join("bootcode.jsc"), bytecode)
-        .expect("failed to write bytecode to file");
+    for profile in PROFILES {
+        let feature = format!("CARGO_FEATURE_PROFILE_{}", profile.to_uppercase());
+        if std::env::var_os(feature).is_none() {
+            continue;
+        }
+        let src_file = std::path::PathBuf::from(format!("js/dist/{profile}.js"));
+        let src = std::fs::read_to_string(&src_file).expect("failed to read bootcode.js");
+        let bytecode =
+            qjsbind::compile(&src, "<bootcode>").expect("failed to compile the bootcode");
+        std::fs::write(outdir.join(format!("{profile}.jsc")), bytecode)
+            .expect("failed to write bytecode to file");
+    }
 }
 
 fn yarn_build() {
@@ -8,6 +8,8 @@ import "./polyfill-abortcontroller";
 import "./polyfill-abortsignal-more";
 import "./polyfill-blob";
 import "./polyfill-websocket";
+import "./polyfill-messaging";
+import "./polyfill-worker";
 
 import { Headers } from "headers-polyfill";
 globalThis.Headers = Headers;
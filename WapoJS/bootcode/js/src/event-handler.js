@@ -0,0 +1,26 @@
+// Defines the `on<type>` attribute of `target`: a single handler, replaced by the next assignment
+// and removed by assigning null. `onSet` is called with the object a handler is assigned to.
+export function defineEventHandler(target, type, onSet) {
+    const key = Symbol('on' + type);
+    Object.defineProperty(target, 'on' + type, {
+        get() {
+            return this[key] ? this[key].handler : null;
+        },
+        set(handler) {
+            if (this[key]) {
+                this.removeEventListener(type, this[key].listener);
+                this[key] = undefined;
+            }
+            if (typeof handler === 'function') {
+                const listener = (event) => handler.call(this, event);
+                this[key] = { handler, listener };
+                this.addEventListener(type, listener);
+                if (onSet) {
+                    onSet(this);
+                }
+            }
+        },
+        enumerable: true,
+        configurable: true,
+    });
+}
@@ -0,0 +1,3 @@
+import "./text-encode-into";
+import "./url-iterators";
+import "./wapo";
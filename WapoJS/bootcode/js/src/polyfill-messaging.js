@@ -0,0 +1,106 @@
+import { defineEventHandler } from "./event-handler";
+import { transferList } from "./transfer-list";
+
+(function (g) {
+    g.MessagePort = class MessagePort extends EventTarget {
+        constructor(port) {
+            super();
+            this._port = port;
+            this._task = undefined;
+        }
+
+        postMessage(data, transfer) {
+            if (!this._port) {
+                return;
+            }
+            Wapo.portPost(this._port, data, transferList(transfer));
+        }
+
+        start() {
+            if (this._task !== undefined || !this._port) {
+                return;
+            }
+            this._task = Wapo.portListen(this._port, (kind, data) => {
+                if (kind === 'message' || kind === 'messageerror') {
+                    this.dispatchEvent(new MessageEvent(kind, { data }));
+                }
+            });
+        }
+
+        close() {
+            if (this._task !== undefined) {
+                Wapo.close(this._task);
+                this._task = undefined;
+            }
+            if (this._port) {
+                Wapo.portClose(this._port);
+                this._port = undefined;
+            }
+        }
+
+    }
+    // Assigning `onmessage` starts the port, unlike `addEventListener`.
+    defineEventHandler(g.MessagePort.prototype, 'message', (port) => port.start());
+    defineEventHandler(g.MessagePort.prototype, 'messageerror');
+
+    g.MessageChannel = class MessageChannel {
+        constructor() {
+            const [port1, port2] = Wapo.portPair();
+            this.port1 = new MessagePort(port1);
+            this.port2 = new MessagePort(port2);
+        }
+    }
+
+    // Channels of this service by name.
+    const channels = new Map();
+
+    g.BroadcastChannel = class BroadcastChannel extends EventTarget {
+        constructor(name) {
+            super();
+            this.name = String(name);
+            this._closed = false;
+            if (!channels.has(this.name)) {
+                channels.set(this.name, new Set());
+            }
+            channels.get(this.name).add(this);
+        }
+
+        postMessage(data) {
+            if (this._closed) {
+                throw new DOMException('BroadcastChannel is closed', 'InvalidStateError');
+            }
+            const message = Wapo.structuredSerialize(data);
+            for (const target of channels.get(this.name)) {
+                if (target === this) {
+                    continue;
+                }
+                setTimeout(() => {
+                    if (target._closed) {
+                        return;
+                    }
+                    let event;
+                    try {
+                        event = new MessageEvent('message', { data: Wapo.structuredDeserialize(message) });
+                    } catch (err) {
+                        event = new MessageEvent('messageerror', { data: err });
+                    }
+                    target.dispatchEvent(event);
+                }, 0);
+            }
+        }
+
+        close() {
+            if (this._closed) {
+                return;
+            }
+            this._closed = true;
+            const set = channels.get(this.name);
+            set.delete(this);
+            if (set.size === 0) {
+                channels.delete(this.name);
+            }
+        }
+    }
+    defineEventHandler(g.BroadcastChannel.prototype, 'message');
+    defineEventHandler(g.BroadcastChannel.prototype, 'messageerror');
+}(globalThis))
@@ -1,3 +1,10 @@
-require('core-js/full/url');
-require('core-js/full/url/can-parse');
-require('core-js/full/url-search-params');
\ No newline at end of file
+require("./url-iterators");
+
+(function (g) {
+    if (typeof g.URLSearchParams !== 'function' || !g.URLSearchParams.prototype.entries) {
+        // Built without the native URL implementation, fall back to core-js.
+        require('core-js/full/url');
+        require('core-js/full/url/can-parse');
+        require('core-js/full/url-search-params');
+    }
+}(globalThis));
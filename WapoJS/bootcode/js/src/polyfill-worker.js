@@ -0,0 +1,70 @@
+import { defineEventHandler } from "./event-handler";
+import { transferList } from "./transfer-list";
+
+(function (g) {
+    if (typeof Wapo.workerOpen !== 'function') {
+        return;
+    }
+
+    function listen(port, target) {
+        return Wapo.portListen(port, (kind, data) => {
+            switch (kind) {
+                case 'message':
+                    target.dispatchEvent(new MessageEvent('message', { data }));
+                    break;
+                case 'messageerror':
+                    target.dispatchEvent(new MessageEvent('messageerror', { data }));
+                    break;
+                case 'error': {
+                    const event = new Event('error');
+                    event.message = data;
+                    event.error = new Error(data);
+                    target.dispatchEvent(event);
+                    break;
+                }
+            }
+        });
+    }
+
+    g.Worker = class Worker extends EventTarget {
+        constructor(url, options = {}) {
+            super();
+            this._port = Wapo.workerOpen(String(url), {
+                eval: !!options.eval,
+                name: options.name,
+            });
+            this._task = listen(this._port, this);
+        }
+
+        postMessage(data, transfer) {
+            Wapo.portPost(this._port, data, transferList(transfer));
+        }
+
+        terminate() {
+            if (this._task === undefined) {
+                return;
+            }
+            Wapo.close(this._task);
+            Wapo.portClose(this._port);
+            this._task = undefined;
+        }
+    }
+    for (const type of ['message', 'messageerror', 'error']) {
+        defineEventHandler(g.Worker.prototype, type);
+    }
+
+    // The global scope of a worker.
+    const parentPort = Wapo.workerParentPort;
+    if (parentPort) {
+        const scope = new EventTarget();
+        g.self = g;
+        g.addEventListener = scope.addEventListener.bind(scope);
+        g.removeEventListener = scope.removeEventListener.bind(scope);
+        g.dispatchEvent = scope.dispatchEvent.bind(scope);
+        g.postMessage = (data, transfer) => Wapo.portPost(parentPort, data, transferList(transfer));
+        g.close = () => Wapo.exit();
+        defineEventHandler(g, 'message');
+        defineEventHandler(g, 'messageerror');
+        listen(parentPort, scope);
+    }
+}(globalThis))
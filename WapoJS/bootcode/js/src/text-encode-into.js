@@ -0,0 +1,13 @@
+// Layers `TextEncoder.prototype.encodeInto` on top of the native TextEncoder, in every profile
+// that exposes it. Does nothing when built without the native text encoding implementation.
+(function (g) {
+    if (typeof g.TextEncoder !== 'function' || !(g.Wapo && g.Wapo.textEncodePrefix)) {
+        return;
+    }
+    const encodePrefix = g.Wapo.textEncodePrefix;
+    g.TextEncoder.prototype.encodeInto = function (source, destination) {
+        const { read, bytes } = encodePrefix(String(source), destination.length);
+        destination.set(bytes);
+        return { read, written: bytes.length };
+    };
+})(globalThis);
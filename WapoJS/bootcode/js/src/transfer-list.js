@@ -0,0 +1,8 @@
+// Normalises the second argument of `postMessage`, either a transfer list or an options object
+// with a `transfer` list.
+export function transferList(transfer) {
+    if (Array.isArray(transfer)) {
+        return transfer;
+    }
+    return (transfer && transfer.transfer) || [];
+}
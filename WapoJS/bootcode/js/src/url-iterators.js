@@ -0,0 +1,27 @@
+// Layers the iterator protocol on top of the native URLSearchParams, in every profile that
+// exposes it. Does nothing when built without the native URL implementation.
+(function (g) {
+    if (typeof g.URLSearchParams !== 'function' || !g.URLSearchParams.prototype.entries) {
+        return;
+    }
+    // The native `entries` returns an array of pairs, layer the iterator protocol on top of it.
+    const proto = g.URLSearchParams.prototype;
+    const pairs = proto.entries;
+    proto.entries = function () {
+        return pairs.call(this)[Symbol.iterator]();
+    };
+    proto.keys = function () {
+        return pairs.call(this).map(([key]) => key)[Symbol.iterator]();
+    };
+    proto.values = function () {
+        return pairs.call(this).map(([, value]) => value)[Symbol.iterator]();
+    };
+    proto.forEach = function (callback, thisArg) {
+        for (const [key, value] of pairs.call(this)) {
+            callback.call(thisArg, value, key, this);
+        }
+    };
+    proto[Symbol.iterator] = proto.entries;
+    Object.defineProperty(proto, Symbol.toStringTag, { value: 'URLSearchParams' });
+    Object.defineProperty(g.URL.prototype, Symbol.toStringTag, { value: 'URL' });
+}(globalThis));
@@ -34,19 +34,128 @@
             depth: 5,
         });
     }
+    let groupDepth = 0;
+    const counters = new Map();
+    const timers = new Map();
+    function log(level, args) {
+        return Wapo.print(level, args, undefined, groupDepth);
+    }
+    function pad(s, width) {
+        return s + ' '.repeat(Math.max(0, width - s.length));
+    }
+    function cell(value) {
+        if (value === undefined) return '';
+        if (typeof value === 'string') return value;
+        if (value !== null && typeof value === 'object') {
+            try {
+                return JSON.stringify(value);
+            } catch (e) {
+                return String(value);
+            }
+        }
+        return String(value);
+    }
+    function formatTable(data, properties) {
+        const rows = [];
+        const columns = [];
+        let hasValues = false;
+        const entries = data instanceof Map ? Array.from(data.entries()) : Object.entries(data);
+        for (const [index, row] of entries) {
+            const line = { '(index)': String(index) };
+            if (row !== null && typeof row === 'object') {
+                for (const key of properties || Object.keys(row)) {
+                    if (!columns.includes(key)) columns.push(key);
+                    line[key] = cell(row[key]);
+                }
+            } else {
+                hasValues = true;
+                line['Values'] = cell(row);
+            }
+            rows.push(line);
+        }
+        const header = ['(index)', ...columns];
+        if (hasValues) header.push('Values');
+        const widths = header.map(h => Math.max(h.length, ...rows.map(r => (r[h] || '').length)));
+        const sep = (l, m, r) => l + widths.map(w => '─'.repeat(w + 2)).join(m) + r;
+        const fmt = values => '│' + values.map((v, i) => ' ' + pad(v, widths[i]) + ' ').join('│') + '│';
+        return [
+            sep('┌', '┬', '┐'),
+            fmt(header),
+            sep('├', '┼', '┤'),
+            ...rows.map(r => fmt(header.map(h => r[h] || ''))),
+            sep('└', '┴', '┘'),
+        ].join('\n');
+    }
     g.console = {
         log(...args) {
-            return Wapo.print(2, args);
+            return log(2, args);
         },
         info(...args) {
-            return Wapo.print(2, args);
+            return log(2, args);
+        },
+        debug(...args) {
+            return log(1, args);
         },
         warn(...args) {
-            return Wapo.print(3, args);
+            return log(3, args);
         },
         error(...args) {
-            return Wapo.print(4, args);
-        }
+            return log(4, args);
+        },
+        trace(...args) {
+            const stack = (new Error().stack || '').split('\n').slice(1).join('\n');
+            // Logged at the level of `console.debug`, as the stack makes it a debugging aid.
+            return log(1, ['Trace:', ...args, '\n' + stack]);
+        },
+        assert(condition, ...args) {
+            if (!condition) {
+                return log(4, ['Assertion failed' + (args.length ? ':' : ''), ...args]);
+            }
+        },
+        table(data, properties) {
+            if (data === null || typeof data !== 'object') {
+                return log(2, [data]);
+            }
+            return log(2, [formatTable(data, properties)]);
+        },
+        group(...args) {
+            if (args.length) log(2, args);
+            groupDepth++;
+        },
+        groupCollapsed(...args) {
+            return this.group(...args);
+        },
+        groupEnd() {
+            groupDepth = Math.max(0, groupDepth - 1);
+        },
+        count(label = 'default') {
+            const count = (counters.get(label) || 0) + 1;
+            counters.set(label, count);
+            return log(2, [`${label}: ${count}`]);
+        },
+        countReset(label = 'default') {
+            counters.delete(label);
+        },
+        time(label = 'default') {
+            if (timers.has(label)) {
+                return log(3, [`Timer '${label}' already exists`]);
+            }
+            timers.set(label, performance.now());
+        },
+        timeLog(label = 'default', ...args) {
+            if (!timers.has(label)) {
+                return log(3, [`Timer '${label}' does not exist`]);
+            }
+            return log(2, [`${label}: ${performance.now() - timers.get(label)}ms`, ...args]);
+        },
+        timeEnd(label = 'default') {
+            if (!timers.has(label)) {
+                return log(3, [`Timer '${label}' does not exist`]);
+            }
+            const elapsed = performance.now() - timers.get(label);
+            timers.delete(label);
+            return log(2, [`${label}: ${elapsed}ms`]);
+        },
     }
     g.print = g.console.log;
     g.btoa = s => Wapo.base64Encode(s, true);
@@ -0,0 +1,2 @@
+import "./common";
+globalThis.self = globalThis;
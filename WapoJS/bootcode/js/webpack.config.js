@@ -2,8 +2,10 @@ const path = require('path');
 
 module.exports = {
   entry: {
+    'minimal': './src/minimal',
     'browser': './src/browser',
     'nodejs': './src/nodejs',
+    'worker': './src/worker',
   },
   mode: 'production',
   output: {
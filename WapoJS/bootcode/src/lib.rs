@@ -1,2 +1,47 @@
 #![no_std]
-pub const BOOT_CODE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/bootcode.jsc"));
+
+macro_rules! profile {
+    ($name: literal) => {
+        include_bytes!(concat!(env!("OUT_DIR"), "/", $name, ".jsc"))
+    };
+}
+
+/// Only the `Wapo` glue: timers, console and the like, without any polyfill.
+#[cfg(feature = "profile-minimal")]
+pub const MINIMAL: &[u8] = profile!("minimal");
+/// Web APIs plus `XMLHttpRequest`.
+#[cfg(feature = "profile-browser")]
+pub const BROWSER: &[u8] = profile!("browser");
+/// Web APIs plus `require` and the Node.js builtin modules.
+#[cfg(feature = "profile-nodejs")]
+pub const NODEJS: &[u8] = profile!("nodejs");
+/// Web APIs as found in a web worker.
+#[cfg(feature = "profile-worker")]
+pub const WORKER: &[u8] = profile!("worker");
+
+/// The profile selected by the `nodejs` or `browser` feature.
+#[cfg(feature = "nodejs")]
+pub const BOOT_CODE: &[u8] = NODEJS;
+#[cfg(all(feature = "browser", not(feature = "nodejs")))]
+pub const BOOT_CODE: &[u8] = BROWSER;
+
+/// The profiles embedded in this build, selected with the `profile-*` features.
+pub const PROFILES: &[(&str, &[u8])] = &[
+    #[cfg(feature = "profile-minimal")]
+    ("minimal", MINIMAL),
+    #[cfg(feature = "profile-browser")]
+    ("browser", BROWSER),
+    #[cfg(feature = "profile-nodejs")]
+    ("nodejs", NODEJS),
+    #[cfg(feature = "profile-worker")]
+    ("worker", WORKER),
+];
+
+/// Looks up a profile by name, `node` is accepted for `nodejs`.
+pub fn profile(name: &str) -> Option<&'static [u8]> {
+    let name = if name == "node" { "nodejs" } else { name };
+    PROFILES
+        .iter()
+        .find(|(profile, _)| *profile == name)
+        .map(|(_, code)| *code)
+}
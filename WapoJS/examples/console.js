@@ -0,0 +1,19 @@
+// Try with: wapojs --log-format json --log-service demo examples/console.js
+console.debug("debug message");
+console.group("group");
+console.log("inside the group");
+console.group();
+console.warn("nested");
+console.groupEnd();
+console.groupEnd();
+console.table([{ a: 1, b: "x" }, { a: 2, c: true }]);
+console.table({ first: 1, second: 2 });
+console.count();
+console.count();
+console.count("other");
+console.assert(1 + 1 == 3, "math is broken");
+console.time("work");
+for (let i = 0; i < 100000; i++) { }
+console.timeEnd("work");
+console.trace("where am I");
+setTimeout(() => console.log("logged from a timer resource"), 10);
@@ -0,0 +1,24 @@
+// Run with: wapojs --coverage coverage examples/coverage.js
+// Then inspect coverage/lcov.info, or render the V8 JSON with `c8 report --temp-directory coverage`.
+function classify(n) {
+    if (n < 0) {
+        return "negative";
+    } else if (n === 0) {
+        return "zero";
+    }
+    return "positive";
+}
+
+// Braceless branches are counted too: only the `else` of this one runs.
+function sign(n) {
+    if (n < 0) return -1;
+    else return 1;
+}
+
+function neverCalled() {
+    return "unreachable";
+}
+
+for (const n of [3, 0, 7]) {
+    console.log(n, classify(n), sign(n));
+}
@@ -0,0 +1,23 @@
+// Run with: wapojs --cpu-prof profile.cpuprofile examples/cpuProfile.js
+// Then open profile.cpuprofile in the Performance panel of Chrome DevTools, or write collapsed
+// stacks with `--cpu-prof profile.folded` and render them with `flamegraph.pl profile.folded`.
+function fib(n) {
+    return n < 2 ? n : fib(n - 1) + fib(n - 2);
+}
+
+function sortRandom(count) {
+    const values = [];
+    for (let i = 0; i < count; i++) {
+        values.push(Math.random());
+    }
+    return values.sort((a, b) => a - b);
+}
+
+function main() {
+    const started = Date.now();
+    console.log("fib(25) =", fib(25));
+    console.log("sorted", sortRandom(200000).length, "numbers");
+    console.log("done in", Date.now() - started, "ms");
+}
+
+main();
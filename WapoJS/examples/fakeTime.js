@@ -0,0 +1,29 @@
+// Run with: wapojs --fake-time examples/fakeTime.js
+const start = Date.now();
+// Dates made through any path use the virtual clock.
+if (new Date().constructor !== Date || new (new Date().constructor)().getTime() !== start) {
+    throw new Error("Date.prototype.constructor is not the fake Date");
+}
+let ticks = 0;
+const id = setInterval(() => {
+    ticks++;
+    console.log(`tick ${ticks} at +${(Date.now() - start) / 3600000}h`);
+    if (ticks == 24) {
+        clearInterval(id);
+    }
+}, 3600 * 1000);
+
+(async () => {
+    await Wapo.advanceTime(90 * 60 * 1000);
+    console.log(`advanced manually to +${(Date.now() - start) / 60000}min, ticks=${ticks}`);
+})();
+
+// Cron schedules follow the virtual clock too: a daily job fires once per virtual day.
+let runs = 0;
+const daily = Wapo.schedule("0 0 * * *", (scheduled) => {
+    runs++;
+    console.log(`daily job ${runs} scheduled at ${new Date(scheduled).toISOString()}`);
+    if (runs == 3) {
+        Wapo.close(daily);
+    }
+});
@@ -0,0 +1,25 @@
+// Run with: wapojs examples/heapStats.js
+// Diff the two snapshots with: diff heap-stats-before.json heap-stats-after.json
+const leaked = [];
+
+function top(stats, n = 5) {
+    return Object.entries(stats.reachable)
+        .sort(([, a], [, b]) => b.count - a.count)
+        .slice(0, n)
+        .map(([name, { count, size }]) => `${name}: ${count} (${size} bytes)`)
+        .join(", ");
+}
+
+const before = Wapo.heapStats();
+console.log(`objects: ${before.runtime.objCount}, top classes: ${top(before)}`);
+Wapo.writeHeapStats?.("heap-stats-before.json");
+
+for (let i = 0; i < 1000; i++) {
+    leaked.push({ id: i, payload: new Uint8Array(64) });
+}
+globalThis.leaked = leaked;
+
+const after = Wapo.heapStats();
+console.log(`objects: ${after.runtime.objCount}, top classes: ${top(after)}`);
+console.log(`Uint8Array retained at: ${after.reachable.Uint8Array.paths.join(", ")}`);
+Wapo.writeHeapStats?.("heap-stats-after.json");
@@ -0,0 +1,20 @@
+// Run with: wapojs --inspect 9229 examples/inspect.js
+// Then open chrome://inspect, or attach VS Code with a "node" attach configuration on port 9229.
+// The console shows the logged objects and can evaluate expressions in the global scope, even
+// while the script is busy inside `fib`. Set a breakpoint on the `return` of `fib` to pause and
+// step, the scope pane shows `n` and the console evaluates expressions in the paused frame.
+function fib(n) {
+    return n < 2 ? n : fib(n - 1) + fib(n - 2);
+}
+
+let round = 0;
+const id = setInterval(() => {
+    round++;
+    const started = Date.now();
+    const value = fib(25);
+    console.log();
+    console.log(`round ${round}: fib(25) = ${value} in ${Date.now() - started}ms`, { round, value });
+    if (round == 60) {
+        clearInterval(id);
+    }
+}, 1000);
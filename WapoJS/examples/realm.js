@@ -0,0 +1,61 @@
+// Run with: wapojs examples/realm.js
+const realm = Wapo.createRealm({
+    globals: { config: { greeting: "hello", tags: new Set(["a", "b"]) } },
+});
+
+// The realm has its own intrinsics and no access to the host APIs.
+console.log("Wapo in realm:", realm.eval("typeof Wapo"));
+Array.prototype.first = function () { return this[0]; };
+console.log("patched Array in realm:", realm.eval("typeof [].first"));
+
+realm.eval(`
+    function greet(name) {
+        return { text: config.greeting + ", " + name, tags: [...config.tags], at: new Date(0) };
+    }
+`);
+const reply = realm.call("greet", ["world"]);
+console.log(reply.text, reply.tags, reply.at instanceof Date);
+
+// Values are copied, cycles included.
+realm.eval("function echo(value) { value.seen = true; return value; }");
+const input = { n: 1 };
+input.self = input;
+const output = realm.call("echo", [input]);
+console.log("input untouched:", input.seen === undefined, "cycle kept:", output.self === output);
+
+try {
+    realm.call("echo", [() => 1]);
+} catch (err) {
+    console.log("functions are not cloneable:", String(err));
+}
+
+// A time budget stops runaway snippets, the realm stays usable afterwards.
+const bounded = Wapo.createRealm({ timeout: 50 });
+try {
+    bounded.eval("for (;;) {}");
+} catch (err) {
+    console.log("budget exceeded:", String(err));
+}
+console.log("still usable:", bounded.eval("1 + 1"));
+// The jobs of the creator are not run by a realm call, with or without a timeout: the call
+// completes synchronously and the reactions follow on the next tick, in order.
+for (const options of [{}, { timeout: 50 }]) {
+    const order = [];
+    const scratch = Wapo.createRealm(options);
+    Promise.resolve().then(() => order.push("creator job"));
+    order.push(`eval ${scratch.eval("6 * 7")}`);
+    order.push("after eval");
+    Promise.resolve().then(() => {
+        console.log(JSON.stringify(options), "order:", order.join(", "));
+        console.assert(order.join() === "eval 42,after eval,creator job", "run-to-completion");
+        scratch.dispose();
+    });
+}
+bounded.dispose();
+
+realm.dispose();
+try {
+    realm.eval("1");
+} catch (err) {
+    console.log("after dispose:", String(err));
+}
@@ -0,0 +1,8 @@
+console.log("Upcoming runs:", Wapo.scheduleUpcoming("* * * * *", { timezone: "UTC" }, 3).map(t => new Date(t)));
+let runs = 0;
+const id = Wapo.schedule("*/2 * * * * *", (scheduledTime) => {
+    console.log("Scheduled at " + new Date(scheduledTime) + ", fired at " + new Date());
+    if (++runs == 3) {
+        Wapo.close(id);
+    }
+});
@@ -0,0 +1,8 @@
+function fail() { throw new Error("boom"); }
+try { fail(); } catch (e) { console.log(e.stack); }
+try { fail(); } catch (e) { Wapo.inspect(JSON.stringify(e.stack)); }
+// Run with: wapojs examples/sourceMap.js
+// The inline map sends line 1 to src/app.ts:5:3 and line 2 to src/app.ts:8:1, so the stack
+// above is printed as `at fail (src/app.ts:5:3)` and `at <eval> (src/app.ts:8:1)`. `e.stack`
+// itself holds the original positions too, as the JSON printed by line 3 shows.
+//# sourceMappingURL=data:application/json;base64,eyJ2ZXJzaW9uIjozLCJzb3VyY2VzIjpbInNyYy9hcHAudHMiXSwibmFtZXMiOltdLCJtYXBwaW5ncyI6IkFBSUU7QUFHRiJ9
@@ -0,0 +1,56 @@
+// Run with: wapojs examples/structuredClone.js
+const original = {
+    date: new Date(0),
+    pattern: /wapo/gi,
+    map: new Map([["key", new Set([1n, 2n])]]),
+    bytes: new Uint8Array([1, 2, 3]),
+};
+original.self = original;
+
+const copy = structuredClone(original);
+console.log("cycle kept:", copy.self === copy, "deep copy:", copy.map !== original.map);
+console.log(copy.date.toISOString(), copy.pattern.flags, [...copy.map.get("key")], copy.bytes);
+
+// Primitive wrappers are cloned as primitives, references to the other objects stay shared.
+const shared = { n: 1 };
+const [one, x, y] = structuredClone([new Number(1), shared, shared]);
+if (one !== 1 || x !== y || x === shared || x.n !== 1) {
+    throw new Error("wrappers broke the shared references");
+}
+console.log("wrappers:", one, "shared kept:", x === y);
+
+// Objects are recognized by their native class, not by tags or globals scripts can change.
+const fake = { [Symbol.toStringTag]: "Uint8Array", length: 1 };
+const RealMap = Map;
+globalThis.Map = function () {
+    throw new Error("the clone called a replaced constructor");
+};
+const [notBytes, stillMap] = structuredClone([fake, new RealMap([[1, 2]])]);
+globalThis.Map = RealMap;
+if (notBytes instanceof Uint8Array || notBytes.length !== 1 || !(stillMap instanceof Map)) {
+    throw new Error("the clone trusted a spoofed type");
+}
+console.log("spoofed tag cloned as:", notBytes, "map kept:", stillMap.get(1));
+
+// The same format can be sent to another service.
+const wire = Wapo.structuredSerialize(original);
+console.log(`wire size: ${wire.length} bytes`);
+console.log("decoded:", Wapo.structuredDeserialize(wire).bytes);
+
+const { port1, port2 } = new MessageChannel();
+port2.onmessage = (event) => {
+    console.log("port2 received:", event.data);
+    port1.close();
+};
+const buffer = new Uint8Array(8);
+port1.postMessage({ hello: "port", buffer }, [buffer]);
+console.log("transferred buffer length:", buffer.length);
+
+const a = new BroadcastChannel("news");
+const b = new BroadcastChannel("news");
+b.onmessage = (event) => {
+    console.log("b received:", event.data);
+    a.close();
+    b.close();
+};
+a.postMessage({ headline: "hello", at: new Date(0) });
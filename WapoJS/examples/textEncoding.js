@@ -0,0 +1,49 @@
+let failures = 0;
+function assertEq(actual, expected, msg = "") {
+  if (actual !== expected) {
+    failures++;
+    console.error(`Assertion failed: ${msg}, actual: ${actual}, expected: ${expected}`);
+  }
+}
+
+const encoder = new TextEncoder();
+const bytes = encoder.encode("héllo 😀");
+assertEq(bytes.length, 11, "utf-8 length");
+const dest = new Uint8Array(8);
+const { read, written } = encoder.encodeInto("héllo 😀", dest);
+assertEq(read, 6, "encodeInto does not split the emoji");
+assertEq(written, 7, "encodeInto written");
+
+// Streaming decode across a chunk boundary inside a multi-byte sequence.
+const decoder = new TextDecoder();
+let text = decoder.decode(bytes.slice(0, 8), { stream: true });
+text += decoder.decode(bytes.slice(8));
+assertEq(text, "héllo 😀", "streaming utf-8");
+
+assertEq(new TextDecoder().decode(new Uint8Array([0xEF, 0xBB, 0xBF, 0x61])), "a", "BOM removed");
+assertEq(new TextDecoder("utf-8", { ignoreBOM: true }).decode(new Uint8Array([0xEF, 0xBB, 0xBF, 0x61])), "\uFEFFa", "ignoreBOM");
+
+function thrown(f) {
+  try {
+    f();
+  } catch (e) {
+    return e;
+  }
+}
+const fatal = thrown(() => new TextDecoder("utf-8", { fatal: true }).decode(new Uint8Array([0xff])));
+assertEq(fatal instanceof TypeError, true, "fatal throws a TypeError on invalid input");
+assertEq(thrown(() => new TextDecoder("nope")) instanceof RangeError, true, "unknown labels throw a RangeError");
+assertEq("encodePrefix" in TextEncoder.prototype, false, "no non-standard methods");
+assertEq(new TextDecoder().decode(new Uint8Array([0x61, 0xff])), "a�", "replacement");
+
+assertEq(new TextDecoder("latin1").encoding, "windows-1252", "latin1 label");
+assertEq(new TextDecoder("latin1").decode(new Uint8Array([0x63, 0x61, 0x66, 0xE9, 0x80])), "café€", "windows-1252");
+assertEq(new TextDecoder("utf-16le").decode(new Uint8Array([0x3D, 0xD8, 0x00, 0xDE])), "😀", "utf-16le");
+assertEq(new TextDecoder("utf-16be").decode(new Uint8Array([0x00, 0x61])), "a", "utf-16be");
+assertEq(new TextDecoder("gbk").decode(new Uint8Array([0xC4, 0xE3, 0xBA, 0xC3])), "你好", "gbk");
+assertEq(new TextDecoder("shift_jis").decode(new Uint8Array([0x82, 0xB1, 0x82, 0xF1])), "こん", "shift_jis");
+
+const sjis = new TextDecoder("shift_jis");
+assertEq(sjis.decode(new Uint8Array([0x82]), { stream: true }) + sjis.decode(new Uint8Array([0xB1])), "こ", "streaming shift_jis");
+
+console.log(failures == 0 ? "all tests passed" : `${failures} assertion(s) failed`);
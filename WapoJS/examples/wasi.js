@@ -0,0 +1,80 @@
+console.log = Wapo.inspect;
+
+// Copies /data/in.txt to stdout and to /data/out.txt, reads the copy back to stderr and exits
+// with code 3.
+const wat = `
+  (module
+    (import "wasi_snapshot_preview1" "path_open"
+      (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_pread" (func $fd_pread (param i32 i32 i32 i64 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+    (memory (export "memory") 1)
+    (data (i32.const 16) "in.txt")
+    (data (i32.const 32) "out.txt")
+    (func (export "_start")
+      ;; the iovec at 0 points to a 1024 bytes buffer at 256
+      (i32.store (i32.const 0) (i32.const 256))
+      (i32.store (i32.const 4) (i32.const 1024))
+      ;; fd 3 is the first preopen
+      (drop (call $path_open (i32.const 3) (i32.const 0) (i32.const 16) (i32.const 6)
+        (i32.const 0) (i64.const 0) (i64.const 0) (i32.const 0) (i32.const 64)))
+      (drop (call $fd_read (i32.load (i32.const 64)) (i32.const 0) (i32.const 1) (i32.const 8)))
+      (i32.store (i32.const 4) (i32.load (i32.const 8)))
+      (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 12)))
+      ;; O_CREAT
+      (drop (call $path_open (i32.const 3) (i32.const 0) (i32.const 32) (i32.const 7)
+        (i32.const 1) (i64.const 0) (i64.const 0) (i32.const 0) (i32.const 68)))
+      (drop (call $fd_write (i32.load (i32.const 68)) (i32.const 0) (i32.const 1) (i32.const 12)))
+      (drop (call $fd_pread (i32.load (i32.const 68)) (i32.const 0) (i32.const 1) (i64.const 0) (i32.const 8)))
+      (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 12)))
+      (call $proc_exit (i32.const 3))))
+`;
+
+// Grows /data/big.txt with fd_filestat_set_size and fd_allocate, returning the errno of each call.
+const growWat = `
+  (module
+    (import "wasi_snapshot_preview1" "path_open"
+      (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_filestat_set_size"
+      (func $set_size (param i32 i64) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_allocate"
+      (func $allocate (param i32 i64 i64) (result i32)))
+    (memory (export "memory") 1)
+    (data (i32.const 16) "big.txt")
+    (func $fd (result i32)
+      (drop (call $path_open (i32.const 3) (i32.const 0) (i32.const 16) (i32.const 7)
+        (i32.const 1) (i64.const 0) (i64.const 0) (i32.const 0) (i32.const 64)))
+      (i32.load (i32.const 64)))
+    (func (export "setSize") (param i64) (result i32)
+      (call $set_size (call $fd) (local.get 0)))
+    (func (export "allocate") (param i64) (result i32)
+      (call $allocate (call $fd) (i64.const 0) (local.get 0))))
+`;
+
+async function main() {
+  const decoder = new TextDecoder();
+  const imports = Wapo.wasi.create({
+    args: ["copy"],
+    env: { LANG: "C" },
+    preopens: {
+      "/data": { "in.txt": "hello from the in-memory filesystem\n" },
+    },
+    stdout: (data) => console.log("stdout:", decoder.decode(data)),
+    stderr: (data) => console.log("stderr:", decoder.decode(data)),
+  });
+  const { instance } = await WebAssembly.instantiate(WebAssembly.parseWat(wat), imports);
+  console.log("exit code:", Wapo.wasi.start(instance));
+
+  // The files may hold maxFsSize bytes together, growing past it fails instead of allocating.
+  const limited = Wapo.wasi.create({ preopens: { "/data": {} }, maxFsSize: 1024 });
+  const grow = (await WebAssembly.instantiate(WebAssembly.parseWat(growWat), limited)).instance;
+  console.assert(grow.exports.setSize(512n) === 0, "within the budget");
+  console.assert(grow.exports.setSize(-1n) === 28, "EINVAL for a negative size");
+  console.assert(grow.exports.setSize(2048n) === 22, "EFBIG past the budget");
+  console.assert(grow.exports.allocate(1n << 60n) === 22, "EFBIG for a huge allocation");
+  console.log("the filesystem budget holds");
+}
+
+main().catch(console.error);
@@ -0,0 +1,29 @@
+console.log = Wapo.inspect;
+
+const add = WebAssembly.parseWat(`
+  (module
+    (func (export "add") (param i32 i32) (result i32)
+      (i32.add (local.get 0) (local.get 1))))
+`);
+const sub = WebAssembly.parseWat(`
+  (module
+    (func (export "sub") (param i32 i32) (result i32)
+      (i32.sub (local.get 0) (local.get 1))))
+`);
+
+async function main() {
+  // The first compilation misses, the others hit whatever API they come from.
+  new WebAssembly.Module(add);
+  await WebAssembly.compile(add);
+  const { instance } = await WebAssembly.instantiate(add);
+  console.log("add(1, 2):", instance.exports.add(1, 2));
+  console.log(Wapo.wasmCacheStats());
+
+  // Only room for one of the binaries: compiling sub evicts add.
+  console.log(Wapo.wasmCacheStats(add.byteLength));
+  new WebAssembly.Module(sub);
+  new WebAssembly.Module(add);
+  console.log(Wapo.wasmCacheStats());
+}
+
+main().catch(console.error);
@@ -0,0 +1,283 @@
+// Hand-written checks modelled on the WebAssembly JS-API conformance tests in
+// https://github.com/WebAssembly/spec/tree/main/test/js-api. They are not copies of the spec
+// cases; each block names the spec file covering the same behaviour.
+
+console.log = Wapo.inspect;
+
+let passed = 0;
+let failed = 0;
+const pending = [];
+
+function test(f, name) {
+  try {
+    f();
+    passed++;
+  } catch (err) {
+    failed++;
+    console.error(`FAIL ${name}: ${err}`);
+  }
+}
+
+function promise_test(f, name) {
+  pending.push(
+    f().then(
+      () => passed++,
+      (err) => {
+        failed++;
+        console.error(`FAIL ${name}: ${err}`);
+      }
+    )
+  );
+}
+
+function assert_equals(actual, expected, msg = "") {
+  if (!Object.is(actual, expected)) {
+    throw new Error(`${msg} expected ${String(expected)}, got ${String(actual)}`);
+  }
+}
+
+function assert_true(value, msg = "") {
+  assert_equals(value, true, msg);
+}
+
+function assert_array_equals(actual, expected, msg = "") {
+  assert_equals(JSON.stringify(actual), JSON.stringify(expected), msg);
+}
+
+function assert_throws_js(Class, f, msg = "") {
+  try {
+    f();
+  } catch (err) {
+    if (!(err instanceof Class)) {
+      throw new Error(`${msg} expected ${Class.name}, got ${err && err.name}: ${err && err.message}`);
+    }
+    return;
+  }
+  throw new Error(`${msg} expected ${Class.name} to be thrown`);
+}
+
+async function promise_rejects_js(Class, promise, msg = "") {
+  try {
+    await promise;
+  } catch (err) {
+    if (!(err instanceof Class)) {
+      throw new Error(`${msg} expected ${Class.name}, got ${err && err.name}: ${err && err.message}`);
+    }
+    return;
+  }
+  throw new Error(`${msg} expected a rejection with ${Class.name}`);
+}
+
+const wasm = (wat) => WebAssembly.parseWat(wat);
+const emptyModuleBinary = new Uint8Array([0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]);
+const invalidModuleBinary = new Uint8Array([0x00, 0x61, 0x73, 0x6d, 0x02, 0x00, 0x00, 0x00]);
+
+// constructor/validate.any.js
+test(() => {
+  assert_true(WebAssembly.validate(emptyModuleBinary));
+  assert_true(WebAssembly.validate(emptyModuleBinary.buffer));
+  assert_equals(WebAssembly.validate(invalidModuleBinary), false);
+  assert_throws_js(TypeError, () => WebAssembly.validate("wasm"));
+  assert_throws_js(TypeError, () => WebAssembly.validate());
+}, "validate");
+
+// module/constructor.any.js
+test(() => {
+  assert_throws_js(TypeError, () => WebAssembly.Module(emptyModuleBinary));
+  assert_throws_js(TypeError, () => new WebAssembly.Module());
+  assert_throws_js(TypeError, () => new WebAssembly.Module({}));
+  assert_throws_js(WebAssembly.CompileError, () => new WebAssembly.Module(invalidModuleBinary));
+  assert_true(new WebAssembly.Module(emptyModuleBinary) instanceof WebAssembly.Module);
+  assert_true(new WebAssembly.Module(new DataView(emptyModuleBinary.buffer)) instanceof WebAssembly.Module);
+}, "Module constructor");
+
+// module/exports.any.js, module/imports.any.js
+test(() => {
+  const module = new WebAssembly.Module(wasm(`
+    (module
+      (import "m" "f" (func))
+      (import "m" "g" (global i32))
+      (import "m2" "mem" (memory 1))
+      (import "m2" "tbl" (table 1 funcref))
+      (func (export "fn"))
+      (global (export "glob") i32 (i32.const 0))
+      (memory (export "memory") 1)
+      (table (export "table") 1 funcref))
+  `));
+  assert_array_equals(WebAssembly.Module.imports(module), [
+    { module: "m", name: "f", kind: "function" },
+    { module: "m", name: "g", kind: "global" },
+    { module: "m2", name: "mem", kind: "memory" },
+    { module: "m2", name: "tbl", kind: "table" },
+  ]);
+  assert_array_equals(WebAssembly.Module.exports(module), [
+    { name: "fn", kind: "function" },
+    { name: "glob", kind: "global" },
+    { name: "memory", kind: "memory" },
+    { name: "table", kind: "table" },
+  ]);
+}, "Module.imports and Module.exports");
+
+// module/customSections.any.js
+test(() => {
+  const binary = new Uint8Array([
+    ...emptyModuleBinary,
+    0x00, 0x06, 0x04, 0x6e, 0x61, 0x6d, 0x65, 0x2a, // custom "name" [42]
+    0x00, 0x05, 0x03, 0x66, 0x6f, 0x6f, 0x01, // custom "foo" [1]
+    0x00, 0x05, 0x03, 0x66, 0x6f, 0x6f, 0x02, // custom "foo" [2]
+  ]);
+  const module = new WebAssembly.Module(binary);
+  const foo = WebAssembly.Module.customSections(module, "foo");
+  assert_equals(foo.length, 2);
+  assert_equals(new Uint8Array(foo[0])[0], 1);
+  assert_equals(new Uint8Array(foo[1])[0], 2);
+  assert_equals(WebAssembly.Module.customSections(module, "name").length, 1);
+  assert_equals(WebAssembly.Module.customSections(module, "bar").length, 0);
+}, "Module.customSections");
+
+// instance/constructor.any.js
+test(() => {
+  const module = new WebAssembly.Module(emptyModuleBinary);
+  assert_throws_js(TypeError, () => WebAssembly.Instance(module));
+  assert_throws_js(TypeError, () => new WebAssembly.Instance({}));
+  assert_throws_js(TypeError, () => new WebAssembly.Instance(module, null));
+  const instance = new WebAssembly.Instance(module);
+  assert_true(instance instanceof WebAssembly.Instance);
+  assert_equals(Object.getPrototypeOf(instance.exports), null);
+  assert_true(Object.isFrozen(instance.exports));
+}, "Instance constructor");
+
+test(() => {
+  const module = new WebAssembly.Module(wasm(`(module (import "m" "f" (func)))`));
+  assert_throws_js(TypeError, () => new WebAssembly.Instance(module));
+  assert_throws_js(TypeError, () => new WebAssembly.Instance(module, {}));
+  assert_throws_js(WebAssembly.LinkError, () => new WebAssembly.Instance(module, { m: {} }));
+  assert_throws_js(WebAssembly.LinkError, () => new WebAssembly.Instance(module, { m: { f: 1 } }));
+}, "Instance imports");
+
+test(() => {
+  const module = new WebAssembly.Module(wasm(`
+    (module
+      (import "m" "g" (global i32))
+      (func (export "get") (result i32) (global.get 0)))
+  `));
+  const instance = new WebAssembly.Instance(module, { m: { g: 42 } });
+  assert_equals(instance.exports.get(), 42);
+  assert_throws_js(WebAssembly.LinkError, () => new WebAssembly.Instance(module, { m: { g: "x" } }));
+}, "Instance imports a global from a number");
+
+test(() => {
+  const module = new WebAssembly.Module(wasm(`(module (func (export "f")))`));
+  const instance = new WebAssembly.Instance(module);
+  assert_equals(instance.exports.f, instance.exports.f);
+  assert_equals(instance.exports, instance.exports);
+}, "Instance exports are created once");
+
+test(() => {
+  const module = new WebAssembly.Module(wasm(`
+    (module
+      (func (export "z"))
+      (memory (export "m") 1)
+      (global (export "a") i32 (i32.const 0))
+      (func (export "b")))
+  `));
+  const { exports } = new WebAssembly.Instance(module);
+  assert_array_equals(Object.keys(exports), ["z", "m", "a", "b"]);
+  assert_true(Object.isFrozen(exports));
+  assert_throws_js(TypeError, () => {
+    "use strict";
+    exports.z = 1;
+  });
+}, "Instance exports keep the module order");
+
+// instance/constructor-caching.any.js, start functions
+test(() => {
+  const calls = [];
+  const module = new WebAssembly.Module(wasm(`
+    (module
+      (import "m" "log" (func $log (param i32)))
+      (func $start (call $log (i32.const 7)))
+      (start $start))
+  `));
+  new WebAssembly.Instance(module, { m: { log: (v) => calls.push(v) } });
+  assert_array_equals(calls, [7]);
+}, "start function");
+
+test(() => {
+  const module = new WebAssembly.Module(wasm(`(module (func $start unreachable) (start $start))`));
+  assert_throws_js(WebAssembly.RuntimeError, () => new WebAssembly.Instance(module));
+}, "trap in start function");
+
+test(() => {
+  const module = new WebAssembly.Module(wasm(`
+    (module (func (export "div") (param i32 i32) (result i32) (i32.div_s (local.get 0) (local.get 1))))
+  `));
+  const instance = new WebAssembly.Instance(module);
+  assert_equals(instance.exports.div(7, 2), 3);
+  assert_throws_js(WebAssembly.RuntimeError, () => instance.exports.div(1, 0));
+}, "trap in exported function");
+
+// error/constructor.any.js
+test(() => {
+  for (const name of ["CompileError", "LinkError", "RuntimeError"]) {
+    const err = new WebAssembly[name]("msg");
+    assert_true(err instanceof Error);
+    assert_equals(err.name, name);
+    assert_equals(err.message, "msg");
+  }
+}, "error classes");
+
+// memory/constructor.any.js, memory/grow.any.js
+test(() => {
+  assert_throws_js(TypeError, () => WebAssembly.Memory({ initial: 1 }));
+  assert_throws_js(RangeError, () => new WebAssembly.Memory({ initial: 2, maximum: 1 }));
+  assert_throws_js(TypeError, () => new WebAssembly.Memory({ initial: 1, shared: true }));
+  const shared = new WebAssembly.Memory({ initial: 1, maximum: 2, shared: true });
+  assert_equals(shared.buffer.byteLength, 65536);
+  const memory = new WebAssembly.Memory({ initial: 1, maximum: 2 });
+  assert_equals(memory.grow(1), 1);
+  assert_equals(memory.buffer.byteLength, 2 * 65536);
+  assert_throws_js(RangeError, () => memory.grow(1));
+}, "Memory");
+
+// table/constructor.any.js, table/get-set.any.js, table/grow.any.js
+test(() => {
+  assert_throws_js(TypeError, () => new WebAssembly.Table({ element: "i32", initial: 1 }));
+  const table = new WebAssembly.Table({ element: "anyfunc", initial: 1, maximum: 2 });
+  assert_equals(table.length, 1);
+  assert_equals(table.get(0), null);
+  assert_throws_js(RangeError, () => table.get(1));
+  assert_equals(table.grow(1), 1);
+  assert_equals(table.length, 2);
+  assert_throws_js(RangeError, () => table.grow(1));
+}, "Table");
+
+// global/constructor.any.js, global/value-get-set.any.js
+test(() => {
+  const global = new WebAssembly.Global({ value: "i32", mutable: false }, 1);
+  assert_equals(global.value, 1);
+  assert_throws_js(TypeError, () => { global.value = 2; });
+  assert_throws_js(TypeError, () => new WebAssembly.Global({ value: "x", mutable: true }));
+}, "Global");
+
+// constructor/compile.any.js, constructor/instantiate.any.js
+promise_test(async () => {
+  const module = await WebAssembly.compile(emptyModuleBinary);
+  assert_true(module instanceof WebAssembly.Module);
+  await promise_rejects_js(WebAssembly.CompileError, WebAssembly.compile(invalidModuleBinary));
+  await promise_rejects_js(TypeError, WebAssembly.compile("wasm"));
+}, "compile");
+
+promise_test(async () => {
+  const result = await WebAssembly.instantiate(emptyModuleBinary);
+  assert_true(result.module instanceof WebAssembly.Module);
+  assert_true(result.instance instanceof WebAssembly.Instance);
+  const instance = await WebAssembly.instantiate(result.module);
+  assert_true(instance instanceof WebAssembly.Instance);
+  const module = new WebAssembly.Module(wasm(`(module (import "m" "f" (func)))`));
+  await promise_rejects_js(WebAssembly.LinkError, WebAssembly.instantiate(module, { m: {} }));
+}, "instantiate");
+
+Promise.all(pending).then(() => {
+  console.log(`${passed} passed, ${failed} failed`);
+});
@@ -0,0 +1,81 @@
+// A wasm plugin awaiting host I/O through a suspending import.
+
+console.log = Wapo.inspect;
+
+function assertEq(actual, expected, msg = "") {
+  if (actual !== expected) {
+    console.error(`Assertion failed: ${msg}, actual: ${actual}, expected: ${expected}`);
+  }
+}
+
+const wat = `
+  (module
+    (import "host" "fetchLength" (func $fetchLength (param i32) (result i32)))
+    (import "host" "log" (func $log (param i32)))
+    ;; Sums the lengths of the pages 0 to n - 1, fetched one after the other.
+    (func (export "total") (param $n i32) (result i32)
+      (local $i i32)
+      (local $sum i32)
+      (block $done
+        (loop $l
+          (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+          (local.set $sum (i32.add (local.get $sum) (call $fetchLength (local.get $i))))
+          (call $log (local.get $sum))
+          (local.set $i (i32.add (local.get $i) (i32.const 1)))
+          (br $l)))
+      (local.get $sum)))
+`;
+
+async function main() {
+  const logged = [];
+  const imports = {
+    host: {
+      // Stands in for a fetch: resolves after a tick.
+      fetchLength: new WebAssembly.Suspending(async (page) => {
+        await new Promise((resolve) => setTimeout(resolve, 1));
+        return (page + 1) * 10;
+      }),
+      log: (sum) => logged.push(sum),
+    },
+  };
+  const { instance } = await WebAssembly.instantiate(WebAssembly.parseWat(wat), imports);
+
+  const total = WebAssembly.promising(instance.exports.total);
+  const pending = total(3);
+  assertEq(pending instanceof Promise, true, "a promising export returns a promise");
+  assertEq(await pending, 60);
+  assertEq(logged.join(","), "10,30,60", "the call resumes where it was suspended");
+
+  // Several calls can be suspended at the same time.
+  const [a, b] = await Promise.all([total(1), total(2)]);
+  assertEq(a, 10);
+  assertEq(b, 30);
+
+  // Without promising the import can not suspend.
+  try {
+    instance.exports.total(1);
+    console.error("expected a SuspendError");
+  } catch (err) {
+    assertEq(err instanceof WebAssembly.SuspendError, true, String(err));
+  }
+
+  // A rejected promise rejects the call.
+  const failing = new WebAssembly.Instance(new WebAssembly.Module(WebAssembly.parseWat(wat)), {
+    host: {
+      fetchLength: new WebAssembly.Suspending(async () => {
+        throw new Error("network down");
+      }),
+      log() {},
+    },
+  });
+  try {
+    await WebAssembly.promising(failing.exports.total)(1);
+    console.error("expected a rejection");
+  } catch (err) {
+    assertEq(err.message, "network down");
+  }
+
+  console.log("done");
+}
+
+main().catch(console.error);
@@ -0,0 +1,62 @@
+console.log = Wapo.inspect;
+
+const wat = `
+  (module
+    (memory (export "memory") 1)
+    (func (export "spin") (loop $l (br $l)))
+    (func (export "sum") (param $n i32) (result i32)
+      (local $acc i32)
+      (block $done
+        (loop $l
+          (br_if $done (i32.eqz (local.get $n)))
+          (local.set $acc (i32.add (local.get $acc) (local.get $n)))
+          (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+          (br $l)))
+      (local.get $acc))
+    (func (export "grow") (param $pages i32) (result i32)
+      (memory.grow (local.get $pages)))
+  )
+`;
+
+const module = new WebAssembly.Module(WebAssembly.parseWat(wat));
+
+function expectRuntimeError(f) {
+  try {
+    f();
+    console.error("expected a RuntimeError");
+  } catch (err) {
+    console.log(err instanceof WebAssembly.RuntimeError, err.message);
+  }
+}
+
+// Limits passed to the constructor.
+{
+  const instance = new WebAssembly.Instance(module, {}, { fuel: 10000 });
+  console.log("sum(10):", instance.exports.sum(10), "fuel left:", instance.fuel);
+  expectRuntimeError(() => instance.exports.spin());
+  console.log("fuel left:", instance.fuel);
+  instance.fuel = 10000;
+  console.log("refueled, sum(10):", instance.exports.sum(10));
+}
+
+// Default limits for the following instances.
+Wapo.wasmLimits({ maxMemory: 4 * 65536 });
+{
+  const instance = new WebAssembly.Instance(module, {});
+  console.log("grow(2):", instance.exports.grow(2));
+  expectRuntimeError(() => instance.exports.grow(2));
+  console.log("pages:", instance.exports.memory.buffer.byteLength / 65536);
+  // Growing the exported memory from JS is held to the same limit.
+  expectRuntimeError(() => instance.exports.memory.grow(2));
+  console.log("grown from JS:", instance.exports.memory.grow(1));
+  // So is a memory created from JS.
+  const memory = new WebAssembly.Memory({ initial: 1 });
+  expectRuntimeError(() => memory.grow(4));
+}
+console.log("previous defaults:", Wapo.wasmLimits({}));
+
+// Without limits the memory grows up to its declared maximum.
+{
+  const instance = new WebAssembly.Instance(module, {});
+  console.log("grow(8):", instance.exports.grow(8), "fuel:", instance.fuel);
+}
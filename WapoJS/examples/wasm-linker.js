@@ -0,0 +1,82 @@
+console.log = Wapo.inspect;
+
+function assertEq(actual, expected, msg = "") {
+  if (actual !== expected) {
+    console.error(`Assertion failed: ${msg}, actual: ${actual}, expected: ${expected}`);
+  }
+}
+
+const wasm = (wat) => new WebAssembly.Module(WebAssembly.parseWat(wat));
+
+// A "crypto" module owning the memory and a "codec" module calling into it.
+const crypto = wasm(`
+  (module
+    (memory (export "memory") 1)
+    (global (export "seed") i32 (i32.const 7))
+    (func (export "mix") (param i32) (result i32)
+      (i32.xor (i32.mul (local.get 0) (i32.const 31)) (i32.const 0x5a5a))))
+`);
+const codec = wasm(`
+  (module
+    (import "crypto" "mix" (func $mix (param i32) (result i32)))
+    (import "crypto" "seed" (global $seed i32))
+    (import "crypto" "memory" (memory 1))
+    (import "env" "log" (func $log (param i32)))
+    (func (export "encode") (param $n i32) (result i32)
+      (local $acc i32)
+      (local.set $acc (global.get $seed))
+      (block $done
+        (loop $l
+          (br_if $done (i32.eqz (local.get $n)))
+          (local.set $acc (call $mix (local.get $acc)))
+          (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+          (br $l)))
+      (i32.store (i32.const 0) (local.get $acc))
+      (call $log (local.get $acc))
+      (local.get $acc)))
+`);
+
+const linker = Wapo.wasmLinker();
+const cryptoInstance = new WebAssembly.Instance(crypto);
+linker.define("crypto", cryptoInstance);
+assertEq(linker.has("crypto"), true);
+console.log("linked modules:", linker.names);
+
+const logged = [];
+const instance = linker.instantiate(codec, { env: { log: (v) => logged.push(v) } });
+const encoded = instance.exports.encode(1000);
+assertEq(logged[0], encoded, "JS imports still work");
+assertEq(
+  new Uint32Array(cryptoInstance.exports.memory.buffer)[0],
+  encoded >>> 0,
+  "the memory is shared"
+);
+
+// Same through the constructor.
+const again = new WebAssembly.Instance(codec, { env: { log() {} } }, undefined, linker);
+assertEq(again.exports.encode(1000), encoded);
+
+// Imports missing from the linked instance fail to link.
+const broken = wasm(`(module (import "crypto" "missing" (func)))`);
+try {
+  linker.instantiate(broken);
+  console.error("expected a LinkError");
+} catch (err) {
+  assertEq(err instanceof WebAssembly.LinkError, true, String(err));
+}
+
+// Linked code runs under the budget of its caller, so instances with limits are refused.
+try {
+  linker.define("metered", new WebAssembly.Instance(crypto, {}, { fuel: 1000 }));
+  console.error("expected a TypeError");
+} catch (err) {
+  assertEq(err instanceof TypeError, true, String(err));
+}
+try {
+  cryptoInstance.fuel = 1000;
+  console.error("expected a TypeError");
+} catch (err) {
+  assertEq(err instanceof TypeError, true, String(err));
+}
+
+console.log("done");
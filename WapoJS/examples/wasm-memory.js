@@ -0,0 +1,57 @@
+console.log = Wapo.inspect;
+
+function assertEq(actual, expected, msg = "") {
+  if (actual !== expected) {
+    console.error(`Assertion failed: ${msg}, actual: ${actual}, expected: ${expected}`);
+  }
+}
+
+const wat = `
+  (module
+    (memory (export "memory") 1 4)
+    (func (export "sum") (param $ptr i32) (param $len i32) (result i32)
+      (local $sum i32)
+      (block $done
+        (loop $l
+          (br_if $done (i32.eqz (local.get $len)))
+          (local.set $sum (i32.add (local.get $sum) (i32.load8_u (local.get $ptr))))
+          (local.set $ptr (i32.add (local.get $ptr) (i32.const 1)))
+          (local.set $len (i32.sub (local.get $len) (i32.const 1)))
+          (br $l)))
+      (local.get $sum))
+    (func (export "fill") (param $ptr i32) (param $len i32) (param $value i32)
+      (memory.fill (local.get $ptr) (local.get $value) (local.get $len)))
+    (func (export "grow") (param $pages i32) (result i32)
+      (memory.grow (local.get $pages))))
+`;
+
+const instance = new WebAssembly.Instance(new WebAssembly.Module(WebAssembly.parseWat(wat)));
+const { memory, sum, fill, grow } = instance.exports;
+
+// Views write to and read from the linear memory directly.
+const buffer = memory.buffer;
+assertEq(memory.buffer, buffer, "the buffer is the same until the memory grows");
+const bytes = new Uint8Array(buffer);
+bytes.set([1, 2, 3, 4], 16);
+assertEq(sum(16, 4), 10, "wasm sees writes through a view");
+fill(32, 4, 7);
+assertEq(bytes[35], 7, "a view sees writes from wasm");
+new DataView(buffer).setUint32(64, 0x01010101, true);
+assertEq(sum(64, 4), 4, "DataView writes");
+
+// Growing detaches the buffer, the contents move to a new one.
+assertEq(grow(1), 1, "grow from wasm");
+assertEq(buffer.byteLength, 0, "the old buffer is detached");
+assertEq(memory.buffer.byteLength, 2 * 65536, "the new buffer has the new size");
+assertEq(new Uint8Array(memory.buffer)[35], 7, "the contents are kept");
+const before = memory.buffer;
+memory.grow(1);
+assertEq(before.byteLength, 0, "growing from JS detaches too");
+assertEq(memory.buffer.byteLength, 3 * 65536, "after growing from JS");
+
+// Text round trip, as wasm-bindgen glue does it.
+const text = "hello wasm";
+const encoded = new TextEncoder().encodeInto(text, new Uint8Array(memory.buffer, 1024));
+assertEq(new TextDecoder().decode(new Uint8Array(memory.buffer, 1024, encoded.written)), text);
+
+console.log("done");
@@ -0,0 +1,55 @@
+// Run with: wapojs examples/worker.js
+// Offloads CPU-heavy work to a worker so that the main event loop stays responsive.
+const worker = new Worker(`
+    function fib(n) {
+        return n < 2 ? n : fib(n - 1) + fib(n - 2);
+    }
+    onmessage = (event) => {
+        const { n, buffer } = event.data;
+        // The buffer was transferred: the parent can no longer access it.
+        buffer.fill(n);
+        postMessage({ n, result: fib(n), buffer }, [buffer]);
+    };
+`, { eval: true, name: "fib" });
+
+const ticker = setInterval(() => console.log("main loop is alive"), 100);
+
+// Assigning a handler replaces the previous one, so only the second one runs.
+worker.onmessage = () => console.log("replaced, never called");
+worker.onmessage = (event) => {
+    const { n, result, buffer } = event.data;
+    console.log(`fib(${n}) = ${result}, buffer: ${buffer.slice(0, 4)}`);
+    clearInterval(ticker);
+    worker.terminate();
+};
+worker.onerror = (event) => console.log("worker error:", event.message);
+
+const buffer = new Uint8Array(1024);
+worker.postMessage({ n: 30, buffer }, [buffer]);
+console.log("buffer length after transfer:", buffer.length);
+
+// Errors thrown after the initial script, by handlers or timers, reach `onerror` as well, and
+// the worker keeps running.
+const faulty = new Worker(`
+    onmessage = (event) => {
+        if (event.data === "timer") {
+            setTimeout(() => { throw new Error("thrown by a timer"); }, 0);
+        } else {
+            throw new Error("thrown by onmessage");
+        }
+    };
+`, { eval: true, name: "faulty" });
+const errors = [];
+faulty.onerror = (event) => {
+    errors.push(event.message);
+    if (errors.length === 1) {
+        faulty.postMessage("timer");
+        return;
+    }
+    faulty.terminate();
+    if (!errors[0].includes("thrown by onmessage") || !errors[1].includes("thrown by a timer")) {
+        throw new Error(`unexpected worker errors: ${errors}`);
+    }
+    console.log("worker errors:", errors.length);
+};
+faulty.postMessage("handler");
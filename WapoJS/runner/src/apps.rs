@@ -0,0 +1,372 @@
+//! Serving the apps declared in a manifest file.
+//!
+//! ```toml
+//! [apps.hello]
+//! script = "hello.js"
+//! args = ["--verbose"]
+//! env = { GREETING = "hi" }
+//! query_size = "2M"
+//!
+//! [apps.raw]
+//! address = "0x0000000000000000000000000000000000000000000000000000000000000001"
+//! code_hash = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+//! on_demand = true
+//! ```
+//!
+//! An app runs either a script with the JS engine of the runner, or a program already in the
+//! blob store given by its code hash. Scripts are resolved relative to the manifest file. The
+//! manifest and the scripts are watched: an app whose declaration or script changed is
+//! redeployed, or kept at its previous version if the new one fails to deploy, and apps removed
+//! from the manifest are removed from the worker.
+//!
+//! The memory of the instances is a setting of the worker, `-m`, shared by all the apps. An app
+//! setting `memory_size` only raises it when the runner starts: a reload asking for more than
+//! the worker was started with is rejected, naming the app, and the running apps are kept.
+//!
+//! The address of an app is derived from its manifest, so a redeployed app gets a new one, unless
+//! the app sets its `address`. With `--address-from label` it is derived from its name only and
+//! stays the same across reloads.
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use tokio::time::sleep;
+use tracing::{error, info, warn};
+use wapod::{
+    config::AddressGenerator,
+    rpc::prpc::{Manifest, StringPair},
+    Address,
+};
+
+use crate::{config, parse_size, Config, Registry, Worker};
+
+/// How often the manifest and the scripts are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct AppsFile {
+    #[serde(default)]
+    apps: BTreeMap<String, AppConfig>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct AppConfig {
+    /// The JS script to run, relative to the manifest file.
+    script: Option<PathBuf>,
+    /// The hash of a program in the blob store, to run instead of a script.
+    code_hash: Option<String>,
+    /// The hash algorithm of `code_hash`.
+    hash_algorithm: Option<String>,
+    /// The hex address to deploy the app at, instead of the derived one.
+    address: Option<String>,
+    args: Vec<String>,
+    env: BTreeMap<String, String>,
+    /// Passes the environment variables of the runner to the app too.
+    inherit_env: bool,
+    /// The memory size the app needs, like `128M`. The instances of all the apps have the
+    /// memory size of the worker, which is raised to fit this when the runner starts.
+    memory_size: Option<String>,
+    /// The maximum size of each payload, like `1M`.
+    query_size: Option<String>,
+    /// Starts the app on demand instead of when deployed.
+    on_demand: bool,
+    /// The bootcode profile of the engine: minimal, node, browser or worker.
+    profile: Option<String>,
+}
+
+/// What an app runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Code {
+    Script { path: PathBuf, source: String },
+    Hash { hash: String, algorithm: String },
+}
+
+/// An app of the manifest with its script loaded, compared to tell whether it changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppSpec {
+    name: String,
+    code: Code,
+    address: Option<Address>,
+    args: Vec<String>,
+    env: BTreeMap<String, String>,
+    memory_size: Option<u64>,
+    query_size: Option<u64>,
+    on_demand: bool,
+    profile: Option<String>,
+}
+
+/// The settings of the runner the apps fall back to.
+pub struct Defaults {
+    pub engine_hash: String,
+    pub memory_size: u64,
+    pub query_size: u64,
+    /// The port of the user service, for the endpoints logged.
+    pub port: u16,
+}
+
+/// Reads the apps of a manifest file.
+pub fn load(path: &Path) -> Result<Vec<AppSpec>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let file: AppsFile =
+        toml::from_str(&content).with_context(|| format!("invalid manifest {}", path.display()))?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    file.apps
+        .into_iter()
+        .map(|(name, config)| {
+            AppSpec::new(&name, config, base).with_context(|| format!("invalid app {name}"))
+        })
+        .collect()
+}
+
+impl AppSpec {
+    fn new(name: &str, config: AppConfig, base: &Path) -> Result<Self> {
+        let code = match (config.script, config.code_hash) {
+            (Some(script), None) => {
+                let path = base.join(script);
+                let source = std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                Code::Script { path, source }
+            }
+            (None, Some(hash)) => Code::Hash {
+                hash,
+                algorithm: config.hash_algorithm.unwrap_or_else(|| "sha256".into()),
+            },
+            (Some(_), Some(_)) => bail!("both script and code_hash are set"),
+            (None, None) => bail!("either script or code_hash must be set"),
+        };
+        let mut env = if config.inherit_env {
+            std::env::vars().collect()
+        } else {
+            BTreeMap::new()
+        };
+        env.extend(config.env);
+        let size = |value: Option<String>| -> Result<Option<u64>> {
+            value
+                .map(|value| parse_size(&value).with_context(|| format!("invalid size {value}")))
+                .transpose()
+        };
+        let address = config
+            .address
+            .map(|address| config::parse_address(&address))
+            .transpose()?;
+        Ok(Self {
+            name: name.into(),
+            code,
+            address,
+            args: config.args,
+            env,
+            memory_size: size(config.memory_size)?,
+            query_size: size(config.query_size)?,
+            on_demand: config.on_demand,
+            profile: config.profile,
+        })
+    }
+
+    pub fn memory_size(&self) -> Option<u64> {
+        self.memory_size
+    }
+
+    fn script_path(&self) -> Option<&Path> {
+        match &self.code {
+            Code::Script { path, .. } => Some(path),
+            Code::Hash { .. } => None,
+        }
+    }
+
+    fn manifest(&self, defaults: &Defaults) -> Result<Manifest> {
+        let mut args = vec![];
+        let (code_hash, hash_algorithm) = match &self.code {
+            Code::Script { source, .. } => {
+                if let Some(profile) = &self.profile {
+                    args.extend(["--env".to_string(), profile.clone()]);
+                }
+                args.extend(["-c".to_string(), source.clone()]);
+                (defaults.engine_hash.clone(), "sha256".to_string())
+            }
+            Code::Hash { hash, algorithm } => (hash.clone(), algorithm.clone()),
+        };
+        args.extend(self.args.iter().cloned());
+        let query_size = self.query_size.unwrap_or(defaults.query_size);
+        Ok(Manifest {
+            version: 1,
+            code_hash,
+            hash_algorithm,
+            args,
+            env_vars: self
+                .env
+                .iter()
+                .map(|(key, value)| StringPair {
+                    key: key.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+            on_demand: self.on_demand,
+            resizable: false,
+            max_query_size: query_size.try_into().context("invalid query size")?,
+            label: self.name.clone(),
+        })
+    }
+}
+
+struct Deployed {
+    spec: AppSpec,
+    address: Address,
+}
+
+/// The modification times of the manifest and the scripts of its apps, `None` for the files that
+/// can not be read. Any file changing, even to an older time, changes the map.
+fn modified(path: &Path, apps: &[AppSpec]) -> BTreeMap<PathBuf, Option<SystemTime>> {
+    let paths = std::iter::once(path).chain(apps.iter().filter_map(AppSpec::script_path));
+    paths
+        .map(|path| {
+            let time = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+            (path.to_path_buf(), time)
+        })
+        .collect()
+}
+
+/// Deploys the apps of the manifest and keeps them in sync with it until the worker stops.
+pub async fn serve(
+    worker: Worker,
+    registry: Registry,
+    path: PathBuf,
+    apps: Vec<AppSpec>,
+    defaults: Defaults,
+) {
+    let mut deployed = BTreeMap::<String, Deployed>::new();
+    // The scripts of all the apps loaded are watched, including the ones that failed to deploy.
+    let mut watched = apps.clone();
+    let mut last_modified = modified(&path, &watched);
+    sync(&worker, &registry, &mut deployed, apps, &defaults).await;
+    loop {
+        sleep(POLL_INTERVAL).await;
+        let now = modified(&path, &watched);
+        if now == last_modified {
+            continue;
+        }
+        last_modified = now;
+        info!("{} changed, reloading", path.display());
+        match load(&path) {
+            Ok(apps) => {
+                watched = apps.clone();
+                last_modified = modified(&path, &watched);
+                if let Err(err) = check_memory(&apps, defaults.memory_size) {
+                    error!("rejected the reload, keeping the running apps: {err:?}");
+                    continue;
+                }
+                sync(&worker, &registry, &mut deployed, apps, &defaults).await;
+            }
+            Err(err) => error!("failed to reload the apps, keeping the running ones: {err:?}"),
+        }
+    }
+}
+
+/// Checks that the apps fit in the memory size of the worker, which can not change once started.
+fn check_memory(apps: &[AppSpec], memory_size: u64) -> Result<()> {
+    for app in apps {
+        if let Some(size) = app.memory_size.filter(|size| *size > memory_size) {
+            bail!(
+                "app {} asks for {size} bytes of memory but the instances have {memory_size}, \
+                 restart the runner or raise -m",
+                app.name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Deploys the apps that are new or changed and removes the ones that are gone.
+async fn sync(
+    worker: &Worker,
+    registry: &Registry,
+    deployed: &mut BTreeMap<String, Deployed>,
+    apps: Vec<AppSpec>,
+    defaults: &Defaults,
+) {
+    let mut apps: BTreeMap<String, AppSpec> = apps
+        .into_iter()
+        .map(|app| (app.name.clone(), app))
+        .collect();
+    let names: Vec<String> = deployed.keys().cloned().collect();
+    for name in names {
+        let unchanged = apps.get(&name) == deployed.get(&name).map(|app| &app.spec);
+        if unchanged {
+            apps.remove(&name);
+            continue;
+        }
+        if apps.contains_key(&name) {
+            // Redeployed below.
+            continue;
+        }
+        let Some(app) = deployed.remove(&name) else {
+            continue;
+        };
+        info!("removing app {name}");
+        remove(worker, registry, &name, app.address).await;
+        config::set_address_override(&name, None);
+    }
+    for (name, spec) in apps {
+        // The new version may take the address of the old one, which has to go first.
+        let previous = deployed.remove(&name);
+        if let Some(app) = &previous {
+            info!("redeploying app {name}");
+            remove(worker, registry, &name, app.address).await;
+        }
+        match deploy(worker, registry, &spec, defaults).await {
+            Ok(address) => {
+                info!("app {name} deployed");
+                deployed.insert(name, Deployed { spec, address });
+            }
+            Err(err) => {
+                error!("failed to deploy app {name}: {err:?}");
+                let Some(app) = previous else {
+                    config::set_address_override(&name, None);
+                    continue;
+                };
+                // Keep the app running as it was rather than losing it.
+                match deploy(worker, registry, &app.spec, defaults).await {
+                    Ok(address) => {
+                        warn!("app {name} kept at its previous version");
+                        let spec = app.spec;
+                        deployed.insert(name, Deployed { spec, address });
+                    }
+                    Err(err) => {
+                        error!("failed to restore app {name}: {err:?}");
+                        config::set_address_override(&name, None);
+                    }
+                }
+            }
+        }
+    }
+    registry.print_table(defaults.port);
+}
+
+async fn remove(worker: &Worker, registry: &Registry, name: &str, address: Address) {
+    registry.remove(&address);
+    if let Err(err) = worker.remove_app(address).await {
+        error!("failed to remove app {name}: {err:?}");
+    }
+}
+
+async fn deploy(
+    worker: &Worker,
+    registry: &Registry,
+    spec: &AppSpec,
+    defaults: &Defaults,
+) -> Result<Address> {
+    let manifest = spec.manifest(defaults)?;
+    config::set_address_override(&spec.name, spec.address);
+    // Two apps at one address could not be told apart by the routes.
+    registry.check(&Config::generate_address(&manifest), &spec.name)?;
+    let app_info = worker
+        .deploy_app(manifest, false)
+        .await
+        .context("failed to deploy the app")?;
+    registry.insert(app_info.address, &spec.name);
+    Ok(app_info.address)
+}
@@ -1,5 +1,8 @@
 use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use wapod::{
     config::{AddressGenerator, DefaultKerProvider, Paths, WorkerConfig},
     rpc::prpc::Manifest,
@@ -14,9 +17,66 @@ impl WorkerConfig for Config {
     type Paths = Self;
 }
 
+/// What the address of an app is derived from.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressFrom {
+    /// The code hash, the args and the label: an app changing gets a new address.
+    Manifest,
+    /// The label only: an app keeps its address when it changes.
+    Label,
+}
+
+static ADDRESS_FROM: Mutex<AddressFrom> = Mutex::new(AddressFrom::Manifest);
+/// Addresses set explicitly, by label.
+static ADDRESS_OVERRIDES: Mutex<BTreeMap<String, Address>> = Mutex::new(BTreeMap::new());
+
+pub fn set_address_from(from: AddressFrom) {
+    *ADDRESS_FROM.lock().unwrap_or_else(|err| err.into_inner()) = from;
+}
+
+/// Makes the app labeled `label` deploy at `address`, or at the derived address if `None`.
+pub fn set_address_override(label: &str, address: Option<Address>) {
+    let mut overrides = ADDRESS_OVERRIDES
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    match address {
+        Some(address) => overrides.insert(label.into(), address),
+        None => overrides.remove(label),
+    };
+}
+
+/// Parses a hex address, with or without the `0x` prefix.
+pub fn parse_address(input: &str) -> Result<Address> {
+    let bytes = hex::decode(input.trim_start_matches("0x")).context("invalid hex address")?;
+    Address::try_from(bytes).map_err(|_| anyhow::anyhow!("an address must be 32 bytes"))
+}
+
 impl AddressGenerator for Config {
-    fn generate_address(_manifest: &Manifest) -> Address {
-        [0; 32]
+    fn generate_address(manifest: &Manifest) -> Address {
+        let overrides = ADDRESS_OVERRIDES
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        if let Some(address) = overrides.get(&manifest.label) {
+            return *address;
+        }
+        let from = *ADDRESS_FROM.lock().unwrap_or_else(|err| err.into_inner());
+        let mut hasher = Sha256::new();
+        // Length prefixed, so that moving bytes from one field to the next changes the hash.
+        let mut field = |bytes: &[u8]| {
+            hasher.update((bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        };
+        field(b"wapojs-run");
+        if from == AddressFrom::Manifest {
+            field(manifest.code_hash.as_bytes());
+            field(manifest.hash_algorithm.as_bytes());
+            field(&(manifest.args.len() as u64).to_le_bytes());
+            for arg in &manifest.args {
+                field(arg.as_bytes());
+            }
+        }
+        field(manifest.label.as_bytes());
+        hasher.finalize().into()
     }
 }
 
@@ -50,3 +110,76 @@ pub fn save_default_engine(engine: &Path) -> Result<()> {
     std::fs::write(path, engine.to_str().context("non string path")?.as_bytes())
         .context("failed to write engine code")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(label: &str, args: &[&str]) -> Manifest {
+        Manifest {
+            version: 1,
+            code_hash: "00".repeat(32),
+            hash_algorithm: "sha256".into(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            env_vars: vec![],
+            on_demand: false,
+            resizable: false,
+            max_query_size: 1024,
+            label: label.into(),
+        }
+    }
+
+    #[test]
+    fn addresses() {
+        let address = [0x12; 32];
+        let hex = "12".repeat(32);
+        assert_eq!(parse_address(&hex).unwrap(), address);
+        assert_eq!(parse_address(&format!("0x{hex}")).unwrap(), address);
+        assert!(parse_address(&"12".repeat(31)).is_err(), "too short");
+        assert!(parse_address(&"12".repeat(33)).is_err(), "too long");
+        assert!(parse_address(&"zz".repeat(32)).is_err(), "not hex");
+        assert!(parse_address("").is_err());
+    }
+
+    // The modes and overrides are global, so they are all checked by a single test.
+    #[test]
+    fn generated_addresses() {
+        let generate = Config::generate_address;
+
+        set_address_from(AddressFrom::Manifest);
+        let app = manifest("app", &["-c", "1"]);
+        assert_eq!(generate(&app), generate(&app.clone()), "deterministic");
+        assert_ne!(generate(&app), generate(&manifest("other", &["-c", "1"])));
+        assert_ne!(generate(&app), generate(&manifest("app", &["-c", "2"])));
+        assert_ne!(
+            generate(&manifest("app", &["ab", "c"])),
+            generate(&manifest("app", &["a", "bc"])),
+            "fields are length prefixed"
+        );
+        let mut rehashed = app.clone();
+        rehashed.code_hash = "11".repeat(32);
+        assert_ne!(generate(&app), generate(&rehashed));
+
+        set_address_from(AddressFrom::Label);
+        assert_eq!(generate(&app), generate(&manifest("app", &["-c", "2"])));
+        assert_eq!(generate(&app), generate(&rehashed));
+        assert_ne!(generate(&app), generate(&manifest("other", &["-c", "1"])));
+        let by_label = generate(&app);
+
+        set_address_from(AddressFrom::Manifest);
+        assert_ne!(
+            generate(&app),
+            by_label,
+            "the label alone is hashed differently"
+        );
+
+        let address = [0x42; 32];
+        let derived = generate(&app);
+        set_address_override("app", Some(address));
+        assert_eq!(generate(&app), address);
+        assert_eq!(generate(&manifest("app", &["-c", "2"])), address);
+        assert_ne!(generate(&manifest("other", &[])), address);
+        set_address_override("app", None);
+        assert_eq!(generate(&app), derived);
+    }
+}
@@ -1,7 +1,7 @@
-use std::time::Duration;
+use std::{path::PathBuf, time::Duration};
 
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use tokio::time::sleep;
 use tracing::info;
 use tracing_subscriber::{filter::LevelFilter, EnvFilter};
@@ -11,40 +11,67 @@ use wapod::{
     WorkerArgs,
 };
 
-use config::Config;
+use config::{AddressFrom, Config};
+use registry::Registry;
+mod apps;
 mod config;
+mod registry;
 mod web_api;
 
 type Worker = wapod::Worker<Config>;
 
 #[derive(Parser, Clone, Debug)]
-#[clap(about = "wapojs", version, author)]
+#[clap(about = "wapojs", version, author, subcommand_negates_reqs = true)]
 pub struct Args {
-    /// Maximum memory size for each instance.
-    #[arg(long, short = 'm', default_value = "128M", value_parser = parse_size)]
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Maximum memory size for each instance, the same for all the apps served.
+    #[arg(long, short = 'm', default_value = "128M", value_parser = parse_size, global = true)]
     memory_size: u64,
     /// Maximum size for each payload.
-    #[arg(long, default_value = "1M", value_parser = parse_size)]
+    #[arg(long, default_value = "1M", value_parser = parse_size, global = true)]
     query_size: u64,
     /// Port number for the user service to listen on.
-    #[arg(long, short = 'p', default_value = "8002")]
+    #[arg(long, short = 'p', default_value = "8002", global = true)]
     port: u16,
     /// The wasmtime compiler to use
-    #[arg(long, short = 'c')]
+    #[arg(long, short = 'c', global = true)]
     wasm_compiler: Option<String>,
     /// The WASM file of the JS engine
-    #[arg(long, short = 'e')]
+    #[arg(long, short = 'e', global = true)]
     engine: Option<String>,
     /// Remember the engine code for future use
-    #[arg(long, short = 'u')]
+    #[arg(long, short = 'u', global = true)]
     save_engine: bool,
+    /// Maximum number of running instances, defaults to 1 for a script and 64 when serving apps
+    #[arg(long, global = true)]
+    max_instances: Option<usize>,
+    /// What app addresses are derived from, defaults to the whole manifest
+    #[arg(long, value_enum, global = true)]
+    address_from: Option<AddressFrom>,
+    /// The address to deploy the script at, instead of the derived one. Not valid with `serve`
+    #[arg(long, value_parser = config::parse_address)]
+    address: Option<wapod::Address>,
+    /// The bootcode profile of the engine: minimal, node, browser or worker. Not valid with `serve`
+    #[arg(long)]
+    env: Option<String>,
     /// The JS script to run
-    script: String,
+    #[arg(required = true)]
+    script: Option<String>,
     /// The rest of the arguments are passed to the WASM program
     #[arg(trailing_var_arg = true, allow_hyphen_values = true, hide = true)]
     args: Vec<String>,
 }
 
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Serves the apps declared in a manifest file, reloading them when it changes
+    Serve {
+        /// The TOML file declaring the apps
+        manifest: PathBuf,
+    },
+}
+
 fn parse_size(input: &str) -> Result<u64, parse_size::Error> {
     parse_size::Config::new().with_binary().parse_size(input)
 }
@@ -72,10 +99,32 @@ async fn main() -> Result<()> {
         None => true,
         _ => return Err(anyhow!("invalid wasm compiler")),
     };
+    let apps = match &args.command {
+        Some(Command::Serve { manifest }) => {
+            if args.env.is_some() || args.address.is_some() {
+                return Err(anyhow!(
+                    "--env and --address only apply to a script, \
+                     set `profile` and `address` per app in the manifest instead"
+                ));
+            }
+            Some(apps::load(manifest)?)
+        }
+        None => None,
+    };
+    // The memory size is a setting of the worker, so it has to fit the largest app.
+    let memory_size = apps
+        .iter()
+        .flatten()
+        .filter_map(apps::AppSpec::memory_size)
+        .fold(args.memory_size, u64::max);
+    config::set_address_from(args.address_from.unwrap_or(AddressFrom::Manifest));
+    let max_instances = args
+        .max_instances
+        .unwrap_or(if apps.is_some() { 64 } else { 1 });
     let worker_args = WorkerArgs::builder()
-        .instance_memory_size(args.memory_size)
-        .max_instances(1)
-        .module_cache_size(1)
+        .instance_memory_size(memory_size)
+        .max_instances(max_instances)
+        .module_cache_size(max_instances)
         .no_mem_pool(true)
         .use_winch(use_winch)
         .tcp_listen_port_range(0..=65535)
@@ -91,18 +140,45 @@ async fn main() -> Result<()> {
         let engine_file = std::fs::canonicalize(&engine_file).expect("canonicalize");
         config::save_default_engine(&engine_file)?;
     }
-    let script = std::fs::read_to_string(&args.script).context("failed to read engine code")?;
     let worker = Worker::crate_running(worker_args).context("failed to create worker state")?;
     let hash_algorithm = "sha256".to_string();
-
-    let mut instance_args = vec!["-c".to_string(), script];
-    instance_args.extend(args.args.into_iter());
-
     let code_hash = worker
         .blob_loader()
         .put(&[], &mut &engine_code[..], &hash_algorithm)
         .await
         .context("failed to upload engine code")?;
+    let registry = Registry::default();
+
+    if let (Some(Command::Serve { manifest }), Some(apps)) = (args.command, apps) {
+        let defaults = apps::Defaults {
+            engine_hash: code_hash,
+            memory_size,
+            query_size: args.query_size,
+            port: args.port,
+        };
+        info!("serving the apps of {}", manifest.display());
+        tokio::spawn(apps::serve(
+            worker.clone(),
+            registry.clone(),
+            manifest,
+            apps,
+            defaults,
+        ));
+        web_api::serve_user(worker, registry, args.port).await?;
+        return Ok(());
+    }
+
+    let Some(script) = args.script else {
+        return Err(anyhow!("no script provided"));
+    };
+    let script = std::fs::read_to_string(&script).context("failed to read the script")?;
+    let mut instance_args = vec![];
+    if let Some(env) = args.env {
+        instance_args.extend(["--env".to_string(), env]);
+    }
+    instance_args.extend(["-c".to_string(), script]);
+    instance_args.extend(args.args.into_iter());
+
     let manifest = Manifest {
         version: 1,
         code_hash,
@@ -116,6 +192,7 @@ async fn main() -> Result<()> {
         max_query_size: args.query_size.try_into().context("invalid query size")?,
         label: "test".to_string(),
     };
+    config::set_address_override(&manifest.label, args.address);
     let app_info = worker
         .deploy_app(manifest, false)
         .await
@@ -124,15 +201,10 @@ async fn main() -> Result<()> {
         "app deployed at address: 0x{:?}",
         hex_fmt::HexFmt(app_info.address)
     );
-    use rocket::yansi::Paint;
-    let url = format!(
-        "http://localhost:{}/app/0x{}/",
-        args.port,
-        hex_fmt::HexFmt(app_info.address)
-    );
-    info!("app endpoint: {}", url.green());
+    registry.insert(app_info.address, "test");
+    registry.print_table(args.port);
     let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
-    let server = web_api::serve_user(worker.clone(), args.port);
+    let server = web_api::serve_user(worker.clone(), registry, args.port);
     tokio::spawn(async move {
         loop {
             if worker.info(false).running_instances == 0 {
@@ -148,3 +220,10 @@ async fn main() -> Result<()> {
     }
     Ok(())
 }
+
+fn app_url(port: u16, address: &wapod::Address) -> String {
+    format!(
+        "http://localhost:{port}/app/0x{}/",
+        hex_fmt::HexFmt(address)
+    )
+}
@@ -0,0 +1,144 @@
+//! The apps deployed by the runner, by address.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use hex_fmt::HexFmt;
+use rocket::http::Status;
+use tracing::info;
+use wapod::Address;
+
+use crate::app_url;
+
+#[derive(Clone, Default)]
+pub struct Registry {
+    apps: Arc<Mutex<BTreeMap<Address, String>>>,
+}
+
+impl Registry {
+    fn apps(&self) -> std::sync::MutexGuard<'_, BTreeMap<Address, String>> {
+        self.apps.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Checks that no other app is deployed at `address`, to be called before deploying.
+    pub fn check(&self, address: &Address, name: &str) -> Result<()> {
+        match self.apps().get(address) {
+            Some(other) if other != name => {
+                bail!("address 0x{} is taken by app {other}", HexFmt(address))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn insert(&self, address: Address, name: &str) {
+        self.apps().insert(address, name.into());
+    }
+
+    pub fn remove(&self, address: &Address) {
+        self.apps().remove(address);
+    }
+
+    /// Resolves the id of an `/app/<id>/` url, which is either a full address or a prefix of the
+    /// address of a single deployed app.
+    pub fn resolve(&self, id: &[u8]) -> Result<Address, (Status, String)> {
+        if let Ok(address) = Address::try_from(id) {
+            return Ok(address);
+        }
+        if id.is_empty() || id.len() > 32 {
+            return Err((
+                Status::BadRequest,
+                format!("invalid app id 0x{}", HexFmt(id)),
+            ));
+        }
+        let apps = self.apps();
+        let mut matches = apps.keys().filter(|address| address.starts_with(id));
+        match (matches.next(), matches.next()) {
+            (Some(address), None) => Ok(*address),
+            (None, _) => Err((Status::NotFound, format!("no app with id 0x{}", HexFmt(id)))),
+            (Some(_), Some(_)) => Err((
+                Status::Conflict,
+                format!("ambiguous app id 0x{}, use a longer one", HexFmt(id)),
+            )),
+        }
+    }
+
+    /// Logs the deployed apps with their endpoints.
+    pub fn print_table(&self, port: u16) {
+        use rocket::yansi::Paint;
+        let apps = self.apps();
+        let width = apps.values().map(String::len).max().unwrap_or(0).max(3);
+        info!("{:width$}  endpoint", "app");
+        for (address, name) in apps.iter() {
+            info!("{name:width$}  {}", app_url(port, address).green());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(prefix: &[u8]) -> Address {
+        let mut address = [0xff; 32];
+        address[..prefix.len()].copy_from_slice(prefix);
+        address
+    }
+
+    fn registry() -> Registry {
+        let registry = Registry::default();
+        registry.insert(address(&[0x12, 0x34]), "a");
+        registry.insert(address(&[0x12, 0x56]), "b");
+        registry.insert(address(&[0xab]), "c");
+        registry
+    }
+
+    fn status(result: Result<Address, (Status, String)>) -> Status {
+        result.expect_err("expected an error").0
+    }
+
+    #[test]
+    fn full_addresses() {
+        let registry = registry();
+        let deployed = address(&[0xab]);
+        assert_eq!(registry.resolve(&deployed), Ok(deployed));
+        // Resolved even if not deployed, the worker tells whether it exists.
+        let unknown = address(&[0x99]);
+        assert_eq!(registry.resolve(&unknown), Ok(unknown));
+    }
+
+    #[test]
+    fn prefixes() {
+        let registry = registry();
+        assert_eq!(registry.resolve(&[0xab]), Ok(address(&[0xab])));
+        assert_eq!(registry.resolve(&[0x12, 0x34]), Ok(address(&[0x12, 0x34])));
+        assert_eq!(
+            registry.resolve(&[0x12, 0x56, 0xff]),
+            Ok(address(&[0x12, 0x56]))
+        );
+        assert_eq!(status(registry.resolve(&[0x99])), Status::NotFound);
+        assert_eq!(status(registry.resolve(&[0xab, 0x00])), Status::NotFound);
+    }
+
+    #[test]
+    fn ambiguous_prefixes() {
+        let registry = registry();
+        assert_eq!(status(registry.resolve(&[0x12])), Status::Conflict);
+        registry.remove(&address(&[0x12, 0x56]));
+        assert_eq!(registry.resolve(&[0x12]), Ok(address(&[0x12, 0x34])));
+    }
+
+    #[test]
+    fn invalid_ids() {
+        let registry = registry();
+        assert_eq!(status(registry.resolve(&[])), Status::BadRequest);
+        assert_eq!(status(registry.resolve(&[0xab; 33])), Status::BadRequest);
+    }
+
+    #[test]
+    fn taken_addresses() {
+        let registry = registry();
+        assert!(registry.check(&address(&[0xab]), "c").is_ok());
+        assert!(registry.check(&address(&[0xab]), "d").is_err());
+        assert!(registry.check(&address(&[0x99]), "d").is_ok());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{Config, Worker};
+use crate::{registry::Registry, Config, Worker};
 use anyhow::Result;
 use rocket::data::Limits;
 use rocket::figment::providers::{Env, Format, Toml};
@@ -16,21 +16,25 @@ type UserService = wapod::prpc_service::UserService<Config>;
 #[post("/app/<id>/<path..>", data = "<body>")]
 async fn connect_vm_post<'r>(
     state: &State<Worker>,
+    registry: &State<Registry>,
     head: RequestInfo,
     id: HexBytes,
     path: PathBuf,
     body: Data<'r>,
 ) -> Result<StreamResponse, (Status, String)> {
+    let id = HexBytes(registry.resolve(&id.0)?.to_vec());
     connect_vm(state, head, id, path, Some(body)).await
 }
 
 #[get("/app/<id>/<path..>")]
 async fn connect_vm_get<'r>(
     state: &State<Worker>,
+    registry: &State<Registry>,
     head: RequestInfo,
     id: HexBytes,
     path: PathBuf,
 ) -> Result<StreamResponse, (Status, String)> {
+    let id = HexBytes(registry.resolve(&id.0)?.to_vec());
     connect_vm(state, head, id, path, None).await
 }
 
@@ -56,7 +60,7 @@ async fn prpc_get(
     handle_prpc::<UserService, _>(state, method, None, limits, content_type, true).await
 }
 
-pub async fn serve_user(state: Worker, port: u16) -> Result<()> {
+pub async fn serve_user(state: Worker, registry: Registry, port: u16) -> Result<()> {
     let figment = Figment::from(rocket::Config::default())
         .merge(Toml::file("Wapod.toml").nested())
         .merge(Env::prefixed("WAPOD_USER_").global())
@@ -64,6 +68,7 @@ pub async fn serve_user(state: Worker, port: u16) -> Result<()> {
         .merge(("port", port));
     let _rocket = rocket::custom(figment)
         .manage(state)
+        .manage(registry)
         .mount("/", routes![connect_vm_get, connect_vm_post])
         .mount("/prpc", routes![prpc_post, prpc_get])
         .launch()
@@ -0,0 +1,105 @@
+//! Developer tooling for the native runtime.
+//!
+//! QuickJS has no debugging API, so the tools here are built on the runtime interrupt
+//! handler, which the interpreter polls periodically while executing bytecode, and on the
+//! backtrace QuickJS records when an `Error` is constructed.
+
+use anyhow::{Context, Result};
+use js::c;
+
+pub(crate) use crate::interrupt::add_interrupt_hook;
+use crate::service::Service;
+
+pub(crate) mod coverage;
+pub(crate) mod inspector;
+pub(crate) mod profiler;
+
+/// Instruments a script for coverage and for the debugger, returns the source itself if neither
+/// is enabled.
+pub(crate) fn instrument(service: &Service, url: &str, source: String) -> Result<String> {
+    let counters = coverage::next_counters();
+    let debugging = inspector::is_started();
+    if counters.is_none() && !debugging {
+        return Ok(source);
+    }
+    let scan = coverage::scan(&source, counters.as_deref().unwrap_or_default());
+    let mut insertions = inspector::instrument(service, url, &source, &scan)?;
+    if let Some(counters) = counters {
+        insertions.extend(scan.insertions);
+        coverage::register(service, url, &source, &counters, scan.blocks)?;
+    }
+    insertions.sort_by_key(|(at, _)| *at);
+    crate::source_map::install(service.context())
+        .context("failed to install the stack rewriter")?;
+    crate::source_map::register_insertions(url, coverage::shifts(&source, &insertions));
+    Ok(coverage::apply(&source, &insertions))
+}
+
+/// A frame of the JS call stack, as reported by the QuickJS backtrace.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct StackFrame {
+    pub function: String,
+    pub url: String,
+    /// 1-based, 0 if unknown.
+    pub line: u32,
+    /// 1-based, 0 if unknown.
+    pub column: u32,
+}
+
+/// Captures the current JS call stack, innermost frame first. Native frames are skipped.
+pub(crate) fn capture_stack(ctx: &js::Context) -> Vec<StackFrame> {
+    // The unwrapped constructor, the one of `source_map` would run JS code.
+    let Ok(error_ctor) = crate::source_map::native_error(ctx) else {
+        return vec![];
+    };
+    // Constructing an Error from C records the backtrace of the running bytecode frames,
+    // without running any JS code.
+    let error = unsafe {
+        let mut args: [c::JSValue; 0] = [];
+        let raw =
+            c::JS_CallConstructor(ctx.as_ptr(), *error_ctor.raw_value(), 0, args.as_mut_ptr());
+        if c::is_exception(raw) {
+            let _ = ctx.get_exception_str();
+            return vec![];
+        }
+        js::Value::new_moved(ctx, raw)
+    };
+    let stack = error
+        .get_property("stack")
+        .and_then(|stack| stack.decode_string())
+        .unwrap_or_default();
+    parse_stack(&stack)
+}
+
+/// Parses a QuickJS `error.stack` string, lines look like `    at name (file:line:column)`.
+pub(crate) fn parse_stack(stack: &str) -> Vec<StackFrame> {
+    stack.lines().filter_map(parse_stack_line).collect()
+}
+
+fn parse_stack_line(line: &str) -> Option<StackFrame> {
+    let line = line.trim().strip_prefix("at ")?;
+    let (function, location) = match line.rfind(" (") {
+        Some(pos) if line.ends_with(')') => (&line[..pos], &line[pos + 2..line.len() - 1]),
+        _ => ("<anonymous>", line),
+    };
+    if location == "native" {
+        return None;
+    }
+    let mut url = location;
+    let mut numbers = vec![];
+    while numbers.len() < 2 {
+        match url.rsplit_once(':') {
+            Some((rest, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => {
+                numbers.insert(0, n.parse().unwrap_or(0));
+                url = rest;
+            }
+            _ => break,
+        }
+    }
+    Some(StackFrame {
+        function: function.to_string(),
+        url: url.to_string(),
+        line: numbers.first().copied().unwrap_or(0),
+        column: numbers.get(1).copied().unwrap_or(0),
+    })
+}
@@ -0,0 +1,1207 @@
+//! Code coverage enabled by `--coverage <dir>`.
+//!
+//! QuickJS has no block execution hooks, so scripts are instrumented at load time instead: a
+//! counter increment is inserted at the start of every brace-delimited block (function bodies,
+//! `if`/`else`, loops, `try`/`catch`/`finally`). The braceless bodies of `if`, `else`, `while`
+//! and `for (;;)` have no block to insert into, they are counted by wrapping the condition that
+//! selects them: `if (a) b(); else c();` runs as
+//! `if ((a) ? (++counters[1], true) : (++counters[2], false)) b(); else c();`. The bodies of
+//! braceless `for`-`in`/`of`, `do` and `with` statements, `?:` branches and short-circuit
+//! operators are attributed to their enclosing block.
+//!
+//! The source is split into tokens by a lightweight scanner rather than parsed, a `/` being taken
+//! for a regex or a division from the token before it. The counters of each script live in a
+//! non-enumerable, read-only global `__wapo_cov_<n>`, which scripts can still read by name. The
+//! inserted text shifts the columns of the lines it lands on, the positions in stack traces are
+//! shifted back by [`crate::source_map`].
+//!
+//! On exit, a V8-compatible `coverage-<pid>-<time>.json` (as written by `NODE_V8_COVERAGE`,
+//! readable by c8) and an `lcov.info` are written to the output directory.
+
+use alloc::collections::BTreeMap;
+use core::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use log::info;
+use serde_json::json;
+
+use crate::service::{OwnedJsValue, Service};
+
+pub(super) struct Block {
+    /// Byte offsets in the original source, from the opening brace to past the closing one, or
+    /// the guessed extent of a braceless body.
+    pub start: usize,
+    pub end: usize,
+    /// The function name if the block is a function body.
+    pub function: Option<String>,
+}
+
+/// A statement directly in a block or at the top level, where the debugger can pause.
+pub(super) struct Statement {
+    /// The byte offset of its first token.
+    pub offset: usize,
+    /// For the first statement of a `catch` block, the expression of the caught exception: the
+    /// name it is bound to, or `undefined` if it is destructured or not bound.
+    pub caught: Option<String>,
+}
+
+/// What the scanner found in a script.
+pub(super) struct Scan {
+    /// Block 0 is the whole script.
+    pub blocks: Vec<Block>,
+    /// The `(byte offset, text)` insertions counting the blocks, sorted by offset.
+    pub insertions: Vec<(usize, String)>,
+    /// Sorted by offset.
+    pub statements: Vec<Statement>,
+}
+
+struct Script {
+    url: String,
+    source: String,
+    /// Block 0 is the whole script.
+    blocks: Vec<Block>,
+    counters: OwnedJsValue,
+}
+
+struct Coverage {
+    dir: PathBuf,
+    scripts: Vec<Script>,
+}
+
+thread_local! {
+    static COVERAGE: RefCell<Option<Coverage>> = const { RefCell::new(None) };
+}
+
+/// Enables coverage collection, reports are written to `dir` by [`finish`].
+pub(crate) fn start(dir: String) {
+    COVERAGE.with(|coverage| {
+        *coverage.borrow_mut() = Some(Coverage {
+            dir: dir.into(),
+            scripts: vec![],
+        })
+    });
+    info!(target: "js::coverage", "coverage collection enabled");
+}
+
+/// The name of the counters of the next script, `None` if coverage is disabled.
+pub(super) fn next_counters() -> Option<String> {
+    COVERAGE.with(|coverage| {
+        let coverage = coverage.borrow();
+        Some(format!("__wapo_cov_{}", coverage.as_ref()?.scripts.len()))
+    })
+}
+
+/// Creates the counters of a script scanned with the name returned by [`next_counters`].
+pub(super) fn register(
+    service: &Service,
+    url: &str,
+    source: &str,
+    counters_name: &str,
+    blocks: Vec<Block>,
+) -> Result<()> {
+    let ctx = service.context();
+    // The top level, block 0, runs once the script is evaluated.
+    let counters = ctx
+        .eval(&js::Code::Source(&format!(
+            "(() => {{ const counters = new Uint32Array({}); counters[0] = 1; \
+             Object.defineProperty(globalThis, '{counters_name}', {{ value: counters }}); \
+             return counters; }})()",
+            blocks.len()
+        )))
+        .map_err(js::Error::msg)
+        .context("failed to install coverage counters")?;
+    let counters = service.to_owned_value(&counters);
+    COVERAGE.with(|coverage| {
+        if let Some(coverage) = coverage.borrow_mut().as_mut() {
+            coverage.scripts.push(Script {
+                url: url.to_string(),
+                source: source.to_string(),
+                blocks,
+                counters,
+            });
+        }
+    });
+    Ok(())
+}
+
+pub(super) fn apply(source: &str, insertions: &[(usize, String)]) -> String {
+    let len = insertions.iter().map(|(_, text)| text.len()).sum::<usize>();
+    let mut instrumented = String::with_capacity(source.len() + len);
+    let mut last = 0;
+    for (pos, text) in insertions {
+        instrumented.push_str(&source[last..*pos]);
+        instrumented.push_str(text);
+        last = *pos;
+    }
+    instrumented.push_str(&source[last..]);
+    instrumented
+}
+
+/// The positions of the insertions in the original source, for the stack traces.
+pub(super) fn shifts(
+    source: &str,
+    insertions: &[(usize, String)],
+) -> Vec<crate::source_map::Insertion> {
+    let line_starts: Vec<usize> = core::iter::once(0)
+        .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    insertions
+        .iter()
+        .map(|(pos, text)| {
+            let line = line_starts.partition_point(|&start| start <= *pos);
+            let line_start = line_starts[line - 1];
+            crate::source_map::Insertion {
+                line: line as u32,
+                column: source[line_start..*pos].chars().count() as u32 + 1,
+                len: text.chars().count() as u32,
+            }
+        })
+        .collect()
+}
+
+/// Writes the coverage reports, does nothing if coverage is disabled.
+pub(crate) fn finish(service: &Service) -> Result<()> {
+    let Some(coverage) = COVERAGE.with(|coverage| coverage.borrow_mut().take()) else {
+        return Ok(());
+    };
+    let ctx = service.context();
+    let array_from = ctx
+        .get_global_object()
+        .get_property("Array")?
+        .get_property("from")?;
+    let mut counts = vec![];
+    for script in &coverage.scripts {
+        let counters = service.to_js_value(&script.counters);
+        let values: Vec<u32> = array_from
+            .call(&js::Value::undefined(), &[counters])?
+            .decode()?;
+        counts.push(values);
+    }
+    std::fs::create_dir_all(&coverage.dir)
+        .with_context(|| format!("failed to create {}", coverage.dir.display()))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let v8_path = coverage
+        .dir
+        .join(format!("coverage-{}-{now}.json", std::process::id()));
+    std::fs::write(&v8_path, coverage.to_v8(&counts))
+        .with_context(|| format!("failed to write {}", v8_path.display()))?;
+    let lcov_path = coverage.dir.join("lcov.info");
+    std::fs::write(&lcov_path, coverage.to_lcov(&counts))
+        .with_context(|| format!("failed to write {}", lcov_path.display()))?;
+    info!(
+        target: "js::coverage",
+        "coverage of {} scripts written to {}",
+        coverage.scripts.len(),
+        coverage.dir.display()
+    );
+    Ok(())
+}
+
+/// The `file://` URL of a script loaded from a file, else its name.
+pub(super) fn file_url(url: &str) -> String {
+    match Path::new(url).canonicalize() {
+        Ok(path) => format!("file://{}", path.display()),
+        Err(_) => url.to_string(),
+    }
+}
+
+impl Script {
+    fn file_url(&self) -> String {
+        file_url(&self.url)
+    }
+
+    fn file_path(&self) -> String {
+        match Path::new(&self.url).canonicalize() {
+            Ok(path) => path.display().to_string(),
+            Err(_) => self.url.clone(),
+        }
+    }
+
+    /// The innermost function enclosing each block, 0 being the script itself.
+    fn owner_functions(&self) -> Vec<usize> {
+        let mut owners = vec![0; self.blocks.len()];
+        let mut stack: Vec<usize> = vec![0];
+        for (id, block) in self.blocks.iter().enumerate().skip(1) {
+            while let Some(&top) = stack.last() {
+                if top == 0 || block.start < self.blocks[top].end {
+                    break;
+                }
+                stack.pop();
+            }
+            owners[id] = stack
+                .iter()
+                .rev()
+                .copied()
+                .find(|&b| b == 0 || self.blocks[b].function.is_some())
+                .unwrap_or(0);
+            stack.push(id);
+        }
+        owners
+    }
+}
+
+impl Coverage {
+    fn to_v8(&self, counts: &[Vec<u32>]) -> String {
+        let mut result = vec![];
+        for (index, script) in self.scripts.iter().enumerate() {
+            let offsets = Utf16Offsets::new(&script.source, &script.blocks);
+            let owners = script.owner_functions();
+            let count = |id: usize| counts[index].get(id).copied().unwrap_or(0);
+            let range = |id: usize| {
+                let block = &script.blocks[id];
+                json!({
+                    "startOffset": offsets.get(block.start),
+                    "endOffset": offsets.get(block.end),
+                    "count": count(id),
+                })
+            };
+            let mut functions = vec![];
+            for (id, block) in script.blocks.iter().enumerate() {
+                if id != 0 && block.function.is_none() {
+                    continue;
+                }
+                let mut ranges = vec![range(id)];
+                for (inner, owner) in owners.iter().enumerate().skip(1) {
+                    if *owner == id && inner != id && script.blocks[inner].function.is_none() {
+                        ranges.push(range(inner));
+                    }
+                }
+                functions.push(json!({
+                    "functionName": block.function.as_deref().unwrap_or(""),
+                    "ranges": ranges,
+                    "isBlockCoverage": true,
+                }));
+            }
+            result.push(json!({
+                "scriptId": index.to_string(),
+                "url": script.file_url(),
+                "functions": functions,
+            }));
+        }
+        json!({ "result": result }).to_string()
+    }
+
+    fn to_lcov(&self, counts: &[Vec<u32>]) -> String {
+        let mut out = String::new();
+        for (index, script) in self.scripts.iter().enumerate() {
+            let count = |id: usize| counts[index].get(id).copied().unwrap_or(0);
+            let line_starts: Vec<usize> = core::iter::once(0)
+                .chain(script.source.match_indices('\n').map(|(i, _)| i + 1))
+                .collect();
+            let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset);
+            out.push_str(&format!("TN:\nSF:{}\n", script.file_path()));
+            let mut found = 0;
+            let mut hit = 0;
+            for (id, block) in script.blocks.iter().enumerate() {
+                let Some(name) = &block.function else {
+                    continue;
+                };
+                let name = format!("{name}@{}", line_of(block.start));
+                out.push_str(&format!("FN:{},{name}\n", line_of(block.start)));
+                out.push_str(&format!("FNDA:{},{name}\n", count(id)));
+                found += 1;
+                if count(id) > 0 {
+                    hit += 1;
+                }
+            }
+            out.push_str(&format!("FNF:{found}\nFNH:{hit}\n"));
+            let mut lines_found = 0;
+            let mut lines_hit = 0;
+            let mut offset = 0;
+            // Blocks are sorted by start, the stack holds the ones enclosing the current line.
+            let mut next_block = 1;
+            let mut open: Vec<usize> = vec![0];
+            for (line_no, line) in script.source.split('\n').enumerate() {
+                let code = line.trim_start();
+                let start = offset + (line.len() - code.len());
+                offset += line.len() + 1;
+                if code.is_empty() || code.starts_with("//") {
+                    continue;
+                }
+                // Attribute the line to the innermost block containing its first character.
+                while next_block < script.blocks.len() && script.blocks[next_block].start <= start {
+                    open.retain(|&b| {
+                        b == 0 || script.blocks[b].end > script.blocks[next_block].start
+                    });
+                    open.push(next_block);
+                    next_block += 1;
+                }
+                open.retain(|&b| b == 0 || script.blocks[b].end > start);
+                let line_count = count(*open.last().unwrap_or(&0));
+                out.push_str(&format!("DA:{},{line_count}\n", line_no + 1));
+                lines_found += 1;
+                if line_count > 0 {
+                    lines_hit += 1;
+                }
+            }
+            out.push_str(&format!(
+                "LF:{lines_found}\nLH:{lines_hit}\nend_of_record\n"
+            ));
+        }
+        out
+    }
+}
+
+/// Converts the byte offsets of the blocks to the UTF-16 offsets used by V8.
+struct Utf16Offsets {
+    offsets: BTreeMap<usize, usize>,
+}
+
+impl Utf16Offsets {
+    fn new(source: &str, blocks: &[Block]) -> Self {
+        let mut offsets: BTreeMap<usize, usize> = blocks
+            .iter()
+            .flat_map(|block| [(block.start, 0), (block.end, 0)])
+            .collect();
+        let mut utf16 = 0;
+        let mut chars = source.char_indices().peekable();
+        for (offset, value) in offsets.iter_mut() {
+            while let Some((_, c)) = chars.next_if(|(i, _)| i < offset) {
+                utf16 += c.len_utf16();
+            }
+            *value = utf16;
+        }
+        Self { offsets }
+    }
+
+    fn get(&self, offset: usize) -> usize {
+        self.offsets.get(&offset).copied().unwrap_or_default()
+    }
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    None,
+    Word(String),
+    Punct(char),
+    /// `++` or `--`, which end an operand when postfix.
+    Increment,
+    Arrow,
+    /// A closing parenthesis, with the token before the matching opening one.
+    CloseParen(Box<Token>),
+    Literal,
+}
+
+enum Brace {
+    Block(usize),
+    /// The body of a `do`-`while` loop.
+    DoBlock(usize),
+    Other,
+    Template,
+}
+
+struct Paren {
+    /// The token before the opening parenthesis.
+    before: Token,
+    /// The byte offset past the opening parenthesis.
+    start: usize,
+    /// The number of braces open at the opening parenthesis.
+    depth: usize,
+    /// The offsets of the `;` directly inside, the separators of a `for (;;)` header.
+    semicolons: Vec<usize>,
+    /// Whether it holds the condition of a `do`-`while` loop.
+    do_while: bool,
+}
+
+/// The condition of an `if`, `while` or `for (;;)`, wrapped to count the braceless branches it
+/// selects since there is no block to insert their counters into.
+struct Condition {
+    /// Byte offsets of the condition, `start == end` for the empty test of `for (;;)`.
+    start: usize,
+    end: usize,
+    on_true: Option<usize>,
+    on_false: Option<usize>,
+}
+
+impl Condition {
+    fn insertions(&self, counters: &str) -> Vec<(usize, String)> {
+        let count = |id: usize, value: &str| format!("(++{counters}[{id}], {value})");
+        let suffix = match (self.on_true, self.on_false) {
+            (None, None) => return vec![],
+            (Some(t), _) if self.start == self.end => {
+                return vec![(self.start, count(t, "true"))];
+            }
+            (Some(t), Some(f)) => format!(") ? {} : {}", count(t, "true"), count(f, "false")),
+            (Some(t), None) => format!(") && {}", count(t, "true")),
+            (None, Some(f)) => format!(") || {}", count(f, "false")),
+        };
+        vec![(self.start, "(".into()), (self.end, suffix)]
+    }
+}
+
+/// A statement waiting for its next token to tell whether its body has braces.
+enum Pending {
+    /// The body of an `if`, `while` or `for (;;)`, with the index of its condition.
+    Body(usize),
+    /// The body of an `else`, with the index of the condition of its `if`.
+    Else(usize),
+}
+
+/// Why the next token may start a statement where the debugger can pause.
+enum After {
+    /// The start of the script or of a block, `true` if directives may come first.
+    Start(bool),
+    Semicolon,
+    /// The end of a block other than a function body.
+    Block,
+    /// The end of a function body, which may be an expression.
+    Function,
+    /// The start of a `catch` block, with the expression of the exception.
+    Catch(String),
+}
+
+const BLOCK_KEYWORDS: &[&str] = &["if", "for", "while", "with", "catch"];
+pub(super) const KEYWORDS: &[&str] = &[
+    "if",
+    "for",
+    "while",
+    "with",
+    "catch",
+    "switch",
+    "function",
+    "return",
+    "typeof",
+    "case",
+    "do",
+    "else",
+    "in",
+    "of",
+    "new",
+    "delete",
+    "void",
+    "throw",
+    "instanceof",
+    "yield",
+    "await",
+    "try",
+    "finally",
+    "static",
+];
+
+/// Words after which a line break does not end a statement, besides the keywords.
+const CONTINUED_WORDS: &[&str] = &[
+    "let", "const", "var", "async", "class", "extends", "import", "export",
+];
+/// Words that do not start a statement the debugger can pause before.
+const NON_STATEMENT_WORDS: &[&str] = &[
+    "else",
+    "catch",
+    "finally",
+    "in",
+    "of",
+    "instanceof",
+    "extends",
+];
+
+impl Token {
+    /// Whether a line break after this token ends the statement if what follows can not
+    /// continue it, which makes inserting a statement there safe.
+    fn ends_expression(&self) -> bool {
+        match self {
+            Token::Literal | Token::Punct(']') => true,
+            Token::Word(word) => {
+                !KEYWORDS.contains(&word.as_str()) && !CONTINUED_WORDS.contains(&word.as_str())
+            }
+            Token::CloseParen(before) => !matches!(
+                &**before,
+                Token::Word(word) if BLOCK_KEYWORDS.contains(&word.as_str())
+                    || word == "switch"
+                    || word == "function"
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether a `/` after this token starts a regex rather than a division, guessed from the
+    /// previous token only.
+    fn allows_regex(&self) -> bool {
+        match self {
+            Token::None | Token::Arrow => true,
+            Token::Punct(c) => !matches!(c, ')' | ']'),
+            Token::Word(word) => KEYWORDS.contains(&word.as_str()),
+            Token::Increment | Token::CloseParen(_) | Token::Literal => false,
+        }
+    }
+}
+
+/// Finds the blocks and statements of a script, and the text to insert to count the blocks,
+/// using `counters` as the name of the counter array.
+///
+/// Statements are only looked for where inserting another one before them keeps the meaning of
+/// the script: after a `;` or a block in a block, at the start of a block, or at the start of a
+/// line ending the previous statement by automatic semicolon insertion.
+pub(super) fn scan(source: &str, counters: &str) -> Scan {
+    let bytes = source.as_bytes();
+    let mut blocks = vec![Block {
+        start: 0,
+        end: source.len(),
+        function: None,
+    }];
+    let mut insertions = vec![];
+    let mut statements = vec![];
+    let mut after = Some(After::Start(true));
+    let mut line_break = false;
+    // The brace depths of the braceless `do` bodies being scanned.
+    let mut braceless_do: Vec<usize> = vec![];
+    // The byte range inside the last closed parenthesis.
+    let mut last_paren = (0, 0);
+    let mut conditions: Vec<Condition> = vec![];
+    // The `if`s that may still get an `else`, with the number of braces open around them.
+    let mut ifs: Vec<(usize, usize)> = vec![];
+    let mut pending = None;
+    let mut closed_do = false;
+    let mut braces: Vec<Brace> = vec![];
+    let mut parens: Vec<Paren> = vec![];
+    let mut prev = Token::None;
+    // A hashbang line, skipped by QuickJS.
+    let mut i = match source.starts_with("#!") {
+        true => source.find('\n').unwrap_or(bytes.len()),
+        false => 0,
+    };
+    while i < bytes.len() {
+        let c = bytes[i];
+        match c {
+            b' ' | b'\t' | b'\r' | b'\n' => {
+                line_break |= c == b'\n';
+                i += 1;
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = source[i..].find('\n').map(|n| i + n).unwrap_or(bytes.len());
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let end = source[i + 2..]
+                    .find("*/")
+                    .map(|n| i + n + 4)
+                    .unwrap_or(bytes.len());
+                line_break |= source[i..end].contains('\n');
+                i = end;
+                continue;
+            }
+            _ => {}
+        }
+        let word = is_word_start(c).then(|| word_at(source, i));
+        if prev == Token::Word("do".into()) && c != b'{' {
+            braceless_do.push(braces.len());
+        }
+        let in_block = matches!(
+            braces.last(),
+            None | Some(Brace::Block(_)) | Some(Brace::DoBlock(_))
+        ) && parens
+            .last()
+            .map_or(true, |paren| paren.depth < braces.len());
+        let new_line = core::mem::take(&mut line_break);
+        let is_statement = match after.take() {
+            Some(After::Catch(caught)) => {
+                statements.push(Statement {
+                    offset: i,
+                    caught: Some(caught),
+                });
+                false
+            }
+            // The `while` of a braceless `do` body.
+            _ if word == Some("while") && braceless_do.last() == Some(&braces.len()) => {
+                braceless_do.pop();
+                false
+            }
+            _ if !in_block || c == b'}' || c == b';' => false,
+            Some(After::Start(directives)) => !(directives && matches!(c, b'\'' | b'"')),
+            Some(After::Semicolon) => word != Some("else"),
+            Some(After::Block) => !matches!(word, Some("else" | "catch" | "finally")),
+            after => {
+                let ends = matches!(after, Some(After::Function)) || prev.ends_expression();
+                new_line
+                    && ends
+                    && pending.is_none()
+                    && word.is_some_and(|word| !NON_STATEMENT_WORDS.contains(&word))
+            }
+        };
+        if is_statement {
+            statements.push(Statement {
+                offset: i,
+                caught: None,
+            });
+        }
+        if let Some(pending) = pending.take() {
+            let else_if = matches!(pending, Pending::Else(_)) && starts_with_word(bytes, i, "if");
+            if c != b'{' && c != b';' && !else_if {
+                let id = blocks.len();
+                blocks.push(Block {
+                    start: i,
+                    end: statement_end(bytes, i),
+                    function: None,
+                });
+                match pending {
+                    Pending::Body(index) => conditions[index].on_true = Some(id),
+                    Pending::Else(index) => conditions[index].on_false = Some(id),
+                }
+            }
+        }
+        let after_do = core::mem::take(&mut closed_do);
+        match c {
+            b'/' if prev.allows_regex() => {
+                i = skip_regex(bytes, i);
+                prev = Token::Literal;
+            }
+            b'\'' | b'"' => {
+                i = skip_string(bytes, i);
+                prev = Token::Literal;
+            }
+            b'`' => {
+                let (end, substitution) = skip_template(bytes, i + 1);
+                i = end;
+                if substitution {
+                    braces.push(Brace::Template);
+                    prev = Token::Punct('{');
+                } else {
+                    prev = Token::Literal;
+                }
+            }
+            b'(' => {
+                let do_while = after_do && prev == Token::Word("while".into());
+                parens.push(Paren {
+                    before: core::mem::replace(&mut prev, Token::Punct('(')),
+                    start: i + 1,
+                    depth: braces.len(),
+                    semicolons: vec![],
+                    do_while,
+                });
+                i += 1;
+            }
+            b')' => {
+                let paren = parens.pop();
+                let is_if = matches!(
+                    paren.as_ref().map(|paren| &paren.before),
+                    Some(Token::Word(word)) if word == "if"
+                );
+                let condition = paren.as_ref().and_then(|paren| match &paren.before {
+                    Token::Word(word) if word == "if" => Some((paren.start, i)),
+                    Token::Word(word) if word == "while" && !paren.do_while => {
+                        Some((paren.start, i))
+                    }
+                    Token::Word(word) if word == "for" && paren.semicolons.len() == 2 => {
+                        let (start, end) = (paren.semicolons[0] + 1, paren.semicolons[1]);
+                        if source[start..end].trim().is_empty() {
+                            Some((start, start))
+                        } else {
+                            Some((start, end))
+                        }
+                    }
+                    _ => None,
+                });
+                if let Some((start, end)) = condition {
+                    let index = conditions.len();
+                    conditions.push(Condition {
+                        start,
+                        end,
+                        on_true: None,
+                        on_false: None,
+                    });
+                    if is_if {
+                        ifs.push((braces.len(), index));
+                    }
+                    pending = Some(Pending::Body(index));
+                }
+                if let Some(paren) = &paren {
+                    last_paren = (paren.start, i);
+                }
+                // The condition of a `do`-`while` loop ends the statement, unlike the other ones.
+                let before = match paren {
+                    Some(paren) if !paren.do_while => paren.before,
+                    _ => Token::None,
+                };
+                prev = Token::CloseParen(Box::new(before));
+                i += 1;
+            }
+            b';' => {
+                if let Some(paren) = parens.last_mut() {
+                    if paren.depth == braces.len() {
+                        paren.semicolons.push(i);
+                    }
+                }
+                after = Some(After::Semicolon);
+                prev = Token::Punct(';');
+                i += 1;
+            }
+            b'=' if bytes.get(i + 1) == Some(&b'>') => {
+                prev = Token::Arrow;
+                i += 2;
+            }
+            b'+' | b'-' if bytes.get(i + 1) == Some(&c) => {
+                prev = Token::Increment;
+                i += 2;
+            }
+            b'{' => {
+                let kind = block_kind(&prev);
+                match kind {
+                    Some(function) => {
+                        let id = blocks.len();
+                        let is_function = function.is_some();
+                        blocks.push(Block {
+                            start: i,
+                            end: source.len(),
+                            function,
+                        });
+                        let at = if is_function {
+                            skip_directives(bytes, i + 1)
+                        } else {
+                            i + 1
+                        };
+                        insertions.push((at, format!("{counters}[{id}]++;")));
+                        after = Some(match &prev {
+                            Token::Word(word) if word == "catch" => {
+                                After::Catch("undefined".into())
+                            }
+                            Token::CloseParen(before)
+                                if **before == Token::Word("catch".into()) =>
+                            {
+                                let binding = source[last_paren.0..last_paren.1].trim();
+                                let simple = !binding.is_empty()
+                                    && !binding.as_bytes()[0].is_ascii_digit()
+                                    && binding.bytes().all(is_word_byte);
+                                After::Catch(if simple { binding } else { "undefined" }.into())
+                            }
+                            _ => After::Start(is_function),
+                        });
+                        if prev == Token::Word("do".into()) {
+                            braces.push(Brace::DoBlock(id));
+                        } else {
+                            braces.push(Brace::Block(id));
+                        }
+                    }
+                    None => braces.push(Brace::Other),
+                }
+                prev = Token::Punct('{');
+                i += 1;
+            }
+            b'}' => {
+                match braces.pop() {
+                    Some(Brace::Template) => {
+                        let (end, substitution) = skip_template(bytes, i + 1);
+                        i = end;
+                        if substitution {
+                            braces.push(Brace::Template);
+                            prev = Token::Punct('{');
+                        } else {
+                            prev = Token::Literal;
+                        }
+                    }
+                    brace => {
+                        match brace {
+                            Some(Brace::Block(id)) => {
+                                blocks[id].end = i + 1;
+                                after = Some(if blocks[id].function.is_some() {
+                                    After::Function
+                                } else {
+                                    After::Block
+                                });
+                            }
+                            Some(Brace::DoBlock(id)) => {
+                                blocks[id].end = i + 1;
+                                closed_do = true;
+                            }
+                            _ => {}
+                        }
+                        prev = Token::Punct('}');
+                        i += 1;
+                    }
+                }
+                ifs.retain(|&(depth, _)| depth <= braces.len());
+            }
+            b'0'..=b'9' => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.') {
+                    i += 1;
+                }
+                prev = Token::Literal;
+            }
+            _ if word.is_some() => {
+                let word = word.unwrap_or_default();
+                i += word.len();
+                if word == "else" {
+                    // An `else` belongs to the innermost `if` that has none yet.
+                    let depth = braces.len();
+                    if let Some(pos) = ifs.iter().rposition(|&(d, _)| d == depth) {
+                        pending = Some(Pending::Else(ifs.remove(pos).1));
+                    }
+                } else if word == "while" && after_do {
+                    closed_do = true;
+                }
+                prev = Token::Word(word.to_string());
+            }
+            _ => {
+                prev = Token::Punct(c as char);
+                i += 1;
+            }
+        }
+    }
+    for condition in &conditions {
+        insertions.extend(condition.insertions(counters));
+    }
+    insertions.sort_by_key(|(at, _)| *at);
+    Scan {
+        blocks,
+        insertions,
+        statements,
+    }
+}
+
+fn is_word_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || matches!(b, b'_' | b'$' | b'\\') || b >= 0x80
+}
+
+pub(super) fn is_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'_' | b'$' | b'\\') || b >= 0x80
+}
+
+fn word_at(source: &str, start: usize) -> &str {
+    let bytes = source.as_bytes();
+    let mut end = start;
+    while end < bytes.len() && is_word_byte(bytes[end]) {
+        end += 1;
+    }
+    &source[start..end]
+}
+
+fn starts_with_word(bytes: &[u8], i: usize, word: &str) -> bool {
+    bytes[i..].starts_with(word.as_bytes())
+        && !bytes.get(i + word.len()).copied().is_some_and(is_word_byte)
+}
+
+/// Guesses where a braceless statement starting at `start` ends, for the reports only: past its
+/// `;`, before a closing bracket it did not open, or at the end of the line.
+fn statement_end(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0usize;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' | b'"' => {
+                i = skip_string(bytes, i);
+                continue;
+            }
+            b'`' => {
+                let (end, substitution) = skip_template(bytes, i + 1);
+                depth += usize::from(substitution);
+                i = end;
+                continue;
+            }
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => match depth.checked_sub(1) {
+                Some(outer) => depth = outer,
+                None => return i,
+            },
+            b';' if depth == 0 => return i + 1,
+            b'\n' if depth == 0 => return i,
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// Tells whether a `{` after `prev` opens a block: `Some(Some(name))` for a function body,
+/// `Some(None)` for another statement block and `None` for anything else.
+fn block_kind(prev: &Token) -> Option<Option<String>> {
+    match prev {
+        Token::Arrow => Some(Some("(anonymous)".into())),
+        Token::Word(word) if matches!(word.as_str(), "else" | "try" | "finally" | "do") => {
+            Some(None)
+        }
+        Token::Word(word) if word == "static" || word == "catch" => Some(None),
+        Token::CloseParen(before) => match &**before {
+            Token::Word(word) if BLOCK_KEYWORDS.contains(&word.as_str()) => Some(None),
+            Token::Word(word) if word == "switch" => None,
+            Token::Word(word) if word == "function" => Some(Some("(anonymous)".into())),
+            Token::Word(word) if !KEYWORDS.contains(&word.as_str()) => Some(Some(word.clone())),
+            Token::Punct(']') | Token::Punct('*') => Some(Some("(anonymous)".into())),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Skips the `"use strict";` style directives at the start of a function body.
+fn skip_directives(bytes: &[u8], mut i: usize) -> usize {
+    let mut insert_at = i;
+    loop {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if i >= bytes.len() || !matches!(bytes[i], b'\'' | b'"') {
+            return insert_at;
+        }
+        i = skip_string(bytes, i);
+        while i < bytes.len() && matches!(bytes[i], b' ' | b'\t') {
+            i += 1;
+        }
+        match bytes.get(i) {
+            Some(b';') => i += 1,
+            // Without a `;`, the counter goes on the next line, where it can not continue the
+            // directive.
+            Some(b'\n') | Some(b'\r') => {
+                while matches!(bytes.get(i), Some(b'\n') | Some(b'\r')) {
+                    i += 1;
+                }
+            }
+            _ => return insert_at,
+        }
+        insert_at = i;
+    }
+}
+
+fn skip_string(bytes: &[u8], start: usize) -> usize {
+    let quote = bytes[start];
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'\n' => return i,
+            c if c == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Skips template characters from `i` up to the closing backtick, or up to the opening of a
+/// substitution. Returns the position after it and whether it was a substitution.
+fn skip_template(bytes: &[u8], mut i: usize) -> (usize, bool) {
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'`' => return (i + 1, false),
+            b'$' if bytes.get(i + 1) == Some(&b'{') => return (i + 2, true),
+            _ => i += 1,
+        }
+    }
+    (bytes.len(), false)
+}
+
+fn skip_regex(bytes: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    let mut in_class = false;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'[' => in_class = true,
+            b']' => in_class = false,
+            b'/' if !in_class => {
+                i += 1;
+                while i < bytes.len() && bytes[i].is_ascii_alphabetic() {
+                    i += 1;
+                }
+                return i;
+            }
+            b'\n' => return i,
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instrument(source: &str) -> (Vec<Block>, String) {
+        let scan = scan(source, "c");
+        let instrumented = apply(source, &scan.insertions);
+        (scan.blocks, instrumented)
+    }
+
+    /// Marks the statements found with `@`, and the caught exceptions with `@(name)`.
+    fn statements(source: &str) -> String {
+        let insertions: Vec<_> = scan(source, "c")
+            .statements
+            .into_iter()
+            .map(|statement| match statement.caught {
+                Some(caught) => (statement.offset, format!("@({caught})")),
+                None => (statement.offset, "@".into()),
+            })
+            .collect();
+        apply(source, &insertions)
+    }
+
+    fn functions(blocks: &[Block]) -> Vec<&str> {
+        blocks
+            .iter()
+            .filter_map(|b| b.function.as_deref())
+            .collect()
+    }
+
+    #[test]
+    fn function_blocks() {
+        let (blocks, out) = instrument(
+            "function f(a) { 'use strict'; return a; }\nconst g = (x) => { return x; };",
+        );
+        assert_eq!(functions(&blocks), ["f", "(anonymous)"]);
+        assert_eq!(
+            out,
+            "function f(a) { 'use strict';c[1]++; return a; }\n\
+             const g = (x) => {c[2]++; return x; };"
+        );
+        let (_, out) = instrument("function f() {\n  'use strict'\n  return 1; }");
+        assert_eq!(out, "function f() {\n  'use strict'\nc[1]++;  return 1; }");
+    }
+
+    #[test]
+    fn regex_literals() {
+        // The braces and quotes in the regex do not open anything.
+        let (blocks, out) = instrument("const r = /[{'\"]\\//g; if (r.test(s)) { f(); }");
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(
+            out,
+            "const r = /[{'\"]\\//g; if (r.test(s)) {c[1]++; f(); }"
+        );
+        let (blocks, _) = instrument("x = a.split(/}/); function f() {}");
+        assert_eq!(functions(&blocks), ["f"]);
+    }
+
+    #[test]
+    fn divisions() {
+        // Read as regexes, these divisions would swallow the block after them.
+        for source in [
+            "a = b / 2; if (c) { d(); } e = f / 3;",
+            "a = (b) / 2; if (c) { d(); } e = f[0] / 3;",
+            "a = i++ / 2; if (c) { d(); } e = j-- / 3;",
+        ] {
+            let (blocks, _) = instrument(source);
+            assert_eq!(blocks.len(), 2, "{source}");
+        }
+    }
+
+    #[test]
+    fn template_strings() {
+        let source =
+            "const s = `{ ${ a ? `}` : '{' } \\` }`;\nfunction f() { return `${ { k: 1 }.k }`; }";
+        let (blocks, out) = instrument(source);
+        assert_eq!(functions(&blocks), ["f"]);
+        assert_eq!(blocks[1].start, source.find("{ return").unwrap());
+        assert_eq!(blocks[1].end, source.len());
+        assert!(out.ends_with("function f() {c[1]++; return `${ { k: 1 }.k }`; }"));
+    }
+
+    #[test]
+    fn braceless_branches() {
+        let (blocks, out) = instrument("if (a) b(); else c();");
+        assert_eq!(
+            out,
+            "if ((a) ? (++c[1], true) : (++c[2], false)) b(); else c();"
+        );
+        assert_eq!((blocks[1].start, blocks[1].end), (7, 11));
+        assert_eq!((blocks[2].start, blocks[2].end), (17, 21));
+
+        let (_, out) = instrument("if (a) { b(); } else c();");
+        assert_eq!(out, "if ((a) || (++c[2], false)) {c[1]++; b(); } else c();");
+
+        let (_, out) = instrument("if (a) b();\nelse if (d) e();\nelse { f(); }");
+        assert_eq!(
+            out,
+            "if ((a) && (++c[1], true)) b();\n\
+             else if ((d) && (++c[2], true)) e();\n\
+             else {c[3]++; f(); }"
+        );
+    }
+
+    #[test]
+    fn dangling_else() {
+        // The else belongs to the inner if.
+        let (_, out) = instrument("if (a) if (b) x(); else y();");
+        assert_eq!(
+            out,
+            "if ((a) && (++c[1], true)) if ((b) ? (++c[2], true) : (++c[3], false)) x(); else y();"
+        );
+        // The inner if is in a block, so the else belongs to the outer one.
+        let (_, out) = instrument("if (a) { if (b) x(); } else y();");
+        assert_eq!(
+            out,
+            "if ((a) || (++c[3], false)) {c[1]++; if ((b) && (++c[2], true)) x(); } else y();"
+        );
+    }
+
+    #[test]
+    fn braceless_loops() {
+        let (_, out) = instrument("while (i--) s += i;");
+        assert_eq!(out, "while ((i--) && (++c[1], true)) s += i;");
+        let (_, out) = instrument("for (let i = 0; i < n; i++) s += i;");
+        assert_eq!(
+            out,
+            "for (let i = 0;( i < n) && (++c[1], true); i++) s += i;"
+        );
+        let (_, out) = instrument("for (;;) break;");
+        assert_eq!(out, "for (;(++c[1], true);) break;");
+        // The `;` of nested functions are not the ones of the header.
+        let (_, out) = instrument("for (const f = () => { a(); }; f();) g();");
+        assert_eq!(
+            out,
+            "for (const f = () => {c[1]++; a(); };( f()) && (++c[2], true);) g();"
+        );
+        // The bodies of for-of and do loops are not counted, nor is an empty body.
+        for source in [
+            "for (const x of xs) f(x);",
+            "do f(); while (g());",
+            "while (f());",
+        ] {
+            let (blocks, out) = instrument(source);
+            assert_eq!((blocks.len(), out.as_str()), (1, source));
+        }
+        // The condition of a do-while loop is not a loop of its own.
+        let (blocks, out) = instrument("do { f(); } while (g())\nh();");
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(out, "do {c[1]++; f(); } while (g())\nh();");
+    }
+
+    #[test]
+    fn insertion_positions() {
+        let source = "let x;\nif (a) b();";
+        let insertions = scan(source, "c").insertions;
+        let shifts: Vec<_> = shifts(source, &insertions)
+            .into_iter()
+            .map(|s| (s.line, s.column, s.len))
+            .collect();
+        assert_eq!(shifts, [(2, 5, 1), (2, 6, 19)]);
+    }
+
+    #[test]
+    fn statement_positions() {
+        assert_eq!(
+            statements(
+                "'use strict';\nlet a = 1; f(a);\nfunction g(x) { if (x) { h(); } return x; }"
+            ),
+            "'use strict';\n@let a = 1; @f(a);\n\
+             @function g(x) { @if (x) { @h(); } @return x; }"
+        );
+        // Nothing is inserted into expressions, headers, braceless bodies or object literals.
+        assert_eq!(
+            statements("for (let i = 0; i < n; i++) s(i);\nif (a) b(); else c();\nx = { k: 1 };"),
+            "@for (let i = 0; i < n; i++) s(i);\n@if (a) b(); else c();\n@x = { k: 1 };"
+        );
+        assert_eq!(
+            statements("try { f(); } catch (e) { g(e); } finally { h(); }\ntry {} catch {}"),
+            "@try { @f(); } catch (e) { @(e)g(e); } finally { @h(); }\n@try {} catch {@(undefined)}"
+        );
+        assert_eq!(
+            statements("do x(); while (y);\ndo { x(); } while (y)\nz();"),
+            "@do x(); while (y);\n@do { @x(); } while (y)\n@z();"
+        );
+    }
+
+    #[test]
+    fn statements_without_semicolons() {
+        assert_eq!(
+            statements("let a = 1\nf(a)\nconst g = function () {}\ng()\nx = y\n  .z\nlet\nb"),
+            "@let a = 1\n@f(a)\n@const g = function () {}\n@g()\n@x = y\n  .z\n@let\nb"
+        );
+        // A line break after an operator or a keyword does not end the statement.
+        assert_eq!(
+            statements("if (a)\n  b()\nelse\n  c()\nx = a +\n  b\nreturn\n"),
+            "@if (a)\n  b()\nelse\n  c()\n@x = a +\n  b\n@return\n"
+        );
+    }
+}
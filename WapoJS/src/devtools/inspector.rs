@@ -0,0 +1,803 @@
+//! Chrome DevTools Protocol server enabled by `--inspect <port>`.
+//!
+//! The WebSocket server runs on its own thread so that the debugger stays responsive while a
+//! script keeps the JS thread busy. Commands are executed on the JS thread, either from the
+//! event loop or from the interrupt handler while a script is running.
+//!
+//! The `Runtime` domain covers console messages, uncaught exceptions, evaluation in the global
+//! scope and object inspection. The `Debugger` domain, breakpoints, stepping, scopes and pausing
+//! on exceptions, relies on probes inserted into the scripts, see [`debugger`].
+//!
+//! The server only accepts requests whose `Host` is the loopback interface, so that web pages
+//! can not reach it through DNS rebinding. The WebSocket is served on a random path that is only
+//! advertised by the discovery endpoints, and handshakes carrying an `Origin` other than the
+//! DevTools frontend are refused, so that a web page can not connect to it either.
+
+use alloc::{collections::BTreeMap, rc::Rc};
+use core::cell::RefCell;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use async_tungstenite::{
+    tungstenite::{handshake::derive_accept_key, protocol::Role, Message},
+    WebSocketStream,
+};
+use futures::{SinkExt as _, StreamExt as _};
+use log::{info, warn};
+use serde_json::{json, Value as Json};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::sync::{mpsc::UnboundedReceiver, mpsc::UnboundedSender, Notify};
+use tokio_util::compat::TokioAsyncReadCompatExt as _;
+
+use super::{add_interrupt_hook, capture_stack, coverage::Scan, StackFrame};
+use crate::service::{js_context_get_runtime, OwnedJsValue, Service};
+
+mod debugger;
+
+const TARGET_NAME: &str = "wapojs";
+const CONTEXT_ID: u64 = 1;
+
+enum Command {
+    Connected,
+    Message(String),
+    Disconnected,
+}
+
+#[derive(Default)]
+struct State {
+    objects: BTreeMap<u64, OwnedJsValue>,
+    next_object_id: u64,
+    runtime_enabled: bool,
+}
+
+struct Inspector {
+    commands: mpsc::Receiver<Command>,
+    events: UnboundedSender<String>,
+    connected: Arc<AtomicBool>,
+    state: RefCell<State>,
+    debugger: RefCell<debugger::Debugger>,
+}
+
+thread_local! {
+    static INSPECTOR: RefCell<Option<Rc<Inspector>>> = const { RefCell::new(None) };
+}
+
+fn current() -> Option<Rc<Inspector>> {
+    INSPECTOR.with(|inspector| inspector.borrow().clone())
+}
+
+/// Starts the CDP server on `127.0.0.1:port` and attaches it to the service.
+pub(crate) fn start(service: &Service, port: u16) -> Result<()> {
+    if current().is_some() {
+        bail!("the inspector has already been started");
+    }
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port))
+        .with_context(|| format!("failed to bind inspector port {port}"))?;
+    listener.set_nonblocking(true)?;
+    let (commands_tx, commands) = mpsc::channel();
+    let (events, events_rx) = tokio::sync::mpsc::unbounded_channel();
+    let notify = Arc::new(Notify::new());
+    let connected = Arc::new(AtomicBool::new(false));
+    let target_id = random_uuid();
+    let server = Server {
+        port,
+        target_id: target_id.clone(),
+        commands: commands_tx,
+        notify: notify.clone(),
+        connected: connected.clone(),
+    };
+    std::thread::Builder::new()
+        .name("inspector".into())
+        .spawn(move || {
+            if let Err(err) = server.run(listener, events_rx) {
+                warn!(target: "js::inspector", "inspector server stopped: {err:?}");
+            }
+        })
+        .context("failed to spawn the inspector thread")?;
+    let inspector = Rc::new(Inspector {
+        commands,
+        events,
+        connected,
+        state: Default::default(),
+        debugger: Default::default(),
+    });
+    INSPECTOR.with(|current| *current.borrow_mut() = Some(inspector));
+
+    // Commands are picked up from the event loop when the script is idle...
+    let weak_service = service.weak_self();
+    crate::runtime::spawn(async move {
+        loop {
+            notify.notified().await;
+            let Some(service) = weak_service.upgrade() else {
+                break;
+            };
+            if let Some(inspector) = current() {
+                inspector.process_commands(service.context());
+            }
+        }
+    });
+    // ...and from the interrupt handler when it is busy.
+    add_interrupt_hook(service, |service| {
+        if let Some(inspector) = current() {
+            inspector.process_commands(service.context());
+        }
+    });
+    info!(target: "js::inspector", "Debugger listening on ws://127.0.0.1:{port}/{target_id}");
+    Ok(())
+}
+
+/// Whether `--inspect` was given, the scripts are then instrumented for the debugger.
+pub(crate) fn is_started() -> bool {
+    current().is_some()
+}
+
+/// Returns the probes to insert into a script for the debugger.
+pub(crate) fn instrument(
+    service: &Service,
+    url: &str,
+    source: &str,
+    scan: &Scan,
+) -> Result<Vec<(usize, String)>> {
+    match current() {
+        Some(inspector) => inspector.instrument(service, url, source, scan),
+        None => Ok(vec![]),
+    }
+}
+
+/// Forwards a console message to the attached debugger.
+pub(crate) fn console_api_called(ctx: &js::Context, level: u32, args: &[js::Value]) {
+    let Some(inspector) = current() else {
+        return;
+    };
+    if !inspector.is_active() {
+        return;
+    }
+    let kind = match level {
+        0 => "trace",
+        1 => "debug",
+        2 => "log",
+        3 => "warning",
+        _ => "error",
+    };
+    let args: Vec<_> = args
+        .iter()
+        .map(|arg| inspector.remote_object(ctx, arg))
+        .collect();
+    let stack = stack_trace(&capture_stack(ctx));
+    inspector.send_event(
+        "Runtime.consoleAPICalled",
+        json!({
+            "type": kind,
+            "args": args,
+            "executionContextId": CONTEXT_ID,
+            "timestamp": now_ms(),
+            "stackTrace": stack,
+        }),
+    );
+}
+
+/// Reports an uncaught error, and pauses if the debugger asked for it.
+pub(crate) fn exception_thrown(ctx: &js::Context, message: &str) {
+    let Some(inspector) = current() else {
+        return;
+    };
+    if !inspector.is_connected() {
+        return;
+    }
+    inspector.pause_on_uncaught(ctx, message);
+    if !inspector.is_active() {
+        return;
+    }
+    let exception = json!({
+        "type": "object",
+        "subtype": "error",
+        "className": "Error",
+        "description": message,
+    });
+    inspector.send_event(
+        "Runtime.exceptionThrown",
+        json!({
+            "timestamp": now_ms(),
+            "exceptionDetails": {
+                "exceptionId": 1,
+                "text": "Uncaught",
+                "lineNumber": 0,
+                "columnNumber": 0,
+                "executionContextId": CONTEXT_ID,
+                "exception": exception,
+            },
+        }),
+    );
+}
+
+impl Inspector {
+    fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    fn is_active(&self) -> bool {
+        self.is_connected() && self.state.borrow().runtime_enabled
+    }
+
+    fn send(&self, message: Json) {
+        if self.connected.load(Ordering::Relaxed) {
+            let _ = self.events.send(message.to_string());
+        }
+    }
+
+    fn send_event(&self, method: &str, params: Json) {
+        self.send(json!({ "method": method, "params": params }));
+    }
+
+    fn process_commands(&self, ctx: &js::Context) {
+        while let Ok(command) = self.commands.try_recv() {
+            self.handle_command(ctx, command);
+        }
+    }
+
+    fn handle_command(&self, ctx: &js::Context, command: Command) {
+        match command {
+            Command::Connected => {
+                info!(target: "js::inspector", "debugger attached");
+            }
+            Command::Disconnected => {
+                info!(target: "js::inspector", "debugger detached");
+                *self.state.borrow_mut() = State::default();
+                self.debugger.borrow_mut().detach();
+                if let Err(err) = self.clear_flags(ctx) {
+                    warn!(target: "js::inspector", "failed to clear the breakpoints: {err:?}");
+                }
+            }
+            Command::Message(message) => self.dispatch(ctx, &message),
+        }
+    }
+
+    fn dispatch(&self, ctx: &js::Context, message: &str) {
+        let Ok(request) = serde_json::from_str::<Json>(message) else {
+            warn!(target: "js::inspector", "invalid message: {message}");
+            return;
+        };
+        let id = request["id"].clone();
+        let method = request["method"].as_str().unwrap_or_default();
+        let params = &request["params"];
+        match self.call(ctx, method, params) {
+            Ok(result) => self.send(json!({ "id": id, "result": result })),
+            Err(err) => self.send(json!({
+                "id": id,
+                "error": { "code": -32000, "message": format!("{err}") },
+            })),
+        }
+        if method == "Debugger.enable" {
+            self.scripts_parsed();
+        }
+        if method == "Runtime.enable" {
+            self.send_event(
+                "Runtime.executionContextCreated",
+                json!({
+                    "context": {
+                        "id": CONTEXT_ID,
+                        "origin": "",
+                        "name": TARGET_NAME,
+                        "uniqueId": TARGET_NAME,
+                    },
+                }),
+            );
+        }
+    }
+
+    fn call(&self, ctx: &js::Context, method: &str, params: &Json) -> Result<Json> {
+        let empty = json!({});
+        let result = match method {
+            "Runtime.enable" => {
+                self.state.borrow_mut().runtime_enabled = true;
+                empty
+            }
+            "Runtime.disable" => {
+                self.state.borrow_mut().runtime_enabled = false;
+                empty
+            }
+            "Runtime.evaluate" => {
+                let expression = params["expression"].as_str().unwrap_or_default();
+                let by_value = params["returnByValue"].as_bool().unwrap_or(false);
+                match ctx.eval(&js::Code::Source(expression)) {
+                    Ok(value) => json!({ "result": self.to_remote(ctx, &value, by_value) }),
+                    Err(err) => exception_result(&err),
+                }
+            }
+            "Runtime.callFunctionOn" => self.call_function_on(ctx, params)?,
+            "Runtime.getProperties" => self.get_properties(ctx, params)?,
+            "Runtime.releaseObject" => {
+                let id = object_id(params)?;
+                self.state.borrow_mut().objects.remove(&id);
+                empty
+            }
+            "Runtime.releaseObjectGroup" => empty,
+            "Runtime.runIfWaitingForDebugger"
+            | "Runtime.discardConsoleEntries"
+            | "Runtime.setAsyncCallStackDepth"
+            | "Profiler.enable"
+            | "Profiler.disable"
+            | "HeapProfiler.enable"
+            | "HeapProfiler.disable" => empty,
+            _ if method.starts_with("Debugger.") => self.debugger_call(ctx, method, params)?,
+            "Schema.getDomains" => json!({
+                "domains": [
+                    { "name": "Runtime", "version": "1.3" },
+                    { "name": "Debugger", "version": "1.3" },
+                ],
+            }),
+            _ => bail!("'{method}' wasn't found"),
+        };
+        Ok(result)
+    }
+
+    fn call_function_on(&self, ctx: &js::Context, params: &Json) -> Result<Json> {
+        let declaration = params["functionDeclaration"].as_str().unwrap_or_default();
+        let by_value = params["returnByValue"].as_bool().unwrap_or(false);
+        let this = match params.get("objectId") {
+            Some(_) => self.object(ctx, object_id(params)?)?,
+            None => js::Value::undefined(),
+        };
+        let mut args = vec![];
+        for arg in params["arguments"].as_array().into_iter().flatten() {
+            args.push(if arg.get("objectId").is_some() {
+                self.object(ctx, object_id(arg)?)?
+            } else {
+                let value = arg.get("value").cloned().unwrap_or(Json::Null);
+                json_parse(ctx, &value.to_string())?
+            });
+        }
+        let func = match ctx.eval(&js::Code::Source(&format!("({declaration})"))) {
+            Ok(func) => func,
+            Err(err) => return Ok(exception_result(&err)),
+        };
+        Ok(match func.call(&this, &args) {
+            Ok(value) => json!({ "result": self.to_remote(ctx, &value, by_value) }),
+            Err(err) => exception_result(&err.to_string()),
+        })
+    }
+
+    fn get_properties(&self, ctx: &js::Context, params: &Json) -> Result<Json> {
+        let object = self.object(ctx, object_id(params)?)?;
+        let names: Vec<String> = helper(ctx, "inspector.own_names", "Object.getOwnPropertyNames")?
+            .call(&js::Value::undefined(), &[object.clone()])?
+            .decode()?;
+        let mut result = vec![];
+        for name in names {
+            let descriptor = match object.get_property(&name) {
+                Ok(value) => json!({
+                    "name": name,
+                    "value": self.remote_object(ctx, &value),
+                    "configurable": true,
+                    "enumerable": true,
+                    "writable": true,
+                    "isOwn": true,
+                }),
+                Err(err) => json!({
+                    "name": name,
+                    "value": { "type": "string", "value": err.to_string() },
+                    "wasThrown": true,
+                    "isOwn": true,
+                }),
+            };
+            result.push(descriptor);
+        }
+        Ok(json!({ "result": result }))
+    }
+
+    fn object(&self, ctx: &js::Context, id: u64) -> Result<js::Value> {
+        let state = self.state.borrow();
+        let object = state
+            .objects
+            .get(&id)
+            .context("could not find object with given id")?;
+        Ok(js::Value::new_cloned(ctx, *object.value()))
+    }
+
+    fn to_remote(&self, ctx: &js::Context, value: &js::Value, by_value: bool) -> Json {
+        if by_value {
+            let json = helper(ctx, "inspector.stringify", "JSON.stringify")
+                .and_then(
+                    |stringify| Ok(stringify.call(&js::Value::undefined(), &[value.clone()])?),
+                )
+                .ok()
+                .filter(|json| json.is_string())
+                .and_then(|json| json.decode_string().ok())
+                .and_then(|json| serde_json::from_str::<Json>(&json).ok());
+            if let Some(json) = json {
+                let kind = match json {
+                    Json::String(_) => "string",
+                    Json::Number(_) => "number",
+                    Json::Bool(_) => "boolean",
+                    _ => "object",
+                };
+                return json!({ "type": kind, "value": json });
+            }
+        }
+        self.remote_object(ctx, value)
+    }
+
+    /// Describes a value as a CDP `Runtime.RemoteObject`, objects are retained until released.
+    fn remote_object(&self, ctx: &js::Context, value: &js::Value) -> Json {
+        let description: Vec<String> = helper(ctx, "inspector.describe", DESCRIBE_JS)
+            .and_then(|describe| Ok(describe.call(&js::Value::undefined(), &[value.clone()])?))
+            .and_then(|description| Ok(description.decode()?))
+            .unwrap_or_default();
+        let [kind, subtype, class_name, text] = <[String; 4]>::try_from(description)
+            .unwrap_or_else(|_| ["undefined".into(), "".into(), "".into(), "".into()]);
+        match kind.as_str() {
+            "undefined" => json!({ "type": "undefined" }),
+            "string" => json!({ "type": "string", "value": text }),
+            "boolean" => json!({ "type": "boolean", "value": text == "true" }),
+            "number" => match text.parse::<f64>() {
+                Ok(n) if n.is_finite() && !(n == 0.0 && text.starts_with('-')) => {
+                    json!({ "type": "number", "value": n, "description": text })
+                }
+                _ => json!({ "type": "number", "unserializableValue": text, "description": text }),
+            },
+            "bigint" => {
+                json!({ "type": "bigint", "unserializableValue": format!("{text}n"), "description": format!("{text}n") })
+            }
+            "symbol" => json!({ "type": "symbol", "description": text }),
+            "object" if subtype == "null" => {
+                json!({ "type": "object", "subtype": "null", "value": Json::Null })
+            }
+            _ => {
+                let Some(engine) = js_context_get_runtime(ctx) else {
+                    return json!({ "type": kind, "className": class_name, "description": text });
+                };
+                let id = {
+                    let mut state = self.state.borrow_mut();
+                    state.next_object_id += 1;
+                    let id = state.next_object_id;
+                    state.objects.insert(id, engine.to_owned_value(value));
+                    id
+                };
+                let mut object = json!({
+                    "type": kind,
+                    "className": class_name,
+                    "description": text,
+                    "objectId": id.to_string(),
+                });
+                if !subtype.is_empty() {
+                    object["subtype"] = subtype.into();
+                }
+                object
+            }
+        }
+    }
+}
+
+/// Returns `[type, subtype, className, description]` for a value.
+const DESCRIBE_JS: &str = r#"(function (v) {
+    const type = typeof v;
+    if (v === null) return ["object", "null", "", "null"];
+    if (type !== "object" && type !== "function") return [type, "", "", String(v)];
+    let className = "Object";
+    try { className = (v.constructor && v.constructor.name) || "Object"; } catch (e) {}
+    const subtype = Array.isArray(v) ? "array"
+        : v instanceof Error ? "error"
+        : v instanceof RegExp ? "regexp"
+        : v instanceof Date ? "date"
+        : v instanceof Map ? "map"
+        : v instanceof Set ? "set"
+        : v instanceof Promise ? "promise"
+        : ArrayBuffer.isView(v) ? "typedarray"
+        : "";
+    const description = type === "function" ? String(v)
+        : subtype === "array" ? `Array(${v.length})`
+        : subtype === "error" ? String(v.stack ? `${v}\n${v.stack}` : v)
+        : subtype === "regexp" || subtype === "date" ? String(v)
+        : className;
+    return [type, subtype, className, description];
+})"#;
+
+fn helper(ctx: &js::Context, name: &str, source: &str) -> Result<js::Value> {
+    ctx.get_qjsbind_object(name, || {
+        ctx.eval(&js::Code::Source(source)).map_err(js::Error::msg)
+    })
+    .context("failed to create inspector helper")
+}
+
+fn json_parse(ctx: &js::Context, json: &str) -> Result<js::Value> {
+    let parse = helper(ctx, "inspector.parse", "JSON.parse")?;
+    Ok(parse.call(&js::Value::undefined(), &[ctx.new_string(json)])?)
+}
+
+fn exception_result(err: &str) -> Json {
+    json!({
+        "result": { "type": "object", "subtype": "error", "className": "Error", "description": err },
+        "exceptionDetails": {
+            "exceptionId": 1,
+            "text": err,
+            "lineNumber": 0,
+            "columnNumber": 0,
+        },
+    })
+}
+
+fn object_id(params: &Json) -> Result<u64> {
+    params["objectId"]
+        .as_str()
+        .and_then(|id| id.parse().ok())
+        .context("invalid objectId")
+}
+
+fn stack_trace(frames: &[StackFrame]) -> Json {
+    let call_frames: Vec<_> = frames
+        .iter()
+        .map(|frame| {
+            json!({
+                "functionName": frame.function,
+                "scriptId": frame.url,
+                "url": frame.url,
+                "lineNumber": frame.line.saturating_sub(1),
+                "columnNumber": frame.column.saturating_sub(1),
+            })
+        })
+        .collect();
+    json!({ "callFrames": call_frames })
+}
+
+fn now_ms() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+        * 1000.0
+}
+
+struct Server {
+    port: u16,
+    target_id: String,
+    commands: mpsc::Sender<Command>,
+    notify: Arc<Notify>,
+    connected: Arc<AtomicBool>,
+}
+
+impl Server {
+    fn run(
+        self,
+        listener: std::net::TcpListener,
+        mut events: UnboundedReceiver<String>,
+    ) -> Result<()> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        rt.block_on(async move {
+            let listener = tokio::net::TcpListener::from_std(listener)?;
+            loop {
+                let (stream, addr) = listener.accept().await?;
+                if let Err(err) = self.handle_connection(stream, &mut events).await {
+                    warn!(target: "js::inspector", "connection from {addr} failed: {err:?}");
+                }
+            }
+        })
+    }
+
+    fn send_command(&self, command: Command) -> Result<()> {
+        self.commands
+            .send(command)
+            .context("the JS thread has exited")?;
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    async fn handle_connection(
+        &self,
+        mut stream: tokio::net::TcpStream,
+        events: &mut UnboundedReceiver<String>,
+    ) -> Result<()> {
+        let head = read_request_head(&mut stream).await?;
+        let mut lines = head.lines();
+        let path = lines
+            .next()
+            .and_then(|line| line.split(' ').nth(1))
+            .unwrap_or("/")
+            .to_string();
+        let headers: Vec<(&str, &str)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .collect();
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.to_string())
+        };
+        if !header("host").is_some_and(|host| is_loopback_host(&host)) {
+            stream
+                .write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+            bail!("rejected a request with Host {:?}", header("host"));
+        }
+        let Some(ws_key) = header("sec-websocket-key") else {
+            return self.handle_http(&mut stream, &path).await;
+        };
+        if path.strip_prefix('/') != Some(self.target_id.as_str()) {
+            stream
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+            bail!("rejected a WebSocket handshake on {path}");
+        }
+        if !header("origin").map_or(true, |origin| is_devtools_origin(&origin)) {
+            stream
+                .write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+            bail!("rejected a WebSocket handshake from Origin {:?}", header("origin"));
+        }
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            derive_accept_key(ws_key.as_bytes())
+        );
+        stream.write_all(response.as_bytes()).await?;
+        let ws = WebSocketStream::from_raw_socket(stream.compat(), Role::Server, None).await;
+        let (mut sink, mut source) = ws.split();
+        // Drop what was produced for a previous session.
+        while events.try_recv().is_ok() {}
+        self.connected.store(true, Ordering::Relaxed);
+        self.send_command(Command::Connected)?;
+        let result = async {
+            loop {
+                tokio::select! {
+                    message = source.next() => match message {
+                        Some(Ok(Message::Text(text))) => {
+                            self.send_command(Command::Message(text.to_string()))?;
+                        }
+                        Some(Ok(Message::Close(_))) | None => break,
+                        Some(Ok(_)) => {}
+                        Some(Err(err)) => bail!("failed to receive message: {err}"),
+                    },
+                    event = events.recv() => match event {
+                        Some(event) => sink.send(Message::Text(event.into())).await?,
+                        None => break,
+                    },
+                }
+            }
+            Ok(())
+        }
+        .await;
+        self.connected.store(false, Ordering::Relaxed);
+        self.send_command(Command::Disconnected)?;
+        result
+    }
+
+    /// Serves the discovery endpoints used by `chrome://inspect` and VS Code.
+    async fn handle_http(&self, stream: &mut tokio::net::TcpStream, path: &str) -> Result<()> {
+        let port = self.port;
+        let target_id = &self.target_id;
+        let ws_url = format!("127.0.0.1:{port}/{target_id}");
+        let body = match path.trim_end_matches('/') {
+            "/json/version" => Some(json!({
+                "Browser": format!("wapojs/v{}", env!("CARGO_PKG_VERSION")),
+                "Protocol-Version": "1.3",
+            })),
+            "/json" | "/json/list" => Some(json!([{
+                "description": "wapojs instance",
+                "devtoolsFrontendUrl": format!("devtools://devtools/bundled/js_app.html?experiments=true&v8only=true&ws={ws_url}"),
+                "id": target_id,
+                "title": TARGET_NAME,
+                "type": "node",
+                "url": "file://",
+                "webSocketDebuggerUrl": format!("ws://{ws_url}"),
+            }])),
+            _ => None,
+        };
+        let response = match body {
+            Some(body) => {
+                let body = body.to_string();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                )
+            }
+            None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string(),
+        };
+        stream.write_all(response.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+/// Whether a `Host` header names the loopback interface, with any port.
+fn is_loopback_host(host: &str) -> bool {
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    };
+    ["127.0.0.1", "localhost", "[::1]"]
+        .iter()
+        .any(|loopback| name.eq_ignore_ascii_case(loopback))
+}
+
+/// Whether an `Origin` header is the one of the DevTools frontend bundled with Chrome.
+fn is_devtools_origin(origin: &str) -> bool {
+    ["devtools://devtools", "chrome-devtools://devtools"]
+        .iter()
+        .any(|devtools| origin.eq_ignore_ascii_case(devtools))
+}
+
+/// A random version 4 UUID, used as the target id.
+fn random_uuid() -> String {
+    use rand::RngCore;
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+async fn read_request_head(stream: &mut tokio::net::TcpStream) -> Result<String> {
+    const MAX_HEAD_LEN: usize = 16 * 1024;
+    let mut head = vec![];
+    let mut byte = [0u8; 1];
+    // Read byte-wise up to the end of the head so that no WebSocket frame data is consumed.
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).await? == 0 {
+            bail!("connection closed");
+        }
+        head.push(byte[0]);
+        if head.len() > MAX_HEAD_LEN {
+            bail!("request head too large");
+        }
+    }
+    String::from_utf8(head).context("invalid request head")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback_hosts() {
+        for host in [
+            "127.0.0.1",
+            "127.0.0.1:9229",
+            "localhost:9229",
+            "LOCALHOST",
+            "[::1]:9229",
+        ] {
+            assert!(is_loopback_host(host), "{host}");
+        }
+        for host in [
+            "",
+            "evil.com",
+            "localhost.evil.com:9229",
+            "127.0.0.1.evil.com",
+            "[::1]x",
+        ] {
+            assert!(!is_loopback_host(host), "{host}");
+        }
+    }
+
+    #[test]
+    fn devtools_origins() {
+        assert!(is_devtools_origin("devtools://devtools"));
+        assert!(is_devtools_origin("chrome-devtools://devtools"));
+        assert!(!is_devtools_origin("http://127.0.0.1:9229"));
+        assert!(!is_devtools_origin("https://evil.com"));
+        assert!(!is_devtools_origin("null"));
+    }
+
+    #[test]
+    fn target_ids_are_random_uuids() {
+        let id = random_uuid();
+        assert_eq!(id.len(), 36);
+        assert_eq!(id.as_bytes()[14], b'4');
+        assert_ne!(id, random_uuid());
+    }
+}
@@ -0,0 +1,979 @@
+//! The `Debugger` domain of the inspector.
+//!
+//! QuickJS has no line or opcode hook, so while the inspector is enabled scripts are instrumented
+//! at load time, like for coverage. A probe is inserted before each statement found by the
+//! coverage scanner, `if (__wapo_dbg.step || __wapo_dbg_<n>[<probe>]) __wapo_dbg.hit(<n>, <probe>,
+//! (__wapo_expr) => eval(__wapo_expr));`. The flags of the probes with a breakpoint are set in the
+//! per-script array, and `step` makes every probe call the host while stepping or after a pause
+//! was requested. A pause blocks the JS thread in `hit`, serving the commands of the debugger
+//! until it resumes.
+//!
+//! The arrow passed to `hit` evaluates expressions in the scope of the statement, for
+//! `Debugger.evaluateOnCallFrame`, breakpoint conditions and the scope of the top frame: the
+//! identifiers of the enclosing functions that resolve to something else than a global. Outer
+//! frames only have their location and the global scope. Stepping compares the depth of the
+//! QuickJS backtrace at each probe with the one of the pause.
+//!
+//! Exceptions can not be stopped where they are thrown. With pause on caught exceptions,
+//! execution pauses at the start of the `catch` blocks, and with pause on uncaught exceptions,
+//! when an uncaught error is reported, with the frames of its stack. Braceless bodies of `if`
+//! and loops, `case` clauses and statements continuing a line have no probe, breakpoints set on
+//! them move to the next statement that has one.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{bail, Context, Result};
+use js::ToJsValue;
+use serde_json::{json, Value as Json};
+
+use super::{current, exception_result, helper, Inspector, CONTEXT_ID, TARGET_NAME};
+use crate::devtools::coverage::{file_url, is_word_byte, Scan, KEYWORDS};
+use crate::devtools::{capture_stack, StackFrame};
+use crate::service::{OwnedJsValue, Service};
+
+/// Identifiers that never name a variable of the scope.
+const NON_VARIABLES: &[&str] = &[
+    "this",
+    "true",
+    "false",
+    "null",
+    "undefined",
+    "let",
+    "const",
+    "var",
+    "class",
+    "super",
+    "async",
+    "import",
+    "export",
+    "extends",
+    "break",
+    "continue",
+    "default",
+    "debugger",
+    "arguments",
+    "eval",
+];
+const MAX_SCOPE_NAMES: usize = 1000;
+
+struct Probe {
+    offset: usize,
+    /// 0-based.
+    line: u32,
+    /// 0-based, in characters.
+    column: u32,
+    /// Whether it starts a `catch` block.
+    caught: bool,
+}
+
+struct Script {
+    /// The name the script was evaluated with.
+    url: String,
+    source: String,
+    probes: Vec<Probe>,
+    /// The byte ranges of the function bodies, for the scopes of the probes.
+    functions: Vec<(usize, usize)>,
+    /// `__wapo_dbg_<n>`, a `Uint8Array` flagging the probes that have a breakpoint.
+    flags: OwnedJsValue,
+}
+
+struct Breakpoint {
+    condition: String,
+    /// The `(script, probe)` it resolved to.
+    locations: Vec<(usize, usize)>,
+    /// What the scripts loaded later are matched with, `None` for a breakpoint set by script id.
+    target: Option<Target>,
+}
+
+struct Target {
+    url: Option<String>,
+    url_regex: Option<String>,
+    line: u32,
+    column: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum PauseOn {
+    #[default]
+    None,
+    Caught,
+    Uncaught,
+    All,
+}
+
+#[derive(Clone, Copy)]
+enum Step {
+    /// Pause at the next statement, wherever it is.
+    Pause,
+    Into,
+    /// Pause at the next statement with a stack at most or less than this deep.
+    Over(usize),
+    Out(usize),
+}
+
+struct Paused {
+    /// The arrow of the probe, `None` when paused on an uncaught error.
+    evaluate: Option<OwnedJsValue>,
+    depth: usize,
+    /// Set by the command resuming, with what to do next.
+    resumed: Option<Option<Step>>,
+}
+
+#[derive(Default)]
+pub(super) struct Debugger {
+    scripts: Vec<Script>,
+    /// `__wapo_dbg`, shared by the scripts.
+    control: Option<OwnedJsValue>,
+    enabled: bool,
+    breakpoints: BTreeMap<String, Breakpoint>,
+    next_breakpoint_id: u64,
+    deactivated: bool,
+    skip_all: bool,
+    pause_on: PauseOn,
+    step: Option<Step>,
+    paused: Option<Paused>,
+    /// Set while the debugger runs JS code itself, which must not pause.
+    busy: bool,
+}
+
+impl Debugger {
+    /// Ends the session of a debugger, the scripts are kept for the next one.
+    pub(super) fn detach(&mut self) {
+        self.enabled = false;
+        self.breakpoints.clear();
+        self.deactivated = false;
+        self.skip_all = false;
+        self.pause_on = PauseOn::None;
+        self.step = None;
+        if let Some(paused) = &mut self.paused {
+            paused.resumed = Some(None);
+        }
+    }
+
+    fn probe(&self, script: usize, probe: usize) -> Option<&Probe> {
+        self.scripts.get(script)?.probes.get(probe)
+    }
+}
+
+#[js::host_call(with_context)]
+fn hit(
+    ctx: js::Context,
+    _this: js::Value,
+    script: u32,
+    probe: u32,
+    evaluate: js::Value,
+    exception: Option<js::Value>,
+) {
+    if let Some(inspector) = current() {
+        inspector.hit(&ctx, script as usize, probe as usize, &evaluate, exception);
+    }
+}
+
+impl Inspector {
+    /// Registers a script and returns the probes to insert before its statements.
+    pub(super) fn instrument(
+        &self,
+        service: &Service,
+        url: &str,
+        source: &str,
+        scan: &Scan,
+    ) -> Result<Vec<(usize, String)>> {
+        let ctx = service.context();
+        self.install_control(service)?;
+        let index = self.debugger.borrow().scripts.len();
+        let flags_name = format!("__wapo_dbg_{index}");
+        let flags = ctx
+            .eval(&js::Code::Source(&format!(
+                "(() => {{ const flags = new Uint8Array({}); \
+                 Object.defineProperty(globalThis, '{flags_name}', {{ value: flags }}); \
+                 return flags; }})()",
+                scan.statements.len()
+            )))
+            .map_err(js::Error::msg)
+            .context("failed to install the breakpoint flags")?;
+        let line_starts: Vec<usize> = core::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let mut probes = vec![];
+        let mut insertions = vec![];
+        for (id, statement) in scan.statements.iter().enumerate() {
+            let line = line_starts.partition_point(|&start| start <= statement.offset) - 1;
+            let column = source[line_starts[line]..statement.offset].chars().count();
+            let (condition, exception) = match &statement.caught {
+                Some(caught) => (" || __wapo_dbg.caught", format!(", {caught}")),
+                None => ("", String::new()),
+            };
+            insertions.push((
+                statement.offset,
+                format!(
+                    "if (__wapo_dbg.step{condition} || {flags_name}[{id}]) __wapo_dbg.hit({index}, \
+                     {id}, (__wapo_expr) => eval(__wapo_expr){exception});"
+                ),
+            ));
+            probes.push(Probe {
+                offset: statement.offset,
+                line: line as u32,
+                column: column as u32,
+                caught: statement.caught.is_some(),
+            });
+        }
+        let functions = scan
+            .blocks
+            .iter()
+            .filter(|block| block.function.is_some())
+            .map(|block| (block.start, block.end))
+            .collect();
+        self.debugger.borrow_mut().scripts.push(Script {
+            url: url.to_string(),
+            source: source.to_string(),
+            probes,
+            functions,
+            flags: service.to_owned_value(&flags),
+        });
+        if self.debugger.borrow().enabled {
+            self.script_parsed(index);
+            self.resolve_pending(ctx, index)?;
+        }
+        Ok(insertions)
+    }
+
+    fn install_control(&self, service: &Service) -> Result<()> {
+        if self.debugger.borrow().control.is_some() {
+            return Ok(());
+        }
+        let ctx = service.context();
+        let control = ctx.new_object("Debugger");
+        control.define_property_fn("hit", hit)?;
+        control.set_property("step", &0i32.to_js_value(ctx)?)?;
+        control.set_property("caught", &0i32.to_js_value(ctx)?)?;
+        ctx.eval(&js::Code::Source(
+            "(control) => Object.defineProperty(globalThis, '__wapo_dbg', { value: control })",
+        ))
+        .map_err(js::Error::msg)?
+        .call(&js::Value::undefined(), &[control.clone()])
+        .context("failed to install the debugger probes")?;
+        self.debugger.borrow_mut().control = Some(service.to_owned_value(&control));
+        Ok(())
+    }
+
+    /// Updates the flags read by all the probes.
+    fn sync_control(&self, ctx: &js::Context) -> Result<()> {
+        let debugger = self.debugger.borrow();
+        let Some(control) = &debugger.control else {
+            return Ok(());
+        };
+        let control = js::Value::new_cloned(ctx, *control.value());
+        let step = i32::from(debugger.step.is_some());
+        let caught = i32::from(matches!(debugger.pause_on, PauseOn::Caught | PauseOn::All));
+        control.set_property("step", &step.to_js_value(ctx)?)?;
+        control.set_property("caught", &caught.to_js_value(ctx)?)?;
+        Ok(())
+    }
+
+    /// Updates the flag of a probe after its breakpoints changed.
+    fn sync_flag(&self, ctx: &js::Context, script: usize, probe: usize) -> Result<()> {
+        let debugger = self.debugger.borrow();
+        let Some(script_data) = debugger.scripts.get(script) else {
+            return Ok(());
+        };
+        let set = debugger
+            .breakpoints
+            .values()
+            .any(|bp| bp.locations.contains(&(script, probe)));
+        let flags = js::Value::new_cloned(ctx, *script_data.flags.value());
+        flags.set_property(&probe.to_string(), &i32::from(set).to_js_value(ctx)?)?;
+        Ok(())
+    }
+
+    fn hit(
+        &self,
+        ctx: &js::Context,
+        script: usize,
+        probe: usize,
+        evaluate: &js::Value,
+        exception: Option<js::Value>,
+    ) {
+        if !self.is_connected() {
+            return;
+        }
+        let (breakpoints, caught, step) = {
+            let debugger = self.debugger.borrow();
+            if !debugger.enabled || debugger.skip_all || debugger.paused.is_some() || debugger.busy
+            {
+                return;
+            }
+            let Some(probe_data) = debugger.probe(script, probe) else {
+                return;
+            };
+            let breakpoints: Vec<(String, String)> = debugger
+                .breakpoints
+                .iter()
+                .filter(|(_, bp)| !debugger.deactivated && bp.locations.contains(&(script, probe)))
+                .map(|(id, bp)| (id.clone(), bp.condition.clone()))
+                .collect();
+            let caught =
+                probe_data.caught && matches!(debugger.pause_on, PauseOn::Caught | PauseOn::All);
+            (breakpoints, caught, debugger.step)
+        };
+        let hit_breakpoints: Vec<String> = breakpoints
+            .into_iter()
+            .filter(|(_, condition)| {
+                condition.is_empty() || self.is_truthy(ctx, evaluate, condition)
+            })
+            .map(|(id, _)| id)
+            .collect();
+        let stack = capture_stack(ctx);
+        let depth = stack.len();
+        let stepped = match step {
+            None => false,
+            Some(Step::Pause | Step::Into) => true,
+            Some(Step::Over(max)) => depth <= max,
+            Some(Step::Out(max)) => depth < max,
+        };
+        if hit_breakpoints.is_empty() && !stepped && !caught {
+            return;
+        }
+        let top = self.top_frame(ctx, script, probe, evaluate, stack.first());
+        let mut frames = vec![top];
+        frames.extend(self.outer_frames(ctx, stack.iter().skip(1), 1));
+        let (reason, data) = match exception {
+            Some(exception) if caught => ("exception", Some(self.remote_object(ctx, &exception))),
+            _ if caught => ("exception", None),
+            _ => ("other", None),
+        };
+        let engine = crate::service::js_context_get_runtime(ctx);
+        let evaluate = engine.map(|engine| engine.to_owned_value(evaluate));
+        self.pause(ctx, frames, evaluate, depth, reason, data, hit_breakpoints);
+    }
+
+    /// Pauses on an uncaught error if the debugger asked for it.
+    pub(super) fn pause_on_uncaught(&self, ctx: &js::Context, message: &str) {
+        {
+            let debugger = self.debugger.borrow();
+            let wanted = matches!(debugger.pause_on, PauseOn::Uncaught | PauseOn::All);
+            if !debugger.enabled || !wanted || debugger.skip_all || debugger.paused.is_some() {
+                return;
+            }
+        }
+        let stack = crate::devtools::parse_stack(message);
+        let frames = self.outer_frames(ctx, stack.iter(), 0);
+        let data = json!({
+            "type": "object",
+            "subtype": "error",
+            "className": "Error",
+            "description": message,
+        });
+        self.pause(ctx, frames, None, 0, "exception", Some(data), vec![]);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn pause(
+        &self,
+        ctx: &js::Context,
+        frames: Vec<Json>,
+        evaluate: Option<OwnedJsValue>,
+        depth: usize,
+        reason: &str,
+        data: Option<Json>,
+        hit_breakpoints: Vec<String>,
+    ) {
+        {
+            let mut debugger = self.debugger.borrow_mut();
+            debugger.step = None;
+            debugger.paused = Some(Paused {
+                evaluate,
+                depth,
+                resumed: None,
+            });
+        }
+        let _ = self.sync_control(ctx);
+        let mut params = json!({
+            "callFrames": frames,
+            "reason": reason,
+            "hitBreakpoints": hit_breakpoints,
+        });
+        if let Some(data) = data {
+            params["data"] = data;
+        }
+        self.send_event("Debugger.paused", params);
+        // The commands are served here until the debugger resumes or goes away.
+        loop {
+            let resumed = match &self.debugger.borrow().paused {
+                Some(paused) => paused.resumed.is_some(),
+                None => true,
+            };
+            if resumed {
+                break;
+            }
+            match self.commands.recv() {
+                Ok(command) => self.handle_command(ctx, command),
+                Err(_) => break,
+            }
+        }
+        let paused = self.debugger.borrow_mut().paused.take();
+        self.debugger.borrow_mut().step = paused.and_then(|paused| paused.resumed).flatten();
+        let _ = self.sync_control(ctx);
+        self.send_event("Debugger.resumed", json!({}));
+    }
+
+    fn is_truthy(&self, ctx: &js::Context, evaluate: &js::Value, expression: &str) -> bool {
+        self.debugger.borrow_mut().busy = true;
+        let result = evaluate
+            .call(&js::Value::undefined(), &[ctx.new_string(expression)])
+            .map_err(anyhow::Error::from)
+            .and_then(|value| {
+                let boolean = helper(ctx, "inspector.boolean", "Boolean")?;
+                Ok(boolean
+                    .call(&js::Value::undefined(), &[value])?
+                    .decode::<bool>()?)
+            });
+        self.debugger.borrow_mut().busy = false;
+        // A condition that throws does not pause.
+        result.unwrap_or(false)
+    }
+
+    fn top_frame(
+        &self,
+        ctx: &js::Context,
+        script: usize,
+        probe: usize,
+        evaluate: &js::Value,
+        frame: Option<&StackFrame>,
+    ) -> Json {
+        let (location, names, in_function) = {
+            let debugger = self.debugger.borrow();
+            let script_data = &debugger.scripts[script];
+            let probe_data = &script_data.probes[probe];
+            let (names, in_function) = scope_names(script_data, probe_data.offset);
+            (location(script, probe_data), names, in_function)
+        };
+        self.debugger.borrow_mut().busy = true;
+        let scope = helper(ctx, "inspector.scope", SCOPE_JS).and_then(|collect| {
+            let names = names.to_js_value(ctx)?;
+            Ok(collect.call(&js::Value::undefined(), &[evaluate.clone(), names])?)
+        });
+        let this = evaluate.call(&js::Value::undefined(), &[ctx.new_string("this")]);
+        self.debugger.borrow_mut().busy = false;
+        let mut scope_chain = vec![];
+        if let Ok(scope) = scope {
+            scope_chain.push(json!({
+                "type": if in_function { "local" } else { "script" },
+                "object": self.remote_object(ctx, &scope),
+            }));
+        }
+        scope_chain.push(self.global_scope(ctx));
+        let this = match this {
+            Ok(this) => self.remote_object(ctx, &this),
+            Err(_) => json!({ "type": "undefined" }),
+        };
+        json!({
+            "callFrameId": "0",
+            "functionName": frame.map(|frame| frame.function.as_str()).unwrap_or_default(),
+            "location": location,
+            "url": self.script_url(script),
+            "scopeChain": scope_chain,
+            "this": this,
+        })
+    }
+
+    /// The frames of a stack with only their location and the global scope.
+    fn outer_frames<'a>(
+        &self,
+        ctx: &js::Context,
+        frames: impl Iterator<Item = &'a StackFrame>,
+        first_id: usize,
+    ) -> Vec<Json> {
+        let mut result = vec![];
+        for (id, frame) in frames.enumerate() {
+            let script = self
+                .debugger
+                .borrow()
+                .scripts
+                .iter()
+                .position(|script| script.url == frame.url);
+            let line = frame.line.saturating_sub(1);
+            let column =
+                match crate::source_map::original_column(&frame.url, frame.line, frame.column) {
+                    Some(column) => column,
+                    None => frame.column,
+                };
+            let (script_id, url) = match script {
+                Some(script) => (script.to_string(), self.script_url(script)),
+                None => (frame.url.clone(), frame.url.clone()),
+            };
+            result.push(json!({
+                "callFrameId": (first_id + id).to_string(),
+                "functionName": frame.function,
+                "location": {
+                    "scriptId": script_id,
+                    "lineNumber": line,
+                    "columnNumber": column.saturating_sub(1),
+                },
+                "url": url,
+                "scopeChain": [self.global_scope(ctx)],
+                "this": { "type": "undefined" },
+            }));
+        }
+        result
+    }
+
+    fn global_scope(&self, ctx: &js::Context) -> Json {
+        json!({
+            "type": "global",
+            "object": self.remote_object(ctx, &ctx.get_global_object()),
+        })
+    }
+
+    fn script_url(&self, script: usize) -> String {
+        let debugger = self.debugger.borrow();
+        debugger
+            .scripts
+            .get(script)
+            .map(|script| file_url(&script.url))
+            .unwrap_or_default()
+    }
+
+    /// Sends `Debugger.scriptParsed` for a script.
+    fn script_parsed(&self, script: usize) {
+        let params = {
+            let debugger = self.debugger.borrow();
+            let source = &debugger.scripts[script].source;
+            let last_line = source.rsplit('\n').next().unwrap_or_default();
+            json!({
+                "scriptId": script.to_string(),
+                "url": file_url(&debugger.scripts[script].url),
+                "startLine": 0,
+                "startColumn": 0,
+                "endLine": source.matches('\n').count(),
+                "endColumn": last_line.chars().count(),
+                "executionContextId": CONTEXT_ID,
+                "hash": "",
+                "length": source.chars().count(),
+            })
+        };
+        self.send_event("Debugger.scriptParsed", params);
+    }
+
+    /// Resolves the breakpoints set by URL in a script loaded after them.
+    fn resolve_pending(&self, ctx: &js::Context, script: usize) -> Result<()> {
+        let targets: Vec<(String, u32, u32, bool)> = {
+            let debugger = self.debugger.borrow();
+            let mut targets = vec![];
+            for (id, bp) in &debugger.breakpoints {
+                if let Some(target) = &bp.target {
+                    let matches = self.target_matches(ctx, target, &debugger.scripts[script].url);
+                    targets.push((id.clone(), target.line, target.column, matches));
+                }
+            }
+            targets
+        };
+        for (id, line, column, matches) in targets {
+            if !matches {
+                continue;
+            }
+            let Some(probe) = self.resolve(script, line, column) else {
+                continue;
+            };
+            if let Some(bp) = self.debugger.borrow_mut().breakpoints.get_mut(&id) {
+                bp.locations.push((script, probe));
+            }
+            self.sync_flag(ctx, script, probe)?;
+            let location = {
+                let debugger = self.debugger.borrow();
+                location(script, &debugger.scripts[script].probes[probe])
+            };
+            self.send_event(
+                "Debugger.breakpointResolved",
+                json!({ "breakpointId": id, "location": location }),
+            );
+        }
+        Ok(())
+    }
+
+    fn target_matches(&self, ctx: &js::Context, target: &Target, url: &str) -> bool {
+        let file_url = file_url(url);
+        if let Some(target_url) = &target.url {
+            return *target_url == file_url || target_url == url;
+        }
+        let Some(pattern) = &target.url_regex else {
+            return false;
+        };
+        helper(ctx, "inspector.url_matches", URL_MATCHES_JS)
+            .and_then(|matches| {
+                Ok(matches
+                    .call(
+                        &js::Value::undefined(),
+                        &[ctx.new_string(pattern), ctx.new_string(&file_url)],
+                    )?
+                    .decode::<bool>()?)
+            })
+            .unwrap_or(false)
+    }
+
+    /// The first probe at or after a position.
+    fn resolve(&self, script: usize, line: u32, column: u32) -> Option<usize> {
+        let debugger = self.debugger.borrow();
+        debugger
+            .scripts
+            .get(script)?
+            .probes
+            .iter()
+            .position(|probe| (probe.line, probe.column) >= (line, column))
+    }
+
+    fn add_breakpoint(
+        &self,
+        ctx: &js::Context,
+        id: String,
+        condition: String,
+        locations: Vec<(usize, usize)>,
+        target: Option<Target>,
+    ) -> Result<Vec<Json>> {
+        self.debugger.borrow_mut().breakpoints.insert(
+            id,
+            Breakpoint {
+                condition,
+                locations: locations.clone(),
+                target,
+            },
+        );
+        let mut result = vec![];
+        for (script, probe) in locations {
+            self.sync_flag(ctx, script, probe)?;
+            let debugger = self.debugger.borrow();
+            result.push(location(script, &debugger.scripts[script].probes[probe]));
+        }
+        Ok(result)
+    }
+
+    fn next_breakpoint_id(&self, line: u32, column: u32, target: &str) -> String {
+        let mut debugger = self.debugger.borrow_mut();
+        debugger.next_breakpoint_id += 1;
+        format!("{}:{line}:{column}:{target}", debugger.next_breakpoint_id)
+    }
+
+    fn resume(&self, step: Option<Step>) -> Result<()> {
+        let mut debugger = self.debugger.borrow_mut();
+        let Some(paused) = &mut debugger.paused else {
+            bail!("can only perform operation while paused");
+        };
+        paused.resumed = Some(step);
+        Ok(())
+    }
+
+    pub(super) fn debugger_call(
+        &self,
+        ctx: &js::Context,
+        method: &str,
+        params: &Json,
+    ) -> Result<Json> {
+        let empty = json!({});
+        let result = match method {
+            "Debugger.enable" => {
+                self.debugger.borrow_mut().enabled = true;
+                json!({ "debuggerId": TARGET_NAME })
+            }
+            "Debugger.disable" => {
+                self.debugger.borrow_mut().detach();
+                self.clear_flags(ctx)?;
+                empty
+            }
+            "Debugger.setAsyncCallStackDepth" | "Debugger.setBlackboxPatterns" => empty,
+            "Debugger.setBreakpointsActive" => {
+                let active = params["active"].as_bool().unwrap_or(true);
+                self.debugger.borrow_mut().deactivated = !active;
+                empty
+            }
+            "Debugger.setSkipAllPauses" => {
+                self.debugger.borrow_mut().skip_all = params["skip"].as_bool().unwrap_or(false);
+                empty
+            }
+            "Debugger.setPauseOnExceptions" => {
+                self.debugger.borrow_mut().pause_on = match params["state"].as_str() {
+                    Some("none") => PauseOn::None,
+                    Some("caught") => PauseOn::Caught,
+                    Some("uncaught") => PauseOn::Uncaught,
+                    Some("all") => PauseOn::All,
+                    state => bail!("invalid pause on exceptions state {state:?}"),
+                };
+                self.sync_control(ctx)?;
+                empty
+            }
+            "Debugger.getScriptSource" => {
+                let script = script_id(&params["scriptId"])?;
+                let debugger = self.debugger.borrow();
+                let script = debugger.scripts.get(script).context("no script for id")?;
+                json!({ "scriptSource": script.source })
+            }
+            "Debugger.getPossibleBreakpoints" => {
+                let start = &params["start"];
+                let script = script_id(&start["scriptId"])?;
+                let from = position(start);
+                let to = match params.get("end") {
+                    Some(end) => position(end),
+                    None => (u32::MAX, u32::MAX),
+                };
+                let debugger = self.debugger.borrow();
+                let script_data = debugger.scripts.get(script).context("no script for id")?;
+                let locations: Vec<_> = script_data
+                    .probes
+                    .iter()
+                    .filter(|probe| (from..to).contains(&(probe.line, probe.column)))
+                    .map(|probe| location(script, probe))
+                    .collect();
+                json!({ "locations": locations })
+            }
+            "Debugger.setBreakpointByUrl" => {
+                let line = params["lineNumber"]
+                    .as_u64()
+                    .context("missing lineNumber")? as u32;
+                let column = params["columnNumber"].as_u64().unwrap_or(0) as u32;
+                let target = Target {
+                    url: params["url"].as_str().map(Into::into),
+                    url_regex: params["urlRegex"].as_str().map(Into::into),
+                    line,
+                    column,
+                };
+                if target.url.is_none() && target.url_regex.is_none() {
+                    bail!("either url or urlRegex must be specified");
+                }
+                let mut locations = vec![];
+                let count = self.debugger.borrow().scripts.len();
+                for script in 0..count {
+                    let url = self.debugger.borrow().scripts[script].url.clone();
+                    if !self.target_matches(ctx, &target, &url) {
+                        continue;
+                    }
+                    if let Some(probe) = self.resolve(script, line, column) {
+                        locations.push((script, probe));
+                    }
+                }
+                let name = target.url.clone().or(target.url_regex.clone());
+                let id = self.next_breakpoint_id(line, column, &name.unwrap_or_default());
+                let condition = params["condition"].as_str().unwrap_or_default().into();
+                let locations =
+                    self.add_breakpoint(ctx, id.clone(), condition, locations, Some(target))?;
+                json!({ "breakpointId": id, "locations": locations })
+            }
+            "Debugger.setBreakpoint" => {
+                let location = &params["location"];
+                let script = script_id(&location["scriptId"])?;
+                let (line, column) = position(location);
+                let probe = self
+                    .resolve(script, line, column)
+                    .context("could not resolve breakpoint")?;
+                let id = self.next_breakpoint_id(line, column, &script.to_string());
+                let condition = params["condition"].as_str().unwrap_or_default().into();
+                let locations =
+                    self.add_breakpoint(ctx, id.clone(), condition, vec![(script, probe)], None)?;
+                json!({ "breakpointId": id, "actualLocation": locations[0] })
+            }
+            "Debugger.removeBreakpoint" => {
+                let id = params["breakpointId"].as_str().unwrap_or_default();
+                let removed = self.debugger.borrow_mut().breakpoints.remove(id);
+                for (script, probe) in removed.map(|bp| bp.locations).unwrap_or_default() {
+                    self.sync_flag(ctx, script, probe)?;
+                }
+                empty
+            }
+            "Debugger.pause" => {
+                if self.debugger.borrow().paused.is_none() {
+                    self.debugger.borrow_mut().step = Some(Step::Pause);
+                    self.sync_control(ctx)?;
+                }
+                empty
+            }
+            "Debugger.resume" => {
+                self.resume(None)?;
+                empty
+            }
+            "Debugger.stepInto" => {
+                self.resume(Some(Step::Into))?;
+                empty
+            }
+            "Debugger.stepOver" | "Debugger.stepOut" => {
+                let depth = self
+                    .debugger
+                    .borrow()
+                    .paused
+                    .as_ref()
+                    .map(|paused| paused.depth)
+                    .unwrap_or_default();
+                // Paused on an uncaught error, there is no frame left to step over or out of.
+                let step = match method {
+                    _ if depth == 0 => Step::Into,
+                    "Debugger.stepOver" => Step::Over(depth),
+                    _ => Step::Out(depth),
+                };
+                self.resume(Some(step))?;
+                empty
+            }
+            "Debugger.evaluateOnCallFrame" => {
+                let expression = params["expression"].as_str().unwrap_or_default();
+                let by_value = params["returnByValue"].as_bool().unwrap_or(false);
+                let evaluate = {
+                    let debugger = self.debugger.borrow();
+                    let paused = debugger.paused.as_ref().context("not paused")?;
+                    paused
+                        .evaluate
+                        .as_ref()
+                        .filter(|_| params["callFrameId"].as_str() == Some("0"))
+                        .map(|evaluate| js::Value::new_cloned(ctx, *evaluate.value()))
+                };
+                let result = match evaluate {
+                    Some(evaluate) => evaluate
+                        .call(&js::Value::undefined(), &[ctx.new_string(expression)])
+                        .map_err(|err| err.to_string()),
+                    None => ctx.eval(&js::Code::Source(expression)),
+                };
+                match result {
+                    Ok(value) => json!({ "result": self.to_remote(ctx, &value, by_value) }),
+                    Err(err) => exception_result(&err),
+                }
+            }
+            _ => bail!("'{method}' wasn't found"),
+        };
+        Ok(result)
+    }
+
+    /// Announces the scripts loaded so far to a debugger that was just enabled.
+    pub(super) fn scripts_parsed(&self) {
+        let count = self.debugger.borrow().scripts.len();
+        for script in 0..count {
+            self.script_parsed(script);
+        }
+    }
+
+    /// Clears the breakpoint flags of all the scripts, after the breakpoints were dropped.
+    pub(super) fn clear_flags(&self, ctx: &js::Context) -> Result<()> {
+        let count = self.debugger.borrow().scripts.len();
+        for script in 0..count {
+            let flags = {
+                let debugger = self.debugger.borrow();
+                js::Value::new_cloned(ctx, *debugger.scripts[script].flags.value())
+            };
+            helper(ctx, "inspector.clear_flags", "(flags) => flags.fill(0)")?
+                .call(&js::Value::undefined(), &[flags])?;
+        }
+        self.sync_control(ctx)
+    }
+}
+
+fn location(script: usize, probe: &Probe) -> Json {
+    json!({
+        "scriptId": script.to_string(),
+        "lineNumber": probe.line,
+        "columnNumber": probe.column,
+    })
+}
+
+fn position(location: &Json) -> (u32, u32) {
+    let line = location["lineNumber"].as_u64().unwrap_or(0) as u32;
+    let column = location["columnNumber"].as_u64().unwrap_or(0) as u32;
+    (line, column)
+}
+
+fn script_id(id: &Json) -> Result<usize> {
+    id.as_str()
+        .and_then(|id| id.parse().ok())
+        .context("invalid scriptId")
+}
+
+/// The identifiers that may name a variable in the scope of an offset: the ones of the outermost
+/// function enclosing it, from the start of the line of its body to include the parameters, or of
+/// the whole script at the top level. Returns whether the offset is in a function.
+fn scope_names(script: &Script, offset: usize) -> (Vec<String>, bool) {
+    let outermost = script
+        .functions
+        .iter()
+        .filter(|(start, end)| *start < offset && offset < *end)
+        .min_by_key(|(start, _)| *start);
+    let (start, end) = match outermost {
+        Some(&(start, end)) => {
+            let line_start = script.source[..start].rfind('\n').map_or(0, |i| i + 1);
+            (line_start, end)
+        }
+        None => (0, script.source.len()),
+    };
+    (identifiers(&script.source[start..end]), outermost.is_some())
+}
+
+/// The identifiers of a piece of source, except property names after a `.` and keywords.
+fn identifiers(source: &str) -> Vec<String> {
+    let bytes = source.as_bytes();
+    let mut names = BTreeSet::new();
+    let mut i = 0;
+    while i < bytes.len() && names.len() < MAX_SCOPE_NAMES {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = source[i..].find('\n').map_or(bytes.len(), |n| i + n);
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = source[i + 2..]
+                    .find("*/")
+                    .map_or(bytes.len(), |n| i + n + 4);
+            }
+            quote @ (b'\'' | b'"' | b'`') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i += 1;
+            }
+            b'0'..=b'9' => {
+                while i < bytes.len() && is_word_byte(bytes[i]) {
+                    i += 1;
+                }
+            }
+            b if is_word_byte(b) => {
+                let start = i;
+                while i < bytes.len() && is_word_byte(bytes[i]) {
+                    i += 1;
+                }
+                let word = &source[start..i];
+                let before = source[..start].trim_end();
+                let is_property = before.ends_with('.') && !before.ends_with("...");
+                if !is_property
+                    && !word.starts_with("__wapo_")
+                    && !KEYWORDS.contains(&word)
+                    && !NON_VARIABLES.contains(&word)
+                {
+                    names.insert(word.to_string());
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    names.into_iter().collect()
+}
+
+/// Collects the variables among `names` that resolve to something else than a global.
+const SCOPE_JS: &str = r#"(function (evaluate, names) {
+    const scope = {};
+    for (const name of names) {
+        const global = Object.getOwnPropertyDescriptor(globalThis, name);
+        if (global && !("value" in global)) continue;
+        let value;
+        try { value = evaluate(name); } catch (e) { continue; }
+        if (global && global.value === value) continue;
+        scope[name] = value;
+    }
+    return scope;
+})"#;
+
+const URL_MATCHES_JS: &str = r#"(function (pattern, url) {
+    try { return new RegExp(pattern).test(url); } catch (e) { return false; }
+})"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_identifiers() {
+        let names = identifiers(
+            "function f(a, ...rest) { const b = a.c + 'd e' + `${x}`; // y\n return g(b, 1e3); }",
+        );
+        assert_eq!(names, ["a", "b", "f", "g", "rest"]);
+    }
+}
@@ -0,0 +1,306 @@
+//! Sampling CPU profiler enabled by `--cpu-prof <file>`.
+//!
+//! The JS call stack is sampled from the interrupt handler at most once per sampling interval.
+//! The profile is written when the runtime exits, as a Chrome `.cpuprofile` if the file name
+//! has that extension, or as collapsed stacks (`a;b;c <count>`) for flamegraph tools otherwise.
+//!
+//! Frames are aggregated by function, identified by its name and script. The stack traces of
+//! QuickJS only tell the line being executed, not where the function starts: functions of a
+//! script sharing a name share a node, whose position is the first line it was sampled at. The
+//! lines a function was sampled at are reported as the `positionTicks` of the cpuprofile.
+
+use alloc::collections::BTreeMap;
+use core::cell::RefCell;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use serde_json::json;
+
+use super::{add_interrupt_hook, capture_stack, StackFrame};
+use crate::service::Service;
+
+pub(crate) const DEFAULT_INTERVAL: Duration = Duration::from_millis(1);
+
+struct Profiler {
+    path: String,
+    interval: Duration,
+    started_at: Instant,
+    started_at_unix_us: u64,
+    last_sample: Instant,
+    /// Stacks are stored innermost frame first, as captured.
+    samples: Vec<(Instant, Vec<StackFrame>)>,
+}
+
+thread_local! {
+    static PROFILER: RefCell<Option<Profiler>> = const { RefCell::new(None) };
+}
+
+/// Starts sampling the service, the profile is written to `path` by [`finish`].
+pub(crate) fn start(service: &Service, path: String, interval: Duration) -> Result<()> {
+    let started = PROFILER.with(|profiler| profiler.borrow().is_some());
+    if started {
+        bail!("the profiler has already been started");
+    }
+    let now = Instant::now();
+    let started_at_unix_us = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64;
+    PROFILER.with(|profiler| {
+        *profiler.borrow_mut() = Some(Profiler {
+            path,
+            interval,
+            started_at: now,
+            started_at_unix_us,
+            last_sample: now,
+            samples: vec![],
+        })
+    });
+    add_interrupt_hook(service, |service| {
+        let due = PROFILER.with(|profiler| {
+            let profiler = profiler.borrow();
+            matches!(&*profiler, Some(p) if p.last_sample.elapsed() >= p.interval)
+        });
+        if !due {
+            return;
+        }
+        let stack = capture_stack(service.context());
+        let now = Instant::now();
+        PROFILER.with(|profiler| {
+            if let Some(profiler) = profiler.borrow_mut().as_mut() {
+                profiler.last_sample = now;
+                if !stack.is_empty() {
+                    profiler.samples.push((now, stack));
+                }
+            }
+        });
+    });
+    info!(target: "js::profiler", "CPU profiling enabled");
+    Ok(())
+}
+
+/// Stops the profiler and writes the profile, does nothing if it was not started.
+pub(crate) fn finish() -> Result<()> {
+    let Some(profiler) = PROFILER.with(|profiler| profiler.borrow_mut().take()) else {
+        return Ok(());
+    };
+    let output = if profiler.path.ends_with(".cpuprofile") {
+        profiler.to_cpuprofile()
+    } else {
+        profiler.to_collapsed()
+    };
+    std::fs::write(&profiler.path, output)
+        .with_context(|| format!("failed to write CPU profile to {}", profiler.path))?;
+    info!(
+        target: "js::profiler",
+        "{} samples written to {}",
+        profiler.samples.len(),
+        profiler.path
+    );
+    Ok(())
+}
+
+fn frame_name(frame: &StackFrame) -> String {
+    if frame.url.is_empty() {
+        frame.function.clone()
+    } else {
+        format!("{} ({})", frame.function, frame.url)
+    }
+}
+
+impl Profiler {
+    fn to_collapsed(&self) -> String {
+        let mut counts = BTreeMap::<String, u64>::new();
+        for (_, stack) in &self.samples {
+            let key = stack
+                .iter()
+                .rev()
+                .map(frame_name)
+                .collect::<Vec<_>>()
+                .join(";");
+            *counts.entry(key).or_default() += 1;
+        }
+        counts
+            .into_iter()
+            .map(|(stack, count)| format!("{stack} {count}\n"))
+            .collect()
+    }
+
+    fn to_cpuprofile(&self) -> String {
+        struct Node<'a> {
+            /// The frame sampled at the lowest line of the function.
+            frame: Option<&'a StackFrame>,
+            children: Vec<usize>,
+            hit_count: u64,
+            /// The samples with the function on top, by line.
+            line_ticks: BTreeMap<u32, u64>,
+        }
+        let mut nodes = vec![Node {
+            frame: None,
+            children: vec![],
+            hit_count: 0,
+            line_ticks: BTreeMap::new(),
+        }];
+        let mut index = BTreeMap::<(usize, &str, &str), usize>::new();
+        let mut samples = vec![];
+        let mut time_deltas = vec![];
+        let mut last = self.started_at;
+        for (at, stack) in &self.samples {
+            let mut parent = 0;
+            for frame in stack.iter().rev() {
+                let key = (parent, frame.function.as_str(), frame.url.as_str());
+                parent = *index.entry(key).or_insert_with(|| {
+                    nodes.push(Node {
+                        frame: Some(frame),
+                        children: vec![],
+                        hit_count: 0,
+                        line_ticks: BTreeMap::new(),
+                    });
+                    let id = nodes.len() - 1;
+                    nodes[parent].children.push(id);
+                    id
+                });
+                let node = &mut nodes[parent];
+                if node.frame.is_some_and(|first| frame.line < first.line) {
+                    node.frame = Some(frame);
+                }
+            }
+            let node = &mut nodes[parent];
+            node.hit_count += 1;
+            if let Some(frame) = stack.first().filter(|frame| frame.line > 0) {
+                *node.line_ticks.entry(frame.line).or_default() += 1;
+            }
+            samples.push(parent + 1);
+            time_deltas.push(at.duration_since(last).as_micros() as u64);
+            last = *at;
+        }
+        // Node ids are 1-based, the root is node 1.
+        let nodes: Vec<_> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let call_frame = match node.frame {
+                    Some(frame) => json!({
+                        "functionName": frame.function,
+                        "scriptId": "0",
+                        "url": frame.url,
+                        "lineNumber": frame.line as i64 - 1,
+                        "columnNumber": frame.column as i64 - 1,
+                    }),
+                    None => json!({
+                        "functionName": "(root)",
+                        "scriptId": "0",
+                        "url": "",
+                        "lineNumber": -1,
+                        "columnNumber": -1,
+                    }),
+                };
+                let position_ticks: Vec<_> = node
+                    .line_ticks
+                    .iter()
+                    .map(|(line, ticks)| json!({ "line": line, "ticks": ticks }))
+                    .collect();
+                json!({
+                    "id": i + 1,
+                    "callFrame": call_frame,
+                    "hitCount": node.hit_count,
+                    "children": node.children.iter().map(|c| c + 1).collect::<Vec<_>>(),
+                    "positionTicks": position_ticks,
+                })
+            })
+            .collect();
+        let start_time = self.started_at_unix_us;
+        let end_time = start_time + last.duration_since(self.started_at).as_micros() as u64;
+        json!({
+            "nodes": nodes,
+            "startTime": start_time,
+            "endTime": end_time,
+            "samples": samples,
+            "timeDeltas": time_deltas,
+        })
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(function: &str, line: u32) -> StackFrame {
+        StackFrame {
+            function: function.into(),
+            url: "main.js".into(),
+            line,
+            column: 5,
+        }
+    }
+
+    /// Samples of `main` calling `a` twice, at two of its lines, and then `b`, innermost frame
+    /// first as captured.
+    fn profiler() -> Profiler {
+        let started_at = Instant::now();
+        let at = |us| started_at + Duration::from_micros(us);
+        let main = frame("main", 10);
+        let a = frame("a", 3);
+        let a_earlier_line = frame("a", 2);
+        let b = frame("b", 6);
+        Profiler {
+            path: "profile.cpuprofile".into(),
+            interval: DEFAULT_INTERVAL,
+            started_at,
+            started_at_unix_us: 1_000_000,
+            last_sample: at(3500),
+            samples: vec![
+                (at(1000), vec![a, main.clone()]),
+                (at(2000), vec![a_earlier_line, main.clone()]),
+                (at(3500), vec![b, main]),
+            ],
+        }
+    }
+
+    #[test]
+    fn collapsed_stacks() {
+        assert_eq!(
+            profiler().to_collapsed(),
+            "main (main.js);a (main.js) 2\nmain (main.js);b (main.js) 1\n"
+        );
+    }
+
+    #[test]
+    fn cpuprofile() {
+        let profile: serde_json::Value = serde_json::from_str(&profiler().to_cpuprofile()).unwrap();
+        let nodes: Vec<_> = profile["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|node| {
+                (
+                    node["id"].as_u64().unwrap(),
+                    node["callFrame"]["functionName"].as_str().unwrap(),
+                    node["callFrame"]["lineNumber"].as_i64().unwrap(),
+                    node["hitCount"].as_u64().unwrap(),
+                    node["children"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            nodes,
+            [
+                (1, "(root)", -1, 0, json!([2])),
+                (2, "main", 9, 0, json!([3, 4])),
+                (3, "a", 1, 2, json!([])),
+                (4, "b", 5, 1, json!([])),
+            ]
+        );
+        assert_eq!(
+            profile["nodes"][2]["positionTicks"],
+            json!([{ "line": 2, "ticks": 1 }, { "line": 3, "ticks": 1 }])
+        );
+        assert_eq!(profile["nodes"][1]["positionTicks"], json!([]));
+        assert_eq!(profile["samples"], json!([3, 3, 4]));
+        assert_eq!(profile["timeDeltas"], json!([1000, 1000, 1500]));
+        assert_eq!(profile["startTime"], 1_000_000);
+        assert_eq!(profile["endTime"], 1_003_500);
+    }
+}
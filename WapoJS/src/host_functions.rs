@@ -5,20 +5,30 @@ use log::error;
 use crate::service::{Service, ServiceRef, ServiceWeakRef};
 use crate::traits::ResultExt;
 
+#[cfg(feature = "fake-time")]
+pub(crate) use fake_time::enable as enable_fake_time;
 #[cfg(feature = "js-http-listen")]
 pub(crate) use http_listen::try_accept_http_request;
+pub(crate) use print::{set_log_config, LogConfig};
 #[cfg(feature = "wapo")]
 pub(crate) use query_listen::try_accept_query;
 
 mod debug;
+#[cfg(feature = "fake-time")]
+mod fake_time;
+mod heap;
 #[cfg(feature = "js-http-listen")]
 mod http_listen;
 mod http_request;
 #[cfg(feature = "mem-stats")]
 mod mem_stats;
+mod message_port;
 mod print;
 #[cfg(feature = "wapo")]
 mod query_listen;
+mod realm;
+#[cfg(feature = "js-schedule")]
+mod schedule;
 mod timer;
 #[cfg(feature = "js-url")]
 mod url;
@@ -27,16 +37,28 @@ mod wapo_ocalls;
 
 mod env;
 mod stream;
+mod structured_clone;
 
 #[cfg(feature = "js-wasm")]
 mod webassambly;
 
+#[cfg(feature = "js-text-encoding")]
+mod text_encoding;
+
 #[cfg(feature = "js-websocket")]
 mod websocket;
 
 #[cfg(feature = "js-hash")]
 mod hash;
 
+#[cfg(feature = "js-worker")]
+mod worker;
+
+#[cfg(any(feature = "js-url", feature = "js-text-encoding"))]
+mod typed_errors;
+#[cfg(any(feature = "js-url", feature = "js-text-encoding"))]
+use typed_errors::typed_errors;
+
 pub(crate) fn setup_host_functions(ctx: &js::Context) -> Result<()> {
     let ns = ctx.new_object("Wapo");
     ctx.get_global_object().set_property("Wapo", &ns)?;
@@ -47,8 +69,12 @@ pub(crate) fn setup_host_functions(ctx: &js::Context) -> Result<()> {
     set_extensions(&ns, ctx)?;
     print::setup(&ns)?;
     timer::setup(&ns)?;
+    #[cfg(feature = "js-schedule")]
+    schedule::setup(&ns)?;
     http_request::setup(&ns)?;
     debug::setup(&ns)?;
+    heap::setup(&ns)?;
+    realm::setup(&ns)?;
     ns.define_property_fn("close", close_res)?;
     ns.define_property_fn("exit", exit)?;
 
@@ -67,10 +93,17 @@ pub(crate) fn setup_host_functions(ctx: &js::Context) -> Result<()> {
 
     stream::setup(&ns)?;
     env::setup(&ns)?;
+    structured_clone::setup(&ns)?;
+    message_port::setup(&ns)?;
+    #[cfg(feature = "js-worker")]
+    worker::setup(&ns)?;
 
     #[cfg(feature = "js-wasm")]
     webassambly::setup(&ctx.get_global_object())?;
 
+    #[cfg(feature = "js-text-encoding")]
+    text_encoding::setup(&ns)?;
+
     #[cfg(feature = "js-websocket")]
     websocket::setup(&ns)?;
 
@@ -0,0 +1,125 @@
+//! Virtual clock for deterministic testing of timer-driven scripts.
+//!
+//! When enabled, the tokio clock is paused. The runtime then auto-advances it to the next timer
+//! deadline whenever all tasks are idle, so hour-long intervals complete instantly. `Date` and
+//! `Date.now()` are patched to follow the virtual clock.
+//!
+//! The clock belongs to the tokio runtime of the thread. A worker, which runs on a runtime of its
+//! own, gets a virtual clock of its own as well, starting at the virtual time of its parent.
+use super::*;
+use crate::service::OwnedJsValue;
+
+use core::cell::Cell;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+#[derive(Clone, Copy)]
+struct VirtualClock {
+    /// Wall clock time in milliseconds when the clock was paused.
+    epoch_ms: f64,
+    /// The paused tokio instant corresponding to `epoch_ms`.
+    start: Instant,
+}
+
+thread_local! {
+    static CLOCK: Cell<Option<VirtualClock>> = const { Cell::new(None) };
+}
+
+impl VirtualClock {
+    fn now_ms(&self) -> f64 {
+        self.epoch_ms + self.start.elapsed().as_secs_f64() * 1000.0
+    }
+}
+
+/// The virtual time in milliseconds since the epoch, or `None` if fake time is not enabled.
+pub(crate) fn now_ms() -> Option<f64> {
+    CLOCK.get().map(|clock| clock.now_ms())
+}
+
+/// Switches the service to the virtual clock, starting at the wall clock time. Must be called
+/// before any timer is created.
+pub(crate) fn enable(service: &Service) -> Result<()> {
+    let epoch_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+        * 1000.0;
+    enable_at(service, epoch_ms)
+}
+
+/// Switches the service to a virtual clock starting at `epoch_ms`.
+pub(crate) fn enable_at(service: &Service, epoch_ms: f64) -> Result<()> {
+    if CLOCK.get().is_some() {
+        anyhow::bail!("fake time has already been enabled");
+    }
+    tokio::time::pause();
+    CLOCK.set(Some(VirtualClock {
+        epoch_ms,
+        start: Instant::now(),
+    }));
+    let ctx = service.context();
+    let ns = ctx.get_global_object().get_property("Wapo")?;
+    ns.define_property_fn("fakeTimeNow", fake_time_now)?;
+    ns.define_property_fn("advanceTime", advance_time)?;
+    ctx.eval(&js::Code::Bytecode(qjsc::compiled!(
+        r#"
+        (function (g) {
+            const RealDate = g.Date;
+            const now = g.Wapo.fakeTimeNow;
+            function Date(...args) {
+                if (!new.target) {
+                    return new RealDate(now()).toString();
+                }
+                return Reflect.construct(RealDate, args.length === 0 ? [now()] : args, new.target);
+            }
+            Date.prototype = RealDate.prototype;
+            Object.defineProperty(Date.prototype, "constructor", {
+                value: Date,
+                writable: true,
+                configurable: true,
+            });
+            Date.now = now;
+            Date.parse = RealDate.parse;
+            Date.UTC = RealDate.UTC;
+            g.Date = Date;
+            const hostAdvance = g.Wapo.advanceTime;
+            g.Wapo.advanceTime = function (ms) {
+                return new Promise(resolve => hostAdvance(ms, resolve));
+            };
+        }(globalThis))
+    "#
+    )))
+    .map_err(js::Error::msg)?;
+    log::info!(target: "js::timer", "fake time enabled");
+    Ok(())
+}
+
+#[js::host_call]
+fn fake_time_now() -> f64 {
+    now_ms().unwrap_or_default()
+}
+
+#[js::host_call(with_context)]
+fn advance_time(
+    service: ServiceRef,
+    _this: js::Value,
+    ms: u64,
+    callback: OwnedJsValue,
+) -> Result<u64> {
+    Ok(service.spawn(callback, do_advance_time, ms))
+}
+
+async fn do_advance_time(service: ServiceWeakRef, id: u64, ms: u64) {
+    tokio::time::advance(Duration::from_millis(ms)).await;
+    // Give the timers that became due a chance to fire before resolving the promise.
+    tokio::task::yield_now().await;
+    let Some(service) = service.upgrade() else {
+        return;
+    };
+    let Some(callback) = service.get_resource_value(id) else {
+        return;
+    };
+    if let Err(err) = service.call_function(callback, ()) {
+        error!(target: "js::timer", "failed to resolve advanceTime {id}: {err}");
+    }
+}
@@ -0,0 +1,248 @@
+//! Heap statistics for leak hunting.
+//!
+//! `Wapo.heapStats()` combines the QuickJS runtime memory usage with a count of the objects
+//! reachable from the global object and from the live resources, grouped by class. The runtime
+//! figures are exact and cover the whole heap. The reachable counts do not: they come from a walk
+//! over properties in JS, which can not see objects held only by closures, module scopes or
+//! pending jobs. Their sizes are shallow estimates, strings are counted once per reference, and
+//! the walk itself allocates in proportion to what it visits. Paths are only built for the few
+//! instances kept per class. Proxies are counted but not entered, so that the walk never runs
+//! their traps; they are recognized natively with `JS_IsProxy`.
+use super::*;
+
+use alloc::collections::BTreeMap;
+use js::{FromJsValue, ToJsValue};
+use serde::Serialize;
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("heapStats", heap_stats)?;
+    #[cfg(feature = "native")]
+    ns.define_property_fn("writeHeapStats", write_heap_stats)?;
+    Ok(())
+}
+
+#[derive(ToJsValue, Serialize, Debug, Default)]
+#[qjs(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeStats {
+    malloc_size: i64,
+    malloc_count: i64,
+    memory_used_size: i64,
+    memory_used_count: i64,
+    atom_count: i64,
+    atom_size: i64,
+    str_count: i64,
+    str_size: i64,
+    obj_count: i64,
+    obj_size: i64,
+    prop_count: i64,
+    prop_size: i64,
+    shape_count: i64,
+    shape_size: i64,
+    js_func_count: i64,
+    js_func_size: i64,
+    js_func_code_size: i64,
+    c_func_count: i64,
+    array_count: i64,
+    fast_array_count: i64,
+    fast_array_elements: i64,
+    binary_object_count: i64,
+    binary_object_size: i64,
+}
+
+#[derive(FromJsValue, ToJsValue, Serialize, Debug, Default)]
+pub struct ClassStats {
+    count: u64,
+    size: u64,
+    /// Paths from the roots to the first few instances, to tell where they are retained.
+    paths: Vec<String>,
+}
+
+#[derive(ToJsValue, Serialize, Debug, Default)]
+pub struct HeapStats {
+    runtime: RuntimeStats,
+    /// The objects reachable through properties from the roots, by class.
+    reachable: BTreeMap<String, ClassStats>,
+}
+
+fn runtime_stats(ctx: &js::Context) -> RuntimeStats {
+    let mut usage = unsafe { core::mem::zeroed::<js::c::JSMemoryUsage>() };
+    unsafe { js::c::JS_ComputeMemoryUsage(js::c::JS_GetRuntime(ctx.as_ptr()), &mut usage) };
+    RuntimeStats {
+        malloc_size: usage.malloc_size,
+        malloc_count: usage.malloc_count,
+        memory_used_size: usage.memory_used_size,
+        memory_used_count: usage.memory_used_count,
+        atom_count: usage.atom_count,
+        atom_size: usage.atom_size,
+        str_count: usage.str_count,
+        str_size: usage.str_size,
+        obj_count: usage.obj_count,
+        obj_size: usage.obj_size,
+        prop_count: usage.prop_count,
+        prop_size: usage.prop_size,
+        shape_count: usage.shape_count,
+        shape_size: usage.shape_size,
+        js_func_count: usage.js_func_count,
+        js_func_size: usage.js_func_size,
+        js_func_code_size: usage.js_func_code_size,
+        c_func_count: usage.c_func_count,
+        array_count: usage.array_count,
+        fast_array_count: usage.fast_array_count,
+        fast_array_elements: usage.fast_array_elements,
+        binary_object_count: usage.binary_object_count,
+        binary_object_size: usage.binary_object_size,
+    }
+}
+
+/// Whether the value is a proxy. The walker has no other way to know, as everything it could ask
+/// a proxy goes through its handler.
+#[js::host_call]
+fn is_proxy(value: js::Value) -> bool {
+    unsafe { js::c::JS_IsProxy(*value.raw_value()) }
+}
+
+fn reachable(service: &Service) -> Result<BTreeMap<String, ClassStats>> {
+    let ctx = service.context();
+    let walker = ctx
+        .get_qjsbind_object("heap.reachable", || {
+            ctx.eval(&js::Code::Bytecode(qjsc::compiled!(
+                r#"
+                (function (roots, isProxy) {
+                    const classes = {};
+                    const seen = new Set();
+                    // The queue is three parallel arrays: a value, the index of the value it was
+                    // found in, and the step from there. Paths are only spelled out at the end.
+                    const values = [];
+                    const parents = [];
+                    const steps = [];
+                    for (const [name, value] of roots) {
+                        values.push(value);
+                        parents.push(-1);
+                        steps.push(name);
+                    }
+                    function enqueue(parent, step, value) {
+                        values.push(value);
+                        parents.push(parent);
+                        steps.push(step);
+                    }
+                    function pathOf(i) {
+                        const parts = [];
+                        for (; i >= 0; i = parents[i]) parts.push(steps[i]);
+                        return parts.reverse().join('');
+                    }
+                    const toTag = Object.prototype.toString;
+                    const getPrototypeOf = Object.getPrototypeOf;
+                    const getOwnPropertyDescriptor = Object.getOwnPropertyDescriptor;
+                    const ownKeys = Reflect.ownKeys;
+                    const isView = ArrayBuffer.isView;
+                    const byteLength = Object.getOwnPropertyDescriptor(ArrayBuffer.prototype, 'byteLength').get;
+                    const viewBuffer = Object.getOwnPropertyDescriptor(getPrototypeOf(Uint8Array.prototype), 'buffer').get;
+                    const dataViewBuffer = Object.getOwnPropertyDescriptor(DataView.prototype, 'buffer').get;
+                    const mapEntries = Map.prototype.entries;
+                    const setValues = Set.prototype.values;
+                    function className(o) {
+                        if (typeof o === 'function') return 'Function';
+                        const tag = toTag.call(o).slice(8, -1);
+                        if (tag !== 'Object') return tag;
+                        try {
+                            const proto = getPrototypeOf(o);
+                            const ctor = proto && getOwnPropertyDescriptor(proto, 'constructor');
+                            return (ctor && typeof ctor.value === 'function' && ctor.value.name) || 'Object';
+                        } catch (e) {
+                            return 'Object';
+                        }
+                    }
+                    function record(name, i, size) {
+                        const stats = classes[name] || (classes[name] = { count: 0, size: 0, paths: [] });
+                        stats.count++;
+                        stats.size += size;
+                        if (stats.paths.length < 3) stats.paths.push(i);
+                    }
+                    for (let i = 0; i < values.length; i++) {
+                        const value = values[i];
+                        if (typeof value === 'string') {
+                            record('(string)', i, 16 + value.length * 2);
+                            continue;
+                        }
+                        if ((typeof value !== 'object' && typeof value !== 'function') || value === null) continue;
+                        if (seen.has(value)) continue;
+                        seen.add(value);
+                        if (isProxy(value)) {
+                            record('Proxy', i, 48);
+                            continue;
+                        }
+                        let keys = [];
+                        try { keys = ownKeys(value); } catch (e) {}
+                        let size = 48 + keys.length * 16;
+                        if (value instanceof ArrayBuffer) size += byteLength.call(value);
+                        record(className(value), i, size);
+                        for (const key of keys) {
+                            let desc;
+                            try { desc = getOwnPropertyDescriptor(value, key); } catch (e) {}
+                            if (!desc) continue;
+                            const step = `.${String(key)}`;
+                            if ('value' in desc) enqueue(i, step, desc.value);
+                            if (desc.get) enqueue(i, `${step}[get]`, desc.get);
+                            if (desc.set) enqueue(i, `${step}[set]`, desc.set);
+                        }
+                        try { enqueue(i, '.__proto__', getPrototypeOf(value)); } catch (e) {}
+                        if (isView(value)) {
+                            const buffer = value instanceof DataView ? dataViewBuffer : viewBuffer;
+                            enqueue(i, '.buffer', buffer.call(value));
+                        }
+                        if (value instanceof Map) {
+                            let n = 0;
+                            for (const [k, v] of mapEntries.call(value)) {
+                                enqueue(i, `[key ${n}]`, k);
+                                enqueue(i, `[value ${n++}]`, v);
+                            }
+                        } else if (value instanceof Set) {
+                            let n = 0;
+                            for (const v of setValues.call(value)) enqueue(i, `[${n++}]`, v);
+                        }
+                    }
+                    for (const name in classes) classes[name].paths = classes[name].paths.map(pathOf);
+                    return classes;
+                })
+                "#
+            )))
+            .map_err(js::Error::msg)
+        })
+        .context("failed to create the heap walker")?;
+    let mut roots = vec![vec![ctx.new_string("globalThis"), ctx.get_global_object()]];
+    for (id, value) in service.resource_values() {
+        roots.push(vec![ctx.new_string(&format!("(resource {id})")), value]);
+    }
+    let roots = roots.to_js_value(ctx)?;
+    proxy_class_id(ctx)?;
+    let helpers = ctx.new_object("HeapHelpers");
+    helpers.define_property_fn("isProxy", is_proxy)?;
+    let is_proxy = helpers.get_property("isProxy")?;
+    let classes = walker.call(&js::Value::undefined(), &[roots, is_proxy])?;
+    Ok(BTreeMap::<String, ClassStats>::from_js_value(classes)?)
+}
+
+fn collect(service: &Service) -> Result<HeapStats> {
+    Ok(HeapStats {
+        runtime: runtime_stats(service.context()),
+        reachable: reachable(service)?,
+    })
+}
+
+#[js::host_call(with_context)]
+fn heap_stats(service: ServiceRef, _this: js::Value) -> Result<HeapStats> {
+    collect(&service)
+}
+
+/// Writes the heap stats as pretty-printed JSON with sorted keys, so that two snapshots can be
+/// compared with any text diff tool.
+#[cfg(feature = "native")]
+#[js::host_call(with_context)]
+fn write_heap_stats(service: ServiceRef, _this: js::Value, path: String) -> Result<()> {
+    let stats = collect(&service)?;
+    let json = serde_json::to_string_pretty(&stats).context("failed to serialize heap stats")?;
+    std::fs::write(&path, json).with_context(|| format!("failed to write {path}"))?;
+    log::info!(target: "js::heap", "heap snapshot written to {path}");
+    Ok(())
+}
@@ -0,0 +1,139 @@
+//! Message ports carrying structured clones between services.
+//!
+//! A port is one end of an entangled pair. Messages are structured clones encoded in the
+//! binary wire format on `post`, so the other end may live in a service on another thread.
+use anyhow::{bail, Context as _};
+use log::{error, info};
+use tokio::sync::mpsc;
+
+use crate::service::OwnedJsValue;
+
+use super::structured_clone;
+use super::*;
+
+pub(crate) enum PortEvent {
+    /// A structured clone in the wire format.
+    Message(Vec<u8>),
+    /// An error raised on the other end, such as an uncaught exception in a worker.
+    Error(String),
+}
+
+/// One end of a pair of entangled ports.
+pub(crate) struct Port {
+    tx: mpsc::UnboundedSender<PortEvent>,
+    rx: Option<mpsc::UnboundedReceiver<PortEvent>>,
+}
+
+impl Port {
+    /// Creates a pair of entangled ports.
+    pub(crate) fn pair() -> (Port, Port) {
+        let (tx1, rx1) = mpsc::unbounded_channel();
+        let (tx2, rx2) = mpsc::unbounded_channel();
+        (
+            Port {
+                tx: tx1,
+                rx: Some(rx2),
+            },
+            Port {
+                tx: tx2,
+                rx: Some(rx1),
+            },
+        )
+    }
+
+    /// Returns a sender to the other end.
+    pub(crate) fn sender(&self) -> mpsc::UnboundedSender<PortEvent> {
+        self.tx.clone()
+    }
+
+    pub(crate) fn into_js_value(self, ctx: &js::Context) -> js::Value {
+        js::Value::new_opaque_object(ctx, Some("MessagePort"), self)
+    }
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("portPair", port_pair)?;
+    ns.define_property_fn("portPost", port_post)?;
+    ns.define_property_fn("portListen", port_listen)?;
+    ns.define_property_fn("portClose", port_close)?;
+    Ok(())
+}
+
+/// Creates two entangled ports, the backend of `MessageChannel`.
+#[js::host_call(with_context)]
+fn port_pair(ctx: js::Context, _this: js::Value) -> Vec<js::Value> {
+    let (port1, port2) = Port::pair();
+    vec![port1.into_js_value(&ctx), port2.into_js_value(&ctx)]
+}
+
+/// Posts a clone of `data` to the other end. The ArrayBuffers, or the buffers of the typed
+/// arrays, in `transfer` are detached.
+#[js::host_call]
+fn port_post(port: js::Value, data: js::Value, transfer: Option<Vec<js::Value>>) -> Result<()> {
+    let buffers = structured_clone::transferred_buffers(&transfer.unwrap_or_default())?;
+    let message = structured_clone::encode(&structured_clone::serialize(&data)?);
+    {
+        let guard = port.opaque_object_data::<Port>();
+        let Some(port) = guard.get() else {
+            bail!("the port is closed");
+        };
+        // Like on the web, messages to a port whose other end is gone are silently dropped.
+        _ = port.tx.send(PortEvent::Message(message));
+    }
+    structured_clone::detach_buffers(&buffers)
+}
+
+/// Starts delivering the messages of the port to `callback(kind, data)`, where kind is one of
+/// `message`, `messageerror` or `error`. Returns the resource id of the listener.
+#[js::host_call(with_context)]
+fn port_listen(
+    service: ServiceRef,
+    _this: js::Value,
+    port: js::Value,
+    callback: OwnedJsValue,
+) -> Result<u64> {
+    let rx = {
+        let mut guard = port.opaque_object_data_mut::<Port>();
+        let port = guard.get_mut().context("the port is closed")?;
+        port.rx
+            .take()
+            .context("the port is already being listened to")?
+    };
+    Ok(service.spawn(callback, listen, rx))
+}
+
+async fn listen(weak_service: ServiceWeakRef, id: u64, mut rx: mpsc::UnboundedReceiver<PortEvent>) {
+    while let Some(event) = rx.recv().await {
+        let Some(service) = weak_service.upgrade() else {
+            info!(target: "js::port", "port {id} exited because the service has been dropped");
+            return;
+        };
+        let Some(callback) = service.get_resource_value(id) else {
+            info!(target: "js::port", "port {id} exited because the resource has been dropped");
+            return;
+        };
+        let result = match event {
+            PortEvent::Message(data) => {
+                let value = structured_clone::decode(&data)
+                    .and_then(|data| structured_clone::deserialize(service.context(), &data));
+                match value {
+                    Ok(value) => service.call_function(callback, ("message", value)),
+                    Err(err) => service.call_function(callback, ("messageerror", err.to_string())),
+                }
+            }
+            PortEvent::Error(message) => service.call_function(callback, ("error", message)),
+        };
+        if let Err(err) = result {
+            error!(target: "js::port", "[{id}] failed to deliver message: {err:?}");
+        }
+    }
+    info!(target: "js::port", "port {id} closed by the other end");
+}
+
+/// Disentangles the port, the listener of the other end stops.
+#[js::host_call]
+fn port_close(port: js::Value) {
+    if port.opaque_object_take_data::<Port>().is_none() {
+        info!(target: "js::port", "port closed twice");
+    }
+}
@@ -1,32 +1,165 @@
 use super::*;
 
-use log::{info, warn, debug, error};
+use core::str::FromStr;
+use log::{debug, error, info, trace, warn};
 use qjs_extensions::repr;
+use serde::Serialize;
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Lines are forwarded to the `log` macros under the `js::console` target.
+    Text,
+    /// One JSON record per message is written to stdout.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => anyhow::bail!("invalid log format: {s}"),
+        }
+    }
+}
+
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// Messages longer than this are truncated, 0 means unlimited.
+    pub max_message_len: usize,
+    /// The service name reported in JSON records, `wapojs` if empty.
+    pub service_name: String,
+}
+
+impl LogConfig {
+    const DEFAULT: Self = Self {
+        format: LogFormat::Text,
+        max_message_len: 2048,
+        service_name: String::new(),
+    };
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+static LOG_CONFIG: RwLock<LogConfig> = RwLock::new(LogConfig::DEFAULT);
+
+pub fn set_log_config(config: LogConfig) {
+    *LOG_CONFIG.write().unwrap() = config;
+}
 
 pub(crate) fn setup(ns: &js::Value) -> Result<()> {
     ns.define_property_fn("print", print)?;
     Ok(())
 }
 
-#[js::host_call]
-fn print(level: u32, args: Vec<js::Value>, config: Option<repr::ReprConfig>) {
+#[derive(Serialize)]
+struct LogRecord<'a> {
+    timestamp: u64,
+    level: &'static str,
+    service: &'a str,
+    resource: Option<u64>,
+    message: &'a str,
+}
+
+fn truncate(buf: &str, max_len: usize) -> (&str, bool) {
+    if max_len == 0 || buf.len() <= max_len {
+        return (buf, false);
+    }
+    let mut end = max_len;
+    while !buf.is_char_boundary(end) {
+        end -= 1;
+    }
+    (&buf[..end], true)
+}
+
+#[js::host_call(with_context)]
+fn print(
+    service: ServiceRef,
+    _this: js::Value,
+    level: u32,
+    args: Vec<js::Value>,
+    config: Option<repr::ReprConfig>,
+    group_depth: Option<usize>,
+) {
+    #[cfg(feature = "devtools")]
+    crate::devtools::inspector::console_api_called(service.context(), level, &args);
+    #[cfg(not(feature = "devtools"))]
+    let _ = service;
     let buf = repr::print(&args, &config.unwrap_or_default());
-    let buf = buf.trim_end();
-    if buf.is_empty() {
-        js_log(level, "");
-    } else {
-        let buf = &buf[..2048.min(buf.len())];
-        for line in buf.lines() {
-            js_log(level, line);
+    let buf = crate::source_map::rewrite(&buf);
+    let log_config = LOG_CONFIG.read().unwrap();
+    let (buf, truncated) = truncate(buf.trim_end(), log_config.max_message_len);
+    let indent = "  ".repeat(group_depth.unwrap_or(0));
+    match log_config.format {
+        LogFormat::Json => {
+            let mut message = buf
+                .lines()
+                .map(|line| format!("{indent}{line}"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            if truncated {
+                message.push_str(" <...>");
+            }
+            emit_json(level, &message, &log_config);
         }
+        LogFormat::Text => {
+            if buf.is_empty() {
+                js_log(level, "");
+            } else {
+                for line in buf.lines() {
+                    js_log(level, &format!("{indent}{line}"));
+                }
+            }
+            if truncated {
+                js_log(level, "<...>");
+            }
+        }
+    }
+}
+
+fn level_name(level: u32) -> &'static str {
+    match level {
+        0 => "trace",
+        1 => "debug",
+        2 => "info",
+        3 => "warn",
+        _ => "error",
     }
-    if buf.len() > 2048 {
-        js_log(level, "<...>");
+}
+
+fn emit_json(level: u32, message: &str, config: &LogConfig) {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    let service = if config.service_name.is_empty() {
+        "wapojs"
+    } else {
+        &config.service_name
+    };
+    let record = LogRecord {
+        timestamp,
+        level: level_name(level),
+        service,
+        resource: crate::service::current_resource_id(),
+        message,
+    };
+    match serde_json::to_string(&record) {
+        Ok(line) => println!("{line}"),
+        Err(err) => error!(target: "js::console", "failed to serialize log record: {err}"),
     }
 }
 
 pub fn js_log(level: u32, msg: &str) {
     match level {
+        0 => trace!(target: "js::console", "{msg}"),
         1 => debug!(target: "js::console", "{msg}"),
         2 => info!(target: "js::console", "{msg}"),
         3 => warn!(target: "js::console", "{msg}"),
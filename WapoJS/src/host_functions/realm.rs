@@ -0,0 +1,171 @@
+//! Isolated realms.
+//!
+//! `Wapo.createRealm({ globals, timeout })` creates a fresh context on the service's runtime with its
+//! own global object and intrinsics, and without any of the `Wapo` host functions. Values
+//! passed in or returned are copied with the structured clone algorithm, so no object is ever
+//! shared between the realm and its creator.
+//!
+//! With `timeout`, each `eval` or `call` is aborted once it has run for that many milliseconds,
+//! the realm being left usable. The budget only covers the synchronous part of the call: the
+//! promise jobs it queues share the job queue of the runtime with those of the creator, and run
+//! on the next tick of the event loop like any other, without a budget. Realms share the memory
+//! of the runtime, there is no limit of their own: a snippet can still exhaust the memory of the
+//! whole service.
+use super::*;
+
+use alloc::rc::Rc;
+use anyhow::{anyhow, Context as _};
+use core::cell::Cell;
+use core::time::Duration;
+use js::FromJsValue;
+
+use super::structured_clone::{self, Cloned};
+use crate::service::JsEngine;
+
+pub use bind::Realm;
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("createRealm", create_realm)?;
+    Ok(())
+}
+
+#[derive(FromJsValue, Default)]
+struct RealmOptions {
+    #[qjs(default)]
+    globals: Option<js::Value>,
+    /// The time budget of each `eval` or `call`, in milliseconds.
+    #[qjs(default)]
+    timeout: Option<f64>,
+}
+
+#[js::host_call(with_context)]
+fn create_realm(
+    service: ServiceRef,
+    _this: js::Value,
+    options: Option<RealmOptions>,
+) -> Result<js::Native<Realm>> {
+    let options = options.unwrap_or_default();
+    let timeout = options
+        .timeout
+        .map(|ms| {
+            Duration::try_from_secs_f64(ms / 1000.0)
+                .map_err(|_| anyhow!("RangeError: invalid realm timeout {ms}"))
+        })
+        .transpose()?;
+    let engine = service.runtime();
+    let id = engine.create_realm();
+    let realm = Realm {
+        engine: Rc::downgrade(&engine),
+        id: Cell::new(Some(id)),
+        timeout,
+    };
+    structured_clone::install(&realm.context()?)?;
+    if crate::source_map::is_active() {
+        crate::source_map::install(&realm.context()?)?;
+    }
+    if let Some(globals) = options.globals {
+        let ctx = realm.context()?;
+        let cloned = structured_clone::serialize(&globals)?;
+        let Cloned::Object(props) = &cloned else {
+            anyhow::bail!("TypeError: globals must be a plain object");
+        };
+        let object = structured_clone::deserialize(&ctx, &cloned)?;
+        let global = ctx.get_global_object();
+        for (key, _) in props {
+            global.set_property(key, &object.get_property(key)?)?;
+        }
+    }
+    Ok(service.context().wrap_native(realm)?)
+}
+
+#[js::qjsbind]
+mod bind {
+    use super::*;
+
+    #[qjs(class(js_name = "Wapo.Realm"))]
+    pub struct Realm {
+        #[gc(skip)]
+        pub(super) engine: alloc::rc::Weak<JsEngine>,
+        #[gc(skip)]
+        pub(super) id: Cell<Option<u64>>,
+        #[gc(skip)]
+        pub(super) timeout: Option<Duration>,
+    }
+
+    impl Realm {
+        pub(super) fn context(&self) -> Result<Rc<js::Context>> {
+            let id = self
+                .id
+                .get()
+                .ok_or_else(|| anyhow!("the realm has been disposed"))?;
+            self.engine
+                .upgrade()
+                .and_then(|engine| engine.realm(id))
+                .context("the realm has been disposed")
+        }
+
+        /// Runs `f` within the time budget of the realm, if any.
+        fn run<T>(&self, realm: &js::Context, f: impl FnOnce() -> js::Result<T>) -> Result<T> {
+            let result = match self.timeout {
+                Some(timeout) => crate::interrupt::with_deadline(realm, timeout, f),
+                None => f(),
+            };
+            result.map_err(|err| anyhow!("{err}"))
+        }
+
+        /// Evaluates a script in the realm and returns a clone of its completion value.
+        #[qjs(method)]
+        fn eval(&self, #[qjs(from_context)] ctx: js::Context, code: String) -> Result<js::Value> {
+            let realm = self.context()?;
+            let result = self.run(&realm, || realm.eval(&js::Code::Source(&code)))?;
+            structured_clone::clone_into(&ctx, &result)
+        }
+
+        /// Calls a global function of the realm with clones of `args`, and returns a clone of
+        /// its return value.
+        #[qjs(method)]
+        fn call(
+            &self,
+            #[qjs(from_context)] ctx: js::Context,
+            name: String,
+            args: Option<js::Value>,
+        ) -> Result<js::Value> {
+            let realm = self.context()?;
+            let func = realm.get_global_object().get_property(&name)?;
+            if func.is_undefined() {
+                anyhow::bail!("ReferenceError: {name} is not defined in the realm");
+            }
+            let args = match args {
+                Some(args) if !args.is_undefined() => {
+                    let args = structured_clone::clone_into(&realm, &args)?;
+                    Vec::<js::Value>::from_js_value(args)?
+                }
+                _ => vec![],
+            };
+            let result = self.run(&realm, || func.call(&js::Value::undefined(), &args))?;
+            structured_clone::clone_into(&ctx, &result)
+        }
+
+        /// Frees the realm. Any further `eval` or `call` throws.
+        #[qjs(method)]
+        fn dispose(&self) {
+            if let (Some(id), Some(engine)) = (self.id.take(), self.engine.upgrade()) {
+                engine.dispose_realm(id);
+            }
+        }
+
+        #[qjs(getter)]
+        fn disposed(&self) -> bool {
+            self.id.get().is_none()
+        }
+    }
+
+    impl Drop for Realm {
+        fn drop(&mut self) {
+            // Dropped from the GC, the context can not be freed right now.
+            if let (Some(id), Some(engine)) = (self.id.take(), self.engine.upgrade()) {
+                engine.release_realm(id);
+            }
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use super::*;
+use crate::{runtime::time::sleep, service::OwnedJsValue};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use core::str::FromStr;
+use js::FromJsValue;
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    // Cancelling a schedule is implemented by `close` on the guest side, the same as timers
+    ns.define_property_fn("schedule", schedule)?;
+    ns.define_property_fn("scheduleUpcoming", schedule_upcoming)?;
+    Ok(())
+}
+
+#[derive(FromJsValue, Debug, Default)]
+#[qjs(rename_all = "camelCase")]
+struct ScheduleOptions {
+    /// IANA timezone name the cron expression is evaluated in. Defaults to UTC.
+    timezone: Option<String>,
+    /// What to do when one or more ticks were missed, either "skip" or "catchUp".
+    missed: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MissedTicks {
+    /// Drop the missed ticks and wait for the next one in the future.
+    Skip,
+    /// Fire every missed tick back-to-back before waiting again.
+    CatchUp,
+}
+
+struct CronJob {
+    schedule: cron::Schedule,
+    tz: Tz,
+    missed: MissedTicks,
+}
+
+impl CronJob {
+    fn parse(expr: &str, options: ScheduleOptions) -> Result<Self> {
+        // Days of the week are numbered like in classic cron in every form. The classic 5-field
+        // form gets its seconds pinned to 0.
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let expr = match fields[..] {
+            [minute, hour, day, month, day_of_week] => {
+                let day_of_week = classic_days_of_week(day_of_week)?;
+                format!("0 {minute} {hour} {day} {month} {day_of_week}")
+            }
+            [second, minute, hour, day, month, day_of_week, ref year @ ..] if year.len() <= 1 => {
+                let day_of_week = classic_days_of_week(day_of_week)?;
+                let mut expr = format!("{second} {minute} {hour} {day} {month} {day_of_week}");
+                for year in year {
+                    expr.push(' ');
+                    expr.push_str(year);
+                }
+                expr
+            }
+            _ => expr.to_string(),
+        };
+        let schedule = cron::Schedule::from_str(&expr)
+            .with_context(|| format!("invalid cron expression: {expr}"))?;
+        let tz = match options.timezone {
+            Some(tz) => Tz::from_str(&tz).map_err(|err| anyhow::anyhow!("{err}"))?,
+            None => Tz::UTC,
+        };
+        let missed = match options.missed.as_deref() {
+            None | Some("skip") => MissedTicks::Skip,
+            Some("catchUp") => MissedTicks::CatchUp,
+            Some(other) => bail!("invalid missed tick policy: {other}"),
+        };
+        Ok(Self {
+            schedule,
+            tz,
+            missed,
+        })
+    }
+
+    fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.schedule
+            .after(&time.with_timezone(&self.tz))
+            .next()
+            .map(|t| t.with_timezone(&Utc))
+    }
+
+    /// The tick to fire after the one scheduled at `previous`, at time `now`. It is derived from
+    /// the previous scheduled time rather than from the time the callback finished, so the
+    /// schedule never drifts. Ticks already past are fired or skipped depending on the policy.
+    fn following(&self, previous: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let following = self.next_after(previous)?;
+        if following <= now && self.missed == MissedTicks::Skip {
+            return self.next_after(now);
+        }
+        Some(following)
+    }
+}
+
+const DAY_NAMES: [&str; 7] = [
+    "sunday",
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+];
+
+/// Translates a day-of-week field of a classic cron expression, where days are numbered from 0
+/// (Sunday) to 6 and 7 is Sunday again, to the numbering of the `cron` crate, from 1 (Sunday) to
+/// 7. Names, which may be mixed with numbers in ranges, are translated too.
+fn classic_days_of_week(field: &str) -> Result<String> {
+    let mut translated = vec![];
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => {
+                let step: usize = step
+                    .parse()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .with_context(|| format!("invalid day-of-week step: {item}"))?;
+                (range, Some(step))
+            }
+            None => (item, None),
+        };
+        if range == "*" || range == "?" {
+            translated.push(item.to_string());
+            continue;
+        }
+        let day = |day: &str| -> Result<u32> {
+            let name = day.to_ascii_lowercase();
+            let by_name = DAY_NAMES
+                .iter()
+                .position(|full| name == *full || name == full[..3]);
+            match by_name {
+                Some(index) => Ok(index as u32),
+                None => day
+                    .parse()
+                    .ok()
+                    .filter(|day| *day <= 7)
+                    .with_context(|| format!("invalid day of week: {item}")),
+            }
+        };
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (day(first)?, day(last)?),
+            // `n/step` runs from `n` to the end of the week.
+            None if step.is_some() => (day(range)?, 6),
+            None => (day(range)?, day(range)?),
+        };
+        if first > last {
+            bail!("invalid day-of-week range: {item}");
+        }
+        for day in (first..=last).step_by(step.unwrap_or(1)) {
+            translated.push((day % 7 + 1).to_string());
+        }
+    }
+    Ok(translated.join(","))
+}
+
+#[js::host_call(with_context)]
+fn schedule(
+    service: ServiceRef,
+    _this: js::Value,
+    expr: String,
+    callback: OwnedJsValue,
+    options: Option<ScheduleOptions>,
+) -> Result<u64> {
+    let job = CronJob::parse(&expr, options.unwrap_or_default())?;
+    Ok(service.spawn(callback, do_schedule, job))
+}
+
+/// The most runs `scheduleUpcoming` returns at once.
+const MAX_UPCOMING: usize = 1000;
+
+/// Returns the timestamps in milliseconds of the next `count` runs of the given cron expression.
+#[js::host_call]
+fn schedule_upcoming(
+    expr: String,
+    options: Option<ScheduleOptions>,
+    count: Option<f64>,
+) -> Result<Vec<f64>> {
+    let count = count.unwrap_or(1.0);
+    if !(0.0..=MAX_UPCOMING as f64).contains(&count) || count.fract() != 0.0 {
+        bail!("invalid count {count}, expected an integer from 0 to {MAX_UPCOMING}");
+    }
+    let job = CronJob::parse(&expr, options.unwrap_or_default())?;
+    Ok(job
+        .schedule
+        .after(&now().with_timezone(&job.tz))
+        .take(count as usize)
+        .map(|t| t.timestamp_millis() as f64)
+        .collect())
+}
+
+fn try_fire_job(service: &Weak<Service>, id: u64, scheduled: DateTime<Utc>) -> Result<()> {
+    let Some(service) = service.upgrade() else {
+        anyhow::bail!("schedule {id} exited because the service has been dropped");
+    };
+    let Some(callback) = service.get_resource_value(id) else {
+        anyhow::bail!("schedule {id} exited because the resource has been dropped");
+    };
+    let scheduled_ms = scheduled.timestamp_millis() as f64;
+    if let Err(err) = service.call_function(callback, (scheduled_ms,)) {
+        error!(target: "js::schedule", "failed to fire schedule {id}: {err}");
+    }
+    Ok(())
+}
+
+/// The wall clock, or the virtual clock when `--fake-time` is enabled.
+fn now() -> DateTime<Utc> {
+    #[cfg(feature = "fake-time")]
+    if let Some(ms) = super::fake_time::now_ms() {
+        if let Some(time) = DateTime::from_timestamp_millis(ms as i64) {
+            return time;
+        }
+    }
+    Utc::now()
+}
+
+async fn sleep_until(deadline: DateTime<Utc>) {
+    // Re-check the wall clock after each sleep so that the tick is aligned to the wall clock
+    // rather than to the monotonic clock the sleep is measured in.
+    loop {
+        let Ok(remaining) = (deadline - now()).to_std() else {
+            break;
+        };
+        if remaining.is_zero() {
+            break;
+        }
+        sleep(remaining).await;
+    }
+}
+
+async fn do_schedule(service: ServiceWeakRef, id: u64, job: CronJob) {
+    let Some(mut next) = job.next_after(now()) else {
+        return;
+    };
+    loop {
+        sleep_until(next).await;
+        if try_fire_job(&service, id, next).log_err().is_err() {
+            break;
+        }
+        let current = now();
+        let Some(following) = job.following(next, current) else {
+            break;
+        };
+        if job.next_after(next).is_some_and(|missed| missed < following) {
+            log::debug!(target: "js::schedule", "schedule {id} skipped missed ticks until {following}");
+        }
+        next = following;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classic_days_are_renumbered() {
+        let days = |field| classic_days_of_week(field).unwrap();
+        assert_eq!(days("*"), "*");
+        assert_eq!(days("*/2"), "*/2");
+        assert_eq!(days("MON-FRI"), "2,3,4,5,6");
+        assert_eq!(days("0"), "1");
+        assert_eq!(days("7"), "1", "7 is Sunday too");
+        assert_eq!(days("6"), "7");
+        assert_eq!(days("1-5"), "2,3,4,5,6");
+        assert_eq!(days("5-7"), "6,7,1");
+        assert_eq!(days("0-6/3"), "1,4,7");
+        assert_eq!(days("2/2"), "3,5,7");
+        assert_eq!(days("1,sat"), "2,7");
+        assert_eq!(days("1-FRI"), "2,3,4,5,6", "a mixed range");
+        assert_eq!(days("Sunday"), "1");
+    }
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+    }
+
+    fn job(expr: &str, timezone: Option<&str>, missed: Option<&str>) -> CronJob {
+        let options = ScheduleOptions {
+            timezone: timezone.map(Into::into),
+            missed: missed.map(Into::into),
+        };
+        CronJob::parse(expr, options).unwrap()
+    }
+
+    #[test]
+    fn every_form_numbers_days_from_sunday() {
+        // 2024-01-06 is a Saturday.
+        let saturday = at("2024-01-06T12:00:00Z");
+        let monday = Some(at("2024-01-08T00:00:00Z"));
+        assert_eq!(job("0 0 * * 1", None, None).next_after(saturday), monday);
+        assert_eq!(job("0 0 0 * * 1", None, None).next_after(saturday), monday);
+        assert_eq!(job("0 0 0 * * 1 2024", None, None).next_after(saturday), monday);
+    }
+
+    #[test]
+    fn next_ticks() {
+        let weekdays = job("30 9 * * 1-5", None, None);
+        assert_eq!(
+            weekdays.next_after(at("2024-01-05T09:30:00Z")),
+            Some(at("2024-01-08T09:30:00Z")),
+            "strictly after, over the weekend"
+        );
+        let paris = job("0 9 * * *", Some("Europe/Paris"), None);
+        assert_eq!(
+            paris.next_after(at("2024-01-05T00:00:00Z")),
+            Some(at("2024-01-05T08:00:00Z"))
+        );
+        let seconds = job("*/15 * * * * *", None, None);
+        assert_eq!(
+            seconds.next_after(at("2024-01-05T00:00:07Z")),
+            Some(at("2024-01-05T00:00:15Z"))
+        );
+    }
+
+    #[test]
+    fn missed_ticks() {
+        let previous = at("2024-01-05T10:00:00Z");
+        // The callback of the 10:00 tick returned at 10:03:30.
+        let now = at("2024-01-05T10:03:30Z");
+        let skip = job("* * * * *", None, None);
+        assert_eq!(
+            skip.following(previous, now),
+            Some(at("2024-01-05T10:04:00Z"))
+        );
+        let catch_up = job("* * * * *", None, Some("catchUp"));
+        assert_eq!(
+            catch_up.following(previous, now),
+            Some(at("2024-01-05T10:01:00Z"))
+        );
+        // Nothing was missed, both policies agree.
+        let now = at("2024-01-05T10:00:01Z");
+        assert_eq!(skip.following(previous, now), catch_up.following(previous, now));
+    }
+
+    #[test]
+    fn invalid_classic_days() {
+        for field in ["8", "5-1", "1/0", "-1", ""] {
+            assert!(classic_days_of_week(field).is_err(), "{field}");
+        }
+    }
+}
@@ -0,0 +1,645 @@
+//! The structured clone algorithm over `js::Value`.
+//!
+//! Values are serialized to a context independent [`Cloned`] graph, which can then be
+//! materialized in any context of any runtime. Shared and cyclic references are preserved:
+//! objects are numbered in the order they are first visited and later visits are encoded as
+//! [`Cloned::Ref`]. Clones crossing a service boundary are encoded with the binary format of
+//! [`wire`].
+use anyhow::{anyhow, bail, Context as _, Result};
+use js::ToJsValue;
+
+pub(crate) use wire::{decode, encode};
+
+mod wire;
+
+const MAX_DEPTH: usize = 1000;
+
+const TYPED_ARRAYS: &[&str] = &[
+    "Int8Array",
+    "Uint8Array",
+    "Uint8ClampedArray",
+    "Int16Array",
+    "Uint16Array",
+    "Int32Array",
+    "Uint32Array",
+    "Float32Array",
+    "Float64Array",
+    "BigInt64Array",
+    "BigUint64Array",
+    "DataView",
+];
+
+const ERRORS: &[&str] = &[
+    "Error",
+    "EvalError",
+    "RangeError",
+    "ReferenceError",
+    "SyntaxError",
+    "TypeError",
+    "URIError",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Cloned {
+    Undefined,
+    Null,
+    Bool(bool),
+    Number(f64),
+    /// Decimal digits of a BigInt.
+    BigInt(String),
+    String(String),
+    /// A reference to the n-th object of the graph.
+    Ref(u32),
+    Object(Vec<(String, Cloned)>),
+    Array {
+        length: u32,
+        props: Vec<(String, Cloned)>,
+    },
+    Date(f64),
+    RegExp {
+        source: String,
+        flags: String,
+    },
+    Map(Vec<(Cloned, Cloned)>),
+    Set(Vec<Cloned>),
+    ArrayBuffer(Vec<u8>),
+    /// A typed array or DataView, `length` is in elements or in bytes for a DataView.
+    View {
+        kind: String,
+        buffer: Box<Cloned>,
+        byte_offset: u32,
+        length: u32,
+    },
+    Error {
+        name: String,
+        message: String,
+        stack: Option<String>,
+    },
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    let ctx = ns.context()?;
+    install(&ctx)?;
+    let global = ctx.get_global_object();
+    global.define_property_fn("structuredClone", structured_clone)?;
+    ns.define_property_fn("structuredSerialize", structured_serialize)?;
+    ns.define_property_fn("structuredDeserialize", structured_deserialize)?;
+    Ok(())
+}
+
+#[derive(js::FromJsValue, Default)]
+struct CloneOptions {
+    #[qjs(default)]
+    transfer: Vec<js::Value>,
+}
+
+#[js::host_call(with_context)]
+fn structured_clone(
+    ctx: js::Context,
+    _this: js::Value,
+    value: js::Value,
+    options: Option<CloneOptions>,
+) -> Result<js::Value> {
+    let transfer = options.unwrap_or_default().transfer;
+    let buffers = transferred_buffers(&transfer)?;
+    let cloned = serialize(&value)?;
+    detach_buffers(&buffers)?;
+    deserialize(&ctx, &cloned)
+}
+
+/// Serializes a value to the wire format, to be sent to another service.
+#[js::host_call]
+fn structured_serialize(value: js::Value) -> Result<js::AsBytes<Vec<u8>>> {
+    Ok(js::AsBytes(encode(&serialize(&value)?)))
+}
+
+#[js::host_call(with_context)]
+fn structured_deserialize(
+    ctx: js::Context,
+    _this: js::Value,
+    bytes: js::Bytes,
+) -> Result<js::Value> {
+    deserialize(&ctx, &decode(bytes.as_bytes())?)
+}
+
+/// Installs the helpers of the algorithm in a context. They capture the intrinsics they use when
+/// installed, so that scripts replacing globals or prototype methods later can not change how
+/// values are cloned. Called before any user code runs in the context.
+pub(crate) fn install(ctx: &js::Context) -> Result<()> {
+    helpers(ctx).map(|_| ())
+}
+
+fn helpers(ctx: &js::Context) -> Result<js::Value> {
+    ctx.get_qjsbind_object("structured_clone.helpers", || {
+        ctx.eval(&js::Code::Bytecode(qjsc::compiled!(
+            r#"
+            (function () {
+                const { apply, construct } = Reflect;
+                const { keys, defineProperty, getOwnPropertyDescriptor, getPrototypeOf } = Object;
+                const getter = (proto, name) => getOwnPropertyDescriptor(proto, name).get;
+                const call = (f, v, ...args) => apply(f, v, args);
+                const toString = Object.prototype.toString;
+                const { slice } = String.prototype;
+                const { isArray } = Array;
+                const [ArrayBuffer_, Array_, Date_, RegExp_, Map_, Set_, BigInt_, Uint8Array_] =
+                    [ArrayBuffer, Array, Date, RegExp, Map, Set, BigInt, Uint8Array];
+                const TypedArray = getPrototypeOf(Uint8Array).prototype;
+                const typedArrayName = getter(TypedArray, Symbol.toStringTag);
+                const typedArrayBuffer = getter(TypedArray, "buffer");
+                const typedArrayOffset = getter(TypedArray, "byteOffset");
+                const typedArrayLength = getter(TypedArray, "length");
+                const typedArraySet = TypedArray.set;
+                const dataViewBuffer = getter(DataView.prototype, "buffer");
+                const dataViewOffset = getter(DataView.prototype, "byteOffset");
+                const dataViewLength = getter(DataView.prototype, "byteLength");
+                const regExpSource = getter(RegExp.prototype, "source");
+                const regExpFlags = getter(RegExp.prototype, "flags");
+                const { get: mapGet, set: mapSet, forEach: mapForEach } = Map.prototype;
+                const mapSize = getter(Map.prototype, "size");
+                const { add: setAdd, forEach: setForEach } = Set.prototype;
+                const dateTime = Date.prototype.getTime;
+                const booleanValue = Boolean.prototype.valueOf;
+                const numberValue = Number.prototype.valueOf;
+                const stringValue = String.prototype.valueOf;
+                const views = {
+                    __proto__: null,
+                    Int8Array, Uint8Array, Uint8ClampedArray, Int16Array, Uint16Array, Int32Array,
+                    Uint32Array, Float32Array, Float64Array, BigInt64Array, BigUint64Array,
+                    DataView,
+                };
+                const errors = {
+                    __proto__: null,
+                    Error, EvalError, RangeError, ReferenceError, SyntaxError, TypeError, URIError,
+                };
+                // Each of these throws unless called on an object of its native class, which can
+                // not be faked with `Symbol.toStringTag` or a prototype.
+                const brands = [
+                    ["ArrayBuffer", getter(ArrayBuffer.prototype, "byteLength")],
+                    ["DataView", dataViewLength],
+                    ["Map", mapSize],
+                    ["Set", getter(Set.prototype, "size")],
+                    ["Date", dateTime],
+                    ["RegExp", regExpSource],
+                    ["Boolean", booleanValue],
+                    ["Number", numberValue],
+                    ["String", stringValue],
+                ];
+                if (typeof SharedArrayBuffer === "function") {
+                    const byteLength = getter(SharedArrayBuffer.prototype, "byteLength");
+                    brands.push(["SharedArrayBuffer", byteLength]);
+                }
+                function is(brand, v) {
+                    try {
+                        call(brand, v);
+                        return true;
+                    } catch {
+                        return false;
+                    }
+                }
+                function kindOf(v) {
+                    const name = call(typedArrayName, v);
+                    if (name !== undefined) return name;
+                    // Indexed, as array iterators can be replaced by scripts.
+                    for (let i = 0; i < brands.length; i++) {
+                        if (is(brands[i][1], v)) return brands[i][0];
+                    }
+                    if (isArray(v)) return "Array";
+                    // Errors have no brand to check, a fake one is only cloned as an error.
+                    const tag = call(slice, call(toString, v), 8, -1);
+                    return tag in errors ? tag : "Object";
+                }
+                function entries(forEach, v) {
+                    const out = [];
+                    call(forEach, v, (value, key) => defineProperty(out, out.length, {
+                        value: forEach === mapForEach ? [key, value] : value,
+                        writable: true, enumerable: true, configurable: true,
+                    }));
+                    return out;
+                }
+                return {
+                    memo: () => new Map_(),
+                    // Returns the type of a value, or the id of an object that has already been
+                    // visited.
+                    classify(v, memo) {
+                        if (v === null) return "null";
+                        const t = typeof v;
+                        if (t !== "object") return t;
+                        const id = call(mapGet, memo, v);
+                        if (id !== undefined) return id;
+                        const kind = kindOf(v);
+                        // Wrappers are cloned as their primitive values, which take no slot in the
+                        // graph.
+                        if (kind !== "Boolean" && kind !== "Number" && kind !== "String") {
+                            call(mapSet, memo, v, call(mapSize, memo));
+                        }
+                        return kind;
+                    },
+                    // Extracts what is needed to clone an object of the given type.
+                    extract(v, tag) {
+                        switch (tag) {
+                            case "bigint": return String(v);
+                            case "Date": return call(dateTime, v);
+                            case "RegExp": return [call(regExpSource, v), call(regExpFlags, v)];
+                            case "Map": return entries(mapForEach, v);
+                            case "Set": return entries(setForEach, v);
+                            case "ArrayBuffer": return new Uint8Array_(v);
+                            case "DataView":
+                                return [
+                                    call(dataViewBuffer, v),
+                                    call(dataViewOffset, v),
+                                    call(dataViewLength, v),
+                                ];
+                            case "Error": {
+                                const stack = v.stack === undefined ? undefined : String(v.stack);
+                                return [String(v.name), String(v.message), stack];
+                            }
+                            case "Boolean": return call(booleanValue, v);
+                            case "Number": return call(numberValue, v);
+                            case "String": return call(stringValue, v);
+                            default:
+                                if (tag in views) {
+                                    return [
+                                        call(typedArrayBuffer, v),
+                                        call(typedArrayOffset, v),
+                                        call(typedArrayLength, v),
+                                    ];
+                                }
+                                return keys(v);
+                        }
+                    },
+                    // Creates an empty object of the given type.
+                    create(tag, a, b, c) {
+                        switch (tag) {
+                            case "Object": return {};
+                            case "Array": return new Array_(a);
+                            case "Date": return new Date_(a);
+                            case "RegExp": return new RegExp_(a, b);
+                            case "Map": return new Map_();
+                            case "Set": return new Set_();
+                            case "bigint": return BigInt_(a);
+                            case "ArrayBuffer": {
+                                const buffer = new ArrayBuffer_(call(typedArrayLength, a));
+                                call(typedArraySet, new Uint8Array_(buffer), a);
+                                return buffer;
+                            }
+                            case "Error": {
+                                const e = new (errors[a] || errors.Error)(b);
+                                if (c !== undefined) {
+                                    const stack = { value: c, writable: true, configurable: true };
+                                    defineProperty(e, "stack", stack);
+                                }
+                                return e;
+                            }
+                            default:
+                                if (!(tag in views)) {
+                                    throw new TypeError(`DataCloneError: unknown type ${tag}`);
+                                }
+                                return construct(views[tag], [a, b, c]);
+                        }
+                    },
+                    insert(collection, k, v, isMap) {
+                        if (isMap) call(mapSet, collection, k, v); else call(setAdd, collection, k);
+                    },
+                    // Resolves an entry of a transfer list to its ArrayBuffer.
+                    transferable(v) {
+                        if (v !== null && typeof v === "object") {
+                            const kind = kindOf(v);
+                            if (kind === "ArrayBuffer") return v;
+                            if (kind === "DataView") return call(dataViewBuffer, v);
+                            if (kind in views) return call(typedArrayBuffer, v);
+                        }
+                        throw new TypeError(
+                            "DataCloneError: only ArrayBuffers and typed arrays can be transferred"
+                        );
+                    },
+                };
+            })()
+            "#
+        )))
+        .map_err(js::Error::msg)
+    })
+    .context("failed to create the structured clone helpers")
+}
+
+fn helper(ctx: &js::Context, name: &str) -> Result<js::Value> {
+    Ok(helpers(ctx)?.get_property(name)?)
+}
+
+fn data_clone_error(what: &str) -> anyhow::Error {
+    anyhow!("DataCloneError: {what} could not be cloned")
+}
+
+/// Serializes a value with the structured clone algorithm.
+pub(crate) fn serialize(value: &js::Value) -> Result<Cloned> {
+    match value {
+        js::Value::Undefined => return Ok(Cloned::Undefined),
+        js::Value::Null => return Ok(Cloned::Null),
+        _ => {}
+    }
+    let ctx = value.context()?;
+    let ctx: &js::Context = &ctx;
+    let memo = helper(ctx, "memo")?.call(&js::Value::undefined(), &[])?;
+    let mut serializer = Serializer {
+        ctx,
+        memo,
+        classify: helper(ctx, "classify")?,
+        extract: helper(ctx, "extract")?,
+    };
+    serializer.serialize(value, 0)
+}
+
+struct Serializer<'a> {
+    ctx: &'a js::Context,
+    memo: js::Value,
+    classify: js::Value,
+    extract: js::Value,
+}
+
+impl Serializer<'_> {
+    fn extract(&self, value: &js::Value, tag: &str) -> Result<js::Value> {
+        Ok(self.extract.call(
+            &js::Value::undefined(),
+            &[value.clone(), self.ctx.new_string(tag)],
+        )?)
+    }
+
+    fn props(
+        &mut self,
+        value: &js::Value,
+        keys: js::Value,
+        depth: usize,
+    ) -> Result<Vec<(String, Cloned)>> {
+        let keys: Vec<String> = keys.decode()?;
+        let mut props = Vec::with_capacity(keys.len());
+        for key in keys {
+            let item = value.get_property(&key)?;
+            props.push((key, self.serialize(&item, depth + 1)?));
+        }
+        Ok(props)
+    }
+
+    fn serialize(&mut self, value: &js::Value, depth: usize) -> Result<Cloned> {
+        if depth > MAX_DEPTH {
+            bail!("DataCloneError: the object graph is too deep");
+        }
+        if value.is_undefined() {
+            return Ok(Cloned::Undefined);
+        }
+        let kind = self
+            .classify
+            .call(&js::Value::undefined(), &[value.clone(), self.memo.clone()])?;
+        if !kind.is_string() {
+            return Ok(Cloned::Ref(kind.decode()?));
+        }
+        let tag = kind.decode_string()?;
+        let cloned = match tag.as_str() {
+            "null" => Cloned::Null,
+            "boolean" => Cloned::Bool(value.decode()?),
+            "number" => Cloned::Number(value.decode()?),
+            "string" => Cloned::String(value.decode_string()?),
+            "bigint" => Cloned::BigInt(self.extract(value, &tag)?.decode_string()?),
+            "symbol" => return Err(data_clone_error("a Symbol")),
+            "function" => return Err(data_clone_error("a function")),
+            "Boolean" | "Number" | "String" => {
+                // Primitive wrappers are cloned as their primitive values, and are not numbered
+                // since the deserializer creates no object for them.
+                let primitive = self.extract(value, &tag)?;
+                self.serialize(&primitive, depth + 1)?
+            }
+            "Array" => {
+                let length = value.get_property("length")?.decode()?;
+                let keys = self.extract(value, &tag)?;
+                Cloned::Array {
+                    length,
+                    props: self.props(value, keys, depth)?,
+                }
+            }
+            "Date" => Cloned::Date(self.extract(value, &tag)?.decode()?),
+            "RegExp" => {
+                let (source, flags) = self.extract(value, &tag)?.decode()?;
+                Cloned::RegExp { source, flags }
+            }
+            "Map" => {
+                let entries: Vec<js::Value> = self.extract(value, &tag)?.decode()?;
+                let mut cloned = Vec::with_capacity(entries.len());
+                for entry in entries {
+                    let key = self.serialize(&entry.get_property("0")?, depth + 1)?;
+                    let value = self.serialize(&entry.get_property("1")?, depth + 1)?;
+                    cloned.push((key, value));
+                }
+                Cloned::Map(cloned)
+            }
+            "Set" => {
+                let items: Vec<js::Value> = self.extract(value, &tag)?.decode()?;
+                let mut cloned = Vec::with_capacity(items.len());
+                for item in items {
+                    cloned.push(self.serialize(&item, depth + 1)?);
+                }
+                Cloned::Set(cloned)
+            }
+            "ArrayBuffer" => Cloned::ArrayBuffer(self.extract(value, &tag)?.decode_bytes()?),
+            "SharedArrayBuffer" => return Err(data_clone_error("a SharedArrayBuffer")),
+            _ if ERRORS.contains(&tag.as_str()) => {
+                let (name, message, stack) = self.extract(value, "Error")?.decode()?;
+                Cloned::Error {
+                    name,
+                    message,
+                    stack,
+                }
+            }
+            _ if TYPED_ARRAYS.contains(&tag.as_str()) => {
+                let info = self.extract(value, &tag)?;
+                let buffer = self.serialize(&info.get_property("0")?, depth + 1)?;
+                Cloned::View {
+                    kind: tag,
+                    buffer: Box::new(buffer),
+                    byte_offset: info.get_property("1")?.decode()?,
+                    length: info.get_property("2")?.decode()?,
+                }
+            }
+            _ => {
+                // Any other object, including class instances, is cloned as a plain object
+                // with its own enumerable properties.
+                let keys = self.extract(value, "Object")?;
+                Cloned::Object(self.props(value, keys, depth)?)
+            }
+        };
+        Ok(cloned)
+    }
+}
+
+/// Materializes a cloned value in the given context.
+pub(crate) fn deserialize(ctx: &js::Context, value: &Cloned) -> Result<js::Value> {
+    let mut deserializer = Deserializer {
+        ctx,
+        create: helper(ctx, "create")?,
+        insert: helper(ctx, "insert")?,
+        objects: vec![],
+    };
+    deserializer.deserialize(value)
+}
+
+struct Deserializer<'a> {
+    ctx: &'a js::Context,
+    create: js::Value,
+    insert: js::Value,
+    /// Objects in the order they were visited while serializing.
+    objects: Vec<js::Value>,
+}
+
+impl Deserializer<'_> {
+    fn create(&mut self, tag: &str, args: &[js::Value]) -> Result<js::Value> {
+        let mut call_args = vec![self.ctx.new_string(tag)];
+        call_args.extend_from_slice(args);
+        let object = self.create.call(&js::Value::undefined(), &call_args)?;
+        Ok(object)
+    }
+
+    fn register(&mut self, object: js::Value) -> js::Value {
+        self.objects.push(object.clone());
+        object
+    }
+
+    fn set_props(&mut self, object: &js::Value, props: &[(String, Cloned)]) -> Result<()> {
+        for (key, value) in props {
+            let value = self.deserialize(value)?;
+            object.set_property(key, &value)?;
+        }
+        Ok(())
+    }
+
+    fn deserialize(&mut self, value: &Cloned) -> Result<js::Value> {
+        let ctx = self.ctx;
+        let value = match value {
+            Cloned::Undefined => js::Value::undefined(),
+            Cloned::Null => js::Value::null(),
+            Cloned::Bool(b) => b.to_js_value(ctx)?,
+            Cloned::Number(n) => n.to_js_value(ctx)?,
+            Cloned::String(s) => ctx.new_string(s),
+            Cloned::BigInt(digits) => self.create("bigint", &[ctx.new_string(digits)])?,
+            Cloned::Ref(id) => self
+                .objects
+                .get(*id as usize)
+                .cloned()
+                .ok_or_else(|| anyhow!("DataCloneError: invalid object reference {id}"))?,
+            Cloned::Object(props) => {
+                let object = self.create("Object", &[])?;
+                let object = self.register(object);
+                self.set_props(&object, props)?;
+                object
+            }
+            Cloned::Array { length, props } => {
+                let array = self.create("Array", &[length.to_js_value(ctx)?])?;
+                let array = self.register(array);
+                self.set_props(&array, props)?;
+                array
+            }
+            Cloned::Date(time) => {
+                let date = self.create("Date", &[time.to_js_value(ctx)?])?;
+                self.register(date)
+            }
+            Cloned::RegExp { source, flags } => {
+                let regexp =
+                    self.create("RegExp", &[ctx.new_string(source), ctx.new_string(flags)])?;
+                self.register(regexp)
+            }
+            Cloned::Map(entries) => {
+                let map = self.create("Map", &[])?;
+                let map = self.register(map);
+                for (key, value) in entries {
+                    let key = self.deserialize(key)?;
+                    let value = self.deserialize(value)?;
+                    let is_map = true.to_js_value(ctx)?;
+                    self.insert
+                        .call(&js::Value::undefined(), &[map.clone(), key, value, is_map])?;
+                }
+                map
+            }
+            Cloned::Set(items) => {
+                let set = self.create("Set", &[])?;
+                let set = self.register(set);
+                for item in items {
+                    let item = self.deserialize(item)?;
+                    let is_map = false.to_js_value(ctx)?;
+                    self.insert.call(
+                        &js::Value::undefined(),
+                        &[set.clone(), item, js::Value::undefined(), is_map],
+                    )?;
+                }
+                set
+            }
+            Cloned::ArrayBuffer(bytes) => {
+                let bytes = js::AsBytes(bytes.clone()).to_js_value(ctx)?;
+                let buffer = self.create("ArrayBuffer", &[bytes])?;
+                self.register(buffer)
+            }
+            Cloned::View {
+                kind,
+                buffer,
+                byte_offset,
+                length,
+            } => {
+                if !TYPED_ARRAYS.contains(&kind.as_str()) {
+                    bail!("DataCloneError: unknown view type {kind}");
+                }
+                // The view was numbered before its buffer, keep its slot.
+                let slot = self.objects.len();
+                self.objects.push(js::Value::undefined());
+                let buffer = self.deserialize(buffer)?;
+                let view = self.create(
+                    kind,
+                    &[
+                        buffer,
+                        byte_offset.to_js_value(ctx)?,
+                        length.to_js_value(ctx)?,
+                    ],
+                )?;
+                self.objects[slot] = view.clone();
+                view
+            }
+            Cloned::Error {
+                name,
+                message,
+                stack,
+            } => {
+                let stack = match stack {
+                    Some(stack) => ctx.new_string(stack),
+                    None => js::Value::undefined(),
+                };
+                let error = self.create(
+                    "Error",
+                    &[ctx.new_string(name), ctx.new_string(message), stack],
+                )?;
+                self.register(error)
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// Clones a value into another context.
+pub(crate) fn clone_into(ctx: &js::Context, value: &js::Value) -> Result<js::Value> {
+    deserialize(ctx, &serialize(value)?)
+}
+
+/// Resolves the ArrayBuffers of a transfer list, to be detached with [`detach_buffers`] once
+/// the message has been serialized.
+pub(crate) fn transferred_buffers(transfer: &[js::Value]) -> Result<Vec<js::Value>> {
+    let mut buffers = Vec::with_capacity(transfer.len());
+    for item in transfer {
+        let ctx = item.context()?;
+        let resolve = helper(&ctx, "transferable")?;
+        buffers.push(resolve.call(&js::Value::undefined(), &[item.clone()])?);
+    }
+    Ok(buffers)
+}
+
+/// Detaches transferred buffers so that the sender can no longer use them. QuickJS can not hand
+/// the memory of a buffer over, so the bytes have been copied into the message.
+pub(crate) fn detach_buffers(buffers: &[js::Value]) -> Result<()> {
+    for buffer in buffers {
+        let ctx = buffer.context()?;
+        unsafe { js::c::JS_DetachArrayBuffer(ctx.as_ptr(), *buffer.raw_value()) };
+    }
+    Ok(())
+}
@@ -0,0 +1,526 @@
+//! The binary wire format of [`Cloned`] values.
+//!
+//! A message starts with the magic byte `0xC5` and a format version, followed by one value.
+//! Each value is a one-byte tag and its payload. Lengths, counts, object ids and small integers
+//! are LEB128 varints, integers are zigzag encoded, floats are little-endian `f64`.
+use alloc::vec::Vec;
+
+use anyhow::{bail, Context as _, Result};
+
+use super::{Cloned, MAX_DEPTH, TYPED_ARRAYS};
+
+const MAGIC: u8 = 0xC5;
+const VERSION: u8 = 1;
+
+mod tag {
+    pub const UNDEFINED: u8 = b'_';
+    pub const NULL: u8 = b'0';
+    pub const TRUE: u8 = b'T';
+    pub const FALSE: u8 = b'F';
+    pub const INT: u8 = b'I';
+    pub const NUMBER: u8 = b'N';
+    pub const BIGINT: u8 = b'Z';
+    pub const STRING: u8 = b'S';
+    pub const REF: u8 = b'^';
+    pub const OBJECT: u8 = b'o';
+    pub const ARRAY: u8 = b'a';
+    pub const DATE: u8 = b'D';
+    pub const REGEXP: u8 = b'R';
+    pub const MAP: u8 = b'M';
+    pub const SET: u8 = b'E';
+    pub const ARRAY_BUFFER: u8 = b'B';
+    pub const VIEW: u8 = b'V';
+    pub const ERROR: u8 = b'!';
+}
+
+/// Encodes a value to the wire format.
+pub(crate) fn encode(value: &Cloned) -> Vec<u8> {
+    let mut out = vec![MAGIC, VERSION];
+    encode_value(&mut out, value);
+    out
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_varint(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn put_props(out: &mut Vec<u8>, props: &[(String, Cloned)]) {
+    put_varint(out, props.len() as u64);
+    for (key, value) in props {
+        put_str(out, key);
+        encode_value(out, value);
+    }
+}
+
+fn encode_value(out: &mut Vec<u8>, value: &Cloned) {
+    match value {
+        Cloned::Undefined => out.push(tag::UNDEFINED),
+        Cloned::Null => out.push(tag::NULL),
+        Cloned::Bool(true) => out.push(tag::TRUE),
+        Cloned::Bool(false) => out.push(tag::FALSE),
+        Cloned::Number(n) => {
+            let int = *n as i32;
+            if int as f64 == *n && !(int == 0 && n.is_sign_negative()) {
+                out.push(tag::INT);
+                put_varint(out, ((int << 1) ^ (int >> 31)) as u32 as u64);
+            } else {
+                out.push(tag::NUMBER);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+        }
+        Cloned::BigInt(digits) => {
+            out.push(tag::BIGINT);
+            put_str(out, digits);
+        }
+        Cloned::String(s) => {
+            out.push(tag::STRING);
+            put_str(out, s);
+        }
+        Cloned::Ref(id) => {
+            out.push(tag::REF);
+            put_varint(out, *id as u64);
+        }
+        Cloned::Object(props) => {
+            out.push(tag::OBJECT);
+            put_props(out, props);
+        }
+        Cloned::Array { length, props } => {
+            out.push(tag::ARRAY);
+            put_varint(out, *length as u64);
+            put_props(out, props);
+        }
+        Cloned::Date(time) => {
+            out.push(tag::DATE);
+            out.extend_from_slice(&time.to_le_bytes());
+        }
+        Cloned::RegExp { source, flags } => {
+            out.push(tag::REGEXP);
+            put_str(out, source);
+            put_str(out, flags);
+        }
+        Cloned::Map(entries) => {
+            out.push(tag::MAP);
+            put_varint(out, entries.len() as u64);
+            for (key, value) in entries {
+                encode_value(out, key);
+                encode_value(out, value);
+            }
+        }
+        Cloned::Set(items) => {
+            out.push(tag::SET);
+            put_varint(out, items.len() as u64);
+            for item in items {
+                encode_value(out, item);
+            }
+        }
+        Cloned::ArrayBuffer(bytes) => {
+            out.push(tag::ARRAY_BUFFER);
+            put_varint(out, bytes.len() as u64);
+            out.extend_from_slice(bytes);
+        }
+        Cloned::View {
+            kind,
+            buffer,
+            byte_offset,
+            length,
+        } => {
+            let kind = TYPED_ARRAYS
+                .iter()
+                .position(|k| k == kind)
+                .expect("views are validated when serialized");
+            out.push(tag::VIEW);
+            out.push(kind as u8);
+            put_varint(out, *byte_offset as u64);
+            put_varint(out, *length as u64);
+            encode_value(out, buffer);
+        }
+        Cloned::Error {
+            name,
+            message,
+            stack,
+        } => {
+            out.push(tag::ERROR);
+            put_str(out, name);
+            put_str(out, message);
+            match stack {
+                Some(stack) => {
+                    out.push(1);
+                    put_str(out, stack);
+                }
+                None => out.push(0),
+            }
+        }
+    }
+}
+
+/// Decodes a value from the wire format.
+pub(crate) fn decode(bytes: &[u8]) -> Result<Cloned> {
+    let mut reader = Reader { bytes, pos: 0 };
+    match (reader.u8()?, reader.u8()?) {
+        (MAGIC, VERSION) => {}
+        (MAGIC, version) => bail!("unsupported structured clone format version {version}"),
+        _ => bail!("not a structured clone message"),
+    }
+    let value = reader.value(0)?;
+    if reader.pos != bytes.len() {
+        bail!("trailing bytes after the structured clone message");
+    }
+    Ok(value)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        let end = self.pos.checked_add(len).context("invalid length")?;
+        let slice = self
+            .bytes
+            .get(self.pos..end)
+            .context("truncated structured clone message")?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn f64(&mut self) -> Result<f64> {
+        let bytes = self.take(8)?;
+        Ok(f64::from_le_bytes(bytes.try_into().expect("8 bytes")))
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("varint overflow")
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.varint()?.try_into().context("value out of range")
+    }
+
+    /// Reads a count of items, each taking at least one byte.
+    fn count(&mut self) -> Result<usize> {
+        let count = self.varint()? as usize;
+        if count > self.bytes.len() - self.pos {
+            bail!("truncated structured clone message");
+        }
+        Ok(count)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.varint()? as usize;
+        let bytes = self.take(len)?;
+        Ok(core::str::from_utf8(bytes)
+            .context("invalid utf-8 in structured clone message")?
+            .to_string())
+    }
+
+    fn props(&mut self, depth: usize) -> Result<Vec<(String, Cloned)>> {
+        let count = self.count()?;
+        let mut props = Vec::with_capacity(count);
+        for _ in 0..count {
+            let key = self.string()?;
+            props.push((key, self.value(depth + 1)?));
+        }
+        Ok(props)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Cloned> {
+        if depth > MAX_DEPTH {
+            bail!("the structured clone message is too deep");
+        }
+        let value = match self.u8()? {
+            tag::UNDEFINED => Cloned::Undefined,
+            tag::NULL => Cloned::Null,
+            tag::TRUE => Cloned::Bool(true),
+            tag::FALSE => Cloned::Bool(false),
+            tag::INT => {
+                let zigzag = self.u32()?;
+                Cloned::Number(((zigzag >> 1) as i32 ^ -((zigzag & 1) as i32)) as f64)
+            }
+            tag::NUMBER => Cloned::Number(self.f64()?),
+            tag::BIGINT => Cloned::BigInt(self.string()?),
+            tag::STRING => Cloned::String(self.string()?),
+            tag::REF => Cloned::Ref(self.u32()?),
+            tag::OBJECT => Cloned::Object(self.props(depth)?),
+            tag::ARRAY => {
+                let length = self.u32()?;
+                Cloned::Array {
+                    length,
+                    props: self.props(depth)?,
+                }
+            }
+            tag::DATE => Cloned::Date(self.f64()?),
+            tag::REGEXP => Cloned::RegExp {
+                source: self.string()?,
+                flags: self.string()?,
+            },
+            tag::MAP => {
+                let count = self.count()?;
+                let mut entries = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let key = self.value(depth + 1)?;
+                    entries.push((key, self.value(depth + 1)?));
+                }
+                Cloned::Map(entries)
+            }
+            tag::SET => {
+                let count = self.count()?;
+                let mut items = Vec::with_capacity(count);
+                for _ in 0..count {
+                    items.push(self.value(depth + 1)?);
+                }
+                Cloned::Set(items)
+            }
+            tag::ARRAY_BUFFER => {
+                let len = self.varint()? as usize;
+                Cloned::ArrayBuffer(self.take(len)?.to_vec())
+            }
+            tag::VIEW => {
+                let kind = self.u8()?;
+                let kind = TYPED_ARRAYS
+                    .get(kind as usize)
+                    .with_context(|| format!("unknown view type {kind}"))?;
+                let byte_offset = self.u32()?;
+                let length = self.u32()?;
+                Cloned::View {
+                    kind: kind.to_string(),
+                    buffer: Box::new(self.value(depth + 1)?),
+                    byte_offset,
+                    length,
+                }
+            }
+            tag::ERROR => {
+                let name = self.string()?;
+                let message = self.string()?;
+                let stack = match self.u8()? {
+                    0 => None,
+                    _ => Some(self.string()?),
+                };
+                Cloned::Error {
+                    name,
+                    message,
+                    stack,
+                }
+            }
+            tag => bail!("unknown structured clone tag {tag:#04x}"),
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(value: &Cloned) -> Cloned {
+        decode(&encode(value)).unwrap()
+    }
+
+    fn nested(depth: usize) -> Cloned {
+        (0..depth).fold(Cloned::Null, |inner, _| Cloned::Array {
+            length: 1,
+            props: vec![("0".into(), inner)],
+        })
+    }
+
+    /// A graph with every kind of value, and a reference back to the root.
+    fn sample() -> Cloned {
+        let buffer = Cloned::ArrayBuffer(vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        Cloned::Object(vec![
+            ("undefined".into(), Cloned::Undefined),
+            ("null".into(), Cloned::Null),
+            (
+                "bools".into(),
+                Cloned::Set(vec![Cloned::Bool(true), Cloned::Bool(false)]),
+            ),
+            (
+                "big".into(),
+                Cloned::BigInt("-123456789012345678901234567890".into()),
+            ),
+            ("text".into(), Cloned::String("héllo, wörld".into())),
+            (
+                "sparse".into(),
+                Cloned::Array {
+                    length: 10,
+                    props: vec![
+                        ("3".into(), Cloned::Number(1.5)),
+                        ("x".into(), Cloned::Ref(0)),
+                    ],
+                },
+            ),
+            ("date".into(), Cloned::Date(1.7e12)),
+            (
+                "re".into(),
+                Cloned::RegExp {
+                    source: "a+b".into(),
+                    flags: "gi".into(),
+                },
+            ),
+            (
+                "map".into(),
+                Cloned::Map(vec![(Cloned::String("k".into()), Cloned::Ref(2))]),
+            ),
+            (
+                "view".into(),
+                Cloned::View {
+                    kind: "Uint16Array".into(),
+                    buffer: Box::new(buffer),
+                    byte_offset: 2,
+                    length: 3,
+                },
+            ),
+            (
+                "error".into(),
+                Cloned::Error {
+                    name: "TypeError".into(),
+                    message: "boom".into(),
+                    stack: Some("at f (a.js:1:1)".into()),
+                },
+            ),
+            (
+                "bare error".into(),
+                Cloned::Error {
+                    name: "Error".into(),
+                    message: String::new(),
+                    stack: None,
+                },
+            ),
+        ])
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let value = sample();
+        assert_eq!(round_trip(&value), value);
+    }
+
+    #[test]
+    fn numbers_round_trip() {
+        for n in [
+            0.0,
+            1.0,
+            -1.0,
+            63.0,
+            64.0,
+            -65.0,
+            i32::MAX as f64,
+            i32::MIN as f64,
+            i32::MAX as f64 + 1.0,
+            0.1,
+            -1e300,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+        ] {
+            assert_eq!(round_trip(&Cloned::Number(n)), Cloned::Number(n), "{n}");
+        }
+        let Cloned::Number(zero) = round_trip(&Cloned::Number(-0.0)) else {
+            panic!("not a number");
+        };
+        assert!(zero == 0.0 && zero.is_sign_negative(), "-0 keeps its sign");
+        let Cloned::Number(nan) = round_trip(&Cloned::Number(f64::NAN)) else {
+            panic!("not a number");
+        };
+        assert!(nan.is_nan());
+        // Small integers take a varint instead of 8 bytes.
+        assert_eq!(encode(&Cloned::Number(5.0)), [MAGIC, VERSION, tag::INT, 10]);
+    }
+
+    #[test]
+    fn truncated_messages() {
+        let bytes = encode(&sample());
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "truncated to {len} bytes");
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(tag::NULL);
+        assert!(decode(&trailing).is_err(), "trailing bytes");
+    }
+
+    #[test]
+    fn depth_limit() {
+        // Unoptimized frames of the decoder take a few KiB per level, more than the default
+        // stack of a test thread holds at the maximum depth.
+        let decoded = std::thread::Builder::new()
+            .stack_size(16 << 20)
+            .spawn(|| {
+                let max = decode(&encode(&nested(MAX_DEPTH))).map(|_| ());
+                let over = decode(&encode(&nested(MAX_DEPTH + 1))).map(|_| ());
+                (max, over)
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(decoded.0.is_ok());
+        let err = decoded.1.unwrap_err();
+        assert!(err.to_string().contains("too deep"), "{err}");
+    }
+
+    #[test]
+    fn malformed_messages() {
+        let message = |payload: &[u8]| [&[MAGIC, VERSION][..], payload].concat();
+        let cases: &[(&str, Vec<u8>)] = &[
+            ("empty", vec![]),
+            ("bad magic", vec![0x00, VERSION, tag::NULL]),
+            ("bad version", vec![MAGIC, VERSION + 1, tag::NULL]),
+            ("unknown tag", message(b"?")),
+            (
+                "unknown view type",
+                message(&[tag::VIEW, TYPED_ARRAYS.len() as u8, 0, 0]),
+            ),
+            ("invalid utf-8", message(&[tag::STRING, 2, 0xff, 0xfe])),
+            ("string past the end", message(&[tag::STRING, 100, b'a'])),
+            (
+                "count past the end",
+                message(&[tag::SET, 0xff, 0xff, 0xff, 0x0f]),
+            ),
+            (
+                "varint overflow",
+                message(&[
+                    tag::REF,
+                    0xff,
+                    0xff,
+                    0xff,
+                    0xff,
+                    0xff,
+                    0xff,
+                    0xff,
+                    0xff,
+                    0xff,
+                    0xff,
+                ]),
+            ),
+            (
+                "ref out of u32",
+                message(&[tag::REF, 0x80, 0x80, 0x80, 0x80, 0x10]),
+            ),
+        ];
+        for (what, bytes) in cases {
+            assert!(decode(bytes).is_err(), "{what}");
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use js::AsBytes;
+
+use super::typed_errors;
+
+pub use bind::*;
+
+pub(crate) fn setup(ns: &js::Value) -> js::Result<()> {
+    use js::NativeClass;
+    ns.define_property_fn("textEncodePrefix", encode_prefix)?;
+    let ctx = ns.context()?;
+    let global = ctx.get_global_object();
+    let encoder = typed_errors(&ctx, TextEncoder::constructor_object(ctx)?)?;
+    global.set_property("TextEncoder", &encoder)?;
+    let decoder = typed_errors(&ctx, TextDecoder::constructor_object(ctx)?)?;
+    global.set_property("TextDecoder", &decoder)?;
+    Ok(())
+}
+
+#[derive(js::ToJsValue)]
+struct EncodedPrefix {
+    /// Number of UTF-16 code units consumed from the source.
+    read: usize,
+    bytes: AsBytes<Vec<u8>>,
+}
+
+/// Encodes the longest prefix of `source` whose UTF-8 form fits in `capacity` bytes without
+/// splitting a character. `TextEncoder.prototype.encodeInto` is built on top of this in the
+/// bootcode.
+#[js::host_call]
+fn encode_prefix(source: String, capacity: usize) -> EncodedPrefix {
+    let mut read = 0;
+    let mut end = 0;
+    for ch in source.chars() {
+        if end + ch.len_utf8() > capacity {
+            break;
+        }
+        end += ch.len_utf8();
+        read += ch.len_utf16();
+    }
+    let mut bytes = source.into_bytes();
+    bytes.truncate(end);
+    EncodedPrefix {
+        read,
+        bytes: AsBytes(bytes),
+    }
+}
+
+#[js::qjsbind]
+mod bind {
+    use anyhow::{anyhow, bail};
+    use core::cell::RefCell;
+    use encoding_rs::{Decoder, DecoderResult, Encoding};
+    use js::AsBytes;
+
+    /// The WHATWG `TextEncoder`. It always encodes to UTF-8 as required by the spec.
+    #[qjs(class(js_name = "TextEncoder"))]
+    pub struct TextEncoder {}
+
+    impl TextEncoder {
+        #[qjs(constructor)]
+        fn new() -> Self {
+            Self {}
+        }
+
+        #[qjs(getter)]
+        fn encoding(&self) -> &'static str {
+            "utf-8"
+        }
+
+        #[qjs(method)]
+        fn encode(&self, input: Option<String>) -> AsBytes<Vec<u8>> {
+            AsBytes(input.unwrap_or_default().into_bytes())
+        }
+    }
+
+    #[derive(js::FromJsValue, Debug, Default)]
+    #[qjs(rename_all = "camelCase")]
+    pub struct DecoderOptions {
+        #[qjs(default)]
+        fatal: bool,
+        #[qjs(default)]
+        ignore_bom: bool,
+    }
+
+    #[derive(js::FromJsValue, Debug, Default)]
+    pub struct DecodeOptions {
+        #[qjs(default)]
+        stream: bool,
+    }
+
+    /// The WHATWG `TextDecoder`, supporting streaming decoding and the legacy encodings
+    /// provided by `encoding_rs` (utf-16le/be, windows-1252, gbk, shift_jis, ...).
+    #[qjs(class(js_name = "TextDecoder"))]
+    pub struct TextDecoder {
+        #[gc(skip)]
+        encoding: &'static Encoding,
+        #[gc(skip)]
+        fatal: bool,
+        #[gc(skip)]
+        ignore_bom: bool,
+        #[gc(skip)]
+        decoder: RefCell<Decoder>,
+    }
+
+    fn new_decoder(encoding: &'static Encoding, ignore_bom: bool) -> Decoder {
+        if ignore_bom {
+            encoding.new_decoder_without_bom_handling()
+        } else {
+            encoding.new_decoder_with_bom_removal()
+        }
+    }
+
+    impl TextDecoder {
+        #[qjs(constructor)]
+        fn new(label: Option<String>, options: Option<DecoderOptions>) -> js::Result<Self> {
+            let label = label.unwrap_or_else(|| "utf-8".into());
+            let encoding = Encoding::for_label(label.trim().as_bytes())
+                .ok_or_else(|| anyhow!("RangeError: unsupported encoding: {label}"))?;
+            if encoding.name() == "replacement" {
+                bail!("RangeError: unsupported encoding: {label}");
+            }
+            let options = options.unwrap_or_default();
+            Ok(Self {
+                encoding,
+                fatal: options.fatal,
+                ignore_bom: options.ignore_bom,
+                decoder: RefCell::new(new_decoder(encoding, options.ignore_bom)),
+            })
+        }
+
+        #[qjs(getter)]
+        fn encoding(&self) -> String {
+            self.encoding.name().to_ascii_lowercase()
+        }
+
+        #[qjs(getter)]
+        fn fatal(&self) -> bool {
+            self.fatal
+        }
+
+        #[qjs(getter, js_name = "ignoreBOM")]
+        fn ignore_bom(&self) -> bool {
+            self.ignore_bom
+        }
+
+        #[qjs(method)]
+        fn decode(
+            &self,
+            input: Option<js::Bytes>,
+            options: Option<DecodeOptions>,
+        ) -> js::Result<String> {
+            let input = input.as_ref().map(|b| b.as_bytes()).unwrap_or_default();
+            let last = !options.unwrap_or_default().stream;
+            let mut decoder = self.decoder.borrow_mut();
+            let capacity = decoder
+                .max_utf8_buffer_length(input.len())
+                .ok_or_else(|| anyhow!("RangeError: input too large"))?;
+            let mut output = String::with_capacity(capacity);
+            let malformed = if self.fatal {
+                let (result, _read) =
+                    decoder.decode_to_string_without_replacement(input, &mut output, last);
+                matches!(result, DecoderResult::Malformed(..))
+            } else {
+                let (_result, _read, _had_errors) =
+                    decoder.decode_to_string(input, &mut output, last);
+                false
+            };
+            // The decoder is reset at the end of a non-streaming call, or after an error.
+            if last || malformed {
+                *decoder = new_decoder(self.encoding, self.ignore_bom);
+            }
+            if malformed {
+                bail!(
+                    "TypeError: the encoded data was not valid for encoding {}",
+                    self.encoding()
+                );
+            }
+            Ok(output)
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use super::*;
+
+/// Wraps a native class so that the errors raised by its constructor, methods and accessors with
+/// a message starting with `TypeError: ` or `RangeError: ` are rethrown as instances of those
+/// classes, as the web specs require.
+pub(crate) fn typed_errors(ctx: &js::Context, class: js::Value) -> Result<js::Value> {
+    let wrap = ctx.get_qjsbind_object("typed_errors", || {
+        ctx.eval(&js::Code::Bytecode(qjsc::compiled!(
+            r#"
+        (function (Native) {
+            function convert(err) {
+                if (!(err instanceof Error) || err.constructor !== Error) {
+                    return err;
+                }
+                const match = /^(TypeError|RangeError): /.exec(err.message);
+                if (!match) {
+                    return err;
+                }
+                const ErrorClass = match[1] === "TypeError" ? TypeError : RangeError;
+                return new ErrorClass(err.message.slice(match[0].length));
+            }
+            function guard(f) {
+                if (typeof f !== "function") {
+                    return f;
+                }
+                return function (...args) {
+                    try {
+                        return f.apply(this, args);
+                    } catch (err) {
+                        throw convert(err);
+                    }
+                };
+            }
+            function Class(...args) {
+                if (!new.target) {
+                    throw new TypeError(`Failed to construct '${Native.name}': Please use the 'new' operator`);
+                }
+                try {
+                    return Reflect.construct(Native, args, new.target);
+                } catch (err) {
+                    throw convert(err);
+                }
+            }
+            for (const [target, source] of [[Class, Native], [Native.prototype, Native.prototype]]) {
+                for (const key of Reflect.ownKeys(source)) {
+                    if (target === Class && (key === "length" || key === "prototype")) {
+                        continue;
+                    }
+                    const desc = Object.getOwnPropertyDescriptor(source, key);
+                    if (key !== "constructor") {
+                        for (const slot of ["value", "get", "set"]) {
+                            desc[slot] = guard(desc[slot]);
+                        }
+                    }
+                    Object.defineProperty(target, key, desc);
+                }
+            }
+            Class.prototype = Native.prototype;
+            Object.defineProperty(Native.prototype, "constructor", {
+                value: Class,
+                writable: true,
+                configurable: true,
+            });
+            return Class;
+        })
+        "#
+        )))
+        .map_err(js::Error::msg)
+    })?;
+    Ok(wrap.call(&js::Value::undefined(), &[class])?)
+}
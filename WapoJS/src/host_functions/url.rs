@@ -3,10 +3,12 @@ use js::ToJsValue;
 use std::collections::BTreeMap;
 use url::{form_urlencoded, Url};
 
-use super::Result;
+use super::{typed_errors, Result};
+
+pub use bind::*;
 
 #[derive(ToJsValue, Debug)]
-struct URL {
+struct UrlParts {
     host: String,
     hostname: String,
     href: String,
@@ -21,7 +23,7 @@ struct URL {
 }
 
 #[js::host_call]
-fn parse_url(url: String, base_url: Option<String>) -> Result<URL> {
+fn parse_url(url: String, base_url: Option<String>) -> Result<UrlParts> {
     let url = match base_url {
         Some(base_url) => {
             let base_url: Url = base_url.parse().context("URL: Invalid base URL")?;
@@ -30,7 +32,7 @@ fn parse_url(url: String, base_url: Option<String>) -> Result<URL> {
         None => url.parse().context("URL: Invalid URL")?,
     };
 
-    Ok(URL {
+    Ok(UrlParts {
         hash: url.fragment().unwrap_or("").to_string(),
         host: url.host_str().unwrap_or("").to_string(),
         hostname: url.host_str().unwrap_or("").to_string(),
@@ -52,8 +54,386 @@ fn parse_search_params(query_str: String) -> Result<BTreeMap<String, String>> {
         .collect())
 }
 
+#[js::host_call]
+fn can_parse(url: js::Value, base: Option<js::Value>) -> bool {
+    bind::parse(&url, base.as_ref()).is_ok()
+}
+
 pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    use js::NativeClass;
     ns.define_property_fn("parseURL", parse_url)?;
     ns.define_property_fn("parseURLParams", parse_search_params)?;
+
+    let ctx = ns.context()?;
+    let global = ctx.get_global_object();
+    let url_constructor = Url::constructor_object(ctx)?;
+    url_constructor.define_property_fn("canParse", can_parse)?;
+    global.set_property("URL", &typed_errors(&ctx, url_constructor)?)?;
+    let params_constructor = UrlSearchParams::constructor_object(ctx)?;
+    global.set_property("URLSearchParams", &typed_errors(&ctx, params_constructor)?)?;
     Ok(())
 }
+
+#[js::qjsbind]
+mod bind {
+    use anyhow::{anyhow, bail};
+    use core::cell::RefCell;
+    use js::FromJsValue;
+    use std::rc::Rc;
+    use url::{form_urlencoded, quirks, Url as RawUrl};
+
+    type SharedUrl = Rc<RefCell<RawUrl>>;
+    type SharedList = Rc<RefCell<Vec<(String, String)>>>;
+
+    /// The WHATWG `URL` class. Setters re-serialize the URL following the spec via `url::quirks`.
+    #[qjs(class(js_name = "URL"))]
+    pub struct Url {
+        #[gc(skip)]
+        url: SharedUrl,
+        #[gc(skip)]
+        list: SharedList,
+        search_params: js::Native<UrlSearchParams>,
+    }
+
+    /// The WHATWG `URLSearchParams` class, keeping repeated keys and their order.
+    ///
+    /// When obtained from `URL.searchParams`, it shares its list with the URL so that mutations
+    /// on either side are reflected on the other.
+    #[qjs(class(js_name = "URLSearchParams"))]
+    pub struct UrlSearchParams {
+        #[gc(skip)]
+        list: SharedList,
+        #[gc(skip)]
+        url: Option<SharedUrl>,
+    }
+
+    fn stringify(value: &js::Value) -> String {
+        if let Ok(url) = value.decode::<js::Native<Url>>() {
+            return url.borrow().url.borrow().to_string();
+        }
+        if value.is_string() {
+            if let Ok(s) = value.decode_string() {
+                return s;
+            }
+        }
+        value.to_string()
+    }
+
+    pub fn parse(url: &js::Value, base: Option<&js::Value>) -> js::Result<RawUrl> {
+        let url = stringify(url);
+        let base = match base {
+            Some(base) if !base.is_undefined() => Some(
+                RawUrl::parse(&stringify(base))
+                    .map_err(|err| anyhow!("TypeError: invalid base URL: {err}"))?,
+            ),
+            _ => None,
+        };
+        RawUrl::options()
+            .base_url(base.as_ref())
+            .parse(&url)
+            .map_err(|err| anyhow!("TypeError: invalid URL: {err}"))
+    }
+
+    fn parse_query(query: &str) -> Vec<(String, String)> {
+        let query = query.strip_prefix('?').unwrap_or(query);
+        form_urlencoded::parse(query.as_bytes())
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect()
+    }
+
+    fn serialize_query(list: &[(String, String)]) -> String {
+        form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(list)
+            .finish()
+    }
+
+    impl Url {
+        #[qjs(constructor)]
+        fn new(
+            #[qjs(from_context)] ctx: js::Context,
+            url: js::Value,
+            base: Option<js::Value>,
+        ) -> js::Result<Self> {
+            let url = parse(&url, base.as_ref())?;
+            let list = Rc::new(RefCell::new(parse_query(url.query().unwrap_or(""))));
+            let url = Rc::new(RefCell::new(url));
+            let search_params = ctx.wrap_native(UrlSearchParams {
+                list: list.clone(),
+                url: Some(url.clone()),
+            })?;
+            Ok(Self {
+                url,
+                list,
+                search_params,
+            })
+        }
+
+        fn refresh_list(&self) {
+            let query = parse_query(self.url.borrow().query().unwrap_or(""));
+            *self.list.borrow_mut() = query;
+        }
+
+        #[qjs(getter)]
+        fn href(&self) -> String {
+            quirks::href(&self.url.borrow()).into()
+        }
+
+        #[qjs(setter, js_name = "href")]
+        fn set_href(&self, value: String) -> js::Result<()> {
+            let result = quirks::set_href(&mut self.url.borrow_mut(), &value);
+            if let Err(err) = result {
+                bail!("TypeError: invalid URL: {err}");
+            }
+            self.refresh_list();
+            Ok(())
+        }
+
+        #[qjs(getter)]
+        fn origin(&self) -> String {
+            quirks::origin(&self.url.borrow())
+        }
+
+        #[qjs(getter)]
+        fn protocol(&self) -> String {
+            quirks::protocol(&self.url.borrow()).into()
+        }
+
+        #[qjs(setter, js_name = "protocol")]
+        fn set_protocol(&self, value: String) {
+            // Invalid values are ignored as required by the spec.
+            let _ = quirks::set_protocol(&mut self.url.borrow_mut(), &value);
+        }
+
+        #[qjs(getter)]
+        fn username(&self) -> String {
+            quirks::username(&self.url.borrow()).into()
+        }
+
+        #[qjs(setter, js_name = "username")]
+        fn set_username(&self, value: String) {
+            let _ = quirks::set_username(&mut self.url.borrow_mut(), &value);
+        }
+
+        #[qjs(getter)]
+        fn password(&self) -> String {
+            quirks::password(&self.url.borrow()).into()
+        }
+
+        #[qjs(setter, js_name = "password")]
+        fn set_password(&self, value: String) {
+            let _ = quirks::set_password(&mut self.url.borrow_mut(), &value);
+        }
+
+        #[qjs(getter)]
+        fn host(&self) -> String {
+            quirks::host(&self.url.borrow()).into()
+        }
+
+        #[qjs(setter, js_name = "host")]
+        fn set_host(&self, value: String) {
+            let _ = quirks::set_host(&mut self.url.borrow_mut(), &value);
+        }
+
+        #[qjs(getter)]
+        fn hostname(&self) -> String {
+            quirks::hostname(&self.url.borrow()).into()
+        }
+
+        #[qjs(setter, js_name = "hostname")]
+        fn set_hostname(&self, value: String) {
+            let _ = quirks::set_hostname(&mut self.url.borrow_mut(), &value);
+        }
+
+        #[qjs(getter)]
+        fn port(&self) -> String {
+            quirks::port(&self.url.borrow()).into()
+        }
+
+        #[qjs(setter, js_name = "port")]
+        fn set_port(&self, value: String) {
+            let _ = quirks::set_port(&mut self.url.borrow_mut(), &value);
+        }
+
+        #[qjs(getter)]
+        fn pathname(&self) -> String {
+            quirks::pathname(&self.url.borrow()).into()
+        }
+
+        #[qjs(setter, js_name = "pathname")]
+        fn set_pathname(&self, value: String) {
+            quirks::set_pathname(&mut self.url.borrow_mut(), &value);
+        }
+
+        #[qjs(getter)]
+        fn search(&self) -> String {
+            quirks::search(&self.url.borrow()).into()
+        }
+
+        #[qjs(setter, js_name = "search")]
+        fn set_search(&self, value: String) {
+            quirks::set_search(&mut self.url.borrow_mut(), &value);
+            self.refresh_list();
+        }
+
+        #[qjs(getter)]
+        fn hash(&self) -> String {
+            quirks::hash(&self.url.borrow()).into()
+        }
+
+        #[qjs(setter, js_name = "hash")]
+        fn set_hash(&self, value: String) {
+            quirks::set_hash(&mut self.url.borrow_mut(), &value);
+        }
+
+        #[qjs(getter)]
+        fn search_params(&self) -> js::Native<UrlSearchParams> {
+            self.search_params.clone()
+        }
+
+        #[qjs(method, js_name = "toString")]
+        fn serialize(&self) -> String {
+            self.href()
+        }
+
+        #[qjs(method, js_name = "toJSON")]
+        fn to_json(&self) -> String {
+            self.href()
+        }
+    }
+
+    impl UrlSearchParams {
+        #[qjs(constructor)]
+        fn new(#[qjs(from_context)] ctx: js::Context, init: Option<js::Value>) -> js::Result<Self> {
+            let list = match init {
+                None => vec![],
+                Some(init) if init.is_null_or_undefined() => vec![],
+                Some(init) => {
+                    if let Ok(other) = init.decode::<js::Native<UrlSearchParams>>() {
+                        let other = other.borrow().list.borrow().clone();
+                        other
+                    } else if init.is_string() {
+                        parse_query(&init.decode_string()?)
+                    } else if init.is_array() {
+                        Vec::<(String, String)>::from_js_value(init)?
+                    } else {
+                        let entries = ctx
+                            .get_global_object()
+                            .get_property("Object")?
+                            .get_property("entries")?
+                            .call(&js::Value::undefined(), &[init])?;
+                        Vec::<(String, String)>::from_js_value(entries)?
+                    }
+                }
+            };
+            Ok(Self {
+                list: Rc::new(RefCell::new(list)),
+                url: None,
+            })
+        }
+
+        /// Runs the URLSearchParams update steps, writing the list back to the associated URL.
+        fn update(&self) {
+            let Some(url) = &self.url else {
+                return;
+            };
+            let query = serialize_query(&self.list.borrow());
+            let query = if query.is_empty() {
+                None
+            } else {
+                Some(query.as_str())
+            };
+            url.borrow_mut().set_query(query);
+        }
+
+        #[qjs(getter)]
+        fn size(&self) -> usize {
+            self.list.borrow().len()
+        }
+
+        #[qjs(method)]
+        fn append(&self, name: String, value: String) {
+            self.list.borrow_mut().push((name, value));
+            self.update();
+        }
+
+        #[qjs(method)]
+        fn delete(&self, name: String, value: Option<String>) {
+            self.list
+                .borrow_mut()
+                .retain(|(k, v)| !(*k == name && value.as_ref().map_or(true, |value| v == value)));
+            self.update();
+        }
+
+        #[qjs(method)]
+        fn get(&self, name: String) -> Option<String> {
+            self.list
+                .borrow()
+                .iter()
+                .find(|(k, _)| *k == name)
+                .map(|(_, v)| v.clone())
+        }
+
+        #[qjs(method)]
+        fn get_all(&self, name: String) -> Vec<String> {
+            self.list
+                .borrow()
+                .iter()
+                .filter(|(k, _)| *k == name)
+                .map(|(_, v)| v.clone())
+                .collect()
+        }
+
+        #[qjs(method)]
+        fn has(&self, name: String, value: Option<String>) -> bool {
+            self.list
+                .borrow()
+                .iter()
+                .any(|(k, v)| *k == name && value.as_ref().map_or(true, |value| v == value))
+        }
+
+        #[qjs(method)]
+        fn set(&self, name: String, value: String) {
+            {
+                let mut list = self.list.borrow_mut();
+                let mut value = Some(value);
+                list.retain_mut(|(k, v)| {
+                    if *k != name {
+                        return true;
+                    }
+                    match value.take() {
+                        Some(value) => {
+                            *v = value;
+                            true
+                        }
+                        None => false,
+                    }
+                });
+                if let Some(value) = value {
+                    list.push((name, value));
+                }
+            }
+            self.update();
+        }
+
+        #[qjs(method)]
+        fn sort(&self) {
+            // Stable sort by the UTF-16 code units of the names, as required by the spec.
+            self.list
+                .borrow_mut()
+                .sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            self.update();
+        }
+
+        /// Returns the name-value pairs as an array. The iterator protocol is layered on top of
+        /// this in the bootcode.
+        #[qjs(method)]
+        fn entries(&self) -> Vec<(String, String)> {
+            self.list.borrow().clone()
+        }
+
+        #[qjs(method, js_name = "toString")]
+        fn serialize(&self) -> String {
+            serialize_query(&self.list.borrow())
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! A cache of compiled modules keyed by the sha256 of their binary.
+//!
+//! Scripts tend to compile the same library again and again, e.g. on every request they serve.
+//! Compiled modules are kept per store, so per service, and evicted least recently used first
+//! once the binaries they were compiled from exceed the capacity.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use js::FromJsContext;
+use sha2::{Digest, Sha256};
+
+use super::engine::GlobalStore;
+
+/// The default capacity in bytes of wasm binaries.
+const DEFAULT_CAPACITY: usize = 32 * 1024 * 1024;
+
+type Key = [u8; 32];
+
+/// A compiled module with the custom sections wasmi drops.
+#[derive(Clone)]
+pub struct Compiled {
+    pub module: wasmi::Module,
+    pub custom_sections: Arc<Vec<(String, Vec<u8>)>>,
+}
+
+struct Entry {
+    compiled: Compiled,
+    size: usize,
+    last_used: u64,
+}
+
+pub struct ModuleCache {
+    entries: BTreeMap<Key, Entry>,
+    /// The keys of the entries by the tick they were last used at.
+    lru: BTreeMap<u64, Key>,
+    tick: u64,
+    size: usize,
+    capacity: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl Default for ModuleCache {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            size: 0,
+            capacity: DEFAULT_CAPACITY,
+            hits: 0,
+            misses: 0,
+        }
+    }
+}
+
+#[derive(js::ToJsValue, Debug)]
+pub struct Stats {
+    hits: u64,
+    misses: u64,
+    entries: usize,
+    /// The total size of the cached binaries.
+    size: usize,
+    capacity: usize,
+}
+
+pub fn key(code: &[u8]) -> Key {
+    Sha256::digest(code).into()
+}
+
+impl ModuleCache {
+    pub fn get(&mut self, key: &Key) -> Option<Compiled> {
+        self.tick += 1;
+        let Some(entry) = self.entries.get_mut(key) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.lru.remove(&entry.last_used);
+        self.lru.insert(self.tick, *key);
+        entry.last_used = self.tick;
+        Some(entry.compiled.clone())
+    }
+
+    pub fn insert(&mut self, key: Key, size: usize, compiled: Compiled) {
+        if size > self.capacity {
+            return;
+        }
+        self.tick += 1;
+        self.remove(&key);
+        self.entries.insert(
+            key,
+            Entry {
+                compiled,
+                size,
+                last_used: self.tick,
+            },
+        );
+        self.lru.insert(self.tick, key);
+        self.size += size;
+        self.evict();
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.last_used);
+            self.size -= entry.size;
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.capacity {
+            let Some((_, key)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.size -= entry.size;
+            }
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+            size: self.size,
+            capacity: self.capacity,
+        }
+    }
+}
+
+/// Returns the statistics of the module cache. Given a capacity in bytes, resizes the cache
+/// first, `0` disables it.
+#[js::host_call(with_context)]
+pub fn wasm_cache_stats(
+    ctx: js::Context,
+    _this: js::Value,
+    capacity: Option<usize>,
+) -> js::Result<Stats> {
+    let store = GlobalStore::from_js_context(&ctx)?;
+    store.with(|store| {
+        let cache = &mut store.data_mut().module_cache;
+        if let Some(capacity) = capacity {
+            cache.capacity = capacity;
+            cache.evict();
+        }
+        cache.stats()
+    })
+}
@@ -2,7 +2,9 @@ use std::sync::{Mutex, Weak};
 
 use anyhow::bail;
 
+use super::cache::ModuleCache;
 use super::externals::ExternObject;
+use super::limits::{self, Limiter, Limits};
 
 pub type EngineStore = wasmi::Store<Data>;
 
@@ -15,6 +17,10 @@ pub fn using_store<T>(store: &mut EngineStore, f: impl FnOnce() -> T) -> T {
 #[derive(Default)]
 pub struct Data {
     ref_js_values: Mutex<Vec<Weak<js::Value>>>,
+    pub(super) limiter: Limiter,
+    /// The limits of instances created without explicit ones, set by `Wapo.wasmLimits`.
+    pub(super) default_limits: Limits,
+    pub(super) module_cache: ModuleCache,
 }
 
 pub struct Store {
@@ -103,9 +109,12 @@ impl GlobalStore {
 impl js::FromJsContext for GlobalStore {
     fn from_js_context(ctx: &js::Context) -> js::Result<Self> {
         let inner = ctx.get_qjsbind_object("wasm.global_store", || {
-            let store = Store {
-                store: wasmi::Store::default(),
-            };
+            let mut config = wasmi::Config::default();
+            config.consume_fuel(true);
+            let engine = wasmi::Engine::new(&config);
+            let mut store = wasmi::Store::new(&engine, Data::default());
+            limits::init_store(&mut store);
+            let store = Store { store };
             js::Native::new_gc_obj_named(ctx, store)
         })?;
         Ok(js::FromJsValue::from_js_value(inner).expect("GlobalStore"))
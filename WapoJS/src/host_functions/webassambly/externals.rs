@@ -1,5 +1,5 @@
 use anyhow::bail;
-use js::ToJsValue;
+use js::{FromJsValue, ToJsValue};
 use wasmi::core::ValType;
 
 use crate::host_functions::webassambly::engine::EngineStore;
@@ -44,7 +44,7 @@ pub fn encode_value(
                 return Ok(js::Value::null());
             };
             let ty = f.ty(store);
-            WasmFn::new("<anonymous>".into(), ty, f.clone()).wrapped(ctx)
+            WasmFn::new("<anonymous>".into(), ty, f.clone(), None).wrapped(ctx)
         }
         wasmi::Val::ExternRef(val) => {
             let Some(ext) = val.data(store) else {
@@ -67,11 +67,35 @@ pub fn decode_type(ty: &str) -> js::Result<ValType> {
         "f64" => F64,
         "anyfunc" => FuncRef,
         "externref" => ExternRef,
-        _ => bail!("invalid type"),
+        _ => bail!("TypeError: invalid value type {ty}"),
     };
     Ok(ty)
 }
 
+/// Decodes what a JS function returned for a function with the result types `results` into
+/// `outputs`, several results are returned as an array.
+pub fn decode_results(
+    store: &mut EngineStore,
+    results: &[ValType],
+    js_output: js::Value,
+    outputs: &mut [wasmi::Val],
+) -> js::Result<()> {
+    if outputs.len() != results.len() {
+        bail!("expected {} results, got {}", results.len(), outputs.len());
+    }
+    match results {
+        [] => {}
+        [ty] => outputs[0] = decode_value_or_default(store, *ty, js_output)?,
+        _ => {
+            let js_outputs = <Vec<js::Value>>::from_js_value(js_output)?;
+            for ((output, ty), val) in outputs.iter_mut().zip(results).zip(js_outputs) {
+                *output = decode_value_or_default(store, *ty, val)?;
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn decode_value_or_default(
     store: &mut EngineStore,
     ty: ValType,
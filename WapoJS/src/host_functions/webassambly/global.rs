@@ -57,7 +57,7 @@ mod bind {
             self.store.with(|store| -> js::Result<_> {
                 let ty = self.val.ty(&*store);
                 if ty.mutability().is_const() {
-                    bail!("global is immutable");
+                    bail!("TypeError: global is immutable");
                 }
                 let new_value = decode_value_or_default(store, ty.content(), val)?;
                 self.val
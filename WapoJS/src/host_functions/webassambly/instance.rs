@@ -9,19 +9,25 @@ pub fn setup(wasm_ns: &js::Value) -> js::Result<()> {
 
 #[js::qjsbind]
 mod bind {
-    use std::{collections::BTreeMap, sync::Arc};
+    use std::sync::Arc;
 
-    use anyhow::bail;
+    use anyhow::{anyhow, bail};
     use js::{ErrorContext, FromJsValue, ToJsValue};
     use log::{debug, trace};
     use wasmi::{AsContextMut, ExternType, FuncType};
 
+    #[cfg(feature = "js-wasi")]
+    use crate::host_functions::webassambly::wasi;
     use crate::host_functions::webassambly::{
         engine::{using_store, Data, EngineStore, GlobalStore},
-        externals::{decode_value_or_default, encode_value},
+        externals::{decode_results, decode_value, decode_value_or_default, encode_value},
         global::Global,
+        host_fn_wrapper,
+        limits::{metered, Budget, Limits},
+        linker::Linker,
         memory::Memory,
         module::Module,
+        promising::{self, Step, Suspend},
         table::Table,
     };
 
@@ -29,6 +35,8 @@ mod bind {
     pub struct Instance {
         #[gc(skip)]
         instance: Arc<wasmi::Instance>,
+        #[gc(skip)]
+        budget: Arc<Budget>,
         store: GlobalStore,
     }
 
@@ -36,33 +44,40 @@ mod bind {
         name: String,
         ty: FuncType,
         callback: Arc<js::Value>,
+        /// Whether the import was wrapped in a `WebAssembly.Suspending`.
+        suspending: bool,
     }
 
     unsafe impl Send for JsFn {}
     unsafe impl Sync for JsFn {}
 
     impl JsFn {
-        fn new(name: String, store: &mut EngineStore, ty: FuncType, callback: js::Value) -> Self {
+        fn new(
+            name: String,
+            store: &mut EngineStore,
+            ty: FuncType,
+            callback: js::Value,
+            suspending: bool,
+        ) -> Self {
             let callback = Arc::new(callback);
             let weak = Arc::downgrade(&callback);
             store.data().push_ref(weak);
-            Self { name, ty, callback }
+            Self {
+                name,
+                ty,
+                callback,
+                suspending,
+            }
         }
 
+        /// Calls the JS function. Returns the promise it returned if the call suspends.
         fn call(
             &self,
             mut caller: wasmi::Caller<'_, Data>,
             args: &[wasmi::Val],
             outputs: &mut [wasmi::Val],
-        ) -> js::Result<()> {
+        ) -> js::Result<Option<js::Value>> {
             trace!(target: "js::wasm", "calling ext function: {}", self.name);
-            if outputs.len() != self.ty.results().len() {
-                bail!(
-                    "expected {} results, got {}",
-                    self.ty.results().len(),
-                    outputs.len()
-                );
-            }
             let ctx = self.callback.context()?;
             let mut js_inputs = vec![];
             for arg in args {
@@ -76,27 +91,16 @@ mod bind {
                 self.callback.call(&js::Value::undefined(), &js_inputs)
             })?;
             log::trace!(target: "js::wasm", "js_output: {:?}", js_output);
-            if self.ty.results().is_empty() {
-                return Ok(());
+            if self.suspending && promising::is_promise(&js_output) {
+                return Ok(Some(js_output));
             }
-            if self.ty.results().len() == 1 {
-                let val = decode_value_or_default(
-                    &mut caller.as_context_mut().store,
-                    self.ty.results()[0],
-                    js_output,
-                )?;
-                outputs[0] = val;
-                return Ok(());
-            }
-            let js_outputs = <Vec<js::Value>>::from_js_value(js_output)?;
-            for (i, val) in js_outputs.into_iter().enumerate() {
-                outputs[i] = decode_value_or_default(
-                    &mut caller.as_context_mut().store,
-                    self.ty.results()[i],
-                    val,
-                )?;
-            }
-            Ok(())
+            decode_results(
+                &mut caller.as_context_mut().store,
+                self.ty.results(),
+                js_output,
+                outputs,
+            )?;
+            Ok(None)
         }
     }
 
@@ -107,6 +111,9 @@ mod bind {
         ty: FuncType,
         #[gc(skip)]
         func: wasmi::Func,
+        /// The budget of the instance exporting the function, if known.
+        #[gc(skip)]
+        budget: Option<Arc<Budget>>,
     }
 
     impl WasmFn {
@@ -114,8 +121,18 @@ mod bind {
             "_funcref"
         }
 
-        pub fn new(name: String, ty: FuncType, func: wasmi::Func) -> Self {
-            Self { name, ty, func }
+        pub fn new(
+            name: String,
+            ty: FuncType,
+            func: wasmi::Func,
+            budget: Option<Arc<Budget>>,
+        ) -> Self {
+            Self {
+                name,
+                ty,
+                func,
+                budget,
+            }
         }
 
         #[qjs(method)]
@@ -140,10 +157,13 @@ mod bind {
             }
             trace!(target: "js::wasm", "{} inputs : {:?}", self.name, inputs);
             let js_outputs = store.with(|store| -> js::Result<_> {
-                wasmi::with_js_context(&ctx, || -> js::Result<_> {
-                    self.func
-                        .call(&mut *store, &inputs, &mut outputs[..])
-                        .context("failed to call host function")
+                wasmi::with_js_context(&ctx, || {
+                    metered(
+                        store,
+                        self.budget.as_deref(),
+                        "failed to call host function",
+                        |store| self.func.call(store, &inputs, &mut outputs[..]),
+                    )
                 })?;
                 trace!(target: "js::wasm", "{} outputs: {:?}", self.name, outputs);
                 outputs
@@ -158,16 +178,87 @@ mod bind {
             }
         }
 
+        /// Starts a call that suspending imports may suspend, see `WebAssembly.promising`.
+        #[qjs(method)]
+        fn call_promising(
+            &self,
+            #[qjs(from_context)] ctx: js::Context,
+            #[qjs(from_context)] store: GlobalStore,
+            args: Vec<js::Value>,
+        ) -> js::Result<Step> {
+            let mut args_iter = args.into_iter();
+            store.with(|store| -> js::Result<_> {
+                let mut inputs = vec![];
+                for ty in self.ty.params().iter() {
+                    let arg = args_iter.next().unwrap_or(js::Value::undefined());
+                    inputs.push(decode_value_or_default(store, *ty, arg)?);
+                }
+                let mut outputs: Vec<_> = self
+                    .ty
+                    .results()
+                    .iter()
+                    .map(|ty| wasmi::Val::default(*ty))
+                    .collect();
+                let suspended = wasmi::with_js_context(&ctx, || {
+                    metered(
+                        store,
+                        self.budget.as_deref(),
+                        "failed to call host function",
+                        |store| {
+                            promising::suspended(self.func.call_resumable(
+                                &mut *store,
+                                &inputs,
+                                &mut outputs[..],
+                            )?)
+                        },
+                    )
+                })?;
+                Step::new(store, &ctx, suspended, outputs)
+            })?
+        }
+
+        /// Resumes a suspended call with the value the promise it was suspended on fulfilled
+        /// with.
+        #[qjs(method)]
+        fn resume(
+            &self,
+            #[qjs(from_context)] ctx: js::Context,
+            #[qjs(from_context)] store: GlobalStore,
+            suspended: js::Value,
+            value: js::Value,
+        ) -> js::Result<Step> {
+            let (invocation, results) = promising::take(&suspended)?;
+            store.with(|store| -> js::Result<_> {
+                let mut inputs: Vec<_> =
+                    results.iter().map(|ty| wasmi::Val::default(*ty)).collect();
+                decode_results(store, &results, value, &mut inputs)?;
+                let mut outputs: Vec<_> = self
+                    .ty
+                    .results()
+                    .iter()
+                    .map(|ty| wasmi::Val::default(*ty))
+                    .collect();
+                let suspended = wasmi::with_js_context(&ctx, || {
+                    metered(
+                        store,
+                        self.budget.as_deref(),
+                        "failed to call host function",
+                        |store| {
+                            promising::suspended(invocation.resume(
+                                &mut *store,
+                                &inputs,
+                                &mut outputs[..],
+                            )?)
+                        },
+                    )
+                })?;
+                Step::new(store, &ctx, suspended, outputs)
+            })?
+        }
+
         pub fn wrapped(self, ctx: &js::Context) -> js::Result<js::Value> {
             let value = ctx.wrap_native(self)?.to_js_value(&ctx)?;
-            let wrapper = ctx
-                .get_qjsbind_object("wasm.host_fn_wrapper", || {
-                    ctx.eval(&js::Code::Source(
-                        r#"(function (fn) { return function(...args){return fn.call(args);}; })"#,
-                    ))
-                    .map_err(js::Error::msg)
-                })
-                .context("failed to create host function wrapper")?;
+            let wrapper = host_fn_wrapper(ctx).context("failed to create host function wrapper")?;
             let wrapped = wrapper.call(&js::Value::null(), &[value.clone()])?;
             wrapped.set_property(WasmFn::flag_attr(), &value)?;
             Ok(wrapped)
@@ -185,10 +276,19 @@ mod bind {
             #[qjs(from_context)] store: GlobalStore,
             module: js::Native<Module>,
             imports: js::Value,
+            limits: Option<Limits>,
+            linker: Option<js::Native<Linker>>,
         ) -> js::Result<Self> {
-            let instance = store.with(|store| Self::new2(ctx, store, module, imports))??;
+            let (instance, budget) = store.with(|store| -> js::Result<_> {
+                let limits = limits.unwrap_or_default().or(store.data().default_limits);
+                let budget = Budget::new(limits);
+                let linker = linker.as_ref().map(|linker| linker.borrow());
+                let instance = Self::new2(ctx, store, module, imports, &budget, linker.as_deref())?;
+                Ok((instance, budget))
+            })??;
             Ok(Self {
                 instance: Arc::new(instance),
+                budget,
                 store,
             })
         }
@@ -198,82 +298,156 @@ mod bind {
             store: &mut EngineStore,
             module: js::Native<Module>,
             imports: js::Value,
+            budget: &Budget,
+            linked: Option<&Linker>,
         ) -> js::Result<wasmi::Instance> {
             debug!(target: "js::wasm", "creating WASM instance");
             let instance = {
                 let module = module.borrow();
                 let engine = store.engine().clone();
                 let mut linker = wasmi::Linker::<Data>::new(&engine);
+                #[cfg(feature = "js-wasi")]
+                let mut wasi_linked = std::collections::BTreeSet::new();
                 for import in module.module.imports() {
                     let module_name = import.module();
                     let field_name = import.name();
-                    let obj = imports
-                        .get_property(module_name)?
-                        .get_property(field_name)?;
+                    if let Some(instance) = linked.and_then(|linked| linked.get(module_name)) {
+                        let export = instance.get_export(&*store, field_name).ok_or_else(|| {
+                            anyhow!("LinkError: {module_name}.{field_name} is not exported by the linked instance")
+                        })?;
+                        linker
+                            .define(module_name, field_name, export)
+                            .context("failed to define linked export")?;
+                        continue;
+                    }
+                    let namespace = imports.get_property(module_name)?;
+                    // A WASI namespace is linked natively as a whole.
+                    #[cfg(feature = "js-wasi")]
+                    if wasi::is_wasi(&namespace) {
+                        if wasi_linked.insert(module_name) {
+                            wasi::link(&namespace, &mut linker, store, module_name)?;
+                        }
+                        continue;
+                    }
+                    let obj = namespace.get_property(field_name)?;
                     debug!(target: "js::wasm", "importing {module_name}.{field_name}, type={:?}", import.ty());
+                    let name = format!("{module_name}.{field_name}");
                     match import.ty().clone() {
-                        ExternType::Global(_) => {
-                            let global = <js::Native<Global>>::from_js_value(obj)?;
-                            let wasmi_global = global.borrow().raw_value().clone();
+                        ExternType::Global(ty) => {
+                            let wasmi_global = match <js::Native<Global>>::from_js_value(
+                                obj.clone(),
+                            ) {
+                                Ok(global) => global.borrow().raw_value().clone(),
+                                // Immutable globals can be imported from plain numbers.
+                                Err(_) if ty.mutability().is_const() => {
+                                    let value = decode_value(store, ty.content(), obj)
+                                        .ok()
+                                        .flatten()
+                                        .ok_or_else(|| {
+                                            anyhow!("LinkError: imported global {name} must be a number or a WebAssembly.Global")
+                                        })?;
+                                    wasmi::Global::new(&mut *store, value, wasmi::Mutability::Const)
+                                }
+                                Err(_) => {
+                                    bail!("LinkError: imported global {name} must be a WebAssembly.Global")
+                                }
+                            };
                             linker
                                 .define(module_name, field_name, wasmi_global)
                                 .context("failed to define global")?;
                         }
                         ExternType::Table(_) => {
-                            let table = <js::Native<Table>>::from_js_value(obj)?;
+                            let table = <js::Native<Table>>::from_js_value(obj).map_err(|_| {
+                                anyhow!(
+                                    "LinkError: imported table {name} must be a WebAssembly.Table"
+                                )
+                            })?;
                             let wasmi_table = table.borrow().raw_value().clone();
                             linker
                                 .define(module_name, field_name, wasmi_table)
                                 .context("failed to define table")?;
                         }
                         ExternType::Memory(_) => {
-                            let memory = <js::Native<Memory>>::from_js_value(obj)?;
+                            let memory = <js::Native<Memory>>::from_js_value(obj).map_err(|_| {
+                                anyhow!("LinkError: imported memory {name} must be a WebAssembly.Memory")
+                            })?;
                             let wasmi_memory = memory.borrow().raw_value().clone();
                             linker
                                 .define(module_name, field_name, wasmi_memory)
                                 .context("failed to define memory")?;
                         }
                         ExternType::Func(ty) => {
-                            if !obj.is_function() {
-                                bail!("imported function {module_name}.{field_name} is not a function");
+                            let (callback, suspending) =
+                                match obj.get_property(promising::SUSPENDING_ATTR) {
+                                    Ok(callback) if callback.is_function() => (callback, true),
+                                    _ => (obj, false),
+                                };
+                            if !callback.is_function() {
+                                bail!("LinkError: imported function {name} is not a function");
                             }
-                            let name = format!("{module_name}.{field_name}");
-                            let js_fn = JsFn::new(name, store, ty.clone(), obj);
+                            let js_fn = JsFn::new(name, store, ty.clone(), callback, suspending);
                             linker.func_new(
                                 module_name,
                                 field_name,
                                 ty,
-                                move |caller, args, rets| {
-                                    js_fn.call(caller, args, rets).map_err(|e| {
-                                        eprintln!("error calling imported function: {e}");
-                                        wasmi::Error::new(e.to_string())
-                                    })
+                                move |caller, args, rets| match js_fn.call(caller, args, rets) {
+                                    Ok(None) => Ok(()),
+                                    Ok(Some(promise)) => Err(wasmi::Error::host(Suspend::new(
+                                        promise,
+                                        js_fn.ty.results(),
+                                    ))),
+                                    Err(e) => Err(wasmi::Error::new(e.to_string())),
                                 },
                             )?;
                         }
                     }
                 }
                 debug!(target: "js::wasm", "instantiating module");
-                wasmi::with_js_context(&ctx, || -> js::Result<_> {
-                    let instance = linker
-                        .instantiate(&mut *store, &module.module)
-                        .context("failed to instantiate module")?;
-                    instance
-                        .ensure_no_start(&mut *store)
-                        .context("unexpected start function")
+                wasmi::with_js_context(&ctx, || {
+                    // The start function runs under the budget of the instance, a trap in it is
+                    // reported as a RuntimeError.
+                    metered(
+                        store,
+                        Some(budget),
+                        "LinkError: failed to instantiate module",
+                        |store| {
+                            linker
+                                .instantiate(&mut *store, &module.module)?
+                                .start(store)
+                        },
+                    )
                 })?
             };
             debug!(target: "js::wasm", "module instantiated");
             Ok(instance)
         }
 
+        pub fn raw_value(&self) -> &wasmi::Instance {
+            &self.instance
+        }
+
+        pub fn budget(&self) -> &Budget {
+            &self.budget
+        }
+
+        /// The fuel left to the instance, `undefined` when it is not metered.
         #[qjs(getter)]
-        fn exports(
-            &self,
-            #[qjs(from_context)] ctx: js::Context,
-        ) -> js::Result<BTreeMap<String, js::Value>> {
+        fn fuel(&self) -> Option<u64> {
+            self.budget.fuel()
+        }
+
+        /// Refuels the instance.
+        #[qjs(setter, js_name = "fuel")]
+        fn set_fuel(&self, fuel: u64) -> js::Result<()> {
+            self.budget.set_fuel(Some(fuel))
+        }
+
+        /// The exports in the order the module declares them. The JS wrapper of the class reads
+        /// this once per instance and keeps a frozen copy as the `exports` own property.
+        #[qjs(getter)]
+        fn exports(&self, #[qjs(from_context)] ctx: js::Context) -> js::Result<js::Value> {
             self.store.with(|store| -> js::Result<_> {
-                let mut output = BTreeMap::new();
+                let output = ctx.new_object("Exports");
                 for entry in self.instance.exports(&*store) {
                     let name = entry.name().to_string();
                     match entry.ty(&*store) {
@@ -283,30 +457,33 @@ mod bind {
                             };
                             let global = Global::from_raw(global, self.store.clone());
                             let js_global = ctx.wrap_native(global)?.to_js_value(&ctx)?;
-                            output.insert(name, js_global);
+                            output.set_property(&name, &js_global)?;
                         }
                         ExternType::Table(_) => {
                             let Some(table) = entry.into_table() else {
                                 continue;
                             };
-                            let table = Table::from_raw(table);
+                            let table = Table::from_raw(table, self.budget.clone());
                             let js_table = ctx.wrap_native(table)?.to_js_value(&ctx)?;
-                            output.insert(name, js_table);
+                            output.set_property(&name, &js_table)?;
                         }
                         ExternType::Memory(_) => {
                             let Some(memory) = entry.into_memory() else {
                                 continue;
                             };
-                            let memory = Memory::from_raw(memory, self.store.clone());
+                            let memory =
+                                Memory::from_raw(memory, self.store.clone(), self.budget.clone());
                             let js_memory = ctx.wrap_native(memory)?.to_js_value(&ctx)?;
-                            output.insert(name, js_memory);
+                            output.set_property(&name, &js_memory)?;
                         }
                         ExternType::Func(ty) => {
                             let Some(f) = entry.into_func() else {
                                 continue;
                             };
-                            let js_fn = WasmFn::new(name.to_string(), ty, f).wrapped(&ctx)?;
-                            output.insert(name, js_fn);
+                            let js_fn =
+                                WasmFn::new(name.to_string(), ty, f, Some(self.budget.clone()))
+                                    .wrapped(&ctx)?;
+                            output.set_property(&name, &js_fn)?;
                         }
                     }
                 }
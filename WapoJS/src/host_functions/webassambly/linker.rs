@@ -0,0 +1,92 @@
+//! A registry of instances whose exports satisfy the imports of other modules natively.
+//!
+//! `Wapo.wasmLinker()` returns a linker where instances are registered under a module name with
+//! `define`. `linker.instantiate(module, imports, limits)`, the same as
+//! `new WebAssembly.Instance(module, imports, limits, linker)`, resolves the imports from the
+//! registered instances first, so calls between the modules stay within wasmi instead of going
+//! through JS. Code of a linked instance called this way runs under the budget of the caller, so
+//! instances with limits or fuel can not be defined, and a defined instance can not be refueled.
+use anyhow::Result;
+
+pub use bind::Linker;
+
+pub(crate) fn setup(wapo: &js::Value) -> Result<()> {
+    let ctx = wapo.context()?;
+    wapo.define_property_fn("wasmLinker", wasm_linker)?;
+    // Instances are created through the `WebAssembly.Instance` constructor, so they get the
+    // same exports as any other instance.
+    ctx.eval(&js::Code::Bytecode(qjsc::compiled!(
+        r#"
+        {
+            const create = Wapo.wasmLinker;
+            let initialized = false;
+            Wapo.wasmLinker = function () {
+                const linker = create();
+                if (!initialized) {
+                    Object.getPrototypeOf(linker).instantiate = function (module, imports, limits) {
+                        return new WebAssembly.Instance(module, imports, limits, this);
+                    };
+                    initialized = true;
+                }
+                return linker;
+            };
+        }
+    "#
+    )))
+    .map_err(js::Error::msg)?;
+    Ok(())
+}
+
+#[js::host_call(with_context)]
+fn wasm_linker(ctx: js::Context, _this: js::Value) -> Result<js::Native<Linker>> {
+    Ok(ctx.wrap_native(Linker::default())?)
+}
+
+#[js::qjsbind]
+mod bind {
+    use std::{cell::RefCell, collections::BTreeMap};
+
+    use anyhow::bail;
+
+    use crate::host_functions::webassambly::instance::Instance;
+
+    #[qjs(class(js_name = "Wapo.WasmLinker"))]
+    #[derive(Default)]
+    pub struct Linker {
+        #[gc(skip)]
+        instances: RefCell<BTreeMap<String, wasmi::Instance>>,
+    }
+
+    impl Linker {
+        /// Registers the exports of `instance` as the module `name`, replacing any instance
+        /// registered under the same name. Fails if the instance has limits.
+        #[qjs(method)]
+        fn define(&self, name: String, instance: js::Native<Instance>) -> js::Result<()> {
+            if name.is_empty() {
+                bail!("TypeError: the module name must not be empty");
+            }
+            let instance = instance.borrow();
+            instance.budget().link()?;
+            let instance = instance.raw_value().clone();
+            self.instances.borrow_mut().insert(name, instance);
+            Ok(())
+        }
+
+        /// Whether an instance is registered as the module `name`.
+        #[qjs(method)]
+        fn has(&self, name: String) -> bool {
+            self.instances.borrow().contains_key(&name)
+        }
+
+        /// The names of the registered modules.
+        #[qjs(getter)]
+        fn names(&self) -> Vec<String> {
+            self.instances.borrow().keys().cloned().collect()
+        }
+
+        /// The instance registered as the module `name`.
+        pub fn get(&self, name: &str) -> Option<wasmi::Instance> {
+            self.instances.borrow().get(name).cloned()
+        }
+    }
+}
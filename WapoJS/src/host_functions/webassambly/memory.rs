@@ -9,16 +9,24 @@ pub fn setup(wasm_ns: &js::Value) -> js::Result<()> {
 
 #[js::qjsbind]
 mod bind {
+    use std::sync::Arc;
+
     use js::NoStdContext;
     use wasmi::core::Pages;
 
-    use crate::host_functions::webassambly::engine::GlobalStore;
+    use crate::host_functions::webassambly::{
+        engine::GlobalStore,
+        limits::{metered, Budget, Limits},
+    };
 
     #[qjs(class(js_name = "WebAssembly.Memory"))]
     pub struct Memory {
         #[gc(skip)]
         memory: wasmi::Memory,
         store: GlobalStore,
+        /// The budget whose limits apply when the memory is grown from JS.
+        #[gc(skip)]
+        budget: Arc<Budget>,
     }
 
     #[derive(js::FromJsValue, Debug)]
@@ -36,18 +44,33 @@ mod bind {
             #[qjs(from_context)] store: GlobalStore,
             descriptor: MemoryDescriptor,
         ) -> js::Result<Self> {
-            if descriptor.shared {
-                return Err(js::Error::msg("shared memory is not supported"));
+            // There are no threads that could share the memory, so a shared memory is a plain
+            // one. Like on the web it must declare a maximum.
+            if descriptor.shared && descriptor.maximum.is_none() {
+                return Err(js::Error::msg(
+                    "TypeError: a shared memory must have a maximum",
+                ));
             }
-            let memory = {
-                let mem_ty = wasmi::MemoryType::new(descriptor.initial, descriptor.maximum)
-                    .context("failed to create memory type")?;
-                store.with(|store| {
-                    wasmi::with_js_context(&js_ctx, || wasmi::Memory::new(store, mem_ty))
-                        .context("failed to create memory")
-                })??
-            };
-            Ok(Self { memory, store })
+            let mem_ty = wasmi::MemoryType::new(descriptor.initial, descriptor.maximum)
+                .map_err(|err| js::Error::msg(format!("RangeError: {err}")))?;
+            let (memory, budget) = store.with(|store| -> js::Result<_> {
+                // A memory created from JS is held to the default limits of the instances.
+                let budget = Budget::new(Limits {
+                    fuel: None,
+                    ..store.data().default_limits
+                });
+                let memory = wasmi::with_js_context(&js_ctx, || {
+                    metered(store, Some(&*budget), "failed to create memory", |store| {
+                        wasmi::Memory::new(store, mem_ty).map_err(wasmi::Error::from)
+                    })
+                })?;
+                Ok((memory, budget))
+            })??;
+            Ok(Self {
+                memory,
+                store,
+                budget,
+            })
         }
 
         #[qjs(method)]
@@ -56,26 +79,45 @@ mod bind {
             #[qjs(from_context)] js_ctx: js::Context,
             delta: u32,
         ) -> js::Result<u32> {
-            let additional_pages = Pages::new(delta).context("invalid number of pages")?;
+            let additional_pages =
+                Pages::new(delta).context("RangeError: invalid number of pages")?;
             let prev_pages = self.store.with(|store| {
-                wasmi::with_js_context(&js_ctx, || self.memory.grow(store, additional_pages))
-                    .context("failed to grow memory")
+                wasmi::with_js_context(&js_ctx, || {
+                    metered(
+                        store,
+                        Some(&*self.budget),
+                        "RangeError: failed to grow memory",
+                        |store| {
+                            self.memory
+                                .grow(store, additional_pages)
+                                .map_err(wasmi::Error::from)
+                        },
+                    )
+                })
             })??;
             Ok(prev_pages.into())
         }
 
+        /// The `ArrayBuffer` the linear memory lives in, so views on it read and write the
+        /// memory directly. Growing the memory, from JS or from wasm, detaches it and moves the
+        /// memory to a new buffer, like on the web.
         #[qjs(getter)]
-        pub fn buffer(&self) -> js::Result<Option<js::JsArrayBuffer>> {
+        pub fn buffer(&self) -> js::Result<js::JsArrayBuffer> {
             self.store
-                .with(|store| self.memory.js_buffer(store).cloned())
+                .with(|store| self.memory.js_buffer(store).cloned())?
+                .context("memory is not backed by an ArrayBuffer")
         }
 
         pub fn raw_value(&self) -> &wasmi::Memory {
             &self.memory
         }
 
-        pub fn from_raw(memory: wasmi::Memory, store: GlobalStore) -> Self {
-            Self { memory, store }
+        pub fn from_raw(memory: wasmi::Memory, store: GlobalStore, budget: Arc<Budget>) -> Self {
+            Self {
+                memory,
+                store,
+                budget,
+            }
         }
     }
 }
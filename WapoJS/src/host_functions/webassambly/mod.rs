@@ -1,12 +1,18 @@
 use anyhow::Result;
 use js::FromJsContext;
 
+mod cache;
 mod engine;
 mod global;
 mod instance;
+mod limits;
+mod linker;
 mod memory;
 mod module;
+mod promising;
 mod table;
+#[cfg(feature = "js-wasi")]
+mod wasi;
 mod externals;
 
 pub(crate) fn setup(ns: &js::Value) -> Result<()> {
@@ -15,31 +21,39 @@ pub(crate) fn setup(ns: &js::Value) -> Result<()> {
     ns.set_property("WebAssembly", &wasm_ns)?;
     ctx.eval(&js::Code::Bytecode(qjsc::compiled!(
         r#"
+        for (const name of ["CompileError", "LinkError", "RuntimeError", "SuspendError"]) {
+            WebAssembly[name] = class extends Error {
+                constructor(message) {
+                    super(message);
+                    this.name = name;
+                }
+            };
+        }
         WebAssembly.compile = async function (source) {
-            return new WebAssembly.Module(new Uint8Array(source));
+            return new WebAssembly.Module(source);
         };
         WebAssembly.compileStreaming = async function (source) {
             let wasm = await source;
             if (wasm instanceof Response) {
                 if (!wasm.ok) {
-                    throw new Error("failed to fetch wasm, status: " + wasm.status);
+                    throw new TypeError("failed to fetch wasm, status: " + wasm.status);
                 }
                 wasm = await wasm.arrayBuffer();
             }
             return WebAssembly.compile(wasm);
         };
-        WebAssembly.instantiate = async function (moduleOrBytes, imports) {
+        WebAssembly.instantiate = async function (moduleOrBytes, imports, limits) {
             if (moduleOrBytes instanceof WebAssembly.Module) {
-                return new WebAssembly.Instance(moduleOrBytes, imports);
+                return new WebAssembly.Instance(moduleOrBytes, imports, limits);
             } else {
                 const module = await WebAssembly.compile(moduleOrBytes);
-                const instance = await WebAssembly.instantiate(module, imports);
+                const instance = await WebAssembly.instantiate(module, imports, limits);
                 return { module, instance };
             }
         };
-        WebAssembly.instantiateStreaming = async function (source, imports) {
+        WebAssembly.instantiateStreaming = async function (source, imports, limits) {
             const module = await WebAssembly.compileStreaming(source);
-            const instance = await WebAssembly.instantiate(module, imports);
+            const instance = await WebAssembly.instantiate(module, imports, limits);
             return { module, instance };
         };
     "#
@@ -52,11 +66,180 @@ pub(crate) fn setup(ns: &js::Value) -> Result<()> {
     instance::setup(&wasm_ns)?;
     table::setup(&wasm_ns)?;
 
+    let wapo = ns.get_property("Wapo")?;
+    wapo.define_property_fn("wasmLimits", limits::wasm_limits)?;
+    wapo.define_property_fn("wasmCacheStats", cache::wasm_cache_stats)?;
+    linker::setup(&wapo)?;
+    #[cfg(feature = "js-wasi")]
+    wasi::setup(&wapo)?;
+
     wasm_ns.define_property_fn("validate", validate)?;
     wasm_ns.define_property_fn("parseWat", parse_wat)?;
+    host_fn_wrapper(&ctx)?;
     Ok(())
 }
 
+/// Makes the native WebAssembly API behave like the JS API: errors raised by the host functions
+/// with a message starting with `CompileError: `, `LinkError: `, `RuntimeError: `,
+/// `SuspendError: `, `TypeError: ` or `RangeError: ` are rethrown as instances of those
+/// classes, buffer sources are normalized, and the exports of an instance are created once.
+///
+/// Returns the function wrapping exported functions, the wrappers are installed on the first call.
+fn host_fn_wrapper(ctx: &js::Context) -> Result<js::Value> {
+    ctx.get_qjsbind_object("wasm.host_fn_wrapper", || {
+        ctx.eval(&js::Code::Bytecode(qjsc::compiled!(
+            r#"
+        (function () {
+            const errors = {
+                CompileError: WebAssembly.CompileError,
+                LinkError: WebAssembly.LinkError,
+                RuntimeError: WebAssembly.RuntimeError,
+                SuspendError: WebAssembly.SuspendError,
+                TypeError,
+                RangeError,
+            };
+            const pattern =
+                /^(CompileError|LinkError|RuntimeError|SuspendError|TypeError|RangeError): /;
+            function convert(err) {
+                if (!(err instanceof Error) || err.constructor !== Error) {
+                    return err;
+                }
+                const match = pattern.exec(err.message);
+                return match ? new errors[match[1]](err.message.slice(match[0].length)) : err;
+            }
+            function wrap(f) {
+                return function (...args) {
+                    try {
+                        return f.apply(this, args);
+                    } catch (err) {
+                        throw convert(err);
+                    }
+                };
+            }
+            function bytes(source) {
+                if (source instanceof ArrayBuffer) {
+                    return new Uint8Array(source);
+                }
+                if (ArrayBuffer.isView(source)) {
+                    return new Uint8Array(source.buffer, source.byteOffset, source.byteLength);
+                }
+                throw new TypeError("first argument must be an ArrayBuffer or typed array object");
+            }
+            function wrapClass(name, prepare, finish) {
+                const Native = WebAssembly[name];
+                for (const obj of [Native, Native.prototype]) {
+                    for (const key of Object.getOwnPropertyNames(obj)) {
+                        const desc = Object.getOwnPropertyDescriptor(obj, key);
+                        if (key === "constructor" || key === "prototype" || !desc.configurable) {
+                            continue;
+                        }
+                        for (const kind of ["value", "get", "set"]) {
+                            if (typeof desc[kind] === "function") {
+                                desc[kind] = wrap(desc[kind]);
+                            }
+                        }
+                        Object.defineProperty(obj, key, desc);
+                    }
+                }
+                const Class = new Proxy(Native, {
+                    construct(target, args, newTarget) {
+                        let obj;
+                        try {
+                            if (prepare) {
+                                args = prepare(args);
+                            }
+                            const ctor = newTarget === Class ? target : newTarget;
+                            obj = Reflect.construct(target, args, ctor);
+                        } catch (err) {
+                            throw convert(err);
+                        }
+                        return finish ? finish(obj) : obj;
+                    },
+                    apply() {
+                        throw new TypeError(`WebAssembly.${name} must be invoked with 'new'`);
+                    },
+                });
+                WebAssembly[name] = Class;
+            }
+
+            const exportsOf = Object.getOwnPropertyDescriptor(
+                WebAssembly.Instance.prototype,
+                "exports"
+            ).get;
+            wrapClass("Module", ([source, ...rest]) => [bytes(source), ...rest]);
+            wrapClass("Instance", ([module, imports, limits, linker]) => {
+                if (!(module instanceof WebAssembly.Module)) {
+                    throw new TypeError("first argument must be a WebAssembly.Module");
+                }
+                if (imports !== undefined && (imports === null || typeof imports !== "object")) {
+                    throw new TypeError("second argument must be an object");
+                }
+                for (const { module: name } of WebAssembly.Module.imports(module)) {
+                    if (linker?.has(name)) {
+                        continue;
+                    }
+                    const ns = imports?.[name];
+                    if (ns === null || (typeof ns !== "object" && typeof ns !== "function")) {
+                        throw new TypeError(`import object field '${name}' is not an object`);
+                    }
+                }
+                return [module, imports, limits, linker];
+            }, instance => {
+                // The exports object is created once, like on the web.
+                const exports = Object.assign(Object.create(null), exportsOf.call(instance));
+                Object.defineProperty(instance, "exports", {
+                    value: Object.freeze(exports),
+                    enumerable: true,
+                });
+                return instance;
+            });
+            wrapClass("Memory");
+            wrapClass("Table");
+            wrapClass("Global");
+            const validate = WebAssembly.validate;
+            WebAssembly.validate = function (source) {
+                return validate(bytes(source));
+            };
+
+            WebAssembly.Suspending = class Suspending {
+                constructor(fn) {
+                    if (typeof fn !== "function") {
+                        throw new TypeError("WebAssembly.Suspending requires a function");
+                    }
+                    Object.defineProperty(this, "_suspending", { value: fn });
+                }
+            };
+            WebAssembly.promising = function (fn) {
+                const raw = typeof fn === "function" ? fn._funcref : undefined;
+                if (raw === undefined) {
+                    throw new TypeError("WebAssembly.promising requires an exported function");
+                }
+                return async function (...args) {
+                    let step = wrap(() => raw.callPromising(args))();
+                    while (!step.done) {
+                        const value = await step.promise;
+                        step = wrap(() => raw.resume(step.suspended, value))();
+                    }
+                    return step.value;
+                };
+            };
+
+            return function (fn) {
+                return function (...args) {
+                    try {
+                        return fn.call(args);
+                    } catch (err) {
+                        throw convert(err);
+                    }
+                };
+            };
+        })()
+        "#
+        )))
+        .map_err(js::Error::msg)
+    })
+}
+
 #[js::host_call(with_context)]
 fn validate(ctx: js::Context, _this: js::Value, source: js::Bytes) -> Result<bool> {
     let store = engine::GlobalStore::from_js_context(&ctx)?;
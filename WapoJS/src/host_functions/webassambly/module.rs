@@ -9,17 +9,34 @@ pub fn setup(wasm_ns: &js::Value) -> js::Result<()> {
 
 #[js::qjsbind]
 mod bind {
-    use anyhow::{bail, Context};
+    use std::sync::Arc;
+
+    use anyhow::{anyhow, bail, Context};
     use js::{Native, Result};
     use log::debug;
     use wasmi::ExternType;
 
-    use crate::host_functions::webassambly::engine::GlobalStore;
+    use crate::host_functions::webassambly::{
+        cache::{self, Compiled},
+        engine::GlobalStore,
+    };
 
     #[qjs(class)]
     pub struct Module {
         #[gc(skip)]
         pub(crate) module: wasmi::Module,
+        /// wasmi drops the custom sections, so they are kept here.
+        #[gc(skip)]
+        custom_sections: Arc<Vec<(String, Vec<u8>)>>,
+    }
+
+    impl From<Compiled> for Module {
+        fn from(compiled: Compiled) -> Self {
+            Self {
+                module: compiled.module,
+                custom_sections: compiled.custom_sections,
+            }
+        }
     }
 
     #[derive(js::ToJsValue)]
@@ -39,17 +56,35 @@ mod bind {
         #[qjs(constructor)]
         pub fn new(#[qjs(from_context)] store: GlobalStore, code: js::Bytes) -> Result<Self> {
             debug!(target: "js::wasm", "creating WASM module, code_length={}", code.len());
+            let key = cache::key(code.as_bytes());
+            if let Some(compiled) = store.with(|store| store.data_mut().module_cache.get(&key))? {
+                debug!(target: "js::wasm", "module cache hit");
+                return Ok(compiled.into());
+            }
             let module = wasmi::Module::new(&store.engine(), &mut code.as_bytes())
-                .context("failed to parse module")?;
-            Ok(Self { module })
+                .map_err(|err| anyhow!("CompileError: {err}"))?;
+            let compiled = Compiled {
+                module,
+                custom_sections: Arc::new(parse_custom_sections(code.as_bytes())?),
+            };
+            store.with(|store| {
+                store
+                    .data_mut()
+                    .module_cache
+                    .insert(key, code.len(), compiled.clone())
+            })?;
+            Ok(compiled.into())
         }
 
         #[qjs(method)]
-        fn custom_sections(
-            _module: Native<Module>,
-            _section_name: js::JsString,
-        ) -> Result<Vec<js::Bytes>> {
-            bail!("Module.customSections not implemented")
+        fn custom_sections(module: Native<Module>, section_name: String) -> Vec<js::Bytes> {
+            module
+                .borrow()
+                .custom_sections
+                .iter()
+                .filter(|(name, _)| *name == section_name)
+                .map(|(_, data)| data.clone().into())
+                .collect()
         }
 
         #[qjs(method)]
@@ -80,6 +115,48 @@ mod bind {
         }
     }
 
+    /// Extracts the custom sections of a validated module.
+    fn parse_custom_sections(code: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+        fn leb128(code: &[u8], pos: &mut usize) -> Result<usize> {
+            let mut value = 0usize;
+            for shift in (0..35).step_by(7) {
+                let byte = *code.get(*pos).context("truncated module")?;
+                *pos += 1;
+                value |= ((byte & 0x7f) as usize) << shift;
+                if byte & 0x80 == 0 {
+                    return Ok(value);
+                }
+            }
+            bail!("invalid LEB128 in module")
+        }
+
+        let mut sections = vec![];
+        // Skips the magic and the version.
+        let mut pos = 8;
+        while pos < code.len() {
+            let id = code[pos];
+            pos += 1;
+            let size = leb128(code, &mut pos)?;
+            let end = pos.checked_add(size).context("invalid section size")?;
+            let payload = code.get(pos..end).context("truncated module")?;
+            if id == 0 {
+                let mut name_pos = 0;
+                let name_len = leb128(payload, &mut name_pos)?;
+                let name_end = name_pos
+                    .checked_add(name_len)
+                    .context("invalid custom section name")?;
+                let name = payload
+                    .get(name_pos..name_end)
+                    .context("truncated custom section")?;
+                let name =
+                    String::from_utf8(name.to_vec()).context("invalid custom section name")?;
+                sections.push((name, payload[name_end..].to_vec()));
+            }
+            pos = end;
+        }
+        Ok(sections)
+    }
+
     pub fn extern_type_kind(ty: &ExternType) -> &'static str {
         match ty {
             ExternType::Global(_) => "global",
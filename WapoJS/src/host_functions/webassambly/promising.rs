@@ -0,0 +1,152 @@
+//! JS promise integration: suspending imports and promising exports.
+//!
+//! An import wrapped in `new WebAssembly.Suspending(fn)` may return a promise. Called from an
+//! export wrapped with `WebAssembly.promising`, the wasm call is then suspended as a wasmi
+//! resumable call and the export returns a promise. Once the promise of the import settles the
+//! call is resumed with its value, or abandoned if it rejects. Called from any other export, a
+//! suspending import returning a promise throws a `WebAssembly.SuspendError`.
+use std::fmt;
+
+use anyhow::anyhow;
+use js::ToJsValue;
+use wasmi::core::{HostError, ValType};
+use wasmi::{ResumableCall, ResumableInvocation};
+
+use super::engine::EngineStore;
+use super::externals::encode_value;
+
+/// The property of a `WebAssembly.Suspending` holding the wrapped function.
+pub const SUSPENDING_ATTR: &str = "_suspending";
+
+pub fn is_promise(value: &js::Value) -> bool {
+    value
+        .get_property("then")
+        .map(|then| then.is_function())
+        .unwrap_or(false)
+}
+
+/// Returned by a suspending import to suspend the call.
+pub struct Suspend {
+    promise: js::Value,
+    /// The result types of the import.
+    results: Vec<ValType>,
+}
+
+unsafe impl Send for Suspend {}
+unsafe impl Sync for Suspend {}
+
+impl Suspend {
+    pub fn new(promise: js::Value, results: &[ValType]) -> Self {
+        Self {
+            promise,
+            results: results.to_vec(),
+        }
+    }
+}
+
+impl fmt::Debug for Suspend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Suspend")
+            .field("results", &self.results)
+            .finish()
+    }
+}
+
+impl fmt::Display for Suspend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(
+            "SuspendError: a suspending import returned a promise outside of WebAssembly.promising",
+        )
+    }
+}
+
+impl HostError for Suspend {}
+
+/// Tells a call suspended by a suspending import from one that finished. Calls stopped by any
+/// other host error fail with it.
+pub fn suspended(call: ResumableCall) -> Result<Option<ResumableInvocation>, wasmi::Error> {
+    match call {
+        ResumableCall::Finished => Ok(None),
+        ResumableCall::Resumable(invocation)
+            if invocation.host_error().downcast_ref::<Suspend>().is_some() =>
+        {
+            Ok(Some(invocation))
+        }
+        ResumableCall::Resumable(invocation) => Err(invocation.into_host_error()),
+    }
+}
+
+/// A suspended call, kept in an opaque object until it is resumed.
+struct Suspended {
+    invocation: ResumableInvocation,
+    results: Vec<ValType>,
+}
+
+/// Takes the call out of a `Step::suspended` to resume it, with the result types of the import
+/// it was suspended in.
+pub fn take(suspended: &js::Value) -> js::Result<(ResumableInvocation, Vec<ValType>)> {
+    let Suspended {
+        invocation,
+        results,
+    } = suspended
+        .opaque_object_take_data::<Suspended>()
+        .ok_or_else(|| anyhow!("TypeError: the call has already been resumed"))?;
+    Ok((invocation, results))
+}
+
+/// The outcome of starting or resuming a promising call: either the value it returned, or the
+/// promise it is suspended on.
+#[derive(js::ToJsValue)]
+pub struct Step {
+    done: bool,
+    value: Option<js::Value>,
+    promise: Option<js::Value>,
+    suspended: Option<js::Value>,
+}
+
+impl Step {
+    pub fn new(
+        store: &EngineStore,
+        ctx: &js::Context,
+        suspended: Option<ResumableInvocation>,
+        outputs: Vec<wasmi::Val>,
+    ) -> js::Result<Self> {
+        let Some(invocation) = suspended else {
+            let mut values = outputs
+                .into_iter()
+                .map(|val| encode_value(store, ctx, val))
+                .collect::<js::Result<Vec<_>>>()?;
+            let value = match values.len() {
+                0 => js::Value::undefined(),
+                1 => values.remove(0),
+                _ => values.to_js_value(ctx)?,
+            };
+            return Ok(Self {
+                done: true,
+                value: Some(value),
+                promise: None,
+                suspended: None,
+            });
+        };
+        let suspend = invocation
+            .host_error()
+            .downcast_ref::<Suspend>()
+            .expect("only calls suspended by a suspending import are kept");
+        let promise = suspend.promise.clone();
+        let results = suspend.results.clone();
+        let suspended = js::Value::new_opaque_object(
+            ctx,
+            Some("WebAssembly.Suspended"),
+            Suspended {
+                invocation,
+                results,
+            },
+        );
+        Ok(Self {
+            done: false,
+            value: None,
+            promise: Some(promise),
+            suspended: Some(suspended),
+        })
+    }
+}
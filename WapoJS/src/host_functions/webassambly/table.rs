@@ -11,17 +11,24 @@ pub fn setup(wasm_ns: &js::Value) -> Result<()> {
 
 #[js::qjsbind]
 mod bind {
-    use anyhow::anyhow;
+    use std::sync::Arc;
+
+    use anyhow::{anyhow, bail};
+    use wasmi::core::ValType;
 
     use crate::host_functions::webassambly::{
         engine::GlobalStore,
         externals::{decode_type, decode_value_or_default, encode_value},
+        limits::{metered, Budget, Limits},
     };
 
     #[qjs(class(js_name = "WebAssembly.Table"))]
     pub struct Table {
         #[gc(skip)]
         val: wasmi::Table,
+        /// The budget whose limits apply when the table is grown from JS.
+        #[gc(skip)]
+        budget: Arc<Budget>,
     }
 
     #[derive(js::FromJsValue, Debug)]
@@ -38,13 +45,27 @@ mod bind {
             descriptor: TableDescriptor,
             value: js::Value,
         ) -> js::Result<Self> {
-            let val = store.with(|store| -> js::Result<_> {
+            let (val, budget) = store.with(|store| -> js::Result<_> {
                 let ty = decode_type(&descriptor.element)?;
+                if !matches!(ty, ValType::FuncRef | ValType::ExternRef) {
+                    bail!("TypeError: invalid table element {}", descriptor.element);
+                }
                 let initial_value = decode_value_or_default(store, ty, value)?;
                 let ty = wasmi::TableType::new(ty, descriptor.initial, descriptor.maximum);
-                Ok(wasmi::Table::new(store, ty, initial_value).map_err(|e| anyhow!(e))?)
+                // A table created from JS is held to the default limits of the instances.
+                let budget = Budget::new(Limits {
+                    fuel: None,
+                    ..store.data().default_limits
+                });
+                let val = metered(
+                    store,
+                    Some(&*budget),
+                    "RangeError: failed to create table",
+                    |store| wasmi::Table::new(store, ty, initial_value).map_err(wasmi::Error::from),
+                )?;
+                Ok((val, budget))
             })??;
-            Ok(Self { val })
+            Ok(Self { val, budget })
         }
 
         #[qjs(getter)]
@@ -60,7 +81,10 @@ mod bind {
             #[qjs(from_context)] ctx: js::Context,
         ) -> js::Result<js::Value> {
             store.with(|store| {
-                let val = self.val.get(&*store, index).ok_or(anyhow!("RangeError"))?;
+                let val = self
+                    .val
+                    .get(&*store, index)
+                    .ok_or(anyhow!("RangeError: table index out of bounds"))?;
                 encode_value(store, &ctx, val)
             })?
         }
@@ -77,7 +101,7 @@ mod bind {
                 let new_value = decode_value_or_default(store, ty.element(), value)?;
                 self.val
                     .set(&mut *store, index, new_value)
-                    .map_err(|e| anyhow!(e))
+                    .map_err(|e| anyhow!("RangeError: {e}"))
             })?
         }
 
@@ -91,9 +115,16 @@ mod bind {
             store.with(|store| {
                 let ty = self.val.ty(&*store);
                 let new_value = decode_value_or_default(store, ty.element(), value)?;
-                self.val
-                    .grow(&mut *store, delta, new_value)
-                    .map_err(|e| anyhow!(e))
+                metered(
+                    store,
+                    Some(&*self.budget),
+                    "RangeError: failed to grow table",
+                    |store| {
+                        self.val
+                            .grow(&mut *store, delta, new_value)
+                            .map_err(wasmi::Error::from)
+                    },
+                )
             })?
         }
 
@@ -101,8 +132,8 @@ mod bind {
             &self.val
         }
 
-        pub fn from_raw(val: wasmi::Table) -> Self {
-            Self { val }
+        pub fn from_raw(val: wasmi::Table, budget: Arc<Budget>) -> Self {
+            Self { val, budget }
         }
     }
 }
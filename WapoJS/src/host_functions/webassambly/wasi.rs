@@ -0,0 +1,1147 @@
+//! WASI preview1 for guest WebAssembly modules.
+//!
+//! `Wapo.wasi.create(options)` returns an import object whose `wasi_snapshot_preview1` namespace
+//! is a native `Wasi` object. An instance importing from it gets the WASI functions linked in
+//! Rust, reading and writing its exported `memory` directly. Files live in an in-memory
+//! filesystem seeded from `preopens`, stdin is a fixed buffer and what the module writes to
+//! stdout and stderr is passed to JS callbacks. The files may hold `maxFsSize` bytes in total,
+//! 64 MiB by default, writes past that fail with `FBIG` or `NOSPC`.
+//!
+//! Everything runs synchronously within the wasm call: `poll_oneoff` reports every
+//! subscription as ready instead of sleeping.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use js::{FromJsValue, ToJsValue};
+use log::{error, info};
+use wasmi::{AsContextMut, Caller, Linker};
+
+use self::errno::Errno;
+use self::vfs::{Fs, Ino, Node};
+use super::engine::{using_store, Data, EngineStore};
+
+mod vfs;
+
+pub(crate) mod errno {
+    pub type Errno = u16;
+
+    pub const SUCCESS: Errno = 0;
+    pub const BADF: Errno = 8;
+    pub const EXIST: Errno = 20;
+    pub const FAULT: Errno = 21;
+    pub const FBIG: Errno = 22;
+    pub const INVAL: Errno = 28;
+    pub const IO: Errno = 29;
+    pub const ISDIR: Errno = 31;
+    pub const NOENT: Errno = 44;
+    pub const NOSPC: Errno = 51;
+    pub const NOSYS: Errno = 52;
+    pub const NOTDIR: Errno = 54;
+    pub const NOTEMPTY: Errno = 55;
+    pub const NOTSUP: Errno = 58;
+    pub const OVERFLOW: Errno = 61;
+    pub const SPIPE: Errno = 70;
+    pub const NOTCAPABLE: Errno = 76;
+}
+
+const FILETYPE_CHARACTER_DEVICE: u8 = 2;
+const FILETYPE_DIRECTORY: u8 = 3;
+const FILETYPE_REGULAR_FILE: u8 = 4;
+
+const OFLAGS_CREAT: i32 = 1;
+const OFLAGS_DIRECTORY: i32 = 2;
+const OFLAGS_EXCL: i32 = 4;
+const OFLAGS_TRUNC: i32 = 8;
+
+const FDFLAGS_APPEND: i32 = 1;
+
+const RIGHTS_ALL: u64 = (1 << 30) - 1;
+
+const DEFAULT_MAX_FS_SIZE: u64 = 64 << 20;
+
+pub(crate) fn setup(wapo: &js::Value) -> Result<()> {
+    let ctx = wapo.context()?;
+    let wasi = ctx.new_object("wasi");
+    wasi.define_property_fn("create", create)?;
+    wapo.set_property("wasi", &wasi)?;
+    ctx.eval(&js::Code::Bytecode(qjsc::compiled!(
+        r#"
+        Wapo.wasi.start = function (instance) {
+            const start = instance.exports._start;
+            if (typeof start !== "function") {
+                throw new TypeError("the instance does not export _start");
+            }
+            try {
+                start();
+                return 0;
+            } catch (err) {
+                const match = /^ExitStatus: (-?\d+)/.exec(err && err.message);
+                if (match) {
+                    return Number(match[1]);
+                }
+                throw err;
+            }
+        };
+    "#
+    )))
+    .map_err(js::Error::msg)?;
+    Ok(())
+}
+
+#[derive(FromJsValue, Default)]
+#[qjs(rename_all = "camelCase")]
+struct WasiOptions {
+    #[qjs(default)]
+    args: Vec<String>,
+    #[qjs(default)]
+    env: BTreeMap<String, String>,
+    /// Guest directory paths mapped to trees of files, a file is a string or bytes and a
+    /// directory is an object.
+    #[qjs(default)]
+    preopens: BTreeMap<String, js::Value>,
+    #[qjs(default)]
+    stdin: Option<js::BytesOrString>,
+    #[qjs(default)]
+    stdout: Option<js::Value>,
+    #[qjs(default)]
+    stderr: Option<js::Value>,
+    /// The bytes all files may hold together, preopens included.
+    #[qjs(default)]
+    max_fs_size: Option<u64>,
+}
+
+/// Creates a WASI import object.
+#[js::host_call(with_context)]
+fn create(ctx: js::Context, _this: js::Value, options: Option<WasiOptions>) -> Result<js::Value> {
+    let options = options.unwrap_or_default();
+    let mut fs = Fs::new(options.max_fs_size.unwrap_or(DEFAULT_MAX_FS_SIZE));
+    let mut fds = BTreeMap::new();
+    fds.insert(0, Fd::Stdin);
+    fds.insert(1, Fd::Stdout);
+    fds.insert(2, Fd::Stderr);
+    for (path, tree) in options.preopens {
+        let root = fs.add(Node::Dir(BTreeMap::new()));
+        populate(&mut fs, root, tree)?;
+        let fd = fds.len() as u32;
+        fds.insert(
+            fd,
+            Fd::Dir {
+                ino: root,
+                preopen: Some(path),
+            },
+        );
+    }
+    let wasi = Wasi {
+        state: Arc::new(Mutex::new(State {
+            args: options.args,
+            env: options
+                .env
+                .into_iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect(),
+            fs,
+            fds,
+            stdin: options
+                .stdin
+                .map(|stdin| stdin.as_bytes().to_vec())
+                .unwrap_or_default(),
+            stdin_pos: 0,
+        })),
+        stdout: Output::new(options.stdout)?,
+        stderr: Output::new(options.stderr)?,
+    };
+    let imports = ctx.new_object("WasiImports");
+    let wasi = js::Value::new_opaque_object(&ctx, Some("Wapo.Wasi"), wasi);
+    imports.set_property("wasi_snapshot_preview1", &wasi)?;
+    Ok(imports)
+}
+
+fn populate(fs: &mut Fs, dir: Ino, tree: js::Value) -> Result<()> {
+    let entries = BTreeMap::<String, js::Value>::from_js_value(tree)?;
+    for (name, value) in entries {
+        if value.is_string() {
+            let data = value.decode_string()?.into_bytes();
+            fs.insert_file(dir, &name, data)
+                .map_err(|errno| anyhow!("invalid preopen file {name}: errno {errno}"))?;
+        } else if let Ok(data) = value.decode::<js::Bytes>() {
+            fs.insert_file(dir, &name, data.as_bytes().to_vec())
+                .map_err(|errno| anyhow!("invalid preopen file {name}: errno {errno}"))?;
+        } else {
+            if name.contains('/') || matches!(name.as_str(), "" | "." | "..") {
+                bail!("invalid preopen directory name {name}");
+            }
+            let sub = match fs
+                .lookup(dir, &name)
+                .map_err(|_| anyhow!("not a directory"))?
+            {
+                Some(ino) => ino,
+                None => {
+                    let ino = fs.add(Node::Dir(BTreeMap::new()));
+                    fs.link(dir, &name, ino)
+                        .map_err(|_| anyhow!("failed to create directory {name}"))?;
+                    ino
+                }
+            };
+            populate(fs, sub, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the value is the `wasi_snapshot_preview1` namespace of a WASI import object.
+pub(crate) fn is_wasi(value: &js::Value) -> bool {
+    value.opaque_object_data::<Wasi>().get().is_some()
+}
+
+pub(crate) struct Wasi {
+    state: Arc<Mutex<State>>,
+    stdout: Output,
+    stderr: Output,
+}
+
+/// A stdout or stderr callback.
+#[derive(Clone)]
+struct Output(Option<Arc<js::Value>>);
+
+unsafe impl Send for Output {}
+unsafe impl Sync for Output {}
+
+impl Output {
+    fn new(callback: Option<js::Value>) -> Result<Self> {
+        match callback {
+            Some(callback) if !callback.is_function() => bail!("TypeError: not a function"),
+            callback => Ok(Self(callback.map(Arc::new))),
+        }
+    }
+
+    fn write(&self, caller: &mut Caller<'_, Data>, name: &str, data: Vec<u8>) {
+        let Some(callback) = &self.0 else {
+            info!(target: "js::wasi", "{name}: {}", String::from_utf8_lossy(&data));
+            return;
+        };
+        let result = (|| -> js::Result<()> {
+            let data = js::AsBytes(data).to_js_value(&callback.context()?)?;
+            using_store(caller.as_context_mut().store, || {
+                callback.call(&js::Value::undefined(), &[data])
+            })?;
+            Ok(())
+        })();
+        if let Err(err) = result {
+            error!(target: "js::wasi", "{name} callback failed: {err}");
+        }
+    }
+}
+
+enum Fd {
+    Stdin,
+    Stdout,
+    Stderr,
+    File { ino: Ino, offset: u64, append: bool },
+    Dir { ino: Ino, preopen: Option<String> },
+}
+
+struct State {
+    args: Vec<String>,
+    env: Vec<String>,
+    fs: Fs,
+    fds: BTreeMap<u32, Fd>,
+    stdin: Vec<u8>,
+    stdin_pos: usize,
+}
+
+impl State {
+    fn fd(&self, fd: i32) -> Result<&Fd, Errno> {
+        self.fds.get(&(fd as u32)).ok_or(errno::BADF)
+    }
+
+    fn fd_mut(&mut self, fd: i32) -> Result<&mut Fd, Errno> {
+        self.fds.get_mut(&(fd as u32)).ok_or(errno::BADF)
+    }
+
+    fn dir_fd(&self, fd: i32) -> Result<Ino, Errno> {
+        match self.fd(fd)? {
+            Fd::Dir { ino, .. } => Ok(*ino),
+            _ => Err(errno::NOTDIR),
+        }
+    }
+
+    fn insert_fd(&mut self, fd: Fd) -> u32 {
+        let mut next = 0;
+        for used in self.fds.keys() {
+            if *used != next {
+                break;
+            }
+            next += 1;
+        }
+        self.fds.insert(next, fd);
+        next
+    }
+
+    /// Reads from an fd at `offset`, or at its current offset which is then advanced.
+    fn read(&mut self, fd: i32, offset: Option<u64>, len: usize) -> Result<Vec<u8>, Errno> {
+        let (ino, pos) = match self.fd(fd)? {
+            Fd::Stdin => {
+                if offset.is_some() {
+                    return Err(errno::SPIPE);
+                }
+                let start = self.stdin_pos;
+                let end = (start + len).min(self.stdin.len());
+                self.stdin_pos = end;
+                return Ok(self.stdin[start..end].to_vec());
+            }
+            Fd::File {
+                ino, offset: pos, ..
+            } => (*ino, offset.unwrap_or(*pos)),
+            Fd::Dir { .. } => return Err(errno::ISDIR),
+            Fd::Stdout | Fd::Stderr => return Err(errno::BADF),
+        };
+        let data = self.fs.file(ino)?;
+        let start = usize::try_from(pos).unwrap_or(usize::MAX).min(data.len());
+        let end = start.saturating_add(len).min(data.len());
+        let chunk = data[start..end].to_vec();
+        if offset.is_none() {
+            if let Fd::File { offset, .. } = self.fd_mut(fd)? {
+                *offset = end as u64;
+            }
+        }
+        Ok(chunk)
+    }
+
+    /// Writes to a file at `offset`, or at its current offset which is then advanced.
+    fn write(&mut self, fd: i32, offset: Option<u64>, data: &[u8]) -> Result<(), Errno> {
+        let Fd::File {
+            ino,
+            offset: pos,
+            append,
+        } = *self.fd(fd)?
+        else {
+            return Err(errno::BADF);
+        };
+        let len = self.fs.file(ino)?.len() as u64;
+        let start = match (offset, append) {
+            (Some(offset), _) => offset,
+            (None, true) => len,
+            (None, false) => pos,
+        };
+        let end = start
+            .checked_add(data.len() as u64)
+            .ok_or(errno::OVERFLOW)?;
+        let file = self.fs.resize(ino, end.max(len))?;
+        // Both fit in memory once the file has been resized to `end`.
+        let (start, end) = (start as usize, end as usize);
+        file[start..end].copy_from_slice(data);
+        if offset.is_none() {
+            if let Fd::File { offset, .. } = self.fd_mut(fd)? {
+                *offset = end as u64;
+            }
+        }
+        Ok(())
+    }
+
+    fn filestat(&self, ino: Ino) -> Result<[u8; 64], Errno> {
+        let (filetype, size) = match self.fs.node(ino)? {
+            Node::File(data) => (FILETYPE_REGULAR_FILE, data.len() as u64),
+            Node::Dir(entries) => (FILETYPE_DIRECTORY, entries.len() as u64),
+        };
+        let mut stat = [0u8; 64];
+        stat[8..16].copy_from_slice(&ino.to_le_bytes());
+        stat[16] = filetype;
+        stat[24..32].copy_from_slice(&1u64.to_le_bytes());
+        stat[32..40].copy_from_slice(&size.to_le_bytes());
+        Ok(stat)
+    }
+}
+
+/// The memory of the calling instance.
+struct Guest<'a> {
+    caller: Caller<'a, Data>,
+    memory: wasmi::Memory,
+}
+
+impl<'a> Guest<'a> {
+    fn new(caller: Caller<'a, Data>) -> Result<Self, Errno> {
+        let memory = caller
+            .get_export("memory")
+            .and_then(wasmi::Extern::into_memory)
+            .ok_or(errno::FAULT)?;
+        Ok(Self { caller, memory })
+    }
+
+    /// Checks that `len` bytes at `ptr` are within the memory.
+    fn check(&self, ptr: i32, len: i32) -> Result<(), Errno> {
+        let start = ptr as u32 as usize;
+        let end = start.checked_add(len as u32 as usize).ok_or(errno::FAULT)?;
+        if end > self.memory.data(&self.caller).len() {
+            return Err(errno::FAULT);
+        }
+        Ok(())
+    }
+
+    fn read(&self, ptr: i32, len: i32) -> Result<Vec<u8>, Errno> {
+        let start = ptr as u32 as usize;
+        let end = start.checked_add(len as u32 as usize).ok_or(errno::FAULT)?;
+        let data = self.memory.data(&self.caller);
+        Ok(data.get(start..end).ok_or(errno::FAULT)?.to_vec())
+    }
+
+    fn write(&mut self, ptr: i32, bytes: &[u8]) -> Result<(), Errno> {
+        let start = ptr as u32 as usize;
+        let end = start.checked_add(bytes.len()).ok_or(errno::FAULT)?;
+        let data = self.memory.data_mut(&mut self.caller);
+        data.get_mut(start..end)
+            .ok_or(errno::FAULT)?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn u32(&self, ptr: i32) -> Result<u32, Errno> {
+        let bytes = self.read(ptr, 4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("4 bytes")))
+    }
+
+    fn put_u32(&mut self, ptr: i32, value: u32) -> Result<(), Errno> {
+        self.write(ptr, &value.to_le_bytes())
+    }
+
+    fn put_u64(&mut self, ptr: i32, value: u64) -> Result<(), Errno> {
+        self.write(ptr, &value.to_le_bytes())
+    }
+
+    fn string(&self, ptr: i32, len: i32) -> Result<String, Errno> {
+        String::from_utf8(self.read(ptr, len)?).map_err(|_| errno::INVAL)
+    }
+
+    /// Reads an array of `(ptr, len)` buffers.
+    fn iovecs(&self, ptr: i32, count: i32) -> Result<Vec<(i32, i32)>, Errno> {
+        let bytes = self.read(
+            ptr,
+            (count as u32).checked_mul(8).ok_or(errno::FAULT)? as i32,
+        )?;
+        Ok(bytes
+            .chunks_exact(8)
+            .map(|chunk| {
+                let ptr = u32::from_le_bytes(chunk[..4].try_into().expect("4 bytes"));
+                let len = u32::from_le_bytes(chunk[4..].try_into().expect("4 bytes"));
+                (ptr as i32, len as i32)
+            })
+            .collect())
+    }
+
+    /// Writes a list of strings as NUL-terminated strings to `buf` with pointers to them in
+    /// `ptrs`.
+    fn put_strings(&mut self, strings: &[String], ptrs: i32, buf: i32) -> Result<(), Errno> {
+        let mut offset = buf;
+        for (i, s) in strings.iter().enumerate() {
+            self.put_u32(ptrs + 4 * i as i32, offset as u32)?;
+            self.write(offset, s.as_bytes())?;
+            self.write(offset + s.len() as i32, &[0])?;
+            offset += s.len() as i32 + 1;
+        }
+        Ok(())
+    }
+
+    fn put_sizes(&mut self, strings: &[String], count: i32, size: i32) -> Result<(), Errno> {
+        self.put_u32(count, strings.len() as u32)?;
+        self.put_u32(size, strings.iter().map(|s| s.len() as u32 + 1).sum())
+    }
+}
+
+fn monotonic_nanos() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+fn realtime_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Defines a WASI function returning an errno. The body runs with the memory of the caller and
+/// the locked WASI state.
+macro_rules! wasi_fn {
+    ($linker:ident, $module:ident, $state:ident, $name:literal,
+     |$guest:ident, $st:ident $(, $arg:ident : $ty:ty)*| $body:expr) => {{
+        let state = $state.clone();
+        $linker.func_wrap(
+            $module,
+            $name,
+            move |caller: Caller<'_, Data>, $($arg: $ty),*| -> i32 {
+                #[allow(unused_mut, unused_variables)]
+                let result = (|| -> Result<(), Errno> {
+                    let mut $guest = Guest::new(caller)?;
+                    let mut $st = state.lock().unwrap();
+                    $body
+                })();
+                match result {
+                    Ok(()) => errno::SUCCESS as i32,
+                    Err(errno) => errno as i32,
+                }
+            },
+        )?;
+    }};
+}
+
+/// Links the WASI functions of `wasi` into `linker` under `module`.
+pub(crate) fn link(
+    wasi: &js::Value,
+    linker: &mut Linker<Data>,
+    store: &EngineStore,
+    module: &str,
+) -> Result<()> {
+    let (state, stdout, stderr) = {
+        let guard = wasi.opaque_object_data::<Wasi>();
+        let wasi = guard
+            .get()
+            .ok_or_else(|| anyhow!("not a WASI import object"))?;
+        for output in [&wasi.stdout, &wasi.stderr] {
+            if let Some(callback) = &output.0 {
+                store.data().push_ref(Arc::downgrade(callback));
+            }
+        }
+        (wasi.state.clone(), wasi.stdout.clone(), wasi.stderr.clone())
+    };
+
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "args_get",
+        |g, s, argv: i32, buf: i32| { g.put_strings(&s.args, argv, buf) }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "args_sizes_get",
+        |g, s, count: i32, size: i32| { g.put_sizes(&s.args, count, size) }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "environ_get",
+        |g, s, environ: i32, buf: i32| { g.put_strings(&s.env, environ, buf) }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "environ_sizes_get",
+        |g, s, count: i32, size: i32| { g.put_sizes(&s.env, count, size) }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "clock_res_get",
+        |g, s, id: i32, out: i32| {
+            match id {
+                0..=3 => g.put_u64(out, 1_000),
+                _ => Err(errno::INVAL),
+            }
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "clock_time_get",
+        |g, s, id: i32, _precision: i64, out: i32| {
+            match id {
+                0 => g.put_u64(out, realtime_nanos()),
+                1..=3 => g.put_u64(out, monotonic_nanos()),
+                _ => Err(errno::INVAL),
+            }
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_advise",
+        |g, s, fd: i32, _offset: i64, _len: i64, _advice: i32| { s.fd(fd).map(|_| ()) }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_allocate",
+        |g, s, fd: i32, offset: i64, len: i64| {
+            let Fd::File { ino, .. } = *s.fd(fd)? else {
+                return Err(errno::BADF);
+            };
+            let offset = u64::try_from(offset).map_err(|_| errno::INVAL)?;
+            let len = u64::try_from(len).map_err(|_| errno::INVAL)?;
+            let end = offset.checked_add(len).ok_or(errno::FBIG)?;
+            if (s.fs.file(ino)?.len() as u64) < end {
+                s.fs.resize(ino, end)?;
+            }
+            Ok(())
+        }
+    );
+    wasi_fn!(linker, module, state, "fd_close", |g, s, fd: i32| {
+        s.fds.remove(&(fd as u32)).map(|_| ()).ok_or(errno::BADF)
+    });
+    wasi_fn!(linker, module, state, "fd_datasync", |g, s, fd: i32| {
+        s.fd(fd).map(|_| ())
+    });
+    wasi_fn!(linker, module, state, "fd_sync", |g, s, fd: i32| {
+        s.fd(fd).map(|_| ())
+    });
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_fdstat_get",
+        |g, s, fd: i32, out: i32| {
+            let (filetype, flags) = match s.fd(fd)? {
+                Fd::Stdin | Fd::Stdout | Fd::Stderr => (FILETYPE_CHARACTER_DEVICE, 0),
+                Fd::File { append, .. } => (
+                    FILETYPE_REGULAR_FILE,
+                    if *append { FDFLAGS_APPEND } else { 0 },
+                ),
+                Fd::Dir { .. } => (FILETYPE_DIRECTORY, 0),
+            };
+            let mut stat = [0u8; 24];
+            stat[0] = filetype;
+            stat[2..4].copy_from_slice(&(flags as u16).to_le_bytes());
+            stat[8..16].copy_from_slice(&RIGHTS_ALL.to_le_bytes());
+            stat[16..24].copy_from_slice(&RIGHTS_ALL.to_le_bytes());
+            g.write(out, &stat)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_fdstat_set_flags",
+        |g, s, fd: i32, flags: i32| {
+            match s.fd_mut(fd)? {
+                Fd::File { append, .. } => {
+                    *append = flags & FDFLAGS_APPEND != 0;
+                    Ok(())
+                }
+                _ => Ok(()),
+            }
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_fdstat_set_rights",
+        |g, s, fd: i32, _base: i64, _inheriting: i64| { s.fd(fd).map(|_| ()) }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_filestat_get",
+        |g, s, fd: i32, out: i32| {
+            let stat = match s.fd(fd)? {
+                Fd::File { ino, .. } | Fd::Dir { ino, .. } => s.filestat(*ino)?,
+                _ => {
+                    let mut stat = [0u8; 64];
+                    stat[16] = FILETYPE_CHARACTER_DEVICE;
+                    stat
+                }
+            };
+            g.write(out, &stat)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_filestat_set_size",
+        |g, s, fd: i32, size: i64| {
+            let Fd::File { ino, .. } = *s.fd(fd)? else {
+                return Err(errno::BADF);
+            };
+            let size = u64::try_from(size).map_err(|_| errno::INVAL)?;
+            s.fs.resize(ino, size)?;
+            Ok(())
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_filestat_set_times",
+        |g, s, fd: i32, _atim: i64, _mtim: i64, _flags: i32| { s.fd(fd).map(|_| ()) }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_pread",
+        |g, s, fd: i32, iovs: i32, iovs_len: i32, offset: i64, nread: i32| {
+            let offset = u64::try_from(offset).map_err(|_| errno::INVAL)?;
+            let mut total = 0u64;
+            for (ptr, len) in g.iovecs(iovs, iovs_len)? {
+                let offset = offset.checked_add(total).ok_or(errno::OVERFLOW)?;
+                let chunk = s.read(fd, Some(offset), len as u32 as usize)?;
+                g.write(ptr, &chunk)?;
+                total += chunk.len() as u64;
+                if chunk.len() < len as u32 as usize {
+                    break;
+                }
+            }
+            g.put_u32(nread, total as u32)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_prestat_get",
+        |g, s, fd: i32, out: i32| {
+            let Fd::Dir {
+                preopen: Some(name),
+                ..
+            } = s.fd(fd)?
+            else {
+                return Err(errno::BADF);
+            };
+            let mut prestat = [0u8; 8];
+            prestat[4..].copy_from_slice(&(name.len() as u32).to_le_bytes());
+            g.write(out, &prestat)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_prestat_dir_name",
+        |g, s, fd: i32, path: i32, len: i32| {
+            let Fd::Dir {
+                preopen: Some(name),
+                ..
+            } = s.fd(fd)?
+            else {
+                return Err(errno::BADF);
+            };
+            let name = name.as_bytes();
+            g.write(path, &name[..name.len().min(len as u32 as usize)])
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_pwrite",
+        |g, s, fd: i32, iovs: i32, iovs_len: i32, offset: i64, nwritten: i32| {
+            let offset = u64::try_from(offset).map_err(|_| errno::INVAL)?;
+            let mut total = 0u64;
+            for (ptr, len) in g.iovecs(iovs, iovs_len)? {
+                let data = g.read(ptr, len)?;
+                let offset = offset.checked_add(total).ok_or(errno::OVERFLOW)?;
+                s.write(fd, Some(offset), &data)?;
+                total += data.len() as u64;
+            }
+            g.put_u32(nwritten, total as u32)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_read",
+        |g, s, fd: i32, iovs: i32, iovs_len: i32, nread: i32| {
+            let mut total = 0u32;
+            for (ptr, len) in g.iovecs(iovs, iovs_len)? {
+                let chunk = s.read(fd, None, len as u32 as usize)?;
+                g.write(ptr, &chunk)?;
+                total += chunk.len() as u32;
+                if chunk.len() < len as u32 as usize {
+                    break;
+                }
+            }
+            g.put_u32(nread, total)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_readdir",
+        |g, s, fd: i32, buf: i32, buf_len: i32, cookie: i64, bufused: i32| {
+            let ino = s.dir_fd(fd)?;
+            let mut out = vec![];
+            for (i, (name, child)) in s.fs.dir(ino)?.iter().enumerate().skip(cookie as usize) {
+                let filetype = match s.fs.node(*child)? {
+                    Node::File(_) => FILETYPE_REGULAR_FILE,
+                    Node::Dir(_) => FILETYPE_DIRECTORY,
+                };
+                let mut dirent = [0u8; 24];
+                dirent[..8].copy_from_slice(&(i as u64 + 1).to_le_bytes());
+                dirent[8..16].copy_from_slice(&child.to_le_bytes());
+                dirent[16..20].copy_from_slice(&(name.len() as u32).to_le_bytes());
+                dirent[20] = filetype;
+                out.extend_from_slice(&dirent);
+                out.extend_from_slice(name.as_bytes());
+                if out.len() >= buf_len as u32 as usize {
+                    break;
+                }
+            }
+            // A full buffer tells the caller to read again with a larger one.
+            out.truncate(buf_len as u32 as usize);
+            g.write(buf, &out)?;
+            g.put_u32(bufused, out.len() as u32)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_renumber",
+        |g, s, fd: i32, to: i32| {
+            let from = s.fds.remove(&(fd as u32)).ok_or(errno::BADF)?;
+            s.fds.insert(to as u32, from);
+            Ok(())
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_seek",
+        |g, s, fd: i32, delta: i64, whence: i32, out: i32| {
+            let size = match s.fd(fd)? {
+                Fd::File { ino, .. } => s.fs.file(*ino)?.len() as i64,
+                Fd::Dir { .. } => return Err(errno::ISDIR),
+                _ => return Err(errno::SPIPE),
+            };
+            let Fd::File { offset, .. } = s.fd_mut(fd)? else {
+                unreachable!("checked above");
+            };
+            let base = match whence {
+                0 => 0,
+                1 => *offset as i64,
+                2 => size,
+                _ => return Err(errno::INVAL),
+            };
+            let position = base
+                .checked_add(delta)
+                .filter(|p| *p >= 0)
+                .ok_or(errno::INVAL)?;
+            *offset = position as u64;
+            g.put_u64(out, position as u64)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "fd_tell",
+        |g, s, fd: i32, out: i32| {
+            match s.fd(fd)? {
+                Fd::File { offset, .. } => g.put_u64(out, *offset),
+                _ => Err(errno::SPIPE),
+            }
+        }
+    );
+    {
+        let state = state.clone();
+        linker.func_wrap(
+            module,
+            "fd_write",
+            move |caller: Caller<'_, Data>,
+                  fd: i32,
+                  iovs: i32,
+                  iovs_len: i32,
+                  nwritten: i32|
+                  -> i32 {
+                let mut guest = match Guest::new(caller) {
+                    Ok(guest) => guest,
+                    Err(errno) => return errno as i32,
+                };
+                let result = (|| -> Result<(), Errno> {
+                    let mut data = vec![];
+                    for (ptr, len) in guest.iovecs(iovs, iovs_len)? {
+                        data.extend(guest.read(ptr, len)?);
+                    }
+                    let len = data.len() as u32;
+                    // The state is unlocked before calling back into JS, which may reenter.
+                    let output = match state.lock().unwrap().fd(fd)? {
+                        Fd::Stdout => Some((&stdout, "stdout")),
+                        Fd::Stderr => Some((&stderr, "stderr")),
+                        _ => None,
+                    };
+                    match output {
+                        Some((output, name)) => output.write(&mut guest.caller, name, data),
+                        None => state.lock().unwrap().write(fd, None, &data)?,
+                    }
+                    guest.put_u32(nwritten, len)
+                })();
+                match result {
+                    Ok(()) => errno::SUCCESS as i32,
+                    Err(errno) => errno as i32,
+                }
+            },
+        )?;
+    }
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "path_create_directory",
+        |g, s, fd: i32, path: i32, path_len: i32| {
+            let dir = s.dir_fd(fd)?;
+            let path = g.string(path, path_len)?;
+            let (parent, name) = s.fs.resolve_parent(dir, &path)?;
+            if s.fs.lookup(parent, name)?.is_some() {
+                return Err(errno::EXIST);
+            }
+            let ino = s.fs.add(Node::Dir(BTreeMap::new()));
+            s.fs.link(parent, name, ino)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "path_filestat_get",
+        |g, s, fd: i32, _flags: i32, path: i32, path_len: i32, out: i32| {
+            let dir = s.dir_fd(fd)?;
+            let path = g.string(path, path_len)?;
+            let ino = s.fs.resolve(dir, &path)?;
+            let stat = s.filestat(ino)?;
+            g.write(out, &stat)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "path_filestat_set_times",
+        |g,
+         s,
+         fd: i32,
+         _flags: i32,
+         path: i32,
+         path_len: i32,
+         _atim: i64,
+         _mtim: i64,
+         _fst_flags: i32| {
+            let dir = s.dir_fd(fd)?;
+            let path = g.string(path, path_len)?;
+            s.fs.resolve(dir, &path).map(|_| ())
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "path_link",
+        |g,
+         s,
+         _fd: i32,
+         _flags: i32,
+         _old: i32,
+         _old_len: i32,
+         _new_fd: i32,
+         _new: i32,
+         _new_len: i32| { Err(errno::NOTSUP) }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "path_open",
+        |g,
+         s,
+         fd: i32,
+         _dirflags: i32,
+         path: i32,
+         path_len: i32,
+         oflags: i32,
+         _base: i64,
+         _inheriting: i64,
+         fdflags: i32,
+         out: i32| {
+            let dir = s.dir_fd(fd)?;
+            let path = g.string(path, path_len)?;
+            let ino = match s.fs.resolve(dir, &path) {
+                Ok(_) if oflags & OFLAGS_CREAT != 0 && oflags & OFLAGS_EXCL != 0 => {
+                    return Err(errno::EXIST);
+                }
+                Ok(ino) => ino,
+                Err(errno::NOENT) if oflags & OFLAGS_CREAT != 0 => {
+                    let (parent, name) = s.fs.resolve_parent(dir, &path)?;
+                    let ino = s.fs.add(Node::File(vec![]));
+                    s.fs.link(parent, name, ino)?;
+                    ino
+                }
+                Err(err) => return Err(err),
+            };
+            let fd = match s.fs.node(ino)? {
+                Node::Dir(_) => Fd::Dir { ino, preopen: None },
+                Node::File(_) if oflags & OFLAGS_DIRECTORY != 0 => return Err(errno::NOTDIR),
+                Node::File(_) => {
+                    if oflags & OFLAGS_TRUNC != 0 {
+                        s.fs.resize(ino, 0)?;
+                    }
+                    Fd::File {
+                        ino,
+                        offset: 0,
+                        append: fdflags & FDFLAGS_APPEND != 0,
+                    }
+                }
+            };
+            let fd = s.insert_fd(fd);
+            g.put_u32(out, fd)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "path_readlink",
+        |g, s, fd: i32, path: i32, path_len: i32, _buf: i32, _buf_len: i32, _used: i32| {
+            let dir = s.dir_fd(fd)?;
+            let path = g.string(path, path_len)?;
+            // There are no symbolic links.
+            s.fs.resolve(dir, &path)?;
+            Err(errno::INVAL)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "path_remove_directory",
+        |g, s, fd: i32, path: i32, path_len: i32| {
+            let dir = s.dir_fd(fd)?;
+            let path = g.string(path, path_len)?;
+            let (parent, name) = s.fs.resolve_parent(dir, &path)?;
+            let ino = s.fs.lookup(parent, name)?.ok_or(errno::NOENT)?;
+            if !s.fs.dir(ino)?.is_empty() {
+                return Err(errno::NOTEMPTY);
+            }
+            s.fs.unlink(parent, name).map(|_| ())
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "path_rename",
+        |g, s, fd: i32, old: i32, old_len: i32, new_fd: i32, new: i32, new_len: i32| {
+            let old_dir = s.dir_fd(fd)?;
+            let new_dir = s.dir_fd(new_fd)?;
+            let old = g.string(old, old_len)?;
+            let new = g.string(new, new_len)?;
+            let (old_parent, old_name) = s.fs.resolve_parent(old_dir, &old)?;
+            let (new_parent, new_name) = s.fs.resolve_parent(new_dir, &new)?;
+            let ino = s.fs.lookup(old_parent, old_name)?.ok_or(errno::NOENT)?;
+            if let Some(target) = s.fs.lookup(new_parent, new_name)? {
+                match (s.fs.node(ino)?, s.fs.node(target)?) {
+                    (Node::Dir(_), Node::File(_)) => return Err(errno::NOTDIR),
+                    (Node::File(_), Node::Dir(_)) => return Err(errno::ISDIR),
+                    (Node::Dir(_), Node::Dir(entries)) if !entries.is_empty() => {
+                        return Err(errno::NOTEMPTY)
+                    }
+                    _ => {}
+                }
+            }
+            s.fs.unlink(old_parent, old_name)?;
+            s.fs.link(new_parent, new_name, ino)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "path_symlink",
+        |g, s, _old: i32, _old_len: i32, _fd: i32, _new: i32, _new_len: i32| { Err(errno::NOTSUP) }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "path_unlink_file",
+        |g, s, fd: i32, path: i32, path_len: i32| {
+            let dir = s.dir_fd(fd)?;
+            let path = g.string(path, path_len)?;
+            let (parent, name) = s.fs.resolve_parent(dir, &path)?;
+            let ino = s.fs.lookup(parent, name)?.ok_or(errno::NOENT)?;
+            s.fs.file(ino)?;
+            s.fs.unlink(parent, name).map(|_| ())
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "poll_oneoff",
+        |g, s, subscriptions: i32, events: i32, count: i32, nevents: i32| {
+            for i in 0..count {
+                let subscription = g.read(subscriptions + i * 48, 48)?;
+                let mut event = [0u8; 32];
+                // userdata
+                event[..8].copy_from_slice(&subscription[..8]);
+                // type
+                event[10] = subscription[8];
+                g.write(events + i * 32, &event)?;
+            }
+            g.put_u32(nevents, count as u32)
+        }
+    );
+    linker.func_wrap(
+        module,
+        "proc_exit",
+        |code: i32| -> Result<(), wasmi::Error> { Err(wasmi::Error::i32_exit(code)) },
+    )?;
+    wasi_fn!(linker, module, state, "proc_raise", |g, s, _signal: i32| {
+        Err(errno::NOSYS)
+    });
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "random_get",
+        |g, s, buf: i32, len: i32| {
+            g.check(buf, len)?;
+            let mut bytes = vec![0u8; len as u32 as usize];
+            crate::runtime::getrandom(&mut bytes).ok_or(errno::IO)?;
+            g.write(buf, &bytes)
+        }
+    );
+    linker.func_wrap(module, "sched_yield", || -> i32 { errno::SUCCESS as i32 })?;
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "sock_accept",
+        |g, s, _fd: i32, _flags: i32, _out: i32| { Err(errno::NOTSUP) }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "sock_recv",
+        |g, s, _fd: i32, _data: i32, _data_len: i32, _flags: i32, _len: i32, _out_flags: i32| {
+            Err(errno::NOTSUP)
+        }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "sock_send",
+        |g, s, _fd: i32, _data: i32, _data_len: i32, _flags: i32, _len: i32| { Err(errno::NOTSUP) }
+    );
+    wasi_fn!(
+        linker,
+        module,
+        state,
+        "sock_shutdown",
+        |g, s, _fd: i32, _how: i32| { Err(errno::NOTSUP) }
+    );
+    Ok(())
+}
@@ -0,0 +1,196 @@
+//! The in-memory filesystem behind the WASI preopens.
+//!
+//! Nodes live in an arena indexed by inode number. Directories map names to inodes; there are
+//! no links, so every node but the roots has exactly one parent. A removed node stays in the
+//! arena while it may still be open and is freed with the filesystem.
+//!
+//! The bytes of all files, removed ones included, are counted against the size budget of the
+//! filesystem. Growing a file past it fails with `FBIG` when the file alone would not fit and
+//! with `NOSPC` otherwise.
+use std::collections::BTreeMap;
+
+use super::errno::{self, Errno};
+
+pub(crate) type Ino = u64;
+
+pub(crate) enum Node {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, Ino>),
+}
+
+pub(crate) struct Fs {
+    nodes: BTreeMap<Ino, Node>,
+    next_ino: Ino,
+    /// The bytes held by files.
+    size: u64,
+    max_size: u64,
+}
+
+impl Fs {
+    pub fn new(max_size: u64) -> Self {
+        Self {
+            nodes: BTreeMap::new(),
+            next_ino: 0,
+            size: 0,
+            max_size,
+        }
+    }
+
+    /// Accounts for a file changing from `old` to `new` bytes.
+    fn charge(&mut self, old: usize, new: u64) -> Result<usize, Errno> {
+        let new_len = usize::try_from(new).map_err(|_| errno::FBIG)?;
+        if new > self.max_size {
+            return Err(errno::FBIG);
+        }
+        let size = (self.size - old as u64)
+            .checked_add(new)
+            .filter(|size| *size <= self.max_size)
+            .ok_or(errno::NOSPC)?;
+        self.size = size;
+        Ok(new_len)
+    }
+
+    pub fn add(&mut self, node: Node) -> Ino {
+        self.next_ino += 1;
+        self.nodes.insert(self.next_ino, node);
+        self.next_ino
+    }
+
+    pub fn node(&self, ino: Ino) -> Result<&Node, Errno> {
+        self.nodes.get(&ino).ok_or(errno::BADF)
+    }
+
+    pub fn node_mut(&mut self, ino: Ino) -> Result<&mut Node, Errno> {
+        self.nodes.get_mut(&ino).ok_or(errno::BADF)
+    }
+
+    pub fn file(&self, ino: Ino) -> Result<&Vec<u8>, Errno> {
+        match self.node(ino)? {
+            Node::File(data) => Ok(data),
+            Node::Dir(_) => Err(errno::ISDIR),
+        }
+    }
+
+    fn file_mut(&mut self, ino: Ino) -> Result<&mut Vec<u8>, Errno> {
+        match self.node_mut(ino)? {
+            Node::File(data) => Ok(data),
+            Node::Dir(_) => Err(errno::ISDIR),
+        }
+    }
+
+    /// Resizes a file to `len` bytes, zero-filling what is added, within the size budget.
+    pub fn resize(&mut self, ino: Ino, len: u64) -> Result<&mut Vec<u8>, Errno> {
+        let old = self.file(ino)?.len();
+        let new = self.charge(old, len)?;
+        let file = self.file_mut(ino).expect("checked above");
+        if file.try_reserve(new.saturating_sub(old)).is_err() {
+            self.size = self.size - len + old as u64;
+            return Err(errno::NOSPC);
+        }
+        file.resize(new, 0);
+        Ok(file)
+    }
+
+    pub fn dir(&self, ino: Ino) -> Result<&BTreeMap<String, Ino>, Errno> {
+        match self.node(ino)? {
+            Node::Dir(entries) => Ok(entries),
+            Node::File(_) => Err(errno::NOTDIR),
+        }
+    }
+
+    fn dir_mut(&mut self, ino: Ino) -> Result<&mut BTreeMap<String, Ino>, Errno> {
+        match self.node_mut(ino)? {
+            Node::Dir(entries) => Ok(entries),
+            Node::File(_) => Err(errno::NOTDIR),
+        }
+    }
+
+    /// Walks `components` from `dir`. `..` can not leave `dir`, paths are confined to the
+    /// directory they are resolved against.
+    fn walk<'p>(&self, dir: Ino, components: impl Iterator<Item = &'p str>) -> Result<Ino, Errno> {
+        let mut stack = vec![dir];
+        for component in components {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    if stack.len() == 1 {
+                        return Err(errno::NOTCAPABLE);
+                    }
+                    stack.pop();
+                }
+                name => {
+                    let current = *stack.last().expect("the stack is never empty");
+                    let ino = *self.dir(current)?.get(name).ok_or(errno::NOENT)?;
+                    stack.push(ino);
+                }
+            }
+        }
+        Ok(*stack.last().expect("the stack is never empty"))
+    }
+
+    /// Resolves a path relative to `dir`.
+    pub fn resolve(&self, dir: Ino, path: &str) -> Result<Ino, Errno> {
+        if path.starts_with('/') {
+            return Err(errno::NOTCAPABLE);
+        }
+        self.walk(dir, path.split('/'))
+    }
+
+    /// Resolves the parent directory of a path relative to `dir`, returning it with the last
+    /// component of the path.
+    pub fn resolve_parent<'p>(&self, dir: Ino, path: &'p str) -> Result<(Ino, &'p str), Errno> {
+        if path.starts_with('/') {
+            return Err(errno::NOTCAPABLE);
+        }
+        let path = path.trim_end_matches('/');
+        let (parent, name) = match path.rsplit_once('/') {
+            Some((parent, name)) => (parent, name),
+            None => ("", path),
+        };
+        if matches!(name, "" | "." | "..") {
+            return Err(errno::INVAL);
+        }
+        let parent = self.walk(dir, parent.split('/'))?;
+        self.dir(parent)?;
+        Ok((parent, name))
+    }
+
+    pub fn lookup(&self, dir: Ino, name: &str) -> Result<Option<Ino>, Errno> {
+        Ok(self.dir(dir)?.get(name).copied())
+    }
+
+    pub fn link(&mut self, dir: Ino, name: &str, ino: Ino) -> Result<(), Errno> {
+        self.dir_mut(dir)?.insert(name.into(), ino);
+        Ok(())
+    }
+
+    pub fn unlink(&mut self, dir: Ino, name: &str) -> Result<Ino, Errno> {
+        self.dir_mut(dir)?.remove(name).ok_or(errno::NOENT)
+    }
+
+    /// Adds a file at a path relative to `dir`, creating the missing parent directories.
+    pub fn insert_file(&mut self, dir: Ino, path: &str, data: Vec<u8>) -> Result<Ino, Errno> {
+        let mut parent = dir;
+        let mut components = path.split('/').filter(|c| !c.is_empty()).peekable();
+        while let Some(name) = components.next() {
+            if matches!(name, "." | "..") {
+                return Err(errno::INVAL);
+            }
+            if components.peek().is_none() {
+                self.charge(0, data.len() as u64)?;
+                let ino = self.add(Node::File(data));
+                self.link(parent, name, ino)?;
+                return Ok(ino);
+            }
+            parent = match self.lookup(parent, name)? {
+                Some(ino) => ino,
+                None => {
+                    let ino = self.add(Node::Dir(BTreeMap::new()));
+                    self.link(parent, name, ino)?;
+                    ino
+                }
+            };
+        }
+        Err(errno::INVAL)
+    }
+}
@@ -0,0 +1,185 @@
+//! Workers: scripts running in a separate service, talking to their creator over a message
+//! port.
+//!
+//! In the native runtime each worker gets its own thread with a current-thread tokio runtime.
+//! On wapo, where there are no threads, the worker is a cooperative isolate scheduled on the
+//! same executor as its parent. Either way it has its own JS runtime, so nothing but
+//! structured clones is ever shared.
+//!
+//! Workers boot with the bootcode of their creator, so that `--env` or `WAPOJS_ENV` apply to
+//! them as well. Every profile but `minimal` sets up the worker scope (`self`, `postMessage`,
+//! `onmessage`); `minimal` has no `Worker` to start one with in the first place.
+//!
+//! A worker keeps running, like on the web, until it calls `close()` or the parent terminates
+//! it. Termination takes effect when the worker yields to the event loop. Uncaught errors, from
+//! the initial script as well as from handlers, timers and promise jobs, are reported to the
+//! parent as `error` events; the worker keeps running after those raised later on.
+use alloc::sync::Arc;
+use core::cell::RefCell;
+
+use anyhow::Context as _;
+use log::{debug, error, info};
+
+use crate::{runtime, service::Service};
+
+use super::message_port::{Port, PortEvent};
+use super::*;
+
+thread_local! {
+    /// The port of the worker being created, picked up by `setup` while the host functions
+    /// of its service are installed.
+    static PENDING_PARENT_PORT: RefCell<Option<Port>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn setup(ns: &js::Value) -> Result<()> {
+    ns.define_property_fn("workerOpen", worker_open)?;
+    if let Some(port) = PENDING_PARENT_PORT.with(|pending| pending.borrow_mut().take()) {
+        // Used by the bootcode to install `postMessage` and `onmessage` in the worker scope.
+        let port = port.into_js_value(&ns.context()?);
+        ns.set_property("workerParentPort", &port)?;
+    }
+    Ok(())
+}
+
+#[derive(js::FromJsValue, Default)]
+struct WorkerOptions {
+    /// Treat the specifier as the source code rather than a path.
+    #[qjs(default)]
+    eval: bool,
+    #[qjs(default)]
+    name: Option<String>,
+}
+
+/// Starts a worker and returns the port to talk to it.
+#[js::host_call(with_context)]
+fn worker_open(
+    service: ServiceRef,
+    _this: js::Value,
+    specifier: String,
+    options: Option<WorkerOptions>,
+) -> Result<js::Value> {
+    let options = options.unwrap_or_default();
+    let name = options.name.unwrap_or_default();
+    let (filename, source) = if options.eval {
+        (format!("<worker {name}>"), specifier)
+    } else {
+        load_script(&specifier)?
+    };
+    let (local, remote) = Port::pair();
+    debug!(target: "js::worker", "starting worker {filename}");
+    start(name, filename, source, service.bootcode(), remote)?;
+    Ok(local.into_js_value(service.context()))
+}
+
+#[cfg(feature = "native")]
+fn load_script(specifier: &str) -> Result<(String, String)> {
+    let path = specifier.strip_prefix("file://").unwrap_or(specifier);
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to load worker script {specifier}"))?;
+    Ok((path.to_string(), source))
+}
+
+#[cfg(not(feature = "native"))]
+fn load_script(specifier: &str) -> Result<(String, String)> {
+    anyhow::bail!(
+        "can not load worker script {specifier}, pass the source code with {{ eval: true }}"
+    )
+}
+
+#[cfg(feature = "native")]
+fn start(
+    name: String,
+    filename: String,
+    source: String,
+    bootcode: Arc<[u8]>,
+    port: Port,
+) -> Result<()> {
+    let thread_name = if name.is_empty() {
+        "js-worker".to_string()
+    } else {
+        format!("js-worker-{name}")
+    };
+    #[cfg(feature = "fake-time")]
+    let clock = super::fake_time::now_ms();
+    #[cfg(not(feature = "fake-time"))]
+    let clock = None;
+    std::thread::Builder::new()
+        .name(thread_name)
+        .spawn(move || {
+            let rt = match tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+            {
+                Ok(rt) => rt,
+                Err(err) => {
+                    error!(target: "js::worker", "failed to create the worker runtime: {err}");
+                    return;
+                }
+            };
+            let worker = run(filename, source, bootcode, clock, port);
+            rt.block_on(runtime::run_local(worker));
+        })
+        .context("failed to spawn the worker thread")?;
+    Ok(())
+}
+
+#[cfg(not(feature = "native"))]
+fn start(
+    _name: String,
+    filename: String,
+    source: String,
+    bootcode: Arc<[u8]>,
+    port: Port,
+) -> Result<()> {
+    runtime::spawn(run(filename, source, bootcode, None, port));
+    Ok(())
+}
+
+/// Runs a worker. `clock` is the virtual time of the parent when it runs with `--fake-time`.
+async fn run(
+    filename: String,
+    source: String,
+    bootcode: Arc<[u8]>,
+    clock: Option<f64>,
+    port: Port,
+) {
+    let parent = port.sender();
+    PENDING_PARENT_PORT.with(|pending| *pending.borrow_mut() = Some(port));
+    let service = Service::new_ref_with_bootcode(&bootcode);
+    if PENDING_PARENT_PORT.with(|pending| pending.borrow_mut().take().is_some()) {
+        error!(target: "js::worker", "the parent port was not installed in {filename}");
+        return;
+    }
+    #[cfg(feature = "fake-time")]
+    if let Some(epoch_ms) = clock {
+        if let Err(err) = super::fake_time::enable_at(&service, epoch_ms) {
+            error!(target: "js::worker", "failed to enable fake time in {filename}: {err:?}");
+            return;
+        }
+    }
+    #[cfg(not(feature = "fake-time"))]
+    let _ = clock;
+    // Errors thrown by handlers and timers after the initial script are `error` events too.
+    let errors = parent.clone();
+    service.runtime().set_uncaught_error_hook(move |err| {
+        _ = errors.send(PortEvent::Error(err.to_string()));
+    });
+    match service.exec_script_with_filename(&source, &filename) {
+        Ok(_) => {
+            tokio::select! {
+                _ = service.wait_for_tasks() => {
+                    debug!(target: "js::worker", "worker {filename} finished");
+                }
+                _ = parent.closed() => {
+                    info!(target: "js::worker", "worker {filename} terminated");
+                    service.close_all();
+                }
+            }
+        }
+        Err(err) => {
+            error!(target: "js::worker", "worker {filename} failed: {err}");
+            _ = parent.send(PortEvent::Error(err));
+        }
+    }
+    service.shutdown().await;
+}
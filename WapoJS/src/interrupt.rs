@@ -0,0 +1,100 @@
+//! The runtime interrupt handler, which the interpreter polls every few thousand bytecode
+//! instructions while executing.
+//!
+//! It runs the hooks of the developer tools and enforces the time budgets of [`with_deadline`].
+//! QuickJS has a single handler per runtime, so everything that needs to be called back while a
+//! script runs goes through here. Workers running on the same thread have runtimes of their
+//! own, so the handler is set on the runtime of each context it is needed for.
+
+use core::cell::Cell;
+use core::ffi::{c_int, c_void};
+use std::time::{Duration, Instant};
+
+use js::c;
+
+#[cfg(feature = "devtools")]
+use crate::service::{Service, ServiceWeakRef};
+
+#[cfg(feature = "devtools")]
+type InterruptHook = alloc::rc::Rc<dyn Fn(&Service)>;
+
+thread_local! {
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+    #[cfg(feature = "devtools")]
+    static INTERRUPT_HOOKS: core::cell::RefCell<Vec<InterruptHook>> = const { core::cell::RefCell::new(Vec::new()) };
+    #[cfg(feature = "devtools")]
+    static HOOKED_SERVICE: core::cell::RefCell<Option<ServiceWeakRef>> = const { core::cell::RefCell::new(None) };
+}
+
+/// Sets the handler on the runtime of `ctx`, setting it again is a no-op.
+fn install(ctx: &js::Context) {
+    unsafe {
+        let rt = c::JS_GetRuntime(ctx.as_ptr());
+        c::JS_SetInterruptHandler(rt, Some(interrupt_handler), core::ptr::null_mut());
+    }
+}
+
+/// Registers a hook that is called each time the interpreter polls for interrupts.
+///
+/// The hook may run JS code in the service context, nested interrupts are not reported.
+#[cfg(feature = "devtools")]
+pub(crate) fn add_interrupt_hook(service: &Service, hook: impl Fn(&Service) + 'static) {
+    let first = INTERRUPT_HOOKS.with(|hooks| {
+        let mut hooks = hooks.borrow_mut();
+        hooks.push(alloc::rc::Rc::new(hook));
+        hooks.len() == 1
+    });
+    if first {
+        HOOKED_SERVICE.with(|s| *s.borrow_mut() = Some(service.weak_self()));
+    }
+    install(service.context());
+}
+
+/// Calls `f`, aborting the JS code it runs once `budget` has elapsed. The aborted code throws an
+/// `InternalError: interrupted` that scripts can not catch. Budgets nest, the earliest deadline
+/// applies.
+pub(crate) fn with_deadline<T>(ctx: &js::Context, budget: Duration, f: impl FnOnce() -> T) -> T {
+    install(ctx);
+    let outer = DEADLINE.with(Cell::get);
+    let deadline = match (outer, Instant::now().checked_add(budget)) {
+        (Some(outer), Some(deadline)) => Some(outer.min(deadline)),
+        (outer, deadline) => outer.or(deadline),
+    };
+    DEADLINE.with(|d| d.set(deadline));
+    let result = f();
+    DEADLINE.with(|d| d.set(outer));
+    result
+}
+
+unsafe extern "C" fn interrupt_handler(rt: *mut c::JSRuntime, _opaque: *mut c_void) -> c_int {
+    #[cfg(feature = "devtools")]
+    run_hooks(rt);
+    #[cfg(not(feature = "devtools"))]
+    let _ = rt;
+    // Returning non-zero aborts the running script.
+    let expired = DEADLINE
+        .with(Cell::get)
+        .is_some_and(|deadline| Instant::now() >= deadline);
+    c_int::from(expired)
+}
+
+/// Runs the hooks when `rt` is the runtime of the service they were registered for.
+#[cfg(feature = "devtools")]
+fn run_hooks(rt: *mut c::JSRuntime) {
+    thread_local! {
+        static IN_HOOK: Cell<bool> = const { Cell::new(false) };
+    }
+    if IN_HOOK.with(|flag| flag.replace(true)) {
+        return;
+    }
+    let service = HOOKED_SERVICE.with(|s| s.borrow().as_ref().and_then(|s| s.upgrade()));
+    let service = service
+        .filter(|service| unsafe { c::JS_GetRuntime(service.context().as_ptr()) } == rt);
+    if let Some(service) = service {
+        let hooks = INTERRUPT_HOOKS.with(|hooks| hooks.borrow().clone());
+        for hook in hooks {
+            hook(&service);
+        }
+    }
+    IN_HOOK.with(|flag| flag.set(false));
+}
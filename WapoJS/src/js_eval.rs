@@ -1,13 +1,64 @@
 use js::ToJsValue;
 
-use crate::{service::ServiceRef, Service};
+use crate::{
+    host_functions::{set_log_config, LogConfig},
+    service::{load_bootcode, ServiceRef},
+    Service,
+};
 use anyhow::{anyhow, bail, Context, Result};
+#[cfg(feature = "devtools")]
+use std::time::Duration;
 
 use pink_types::js::{JsCode, JsValue};
 
+enum Source {
+    /// Code given with `-c`.
+    Code(String),
+    /// A script file, at the path of the script name.
+    File,
+    /// Code stored by the host, under the hash of the script name.
+    #[cfg(feature = "wapo")]
+    CodeHash,
+}
+
+struct Script {
+    /// The file name reported in stack traces.
+    name: String,
+    source: Source,
+    /// The source map given with `--source-map`.
+    source_map: Option<String>,
+}
+
+impl Script {
+    fn load(&self) -> Result<JsCode> {
+        let code = match &self.source {
+            Source::Code(code) => code.clone(),
+            Source::File => {
+                std::fs::read_to_string(&self.name).context("failed to read script file")?
+            }
+            #[cfg(feature = "wapo")]
+            Source::CodeHash => {
+                load_code(&self.name).context("failed to load code with given hash")?
+            }
+        };
+        Ok(JsCode::Source(code))
+    }
+}
+
 struct Args {
-    codes: Vec<JsCode>,
+    codes: Vec<Script>,
+    /// The bootcode profile or `.jsc` file given with `--env`.
+    env: Option<String>,
     js_args: Vec<String>,
+    log_config: LogConfig,
+    #[cfg(feature = "fake-time")]
+    fake_time: bool,
+    #[cfg(feature = "devtools")]
+    inspect: Option<u16>,
+    #[cfg(feature = "devtools")]
+    cpu_prof: Option<(String, Duration)>,
+    #[cfg(feature = "devtools")]
+    coverage: Option<String>,
 }
 
 #[cfg(feature = "wapo")]
@@ -24,8 +75,28 @@ fn load_code(code_hash: &str) -> Result<String> {
     Ok(source_code)
 }
 
+/// Whether `--fake-time` is among the options, which the runtime has to know before it is built.
+/// Invalid arguments are reported by [`run`].
+#[cfg(feature = "fake-time")]
+pub fn wants_fake_time(args: impl Iterator<Item = String>) -> bool {
+    parse_args(args).is_ok_and(|args| args.fake_time)
+}
+
 fn parse_args(args: impl Iterator<Item = String>) -> Result<Args> {
     let mut codes = vec![];
+    let mut source_map = None;
+    let mut env = None;
+    let mut log_config = LogConfig::default();
+    #[cfg(feature = "fake-time")]
+    let mut fake_time = false;
+    #[cfg(feature = "devtools")]
+    let mut inspect = None;
+    #[cfg(feature = "devtools")]
+    let mut cpu_prof = None;
+    #[cfg(feature = "devtools")]
+    let mut coverage = None;
+    #[cfg(feature = "devtools")]
+    let mut cpu_prof_interval = crate::devtools::profiler::DEFAULT_INTERVAL;
     let mut iter = args.skip(1);
     while let Some(arg) = iter.next() {
         if arg.starts_with("-") {
@@ -38,31 +109,110 @@ fn parse_args(args: impl Iterator<Item = String>) -> Result<Args> {
                     let code_hash = iter
                         .next()
                         .ok_or(anyhow!("missing value after --code-hash"))?;
-                    let code =
-                        load_code(&code_hash).context("failed to load code with given hash")?;
-                    codes.push(JsCode::Source(code));
+                    codes.push(Script {
+                        name: code_hash,
+                        source: Source::CodeHash,
+                        source_map: source_map.take(),
+                    });
                 }
                 "-c" => {
                     let code = iter.next().ok_or(anyhow!("missing code after -c"))?;
-                    codes.push(JsCode::Source(code));
+                    codes.push(Script {
+                        name: format!("<code{}>", codes.len()),
+                        source: Source::Code(code),
+                        source_map: source_map.take(),
+                    });
+                }
+                "--env" => {
+                    env = Some(iter.next().ok_or(anyhow!("missing profile after --env"))?);
+                }
+                "--source-map" => {
+                    let path = iter
+                        .next()
+                        .ok_or(anyhow!("missing file after --source-map"))?;
+                    source_map = Some(path);
+                }
+                "--log-format" => {
+                    let format = iter
+                        .next()
+                        .ok_or(anyhow!("missing value after --log-format"))?;
+                    log_config.format = format.parse()?;
+                }
+                "--log-max-len" => {
+                    let max_len = iter
+                        .next()
+                        .ok_or(anyhow!("missing value after --log-max-len"))?;
+                    log_config.max_message_len =
+                        max_len.parse().context("invalid --log-max-len")?;
                 }
-                _ => {
-                    print_usage();
-                    bail!("unknown option: {}", arg);
+                "--log-service" => {
+                    log_config.service_name = iter
+                        .next()
+                        .ok_or(anyhow!("missing value after --log-service"))?;
+                }
+                #[cfg(feature = "fake-time")]
+                "--fake-time" => {
+                    fake_time = true;
+                }
+                #[cfg(feature = "devtools")]
+                "--inspect" => {
+                    let port = iter.next().ok_or(anyhow!("missing port after --inspect"))?;
+                    inspect = Some(port.parse().context("invalid --inspect port")?);
+                }
+                #[cfg(feature = "devtools")]
+                "--cpu-prof" => {
+                    let path = iter
+                        .next()
+                        .ok_or(anyhow!("missing file after --cpu-prof"))?;
+                    cpu_prof = Some(path);
                 }
+                #[cfg(feature = "devtools")]
+                "--coverage" => {
+                    let dir = iter
+                        .next()
+                        .ok_or(anyhow!("missing directory after --coverage"))?;
+                    coverage = Some(dir);
+                }
+                #[cfg(feature = "devtools")]
+                "--cpu-prof-interval" => {
+                    let interval = iter
+                        .next()
+                        .ok_or(anyhow!("missing value after --cpu-prof-interval"))?;
+                    cpu_prof_interval = Duration::from_micros(
+                        interval.parse().context("invalid --cpu-prof-interval")?,
+                    );
+                }
+                _ => bail!("unknown option: {}", arg),
             }
         } else {
-            // File name
-            let code = std::fs::read_to_string(arg).context("failed to read script file")?;
-            codes.push(JsCode::Source(code));
+            codes.push(Script {
+                name: arg,
+                source: Source::File,
+                source_map: source_map.take(),
+            });
         }
     }
+    if source_map.is_some() {
+        bail!("--source-map must be followed by the script it applies to");
+    }
     if codes.is_empty() {
-        print_usage();
         bail!("no script file provided");
     }
     let js_args = iter.collect();
-    Ok(Args { codes, js_args })
+    Ok(Args {
+        codes,
+        env,
+        js_args,
+        log_config,
+        #[cfg(feature = "fake-time")]
+        fake_time,
+        #[cfg(feature = "devtools")]
+        inspect,
+        #[cfg(feature = "devtools")]
+        cpu_prof: cpu_prof.map(|path| (path, cpu_prof_interval)),
+        #[cfg(feature = "devtools")]
+        coverage,
+    })
 }
 
 fn print_usage() {
@@ -73,21 +223,66 @@ fn print_usage() {
     println!("  -c <code>        Execute code");
     #[cfg(feature = "wapo")]
     println!("  --code-hash <code_hash>  Execute code");
+    println!("  --env <profile>  Bootcode: minimal, node, browser, worker or a .jsc file");
+    println!("  --source-map <file>  Source map of the next script, inline maps are detected");
+    println!("  --log-format <text|json>  Format of the console output");
+    println!("  --log-max-len <bytes>     Truncate longer console messages, 0 for unlimited");
+    println!("  --log-service <name>      Service name reported in JSON log records");
+    #[cfg(feature = "fake-time")]
+    println!("  --fake-time      Use a virtual clock that jumps to the next timer when idle");
+    #[cfg(feature = "devtools")]
+    println!("  --inspect <port> Serve the Chrome DevTools Protocol on 127.0.0.1:<port>");
+    #[cfg(feature = "devtools")]
+    println!("  --cpu-prof <file>  Write a CPU profile on exit, .cpuprofile or collapsed stacks");
+    #[cfg(feature = "devtools")]
+    println!("  --cpu-prof-interval <us>  Sampling interval of the CPU profiler, 1000 by default");
+    #[cfg(feature = "devtools")]
+    println!("  --coverage <dir> Write V8 JSON and lcov coverage of the scripts on exit");
     println!("  --               Stop processing options");
 }
 
 pub async fn run(args: impl Iterator<Item = String>) -> Result<JsValue> {
-    let service = Service::new_ref();
+    let args = parse_args(args).inspect_err(|_| print_usage())?;
+    let service = match &args.env {
+        Some(env) => Service::new_ref_with_bootcode(&load_bootcode(env)?),
+        None => Service::new_ref(),
+    };
     let rv = run_with_service(service.clone(), args).await;
+    #[cfg(feature = "devtools")]
+    if let Err(err) = crate::devtools::profiler::finish() {
+        log::error!(target: "js", "{err:?}");
+    }
+    #[cfg(feature = "devtools")]
+    if let Err(err) = crate::devtools::coverage::finish(&service) {
+        log::error!(target: "js", "{err:?}");
+    }
     service.shutdown().await;
     rv
 }
 
-async fn run_with_service(
-    service: ServiceRef,
-    args: impl Iterator<Item = String>,
-) -> Result<JsValue> {
-    let args = parse_args(args)?;
+async fn run_with_service(service: ServiceRef, args: Args) -> Result<JsValue> {
+    set_log_config(args.log_config);
+    let mut scripts = Vec::with_capacity(args.codes.len());
+    for script in args.codes {
+        scripts.push((script.load()?, script));
+    }
+    #[cfg(feature = "fake-time")]
+    if args.fake_time {
+        crate::host_functions::enable_fake_time(&service).context("failed to enable fake time")?;
+    }
+    #[cfg(feature = "devtools")]
+    if let Some(port) = args.inspect {
+        crate::devtools::inspector::start(&service, port).context("failed to start inspector")?;
+    }
+    #[cfg(feature = "devtools")]
+    if let Some((path, interval)) = args.cpu_prof {
+        crate::devtools::profiler::start(&service, path, interval)
+            .context("failed to start the CPU profiler")?;
+    }
+    #[cfg(feature = "devtools")]
+    if let Some(dir) = args.coverage {
+        crate::devtools::coverage::start(dir);
+    }
     let js_ctx = service.context();
     let js_args = args
         .js_args
@@ -98,14 +293,31 @@ async fn run_with_service(
         .set_property("scriptArgs", &js_args)
         .context("failed to set scriptArgs")?;
     let mut expr_val = None;
-    for code in args.codes.into_iter() {
+    for (code, script) in scripts {
         let result = match code {
-            JsCode::Source(src) => service.exec_script(&src),
+            JsCode::Source(src) => {
+                let map_file = script.source_map.as_deref();
+                match crate::source_map::load(&script.name, &src, map_file) {
+                    Ok(true) => crate::source_map::install(&js_ctx)
+                        .context("failed to install the stack rewriter")?,
+                    Ok(false) => {}
+                    Err(err) if map_file.is_some() => return Err(err),
+                    Err(err) => {
+                        log::warn!(target: "js", "ignored the source map of {}: {err:?}", script.name)
+                    }
+                }
+                #[cfg(feature = "devtools")]
+                let src = crate::devtools::instrument(&service, &script.name, src)
+                    .context("failed to instrument the script")?;
+                service.exec_script_with_filename(&src, &script.name)
+            }
             JsCode::Bytecode(bytes) => service.exec_bytecode(&bytes),
         };
         match result {
             Ok(value) => expr_val = value.to_js_value(),
             Err(err) => {
+                #[cfg(feature = "devtools")]
+                crate::devtools::inspector::exception_thrown(&js_ctx, &err);
                 bail!("failed to execute script: {err}");
             }
         }
@@ -2,8 +2,12 @@ extern crate alloc;
 
 pub use service::Service;
 
+#[cfg(feature = "devtools")]
+mod devtools;
 mod host_functions;
+mod interrupt;
 mod service;
+mod source_map;
 
 pub mod js_eval;
 mod traits;
@@ -2,12 +2,20 @@ use alloc::{
     boxed::Box,
     collections::BTreeMap,
     rc::{Rc, Weak},
+    sync::Arc,
+};
+use core::{
+    any::Any,
+    cell::{Cell, RefCell},
+    ops::Deref,
+    pin::Pin,
+    task::{Context as TaskContext, Poll},
+    time::Duration,
 };
-use core::{any::Any, cell::RefCell, ops::Deref, time::Duration};
 use log::{debug, error};
-use std::{borrow::Cow, future::Future, sync::Mutex};
+use std::{borrow::Cow, ffi::CString, future::Future, sync::Mutex};
 
-use crate::{host_functions::setup_host_functions, runtime};
+use crate::{host_functions::setup_host_functions, runtime, source_map};
 use anyhow::{Context, Result};
 use js::{c, Code, Error as ValueError, ToArgs};
 use tokio::sync::broadcast;
@@ -56,9 +64,16 @@ impl TryFrom<js::Context> for ServiceRef {
 
 pub struct JsEngine {
     pub ctx: js::Context,
+    /// Contexts created by `Wapo.createRealm`, they must be freed before the runtime.
+    realms: RefCell<BTreeMap<u64, Rc<js::Context>>>,
+    /// Realms whose handle has been garbage collected, freed after the pending jobs.
+    released_realms: RefCell<Vec<u64>>,
+    next_realm_id: Cell<u64>,
     runtime: js::Runtime,
     weak_self: Weak<JsEngine>,
     last_error: Mutex<Option<String>>,
+    /// Called with every uncaught error, workers set it to forward them to their parent.
+    uncaught_error_hook: RefCell<Option<Box<dyn Fn(&str)>>>,
 }
 
 impl JsEngine {
@@ -79,6 +94,37 @@ impl JsEngine {
         self.dup_value(*js_value.raw_value())
     }
 
+    pub(crate) fn create_realm(&self) -> u64 {
+        let id = self.next_realm_id.get();
+        self.next_realm_id.set(id + 1);
+        let ctx = self.runtime.new_context();
+        self.realms.borrow_mut().insert(id, Rc::new(ctx));
+        id
+    }
+
+    pub(crate) fn realm(&self, id: u64) -> Option<Rc<js::Context>> {
+        self.realms.borrow().get(&id).cloned()
+    }
+
+    pub(crate) fn dispose_realm(&self, id: u64) {
+        let realm = self.realms.borrow_mut().remove(&id);
+        drop(realm);
+    }
+
+    pub(crate) fn release_realm(&self, id: u64) {
+        self.released_realms.borrow_mut().push(id);
+    }
+
+    pub(crate) fn set_uncaught_error_hook(&self, hook: impl Fn(&str) + 'static) {
+        *self.uncaught_error_hook.borrow_mut() = Some(Box::new(hook));
+    }
+
+    pub(crate) fn report_uncaught_error(&self, err: &str) {
+        if let Some(hook) = &*self.uncaught_error_hook.borrow() {
+            hook(err);
+        }
+    }
+
     pub fn take_last_error(&self) -> Option<String> {
         self.last_error.lock().unwrap().take()
     }
@@ -92,18 +138,28 @@ impl JsEngine {
                     debug!(target: "js::rt", "executed {cnt} pending jobs");
                 }
                 Err(err) => {
+                    let err = source_map::rewrite(&err).into_owned();
                     error!(target: "js::rt", "uncatched error: {err}");
+                    #[cfg(feature = "devtools")]
+                    crate::devtools::inspector::exception_thrown(self.context(), &err);
+                    self.report_uncaught_error(&err);
                     *self.last_error.lock().unwrap() = Some(err);
                     continue;
                 }
             }
         }
+        let released = core::mem::take(&mut *self.released_realms.borrow_mut());
+        for id in released {
+            self.dispose_realm(id);
+        }
     }
 }
 
 pub struct Service {
     runtime: Rc<JsEngine>,
     state: RefCell<ServiceState>,
+    /// The bootcode the service was created with, inherited by its workers.
+    bootcode: Arc<[u8]>,
 }
 
 struct ServiceState {
@@ -138,27 +194,19 @@ impl Default for ServiceState {
 }
 
 impl Service {
-    pub(crate) fn new(weak_self: ServiceWeakRef) -> Self {
+    pub(crate) fn new(weak_self: ServiceWeakRef, bootcode: &[u8]) -> Self {
         let runtime = js::Runtime::new();
         let ctx = runtime.new_context();
         let boxed_self = Box::into_raw(Box::new(weak_self));
         unsafe { c::JS_SetContextOpaque(ctx.as_ptr(), boxed_self as *mut _) };
         setup_host_functions(&ctx).expect("failed to setup host functions");
 
-        #[cfg(feature = "external-bootcode")]
-        let bootcode: Cow<'_, [u8]> = if let Ok(bootcode_path) = std::env::var("WAPOJS_BOOTCODE") {
-            let source = std::fs::read_to_string(bootcode_path).expect("failed to read bootcode");
-            let code = js::compile(&source, "<bootcode>").expect("failed to compile bootcode");
-
-            Cow::Owned(code)
-        } else {
-            Cow::Borrowed(bootcode::BOOT_CODE)
-        };
-        #[cfg(not(feature = "external-bootcode"))]
-        let bootcode = Cow::Borrowed(bootcode::BOOT_CODE);
-
-        let bootcode = Code::Bytecode(&bootcode);
-        ctx.eval(&bootcode).expect("failed to eval bootcode");
+        ctx.eval(&Code::Bytecode(bootcode))
+            .expect("failed to eval bootcode");
+        if source_map::is_active() {
+            // A worker started by a script with mapped or instrumented code.
+            source_map::install(&ctx).expect("failed to install the stack rewriter");
+        }
 
         if let Ok(v) = std::env::var("WAPO_RT_FLAGS") {
             if let Ok(v) = v.parse::<u32>() {
@@ -170,16 +218,25 @@ impl Service {
             runtime: Rc::new_cyclic(|weak_self| JsEngine {
                 runtime,
                 ctx,
+                realms: Default::default(),
+                released_realms: Default::default(),
+                next_realm_id: Cell::new(0),
                 weak_self: weak_self.clone(),
                 last_error: Default::default(),
+                uncaught_error_hook: Default::default(),
             }),
             state,
+            bootcode: bootcode.into(),
         }
     }
 
     pub fn new_ref() -> ServiceRef {
+        Self::new_ref_with_bootcode(&default_bootcode())
+    }
+
+    pub fn new_ref_with_bootcode(bootcode: &[u8]) -> ServiceRef {
         ServiceRef(Rc::new_cyclic(|weak_self| {
-            Service::new(ServiceWeakRef(weak_self.clone()))
+            Service::new(ServiceWeakRef(weak_self.clone()), bootcode)
         }))
     }
 
@@ -199,16 +256,52 @@ impl Service {
         self.runtime.clone()
     }
 
+    pub(crate) fn bootcode(&self) -> Arc<[u8]> {
+        self.bootcode.clone()
+    }
+
     pub fn exec_script(&self, script: &str) -> Result<OwnedJsValue, String> {
         self.eval(Code::Source(script))
     }
 
+    /// Evaluates a script under the given file name, which is reported in stack traces and
+    /// used to look up its source map.
+    pub fn exec_script_with_filename(
+        &self,
+        script: &str,
+        filename: &str,
+    ) -> Result<OwnedJsValue, String> {
+        let ctx = self.context();
+        let source = CString::new(script).map_err(|_| "script contains a NUL byte".to_string())?;
+        let filename =
+            CString::new(filename).map_err(|_| "file name contains a NUL byte".to_string())?;
+        let ret = unsafe {
+            c::JS_Eval(
+                ctx.as_ptr(),
+                source.as_ptr(),
+                script.len(),
+                filename.as_ptr(),
+                c::JS_EVAL_TYPE_GLOBAL as _,
+            )
+        };
+        let result = if c::is_exception(ret) {
+            Err(source_map::rewrite(&ctx.get_exception_str()).into_owned())
+        } else {
+            js::Value::new_moved(ctx, ret)
+                .try_into()
+                .map_err(|err: ValueError| err.to_string())
+        };
+        self.runtime.exec_pending_jobs();
+        result
+    }
+
     pub fn exec_bytecode(&self, script: &[u8]) -> Result<OwnedJsValue, String> {
         self.eval(Code::Bytecode(script))
     }
 
     pub fn eval(&self, code: Code) -> Result<OwnedJsValue, String> {
         let result = js::eval(self.context(), &code)
+            .map_err(|err| source_map::rewrite(&err).into_owned())
             .map(|value| value.try_into().map_err(|err: ValueError| err.to_string()))?;
         self.runtime.exec_pending_jobs();
         result
@@ -226,7 +319,10 @@ impl Service {
             c::JS_Call(ctx.as_ptr(), func, this, args_len, args)
         };
         if c::is_exception(ret) {
-            let err = self.context().get_exception_str();
+            let err = source_map::rewrite(&self.context().get_exception_str()).into_owned();
+            #[cfg(feature = "devtools")]
+            crate::devtools::inspector::exception_thrown(self.context(), &err);
+            self.runtime.report_uncaught_error(&err);
             anyhow::bail!("failed to call function: {err}");
         }
         self.runtime.exec_pending_jobs();
@@ -284,7 +380,7 @@ impl Service {
         let res = Resource::new(js_callback, Some(Box::new(cancel_tx)));
         let id = self.push_resource(res);
         let weak_service = self.weak_self();
-        let _handle = crate::runtime::spawn(async move {
+        let _handle = crate::runtime::spawn(ResourceScope::new(id, async move {
             tokio::select! {
                 _ = fut_gen(weak_service.clone(), id, args) => {
                 }
@@ -293,7 +389,7 @@ impl Service {
             }
             debug!(target: "js::rt", "task {id} finished");
             close(weak_service, id);
-        });
+        }));
         id
     }
 
@@ -305,6 +401,16 @@ impl Service {
         let _ = rx.recv().await;
     }
 
+    /// Returns the JS values held by the live resources, with their ids.
+    pub(crate) fn resource_values(&self) -> Vec<(u64, js::Value)> {
+        let state = self.state.borrow();
+        state
+            .recources
+            .iter()
+            .map(|(id, res)| (*id, self.to_js_value(&res.js_value)))
+            .collect()
+    }
+
     pub fn number_of_tasks(&self) -> usize {
         self.state.borrow().recources.len()
     }
@@ -342,6 +448,80 @@ impl Service {
     }
 }
 
+/// The bootcode of services created with [`Service::new_ref`].
+///
+/// It is the profile named by `WAPOJS_ENV` if set, which lets an app manifest pick one, or the
+/// profile selected at build time. With `external-bootcode`, `WAPOJS_BOOTCODE` names a source
+/// file that overrides both. Workers boot with the bootcode of the service that created them.
+pub fn default_bootcode() -> Cow<'static, [u8]> {
+    #[cfg(feature = "external-bootcode")]
+    if let Ok(bootcode_path) = std::env::var("WAPOJS_BOOTCODE") {
+        let source = std::fs::read_to_string(bootcode_path).expect("failed to read bootcode");
+        let code = js::compile(&source, "<bootcode>").expect("failed to compile bootcode");
+        return Cow::Owned(code);
+    }
+    if let Ok(env) = std::env::var("WAPOJS_ENV") {
+        match load_bootcode(&env) {
+            Ok(code) => return code,
+            Err(err) => error!(target: "js::rt", "ignored WAPOJS_ENV: {err:?}"),
+        }
+    }
+    Cow::Borrowed(bootcode::BOOT_CODE)
+}
+
+/// Resolves a bootcode profile name, one of `minimal`, `node`, `browser` or `worker`. Only the
+/// profiles embedded in the build are found: all of them in the native runtime, just the default
+/// one in the wasm build. In the native runtime, a path to a precompiled `.jsc` file is accepted
+/// as well.
+pub fn load_bootcode(env: &str) -> Result<Cow<'static, [u8]>> {
+    if let Some(code) = bootcode::profile(env) {
+        return Ok(Cow::Borrowed(code));
+    }
+    #[cfg(feature = "native")]
+    if env.ends_with(".jsc") {
+        let code = std::fs::read(env).with_context(|| format!("failed to read bootcode {env}"))?;
+        return Ok(Cow::Owned(code));
+    }
+    let profiles: Vec<_> = bootcode::PROFILES.iter().map(|(name, _)| *name).collect();
+    anyhow::bail!("unknown env {env}, expected one of {}", profiles.join(", "))
+}
+
+thread_local! {
+    static CURRENT_RESOURCE: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Returns the id of the resource whose task is currently being polled, if any.
+pub(crate) fn current_resource_id() -> Option<u64> {
+    CURRENT_RESOURCE.with(|current| current.get())
+}
+
+/// Marks the resource `id` as the current one while polling the wrapped future, so that
+/// callbacks fired from its task can be attributed to it.
+struct ResourceScope<F> {
+    id: u64,
+    inner: Pin<Box<F>>,
+}
+
+impl<F> ResourceScope<F> {
+    fn new(id: u64, inner: F) -> Self {
+        Self {
+            id,
+            inner: Box::pin(inner),
+        }
+    }
+}
+
+impl<F: Future> Future for ResourceScope<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        let prev = CURRENT_RESOURCE.with(|current| current.replace(Some(self.id)));
+        let result = self.inner.as_mut().poll(cx);
+        CURRENT_RESOURCE.with(|current| current.set(prev));
+        result
+    }
+}
+
 pub(crate) fn close(weak_service: ServiceWeakRef, id: u64) {
     let Some(service) = weak_service.upgrade() else {
         return;
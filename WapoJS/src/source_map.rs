@@ -0,0 +1,552 @@
+//! Source map support for stack traces.
+//!
+//! Maps are attached to scripts by file name, either from an inline
+//! `//# sourceMappingURL=data:...;base64,...` comment or from `--source-map <file>`. Locations
+//! in error stacks and console output are then rewritten to the original sources.
+//!
+//! `error.stack` is an own property QuickJS fills in when an error is created. Once a map is
+//! loaded, the global error constructors are wrapped so that the stacks of the errors created
+//! with them, `new Error()` and subclasses included, are rewritten right away. Errors raised by
+//! the engine itself, like a `TypeError` on a property read of `undefined`, do not go through
+//! the constructors: their stacks are only rewritten when printed or reported as uncaught.
+//!
+//! Scripts instrumented for coverage have text inserted into their lines. The columns of their
+//! frames are shifted back to the source as loaded before any map is applied.
+//!
+//! The maps and insertions are shared by the whole process, so that the stacks of workers, which
+//! run on threads of their own, are rewritten as well.
+
+use alloc::{borrow::Cow, collections::BTreeMap, sync::Arc};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use base64::Engine as _;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawSourceMap {
+    version: u32,
+    #[serde(default)]
+    source_root: Option<String>,
+    #[serde(default)]
+    sources: Vec<Option<String>>,
+    #[serde(default)]
+    names: Vec<String>,
+    mappings: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Mapping {
+    generated_column: u32,
+    source: u32,
+    original_line: u32,
+    original_column: u32,
+    name: Option<u32>,
+}
+
+/// A decoded version 3 source map. Index maps with `sections` are not supported.
+pub(crate) struct SourceMap {
+    sources: Vec<String>,
+    names: Vec<String>,
+    /// Mappings of each generated line, sorted by generated column.
+    lines: Vec<Vec<Mapping>>,
+}
+
+/// A resolved original position, lines and columns are 1-based.
+pub(crate) struct OriginalLocation<'a> {
+    pub source: &'a str,
+    pub line: u32,
+    pub column: u32,
+    pub name: Option<&'a str>,
+}
+
+impl SourceMap {
+    pub fn parse(json: &str) -> Result<Self> {
+        let raw: RawSourceMap = serde_json::from_str(json).context("invalid source map")?;
+        if raw.version != 3 {
+            bail!("unsupported source map version {}", raw.version);
+        }
+        let root = raw.source_root.unwrap_or_default();
+        let sources = raw
+            .sources
+            .into_iter()
+            .map(|source| {
+                let source = source.unwrap_or_default();
+                if root.is_empty() {
+                    source
+                } else {
+                    format!("{}/{source}", root.trim_end_matches('/'))
+                }
+            })
+            .collect();
+        Ok(Self {
+            sources,
+            names: raw.names,
+            lines: decode_mappings(&raw.mappings)?,
+        })
+    }
+
+    /// Looks up the original position of a 1-based generated line and column. A column of 0
+    /// means unknown, the first mapping of the line is used then.
+    pub fn lookup(&self, line: u32, column: u32) -> Option<OriginalLocation<'_>> {
+        let mappings = self.lines.get(line.checked_sub(1)? as usize)?;
+        let column = column.saturating_sub(1);
+        let index = match mappings.partition_point(|m| m.generated_column <= column) {
+            0 => 0,
+            n => n - 1,
+        };
+        let mapping = mappings.get(index)?;
+        Some(OriginalLocation {
+            source: self.sources.get(mapping.source as usize)?,
+            line: mapping.original_line + 1,
+            column: mapping.original_column + 1,
+            name: mapping
+                .name
+                .and_then(|name| self.names.get(name as usize))
+                .map(|name| name.as_str()),
+        })
+    }
+}
+
+fn decode_mappings(mappings: &str) -> Result<Vec<Vec<Mapping>>> {
+    let mut lines = vec![];
+    let (mut source, mut original_line, mut original_column, mut name) = (0i64, 0i64, 0i64, 0i64);
+    for line in mappings.split(';') {
+        let mut generated_column = 0i64;
+        let mut decoded = vec![];
+        for segment in line.split(',').filter(|s| !s.is_empty()) {
+            let fields = decode_vlq(segment)?;
+            generated_column += fields[0];
+            match fields.len() {
+                1 => continue,
+                4 | 5 => {}
+                n => bail!("invalid source map segment with {n} fields"),
+            }
+            source += fields[1];
+            original_line += fields[2];
+            original_column += fields[3];
+            let has_name = fields.len() == 5;
+            if has_name {
+                name += fields[4];
+            }
+            decoded.push(Mapping {
+                generated_column: generated_column.try_into()?,
+                source: source.try_into()?,
+                original_line: original_line.try_into()?,
+                original_column: original_column.try_into()?,
+                name: if has_name {
+                    Some(name.try_into()?)
+                } else {
+                    None
+                },
+            });
+        }
+        decoded.sort_by_key(|m| m.generated_column);
+        lines.push(decoded);
+    }
+    Ok(lines)
+}
+
+fn decode_vlq(segment: &str) -> Result<Vec<i64>> {
+    let mut values = vec![];
+    let mut value = 0i64;
+    let mut shift = 0;
+    for b in segment.bytes() {
+        let digit = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => bail!("invalid base64 VLQ character {:?}", b as char),
+        } as i64;
+        if shift > 60 {
+            bail!("base64 VLQ value overflow");
+        }
+        value |= (digit & 0x1f) << shift;
+        if digit & 0x20 != 0 {
+            shift += 5;
+            continue;
+        }
+        values.push(if value & 1 == 1 {
+            -(value >> 1)
+        } else {
+            value >> 1
+        });
+        value = 0;
+        shift = 0;
+    }
+    if shift != 0 || values.is_empty() {
+        bail!("truncated base64 VLQ segment");
+    }
+    Ok(values)
+}
+
+/// Text inserted into a script before it was evaluated, at a 1-based line and column of the
+/// script as loaded.
+pub(crate) struct Insertion {
+    pub line: u32,
+    pub column: u32,
+    pub len: u32,
+}
+
+static SOURCE_MAPS: Mutex<BTreeMap<String, Arc<SourceMap>>> = Mutex::new(BTreeMap::new());
+/// The insertions of each line of the instrumented scripts, sorted by column.
+static INSERTIONS: Mutex<BTreeMap<String, BTreeMap<u32, Vec<(u32, u32)>>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Records the insertions made into the script evaluated with the given file name, sorted by
+/// position, so that the columns of its frames are shifted back.
+pub(crate) fn register_insertions(filename: &str, insertions: Vec<Insertion>) {
+    if insertions.is_empty() {
+        return;
+    }
+    let mut lines = BTreeMap::<u32, Vec<(u32, u32)>>::new();
+    for insertion in insertions {
+        lines
+            .entry(insertion.line)
+            .or_default()
+            .push((insertion.column, insertion.len));
+    }
+    INSERTIONS.lock().unwrap().insert(filename.into(), lines);
+}
+
+/// Maps a column of an instrumented line back to the script as loaded. Returns `None` if nothing
+/// was inserted into the line.
+pub(crate) fn original_column(filename: &str, line: u32, column: u32) -> Option<u32> {
+    let all = INSERTIONS.lock().unwrap();
+    let insertions = all.get(filename)?.get(&line)?;
+    let mut removed = 0;
+    for &(at, len) in insertions {
+        if column < at + removed {
+            break;
+        }
+        if column < at + removed + len {
+            // Inside the inserted text, blame the code it was inserted before.
+            return Some(at);
+        }
+        removed += len;
+    }
+    Some(column - removed)
+}
+
+/// Attaches a source map to the script evaluated with the given file name.
+pub(crate) fn register(filename: &str, map: SourceMap) {
+    SOURCE_MAPS.lock().unwrap().insert(filename.into(), Arc::new(map));
+}
+
+fn get(filename: &str) -> Option<Arc<SourceMap>> {
+    SOURCE_MAPS.lock().unwrap().get(filename).cloned()
+}
+
+/// Loads the source map of a script, from `map_file` if given or else from an inline
+/// `sourceMappingURL` data URL. Returns whether a map was attached.
+pub(crate) fn load(filename: &str, source: &str, map_file: Option<&str>) -> Result<bool> {
+    let json = match map_file {
+        Some(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read source map {path}"))?,
+        None => match inline_source_map(source)? {
+            Some(json) => json,
+            None => return Ok(false),
+        },
+    };
+    register(filename, SourceMap::parse(&json)?);
+    Ok(true)
+}
+
+fn inline_source_map(source: &str) -> Result<Option<String>> {
+    let Some(url) = source.lines().rev().take(8).find_map(|line| {
+        let line = line.trim();
+        line.strip_prefix("//# sourceMappingURL=")
+            .or_else(|| line.strip_prefix("//@ sourceMappingURL="))
+    }) else {
+        return Ok(None);
+    };
+    let Some(data) = url.strip_prefix("data:") else {
+        // External maps must be given with --source-map.
+        return Ok(None);
+    };
+    let Some((_mime, encoded)) = data.split_once(";base64,") else {
+        bail!("only base64 data URLs are supported for inline source maps");
+    };
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .context("invalid base64 in inline source map")?;
+    Ok(Some(
+        String::from_utf8(decoded).context("inline source map is not valid utf-8")?,
+    ))
+}
+
+/// The `Error` constructor of the engine, unwrapped.
+pub(crate) fn native_error(ctx: &js::Context) -> Result<js::Value> {
+    Ok(ctx.get_qjsbind_object("source_map.native_error", || {
+        ctx.get_global_object().get_property("Error")
+    })?)
+}
+
+/// Whether any script has a source map or was instrumented, so that the error constructors of
+/// the contexts created afterwards, those of workers included, need wrapping too.
+pub(crate) fn is_active() -> bool {
+    !SOURCE_MAPS.lock().unwrap().is_empty() || !INSERTIONS.lock().unwrap().is_empty()
+}
+
+/// Wraps the global error constructors of a context so that the stacks they record are
+/// rewritten. Does nothing if they are already wrapped.
+pub(crate) fn install(ctx: &js::Context) -> Result<()> {
+    native_error(ctx)?;
+    ctx.get_qjsbind_object("source_map.installed", || {
+        let wrap = ctx
+            .eval(&js::Code::Bytecode(qjsc::compiled!(
+                r#"
+            (function (g, { rewrite }) {
+                const names = ["Error", "EvalError", "RangeError", "ReferenceError", "SyntaxError",
+                    "TypeError", "URIError", "AggregateError"];
+                for (const name of names) {
+                    const Native = g[name];
+                    if (typeof Native !== "function") {
+                        continue;
+                    }
+                    const Wrapped = function (...args) {
+                        const err = Reflect.construct(Native, args, new.target || Wrapped);
+                        const desc = Object.getOwnPropertyDescriptor(err, "stack");
+                        if (desc && typeof desc.value === "string") {
+                            // Drop the frames of Reflect.construct and of this wrapper.
+                            const lines = desc.value.split("\n");
+                            let skip = 0;
+                            while (skip < lines.length && lines[skip].endsWith("(native)")) {
+                                skip++;
+                            }
+                            const stack = rewrite(lines.slice(skip + 1).join("\n"));
+                            Object.defineProperty(err, "stack", {
+                                value: stack,
+                                writable: true,
+                                configurable: true,
+                            });
+                        }
+                        return err;
+                    };
+                    for (const key of Reflect.ownKeys(Native)) {
+                        if (key !== "prototype" && key !== "length") {
+                            Object.defineProperty(Wrapped, key, Object.getOwnPropertyDescriptor(Native, key));
+                        }
+                    }
+                    Wrapped.prototype = Native.prototype;
+                    Object.defineProperty(Native.prototype, "constructor", {
+                        value: Wrapped,
+                        writable: true,
+                        configurable: true,
+                    });
+                    g[name] = Wrapped;
+                }
+            })
+            "#
+            )))
+            .map_err(js::Error::msg)?;
+        let helpers = ctx.new_object("SourceMap");
+        helpers.define_property_fn("rewrite", rewrite_stack)?;
+        wrap.call(&js::Value::undefined(), &[ctx.get_global_object(), helpers])?;
+        Ok(wrap)
+    })?;
+    Ok(())
+}
+
+#[js::host_call]
+fn rewrite_stack(stack: String) -> String {
+    rewrite(&stack).into_owned()
+}
+
+/// Rewrites the `at name (file:line:column)` lines of a stack trace, or of any text containing
+/// one, to original positions.
+pub(crate) fn rewrite(text: &str) -> Cow<'_, str> {
+    if !is_active() || !text.contains("at ") {
+        return Cow::Borrowed(text);
+    }
+    let mut changed = false;
+    let lines: Vec<Cow<str>> = text
+        .split('\n')
+        .map(|line| match rewrite_line(line) {
+            Some(line) => {
+                changed = true;
+                Cow::Owned(line)
+            }
+            None => Cow::Borrowed(line),
+        })
+        .collect();
+    if changed {
+        Cow::Owned(lines.join("\n"))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+fn rewrite_line(line: &str) -> Option<String> {
+    let body = line.trim_start();
+    let indent = &line[..line.len() - body.len()];
+    let frame = body.strip_prefix("at ")?;
+    let (function, location) = frame.strip_suffix(')')?.rsplit_once(" (")?;
+    let (filename, line_no, column) = split_location(location)?;
+    let shifted = match column {
+        0 => None,
+        column => original_column(filename, line_no, column),
+    };
+    let Some(map) = get(filename) else {
+        let column = shifted?;
+        return Some(format!(
+            "{indent}at {function} ({filename}:{line_no}:{column})"
+        ));
+    };
+    let original = map.lookup(line_no, shifted.unwrap_or(column))?;
+    let function = match original.name {
+        Some(name) if function == "<anonymous>" => name,
+        _ => function,
+    };
+    Some(format!(
+        "{indent}at {function} ({}:{}:{})",
+        original.source, original.line, original.column
+    ))
+}
+
+fn split_location(location: &str) -> Option<(&str, u32, u32)> {
+    let (rest, last) = location.rsplit_once(':')?;
+    let last: u32 = last.parse().ok()?;
+    match rest.rsplit_once(':') {
+        Some((filename, line)) if line.bytes().all(|b| b.is_ascii_digit()) && !line.is_empty() => {
+            Some((filename, line.parse().ok()?, last))
+        }
+        _ => Some((rest, last, 0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(mappings: &str) -> SourceMap {
+        SourceMap::parse(&format!(
+            r#"{{"version":3,"sources":["a.ts","b.ts"],"names":["foo","bar"],"mappings":"{mappings}"}}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn vlq_values() {
+        assert_eq!(decode_vlq("A").unwrap(), [0]);
+        assert_eq!(decode_vlq("C").unwrap(), [1]);
+        assert_eq!(decode_vlq("D").unwrap(), [-1]);
+        assert_eq!(decode_vlq("gB").unwrap(), [16]);
+        assert_eq!(decode_vlq("2H").unwrap(), [123]);
+        assert_eq!(decode_vlq("3H").unwrap(), [-123]);
+        assert_eq!(decode_vlq("AACDE").unwrap(), [0, 0, 1, -1, 2]);
+    }
+
+    #[test]
+    fn vlq_errors() {
+        assert!(decode_vlq("").is_err());
+        assert!(
+            decode_vlq("g").is_err(),
+            "a continuation without a next digit"
+        );
+        assert!(decode_vlq("A*").is_err());
+        assert!(decode_vlq("gggggggggggggB").is_err(), "overflow");
+    }
+
+    #[test]
+    fn multi_segment_lines() {
+        // Line 1: columns 0, 4 and 8 map to a.ts 1:1, a.ts 1:5 and b.ts 3:4 named `bar`.
+        let map = map("AAAA,IAAI,ICEDC;");
+        let at = |line, column| {
+            let loc = map.lookup(line, column).unwrap();
+            (
+                loc.source.to_string(),
+                loc.line,
+                loc.column,
+                loc.name.map(String::from),
+            )
+        };
+        assert_eq!(at(1, 1), ("a.ts".into(), 1, 1, None));
+        assert_eq!(at(1, 4), ("a.ts".into(), 1, 1, None));
+        assert_eq!(at(1, 5), ("a.ts".into(), 1, 5, None));
+        assert_eq!(at(1, 9), ("b.ts".into(), 3, 4, Some("bar".into())));
+    }
+
+    #[test]
+    fn negative_deltas() {
+        // The original line and column are relative to the previous segment, across lines.
+        let map = map("AAEI;AADD");
+        let loc = map.lookup(1, 1).unwrap();
+        assert_eq!((loc.source, loc.line, loc.column), ("a.ts", 3, 5));
+        let loc = map.lookup(2, 1).unwrap();
+        assert_eq!((loc.source, loc.line, loc.column), ("a.ts", 2, 4));
+    }
+
+    #[test]
+    fn out_of_range_positions() {
+        let map = map("AAAA,EAAE;;IAAI");
+        // Past the last segment of a line, the last segment applies.
+        assert_eq!(map.lookup(1, 1000).unwrap().column, 3);
+        // Column 0 means unknown, the first segment applies.
+        assert_eq!(map.lookup(1, 0).unwrap().column, 1);
+        // Before the first segment of a line, the first segment applies.
+        assert_eq!(map.lookup(3, 1).unwrap().column, 7);
+        assert!(map.lookup(2, 1).is_none(), "a line without segments");
+        assert!(map.lookup(4, 1).is_none(), "a line past the end");
+        assert!(map.lookup(0, 1).is_none());
+    }
+
+    #[test]
+    fn invalid_maps() {
+        assert!(SourceMap::parse(r#"{"version":2,"mappings":""}"#).is_err());
+        assert!(SourceMap::parse(r#"{"version":3,"mappings":"AA"}"#).is_err());
+        assert!(
+            SourceMap::parse(r#"{"version":3,"mappings":"AAAA,ADAA"}"#).is_err(),
+            "a negative source index"
+        );
+    }
+
+    #[test]
+    fn rewrites_stack_lines() {
+        register("bundle.js", map("AAAA,IAAI;AACA"));
+        let stack = "    at <anonymous> (bundle.js:1:5)\n    at main (bundle.js:2:1)\n    at f (other.js:1:1)";
+        assert_eq!(
+            rewrite(stack),
+            "    at <anonymous> (a.ts:1:5)\n    at main (a.ts:2:5)\n    at f (other.js:1:1)"
+        );
+        assert!(matches!(rewrite("no frames here"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn shifts_instrumented_columns() {
+        let insertion = |line, column, len| Insertion { line, column, len };
+        register_insertions(
+            "covered.js",
+            vec![insertion(1, 5, 10), insertion(1, 8, 3), insertion(3, 1, 4)],
+        );
+        let at = |line: u32, column: u32| {
+            rewrite(&format!("at f (covered.js:{line}:{column})")).into_owned()
+        };
+        assert_eq!(at(1, 4), "at f (covered.js:1:4)");
+        assert_eq!(at(1, 15), "at f (covered.js:1:5)");
+        assert_eq!(
+            at(1, 9),
+            "at f (covered.js:1:5)",
+            "inside the inserted text"
+        );
+        assert_eq!(at(1, 19), "at f (covered.js:1:8)");
+        assert_eq!(at(1, 21), "at f (covered.js:1:8)");
+        assert_eq!(at(1, 22), "at f (covered.js:1:9)");
+        assert_eq!(
+            at(2, 20),
+            "at f (covered.js:2:20)",
+            "a line without insertions"
+        );
+        assert_eq!(at(3, 7), "at f (covered.js:3:3)");
+
+        register("covered.js", map("AAAA,IAAI"));
+        assert_eq!(
+            at(1, 15),
+            "at f (a.ts:1:5)",
+            "shifted before the map applies"
+        );
+    }
+}
@@ -4,8 +4,29 @@ extern crate alloc;
 
 use wapo_quickjs::{js_eval, runtime};
 
+#[cfg(not(feature = "fake-time"))]
 #[runtime::main]
 async fn main() {
+    run().await
+}
+
+/// The virtual clock of `--fake-time` can only be paused on a current-thread runtime, other runs
+/// keep the default multi-threaded one.
+#[cfg(feature = "fake-time")]
+fn main() {
+    let mut builder = if js_eval::wants_fake_time(std::env::args()) {
+        tokio::runtime::Builder::new_current_thread()
+    } else {
+        tokio::runtime::Builder::new_multi_thread()
+    };
+    builder
+        .enable_all()
+        .build()
+        .expect("failed to create the tokio runtime")
+        .block_on(run())
+}
+
+async fn run() {
     runtime::init_logger();
     log::debug!(target: "js", "WapoJS started");
     let _output = runtime::run_local(js_eval::run(std::env::args()))
@@ -48,6 +48,42 @@ export interface ScaleCodec {
   codec(typeId: number | number[], typeRegistry: TypeRegistry): Codec;
 }
 
+/** An isolated realm created by `Wapo.createRealm`. */
+export interface Realm {
+  /** Evaluates a script in the realm and returns a copy of its completion value. */
+  eval(code: string): any;
+  /** Calls a global function of the realm with copies of `args` and returns a copy of its result. */
+  call(fnName: string, args?: any[]): any;
+  /** Frees the realm, further calls throw. */
+  dispose(): void;
+  readonly disposed: boolean;
+}
+
+/** A registry of instances created by `Wapo.wasmLinker`. */
+export interface WasmLinker {
+  /** Registers the exports of an instance as the module `name`. */
+  define(name: string, instance: WebAssembly.Instance): void;
+  /** Whether an instance is registered as the module `name`. */
+  has(name: string): boolean;
+  /** The names of the registered modules. */
+  readonly names: string[];
+  /**
+   * Instantiates a module, importing from the registered instances first and then from
+   * `imports`. Code of the registered instances called this way runs under the limits of the
+   * new instance.
+   */
+  instantiate(
+    module: WebAssembly.Module,
+    imports?: WebAssembly.Imports,
+    limits?: { fuel?: number; maxMemory?: number; maxTableElements?: number }
+  ): WebAssembly.Instance;
+}
+
+/** The files of a WASI preopened directory: a string or bytes is a file, an object a directory. */
+export interface WasiTree {
+  [name: string]: string | Uint8Array | WasiTree;
+}
+
 declare global {
   /** The input arguments passed to the contract eval */
   var scriptArgs: string[];
@@ -72,6 +108,143 @@ declare global {
      */
     hash(algrithm: string, message: Uint8Array | string): Uint8Array;
 
+    /**
+     * Runs the callback on a cron schedule, aligned to the wall clock.
+     * @param {string} cronExpr - A 5-field (minute precision), 6-field (second precision) or 7-field
+     *    (with a year) cron expression. Days of the week are numbered like in classic cron in every
+     *    form, from 0 (Sunday) to 6 with 7 being Sunday too. Names work as well, also in ranges.
+     * @param {function} callback - Called with the scheduled time in milliseconds since the epoch.
+     * @param {object} options - `timezone` is an IANA timezone name, defaults to UTC.
+     *    `missed` is either "skip" (default) or "catchUp".
+     * @returns {number} - The resource id, which can be cancelled with `Wapo.close`.
+     */
+    schedule(
+      cronExpr: string,
+      callback: (scheduledTime: number) => void,
+      options?: { timezone?: string; missed?: "skip" | "catchUp" }
+    ): number;
+
+    /**
+     * Returns the next `count` run times of a cron expression, in milliseconds since the epoch.
+     * `count` defaults to 1 and can be at most 1000.
+     */
+    scheduleUpcoming(
+      cronExpr: string,
+      options?: { timezone?: string },
+      count?: number
+    ): number[];
+
+    /**
+     * Advances the virtual clock by `ms` milliseconds, firing the timers that become due.
+     * Only available when the runtime is started with `--fake-time`.
+     */
+    advanceTime?(ms: number): Promise<void>;
+
+    /**
+     * Returns the QuickJS runtime memory usage together with the objects reachable through
+     * properties from `globalThis` and the live resources, grouped by class. Objects held only
+     * by closures, module scopes or pending jobs are not in `reachable`, its sizes are shallow
+     * estimates and strings are counted once per reference. Proxies are counted as `Proxy`
+     * without running their traps, so what they wrap is only found through other references.
+     */
+    heapStats(): {
+      runtime: Record<string, number>;
+      reachable: Record<string, { count: number; size: number; paths: string[] }>;
+    };
+
+    /**
+     * Writes `heapStats()` to a file as JSON with sorted keys, to be diffed between two points
+     * in time. Only available in the native runtime.
+     */
+    writeHeapStats?(path: string): void;
+
+    /**
+     * Creates an isolated realm: a fresh context on the same runtime with its own global
+     * object and without the `Wapo` APIs. `globals` are copied into its global object.
+     * Values crossing the realm boundary are copied with the structured clone algorithm.
+     * With `timeout`, an `eval` or `call` running longer than that many milliseconds throws an
+     * `InternalError: interrupted`. Realms share the memory of the runtime and have no memory
+     * limit of their own.
+     */
+    createRealm(options?: { globals?: Record<string, any>; timeout?: number }): Realm;
+
+    /**
+     * Sets the default limits of the `WebAssembly.Instance`s created afterwards, which can also
+     * be passed as the third argument of the constructor or of `WebAssembly.instantiate`.
+     * Exceeding a limit throws a `WebAssembly.RuntimeError`. Returns the previous defaults.
+     * @param {object} limits - `fuel` is the number of instructions, roughly, an instance may
+     *    execute; `maxMemory` is the maximum size in bytes of each memory; `maxTableElements`
+     *    is the maximum number of elements of each table.
+     */
+    wasmLimits(limits?: {
+      fuel?: number;
+      maxMemory?: number;
+      maxTableElements?: number;
+    }): { fuel?: number; maxMemory?: number; maxTableElements?: number };
+
+    /**
+     * Returns the statistics of the cache of compiled `WebAssembly.Module`s, keyed by the sha256
+     * of their binary and shared by `new WebAssembly.Module`, `compile`, `compileStreaming` and
+     * `instantiate`.
+     * @param {number} capacity - If given, resizes the cache to hold binaries of up to this many
+     *    bytes in total first, evicting the least recently used modules. `0` disables the cache.
+     */
+    wasmCacheStats(capacity?: number): {
+      hits: number;
+      misses: number;
+      entries: number;
+      size: number;
+      capacity: number;
+    };
+
+    /**
+     * Creates a registry of instances whose exports satisfy the imports of other modules
+     * directly, without calling through JS.
+     */
+    wasmLinker(): WasmLinker;
+
+    /**
+     * WASI preview1 for WebAssembly modules.
+     */
+    wasi: {
+      /**
+       * Creates the import object of a WASI module, to be passed to `WebAssembly.instantiate`.
+       * @param {object} options - `preopens` maps guest directories to trees of files, where a
+       *    string or bytes is a file and an object is a directory. The files live in memory and
+       *    are not shared between calls. `stdout` and `stderr` receive what the module writes,
+       *    which is logged when they are not given.
+       */
+      create(options?: {
+        args?: string[];
+        env?: Record<string, string>;
+        preopens?: Record<string, WasiTree>;
+        stdin?: string | Uint8Array;
+        stdout?: (data: Uint8Array) => void;
+        stderr?: (data: Uint8Array) => void;
+      }): { wasi_snapshot_preview1: object };
+
+      /**
+       * Runs the `_start` export of a WASI instance and returns its exit code.
+       */
+      start(instance: WebAssembly.Instance): number;
+    };
+
+    /**
+     * Serializes a value with the structured clone algorithm to a compact binary format,
+     * to be passed to another service.
+     */
+    structuredSerialize(value: any): Uint8Array;
+
+    /**
+     * Deserializes a value produced by `structuredSerialize`.
+     */
+    structuredDeserialize(bytes: Uint8Array): any;
+
+    /**
+     * Closes a resource such as a timer or a schedule.
+     */
+    close(id: number): void;
+
     /**
      * Terminates the script execution.
      */